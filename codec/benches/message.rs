@@ -0,0 +1,54 @@
+//! Compare encoding of message on every send with splicing
+//! pre-encoded bare message.
+use bytes::{Bytes, BytesMut};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use ntex_amqp_codec::{Encode, Message};
+
+const BODY: &[u8] = b"counter:0000000000000000";
+
+fn build(counter: u64) -> Message {
+    let mut body = BytesMut::from(BODY);
+    body[8..].copy_from_slice(format!("{:016}", counter).as_bytes());
+
+    Message::build()
+        .body(body.freeze())
+        .message_id(Bytes::from_static(b"producer-1"))
+        .reply_to("replies")
+        .app_property("tenant", "default")
+        .app_property("kind", 1)
+        .done()
+}
+
+fn bench_encode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("small-message");
+
+    group.bench_function("build-and-encode", |b| {
+        let mut counter = 0u64;
+        b.iter(|| {
+            counter += 1;
+            let msg = build(counter);
+            let mut buf = BytesMut::with_capacity(msg.encoded_size());
+            msg.encode(&mut buf);
+            black_box(buf.freeze())
+        })
+    });
+
+    group.bench_function("splice-preencoded", |b| {
+        let template = build(0).encode_bare();
+        let offset = template.len() - 16;
+        let mut counter = 0u64;
+        b.iter(|| {
+            counter += 1;
+            black_box(Message::splice(
+                &template,
+                offset,
+                format!("{:016}", counter).as_bytes(),
+            ))
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_encode);
+criterion_main!(benches);
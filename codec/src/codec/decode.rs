@@ -7,12 +7,14 @@ use chrono::{DateTime, TimeZone, Utc};
 use ordered_float::OrderedFloat;
 use uuid::Uuid;
 
+use crate::codec::encode::variant_array_format_code;
 use crate::codec::{self, ArrayDecode, Decode, DecodeFormatted};
 use crate::error::AmqpParseError;
 use crate::framing::{self, AmqpFrame, SaslFrame, HEADER_LEN};
 use crate::protocol::{self, CompoundHeader};
 use crate::types::{
-    Descriptor, List, Multiple, Str, Symbol, Variant, VariantMap, VecStringMap, VecSymbolMap,
+    Decimal128, Decimal32, Decimal64, Descriptor, List, Multiple, Str, Symbol, Variant,
+    VariantArray, VariantMap, VecStringMap, VecSymbolMap,
 };
 use crate::HashMap;
 
@@ -184,6 +186,24 @@ impl DecodeFormatted for Uuid {
     }
 }
 
+macro_rules! decimal_decode {
+    ($type:ident, $code:expr, $size:expr) => {
+        impl DecodeFormatted for $type {
+            fn decode_with_format(input: &[u8], fmt: u8) -> Result<(&[u8], Self), AmqpParseError> {
+                validate_code!(fmt, $code);
+                decode_check_len!(input, $size);
+                let mut value = [0; $size];
+                value.copy_from_slice(&input[..$size]);
+                Ok((&input[$size..], $type(value)))
+            }
+        }
+    };
+}
+
+decimal_decode!(Decimal32, codec::FORMATCODE_DECIMAL32, 4);
+decimal_decode!(Decimal64, codec::FORMATCODE_DECIMAL64, 8);
+decimal_decode!(Decimal128, codec::FORMATCODE_DECIMAL128, 16);
+
 impl DecodeFormatted for Bytes {
     fn decode_with_format(input: &[u8], fmt: u8) -> Result<(&[u8], Self), AmqpParseError> {
         match fmt {
@@ -276,6 +296,7 @@ impl<K: Decode + Eq + Hash, V: Decode, S: BuildHasher + Default> DecodeFormatted
 impl<T: DecodeFormatted> DecodeFormatted for Vec<T> {
     fn decode_with_format(input: &[u8], fmt: u8) -> Result<(&[u8], Self), AmqpParseError> {
         let (input, header) = decode_array_header(input, fmt)?;
+        decode_check_len!(input, 1);
         let item_fmt = input[0]; // todo: support descriptor
         let mut input = &input[1..];
         let mut result: Vec<T> = Vec::with_capacity(header.count as usize);
@@ -288,6 +309,28 @@ impl<T: DecodeFormatted> DecodeFormatted for Vec<T> {
     }
 }
 
+impl DecodeFormatted for VariantArray {
+    fn decode_with_format(input: &[u8], fmt: u8) -> Result<(&[u8], Self), AmqpParseError> {
+        let (input, header) = decode_array_header(input, fmt)?;
+        decode_check_len!(input, 1);
+        let item_fmt = input[0];
+        let mut input = &input[1..];
+        let mut items = Vec::with_capacity(header.count as usize);
+        for _ in 0..header.count {
+            let (new_input, decoded) = Variant::decode_with_format(input, item_fmt)?;
+            items.push(decoded);
+            input = new_input;
+        }
+        if let Some(first) = items.first() {
+            let code = variant_array_format_code(first);
+            if code.is_none() || items.iter().any(|i| variant_array_format_code(i) != code) {
+                return Err(AmqpParseError::ArrayTypeMismatch);
+            }
+        }
+        Ok((input, VariantArray::with_ctor(items, item_fmt)))
+    }
+}
+
 impl DecodeFormatted for VecSymbolMap {
     fn decode_with_format(input: &[u8], fmt: u8) -> Result<(&[u8], Self), AmqpParseError> {
         let (input, header) = decode_map_header(input, fmt)?;
@@ -310,15 +353,16 @@ impl DecodeFormatted for VecStringMap {
         let (input, header) = decode_map_header(input, fmt)?;
         let mut map_input = &input[..header.size as usize];
         let count = header.count / 2;
-        let mut map = Vec::with_capacity(count as usize);
+        let mut map = VecStringMap(Vec::with_capacity(count as usize));
         for _ in 0..count {
             let (input1, key) = Str::decode(map_input)?;
             let (input2, value) = Variant::decode(input1)?;
             map_input = input2;
-            map.push((key, value)); // todo: ensure None returned?
+            // duplicate key, last value wins
+            map.insert(key, value);
         }
         // todo: validate map_input is empty
-        Ok((&input[header.size as usize..], VecStringMap(map)))
+        Ok((&input[header.size as usize..], map))
     }
 }
 
@@ -401,9 +445,15 @@ impl DecodeFormatted for Variant {
                 .map(|(i, o)| (i, Variant::Float(OrderedFloat(o)))),
             codec::FORMATCODE_DOUBLE => f64::decode_with_format(input, fmt)
                 .map(|(i, o)| (i, Variant::Double(OrderedFloat(o)))),
-            // codec::FORMATCODE_DECIMAL32 => x::decode_with_format(input, fmt).map(|(i, o)| (i, Variant::Decimal(o))),
-            // codec::FORMATCODE_DECIMAL64 => x::decode_with_format(input, fmt).map(|(i, o)| (i, Variant::Decimal(o))),
-            // codec::FORMATCODE_DECIMAL128 => x::decode_with_format(input, fmt).map(|(i, o)| (i, Variant::Decimal(o))),
+            codec::FORMATCODE_DECIMAL32 => {
+                Decimal32::decode_with_format(input, fmt).map(|(i, o)| (i, Variant::Decimal32(o)))
+            }
+            codec::FORMATCODE_DECIMAL64 => {
+                Decimal64::decode_with_format(input, fmt).map(|(i, o)| (i, Variant::Decimal64(o)))
+            }
+            codec::FORMATCODE_DECIMAL128 => {
+                Decimal128::decode_with_format(input, fmt).map(|(i, o)| (i, Variant::Decimal128(o)))
+            }
             codec::FORMATCODE_CHAR => {
                 char::decode_with_format(input, fmt).map(|(i, o)| (i, Variant::Char(o)))
             }
@@ -439,8 +489,9 @@ impl DecodeFormatted for Variant {
                 .map(|(i, o)| (i, Variant::Map(VariantMap::new(o)))),
             codec::FORMATCODE_MAP32 => HashMap::<Variant, Variant>::decode_with_format(input, fmt)
                 .map(|(i, o)| (i, Variant::Map(VariantMap::new(o)))),
-            // codec::FORMATCODE_ARRAY8 => Vec::<Variant>::decode_with_format(input, fmt).map(|(i, o)| (i, Variant::Array(o))),
-            // codec::FORMATCODE_ARRAY32 => Vec::<Variant>::decode_with_format(input, fmt).map(|(i, o)| (i, Variant::Array(o))),
+            codec::FORMATCODE_ARRAY8 | codec::FORMATCODE_ARRAY32 => {
+                VariantArray::decode_with_format(input, fmt).map(|(i, o)| (i, Variant::Array(o)))
+            }
             codec::FORMATCODE_DESCRIBED => {
                 let (input, descriptor) = Descriptor::decode(input)?;
                 let (input, value) = Variant::decode(input)?;
@@ -496,7 +547,7 @@ impl Decode for SaslFrame {
     }
 }
 
-fn decode_frame_header(
+pub(crate) fn decode_frame_header(
     input: &[u8],
     expected_frame_type: u8,
 ) -> Result<(&[u8], u16), AmqpParseError> {
@@ -632,6 +683,11 @@ mod tests {
 
         test_char: char, '💯', '💯',
 
+        decimal32: Decimal32, Decimal32([0x22, 0x50, 0, 0x01]), Decimal32([0x22, 0x50, 0, 0x01]),
+        decimal64: Decimal64, Decimal64([0x22, 0x38, 0, 0, 0, 0, 0, 0x01]), Decimal64([0x22, 0x38, 0, 0, 0, 0, 0, 0x01]),
+        decimal128: Decimal128, Decimal128([0x22, 0x08, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x01]),
+        Decimal128([0x22, 0x08, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x01]),
+
         uuid: Uuid, Uuid::from_slice(&[4, 54, 67, 12, 43, 2, 98, 76, 32, 50, 87, 5, 1, 33, 43, 87]).expect("parse error"),
         Uuid::parse_str("0436430c2b02624c2032570501212b57").expect("parse error"),
 
@@ -671,6 +727,11 @@ mod tests {
 
         variant_char: Variant, Variant::Char('💯'), Variant::Char('💯'),
 
+        variant_decimal32: Variant, Variant::Decimal32(Decimal32([0xa2, 0x50, 0, 0x0f])), Variant::Decimal32(Decimal32([0xa2, 0x50, 0, 0x0f])),
+        variant_decimal64: Variant, Variant::Decimal64(Decimal64([0x22, 0x34, 0, 0, 0, 0, 0x30, 0x39])), Variant::Decimal64(Decimal64([0x22, 0x34, 0, 0, 0, 0, 0x30, 0x39])),
+        variant_decimal128: Variant, Variant::Decimal128(Decimal128([0x22, 0x08, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x07])),
+        Variant::Decimal128(Decimal128([0x22, 0x08, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x07])),
+
         variant_uuid: Variant, Variant::Uuid(Uuid::from_slice(&[4, 54, 67, 12, 43, 2, 98, 76, 32, 50, 87, 5, 1, 33, 43, 87]).expect("parse error")),
         Variant::Uuid(Uuid::parse_str("0436430c2b02624c2032570501212b57").expect("parse error")),
 
@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::hash::{BuildHasher, Hash};
 use std::{i8, u8};
 
@@ -10,7 +11,8 @@ use uuid::Uuid;
 use crate::codec::{self, ArrayEncode, Encode};
 use crate::framing::{self, AmqpFrame, SaslFrame};
 use crate::types::{
-    Descriptor, List, Multiple, StaticSymbol, Str, Symbol, Variant, VecStringMap, VecSymbolMap,
+    Decimal128, Decimal32, Decimal64, Descriptor, List, Multiple, StaticSymbol, Str, Symbol,
+    Variant, VariantArray, VecStringMap, VecSymbolMap,
 };
 
 fn encode_null(buf: &mut BytesMut) {
@@ -288,6 +290,26 @@ impl ArrayEncode for Uuid {
     }
 }
 
+macro_rules! decimal_encode {
+    ($type:ident, $code:expr, $size:expr) => {
+        impl FixedEncode for $type {}
+
+        impl ArrayEncode for $type {
+            const ARRAY_FORMAT_CODE: u8 = $code;
+            fn array_encoded_size(&self) -> usize {
+                $size
+            }
+            fn array_encode(&self, buf: &mut BytesMut) {
+                buf.extend_from_slice(&self.0);
+            }
+        }
+    };
+}
+
+decimal_encode!(Decimal32, codec::FORMATCODE_DECIMAL32, 4);
+decimal_encode!(Decimal64, codec::FORMATCODE_DECIMAL64, 8);
+decimal_encode!(Decimal128, codec::FORMATCODE_DECIMAL128, 16);
+
 impl Encode for Bytes {
     fn encoded_size(&self) -> usize {
         let length = self.len();
@@ -485,7 +507,7 @@ impl<K: Eq + Hash + Encode, V: Encode, S: BuildHasher> Encode for HashMap<K, V,
     }
 }
 
-impl<K: Eq + Hash + Encode, V: Encode> ArrayEncode for HashMap<K, V> {
+impl<K: Eq + Hash + Encode, V: Encode, S: BuildHasher> ArrayEncode for HashMap<K, V, S> {
     const ARRAY_FORMAT_CODE: u8 = codec::FORMATCODE_MAP32;
     fn array_encoded_size(&self) -> usize {
         8 + map_encoded_size(self)
@@ -542,8 +564,7 @@ impl Encode for VecSymbolMap {
 impl Encode for VecStringMap {
     fn encoded_size(&self) -> usize {
         let size = self
-            .0
-            .iter()
+            .unique()
             .fold(0, |r, (k, v)| r + k.encoded_size() + v.encoded_size());
 
         // f:1 + s:4 + c:4 vs f:1 + s:1 + c:1
@@ -552,10 +573,10 @@ impl Encode for VecStringMap {
     }
 
     fn encode(&self, buf: &mut BytesMut) {
-        let count = self.len() * 2; // key-value pair accounts for two items in count
+        // duplicate keys are not encoded, last value wins
+        let count = self.unique().count() * 2; // key-value pair accounts for two items in count
         let size = self
-            .0
-            .iter()
+            .unique()
             .fold(0, |r, (k, v)| r + k.encoded_size() + v.encoded_size());
 
         if size + 1 > u8::MAX as usize {
@@ -568,7 +589,7 @@ impl Encode for VecStringMap {
             buf.put_u8(count as u8);
         }
 
-        for (k, v) in self.iter() {
+        for (k, v) in self.unique() {
             k.encode(buf);
             v.encode(buf);
         }
@@ -649,11 +670,11 @@ impl Encode for List {
     fn encode(&self, buf: &mut BytesMut) {
         let size = list_encoded_size(self);
         if size + 1 > u8::MAX as usize {
-            buf.put_u8(codec::FORMATCODE_ARRAY32);
+            buf.put_u8(codec::FORMATCODE_LIST32);
             buf.put_u32((size + 4) as u32); // +4 for 4 byte count that follow
             buf.put_u32(self.len() as u32);
         } else {
-            buf.put_u8(codec::FORMATCODE_ARRAY8);
+            buf.put_u8(codec::FORMATCODE_LIST8);
             buf.put_u8((size + 1) as u8); // +1 for 1 byte count that follow
             buf.put_u8(self.len() as u8);
         }
@@ -663,6 +684,253 @@ impl Encode for List {
     }
 }
 
+/// Format code of the element constructor used for `Variant` inside an array.
+///
+/// Symbol and static symbol share a code. Described values are not supported.
+pub(crate) fn variant_array_format_code(v: &Variant) -> Option<u8> {
+    let code = match *v {
+        Variant::Null => codec::FORMATCODE_NULL,
+        Variant::Boolean(_) => bool::ARRAY_FORMAT_CODE,
+        Variant::Ubyte(_) => u8::ARRAY_FORMAT_CODE,
+        Variant::Ushort(_) => u16::ARRAY_FORMAT_CODE,
+        Variant::Uint(_) => u32::ARRAY_FORMAT_CODE,
+        Variant::Ulong(_) => u64::ARRAY_FORMAT_CODE,
+        Variant::Byte(_) => i8::ARRAY_FORMAT_CODE,
+        Variant::Short(_) => i16::ARRAY_FORMAT_CODE,
+        Variant::Int(_) => i32::ARRAY_FORMAT_CODE,
+        Variant::Long(_) => i64::ARRAY_FORMAT_CODE,
+        Variant::Float(_) => f32::ARRAY_FORMAT_CODE,
+        Variant::Double(_) => f64::ARRAY_FORMAT_CODE,
+        Variant::Decimal32(_) => Decimal32::ARRAY_FORMAT_CODE,
+        Variant::Decimal64(_) => Decimal64::ARRAY_FORMAT_CODE,
+        Variant::Decimal128(_) => Decimal128::ARRAY_FORMAT_CODE,
+        Variant::Char(_) => char::ARRAY_FORMAT_CODE,
+        Variant::Timestamp(_) => DateTime::<Utc>::ARRAY_FORMAT_CODE,
+        Variant::Uuid(_) => Uuid::ARRAY_FORMAT_CODE,
+        Variant::Binary(_) => Bytes::ARRAY_FORMAT_CODE,
+        Variant::String(_) => str::ARRAY_FORMAT_CODE,
+        Variant::Symbol(_) | Variant::StaticSymbol(_) => Symbol::ARRAY_FORMAT_CODE,
+        Variant::List(_) => codec::FORMATCODE_LIST32,
+        Variant::Map(_) => codec::FORMATCODE_MAP32,
+        Variant::Array(_) => codec::FORMATCODE_ARRAY32,
+        Variant::Described(_) => return None,
+    };
+    Some(code)
+}
+
+fn variant_array_encoded_size(v: &Variant) -> usize {
+    match *v {
+        Variant::Null => 0,
+        Variant::Boolean(b) => b.array_encoded_size(),
+        Variant::Ubyte(b) => b.array_encoded_size(),
+        Variant::Ushort(s) => s.array_encoded_size(),
+        Variant::Uint(i) => i.array_encoded_size(),
+        Variant::Ulong(l) => l.array_encoded_size(),
+        Variant::Byte(b) => b.array_encoded_size(),
+        Variant::Short(s) => s.array_encoded_size(),
+        Variant::Int(i) => i.array_encoded_size(),
+        Variant::Long(l) => l.array_encoded_size(),
+        Variant::Float(f) => f.array_encoded_size(),
+        Variant::Double(d) => d.array_encoded_size(),
+        Variant::Decimal32(ref d) => d.array_encoded_size(),
+        Variant::Decimal64(ref d) => d.array_encoded_size(),
+        Variant::Decimal128(ref d) => d.array_encoded_size(),
+        Variant::Char(c) => c.array_encoded_size(),
+        Variant::Timestamp(ref t) => t.array_encoded_size(),
+        Variant::Uuid(ref u) => u.array_encoded_size(),
+        Variant::Binary(ref b) => b.array_encoded_size(),
+        Variant::String(ref s) => s.as_str().array_encoded_size(),
+        Variant::Symbol(ref s) => s.array_encoded_size(),
+        Variant::StaticSymbol(ref s) => 4 + s.0.len(),
+        Variant::List(ref l) => 8 + list_encoded_size(l),
+        Variant::Map(ref m) => m.map.array_encoded_size(),
+        Variant::Array(ref a) => 9 + variant_array_content_size(a, variant_array_ctor(a)),
+        Variant::Described(_) => unreachable!(),
+    }
+}
+
+fn variant_array_encode(v: &Variant, buf: &mut BytesMut) {
+    match *v {
+        Variant::Null => (),
+        Variant::Boolean(b) => b.array_encode(buf),
+        Variant::Ubyte(b) => b.array_encode(buf),
+        Variant::Ushort(s) => s.array_encode(buf),
+        Variant::Uint(i) => i.array_encode(buf),
+        Variant::Ulong(l) => l.array_encode(buf),
+        Variant::Byte(b) => b.array_encode(buf),
+        Variant::Short(s) => s.array_encode(buf),
+        Variant::Int(i) => i.array_encode(buf),
+        Variant::Long(l) => l.array_encode(buf),
+        Variant::Float(f) => f.array_encode(buf),
+        Variant::Double(d) => d.array_encode(buf),
+        Variant::Decimal32(ref d) => d.array_encode(buf),
+        Variant::Decimal64(ref d) => d.array_encode(buf),
+        Variant::Decimal128(ref d) => d.array_encode(buf),
+        Variant::Char(c) => c.array_encode(buf),
+        Variant::Timestamp(ref t) => t.array_encode(buf),
+        Variant::Uuid(ref u) => u.array_encode(buf),
+        Variant::Binary(ref b) => b.array_encode(buf),
+        Variant::String(ref s) => s.as_str().array_encode(buf),
+        Variant::Symbol(ref s) => s.array_encode(buf),
+        Variant::StaticSymbol(ref s) => {
+            buf.put_u32(s.0.len() as u32);
+            buf.put_slice(s.0.as_bytes());
+        }
+        Variant::List(ref l) => {
+            buf.put_u32((list_encoded_size(l) + 4) as u32); // +4 for 4 byte count that follows
+            buf.put_u32(l.len() as u32);
+            for i in l.iter() {
+                i.encode(buf);
+            }
+        }
+        Variant::Map(ref m) => m.map.array_encode(buf),
+        Variant::Array(ref a) => {
+            let ctor = variant_array_ctor(a);
+            // +5 for 4 byte count and 1 byte item ctor that follow
+            buf.put_u32((variant_array_content_size(a, ctor) + 5) as u32);
+            buf.put_u32(a.len() as u32);
+            buf.put_u8(ctor);
+            for i in a.iter() {
+                variant_array_item_encode(i, ctor, buf);
+            }
+        }
+        Variant::Described(_) => unreachable!(),
+    }
+}
+
+/// Size of array item encoded with narrow constructor of decoded array,
+/// `None` if constructor is not narrow form of item type or value does not fit.
+fn variant_array_narrow_size(v: &Variant, ctor: u8) -> Option<usize> {
+    let small = |len: usize| {
+        if len <= u8::MAX as usize {
+            Some(1 + len)
+        } else {
+            None
+        }
+    };
+    match (ctor, v) {
+        (codec::FORMATCODE_SYMBOL8, Variant::Symbol(s)) => small(s.len()),
+        (codec::FORMATCODE_SYMBOL8, Variant::StaticSymbol(s)) => small(s.0.len()),
+        (codec::FORMATCODE_STRING8, Variant::String(s)) => small(s.as_str().len()),
+        (codec::FORMATCODE_BINARY8, Variant::Binary(b)) => small(b.len()),
+        (codec::FORMATCODE_SMALLUINT, Variant::Uint(v)) if *v <= u32::from(u8::MAX) => Some(1),
+        (codec::FORMATCODE_SMALLULONG, Variant::Ulong(v)) if *v <= u64::from(u8::MAX) => Some(1),
+        (codec::FORMATCODE_SMALLINT, Variant::Int(v)) if i8::try_from(*v).is_ok() => Some(1),
+        (codec::FORMATCODE_SMALLLONG, Variant::Long(v)) if i8::try_from(*v).is_ok() => Some(1),
+        (codec::FORMATCODE_UINT_0, Variant::Uint(0))
+        | (codec::FORMATCODE_ULONG_0, Variant::Ulong(0))
+        | (codec::FORMATCODE_BOOLEAN_TRUE, Variant::Boolean(true))
+        | (codec::FORMATCODE_BOOLEAN_FALSE, Variant::Boolean(false)) => Some(0),
+        _ => None,
+    }
+}
+
+fn variant_array_item_size(v: &Variant, ctor: u8) -> usize {
+    variant_array_narrow_size(v, ctor).unwrap_or_else(|| variant_array_encoded_size(v))
+}
+
+fn variant_array_item_encode(v: &Variant, ctor: u8, buf: &mut BytesMut) {
+    if variant_array_narrow_size(v, ctor).is_none() {
+        return variant_array_encode(v, buf);
+    }
+    match *v {
+        Variant::Symbol(ref s) => {
+            buf.put_u8(s.len() as u8);
+            buf.put_slice(s.as_bytes());
+        }
+        Variant::StaticSymbol(ref s) => {
+            buf.put_u8(s.0.len() as u8);
+            buf.put_slice(s.0.as_bytes());
+        }
+        Variant::String(ref s) => {
+            buf.put_u8(s.as_str().len() as u8);
+            buf.put_slice(s.as_bytes());
+        }
+        Variant::Binary(ref b) => {
+            buf.put_u8(b.len() as u8);
+            buf.put_slice(b);
+        }
+        Variant::Uint(v) => buf.put_u8(v as u8),
+        Variant::Ulong(v) => buf.put_u8(v as u8),
+        Variant::Int(v) => buf.put_i8(v as i8),
+        Variant::Long(v) => buf.put_i8(v as i8),
+        _ => (),
+    }
+}
+
+fn variant_array_content_size(arr: &VariantArray, ctor: u8) -> usize {
+    arr.iter()
+        .fold(0, |r, i| r + variant_array_item_size(i, ctor))
+}
+
+/// Element constructor shared by all items.
+///
+/// Constructor of decoded array is used if all items fit it, empty
+/// array without one uses `null`. Panics if items are of different
+/// types or contain described values.
+fn variant_array_ctor(arr: &VariantArray) -> u8 {
+    let code = arr
+        .first()
+        .map_or(Some(codec::FORMATCODE_NULL), variant_array_format_code);
+    let code = match code {
+        Some(code)
+            if arr
+                .iter()
+                .all(|i| variant_array_format_code(i) == Some(code)) =>
+        {
+            code
+        }
+        _ => panic!(
+            "Array items must be of the same non-described type: {:?}",
+            arr
+        ),
+    };
+    match arr.ctor() {
+        Some(ctor) if arr.is_empty() => ctor,
+        Some(ctor)
+            if arr
+                .iter()
+                .all(|i| variant_array_narrow_size(i, ctor).is_some()) =>
+        {
+            ctor
+        }
+        _ => code,
+    }
+}
+
+fn variant_array_is_small(arr: &VariantArray, size: usize) -> bool {
+    // +2 for 1 byte count and 1 byte item ctor
+    size + 2 <= u8::MAX as usize && arr.len() <= u8::MAX as usize
+}
+
+fn variant_array_size(arr: &VariantArray) -> usize {
+    let size = variant_array_content_size(arr, variant_array_ctor(arr));
+    // format_code + size + count + item constructor
+    if variant_array_is_small(arr, size) {
+        4 + size
+    } else {
+        10 + size
+    }
+}
+
+fn encode_variant_array(arr: &VariantArray, buf: &mut BytesMut) {
+    let ctor = variant_array_ctor(arr);
+    let size = variant_array_content_size(arr, ctor);
+    if variant_array_is_small(arr, size) {
+        buf.put_u8(codec::FORMATCODE_ARRAY8);
+        buf.put_u8((size + 2) as u8); // +2 for 1 byte count and 1 byte item ctor that follow
+        buf.put_u8(arr.len() as u8);
+    } else {
+        buf.put_u8(codec::FORMATCODE_ARRAY32);
+        buf.put_u32((size + 5) as u32); // +5 for 4 byte count and 1 byte item ctor that follow
+        buf.put_u32(arr.len() as u32);
+    }
+    buf.put_u8(ctor);
+    for i in arr.iter() {
+        variant_array_item_encode(i, ctor, buf);
+    }
+}
+
 impl Encode for Variant {
     fn encoded_size(&self) -> usize {
         match *self {
@@ -678,6 +946,9 @@ impl Encode for Variant {
             Variant::Long(l) => l.encoded_size(),
             Variant::Float(f) => f.encoded_size(),
             Variant::Double(d) => d.encoded_size(),
+            Variant::Decimal32(ref d) => d.encoded_size(),
+            Variant::Decimal64(ref d) => d.encoded_size(),
+            Variant::Decimal128(ref d) => d.encoded_size(),
             Variant::Char(c) => c.encoded_size(),
             Variant::Timestamp(ref t) => t.encoded_size(),
             Variant::Uuid(ref u) => u.encoded_size(),
@@ -687,6 +958,7 @@ impl Encode for Variant {
             Variant::StaticSymbol(ref s) => s.encoded_size(),
             Variant::List(ref l) => l.encoded_size(),
             Variant::Map(ref m) => m.map.encoded_size(),
+            Variant::Array(ref a) => variant_array_size(a),
             Variant::Described(ref dv) => dv.0.encoded_size() + dv.1.encoded_size(),
         }
     }
@@ -706,6 +978,9 @@ impl Encode for Variant {
             Variant::Long(l) => l.encode(buf),
             Variant::Float(f) => f.encode(buf),
             Variant::Double(d) => d.encode(buf),
+            Variant::Decimal32(ref d) => d.encode(buf),
+            Variant::Decimal64(ref d) => d.encode(buf),
+            Variant::Decimal128(ref d) => d.encode(buf),
             Variant::Char(c) => c.encode(buf),
             Variant::Timestamp(ref t) => t.encode(buf),
             Variant::Uuid(ref u) => u.encode(buf),
@@ -715,6 +990,7 @@ impl Encode for Variant {
             Variant::StaticSymbol(ref s) => s.encode(buf),
             Variant::List(ref l) => l.encode(buf),
             Variant::Map(ref m) => m.map.encode(buf),
+            Variant::Array(ref a) => encode_variant_array(a, buf),
             Variant::Described(ref dv) => {
                 dv.0.encode(buf);
                 dv.1.encode(buf);
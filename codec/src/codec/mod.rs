@@ -14,8 +14,11 @@ macro_rules! decode_check_len {
 #[macro_use]
 mod decode;
 mod encode;
+#[cfg(test)]
+mod vectors;
 
-pub(crate) use self::decode::decode_list_header;
+pub(crate) use self::decode::{decode_frame_header, decode_list_header};
+pub(crate) use self::encode::variant_array_format_code;
 
 pub trait Encode {
     fn encoded_size(&self) -> usize;
@@ -82,9 +85,9 @@ pub const FORMATCODE_SMALLINT: u8 = 0x54;
 pub const FORMATCODE_SMALLLONG: u8 = 0x55;
 pub const FORMATCODE_FLOAT: u8 = 0x72;
 pub const FORMATCODE_DOUBLE: u8 = 0x82;
-// pub const FORMATCODE_DECIMAL32: u8 = 0x74;
-// pub const FORMATCODE_DECIMAL64: u8 = 0x84;
-// pub const FORMATCODE_DECIMAL128: u8 = 0x94;
+pub const FORMATCODE_DECIMAL32: u8 = 0x74;
+pub const FORMATCODE_DECIMAL64: u8 = 0x84;
+pub const FORMATCODE_DECIMAL128: u8 = 0x94;
 pub const FORMATCODE_CHAR: u8 = 0x73;
 pub const FORMATCODE_TIMESTAMP: u8 = 0x83;
 pub const FORMATCODE_UUID: u8 = 0x98;
@@ -105,11 +108,18 @@ pub const FORMATCODE_ARRAY32: u8 = 0xf0;
 #[cfg(test)]
 mod tests {
     use bytes::{Bytes, BytesMut};
+    use bytestring::ByteString;
 
     use crate::codec::{Decode, Encode};
     use crate::error::AmqpCodecError;
     use crate::framing::{AmqpFrame, SaslFrame};
-    use crate::protocol::SaslFrameBody;
+    use crate::protocol::{
+        Accepted, AmqpError, DeliveryState, Disposition, Error, Frame, LifetimePolicy, Outcome,
+        Rejected, Role, SaslCode, SaslFrameBody, Target, TerminusDurability, TerminusExpiryPolicy,
+        TransactionalState,
+    };
+    use crate::types::{Descriptor, Symbol, Variant, VariantMap};
+    use crate::HashMap;
 
     #[test]
     fn test_sasl_mechanisms() -> Result<(), AmqpCodecError> {
@@ -131,6 +141,89 @@ mod tests {
         Ok(())
     }
 
+    fn sasl_roundtrip(data: &'static [u8]) -> Result<SaslFrameBody, AmqpCodecError> {
+        let (remainder, frame) = SaslFrame::decode(data)?;
+        assert!(remainder.is_empty());
+
+        let mut buf = BytesMut::new();
+        buf.reserve(frame.encoded_size());
+        frame.encode(&mut buf);
+        let _ = buf.split_to(4);
+        assert_eq!(Bytes::from_static(data), buf.freeze());
+
+        Ok(frame.body)
+    }
+
+    #[test]
+    fn test_sasl_init_hostname() -> Result<(), AmqpCodecError> {
+        let data = b"\x02\x01\0\0\0SA\xc0\x1c\x03\xa3\x05PLAIN\xa0\x0a\0user\0pass\xa1\x06vhost1";
+        match sasl_roundtrip(data)? {
+            SaslFrameBody::SaslInit(init) => {
+                assert_eq!(init.mechanism, Symbol::from("PLAIN"));
+                assert_eq!(
+                    init.initial_response,
+                    Some(Bytes::from_static(b"\0user\0pass"))
+                );
+                assert_eq!(init.hostname, Some(ByteString::from_static("vhost1")));
+            }
+            _ => panic!("error"),
+        }
+
+        // empty initial response, absent hostname
+        let data = b"\x02\x01\0\0\0SA\xc0\x0b\x03\xa3\x05PLAIN\xa0\0@";
+        match sasl_roundtrip(data)? {
+            SaslFrameBody::SaslInit(init) => {
+                assert_eq!(init.initial_response, Some(Bytes::new()));
+                assert_eq!(init.hostname, None);
+            }
+            _ => panic!("error"),
+        }
+
+        // absent initial response, empty hostname
+        let data = b"\x02\x01\0\0\0SA\xc0\x0b\x03\xa3\x05PLAIN@\xa1\0";
+        match sasl_roundtrip(data)? {
+            SaslFrameBody::SaslInit(init) => {
+                assert_eq!(init.initial_response, None);
+                assert_eq!(init.hostname, Some(ByteString::new()));
+            }
+            _ => panic!("error"),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sasl_outcome_additional_data() -> Result<(), AmqpCodecError> {
+        let data = b"\x02\x01\0\0\0SD\xc0\x0a\x02P\0\xa0\x05token";
+        match sasl_roundtrip(data)? {
+            SaslFrameBody::SaslOutcome(outcome) => {
+                assert_eq!(outcome.code, SaslCode::Ok);
+                assert_eq!(outcome.additional_data, Some(Bytes::from_static(b"token")));
+            }
+            _ => panic!("error"),
+        }
+
+        // empty additional data is not the same as absent one
+        let data = b"\x02\x01\0\0\0SD\xc0\x05\x02P\0\xa0\0";
+        match sasl_roundtrip(data)? {
+            SaslFrameBody::SaslOutcome(outcome) => {
+                assert_eq!(outcome.additional_data, Some(Bytes::new()))
+            }
+            _ => panic!("error"),
+        }
+
+        let data = b"\x02\x01\0\0\0SD\xc0\x04\x02P\x01@";
+        match sasl_roundtrip(data)? {
+            SaslFrameBody::SaslOutcome(outcome) => {
+                assert_eq!(outcome.code, SaslCode::Auth);
+                assert_eq!(outcome.additional_data, None);
+            }
+            _ => panic!("error"),
+        }
+
+        Ok(())
+    }
+
     #[test]
     fn test_disposition() -> Result<(), AmqpCodecError> {
         let data = b"\x02\0\0\0\0S\x15\xc0\x0c\x06AC@A\0S$\xc0\x01\0B";
@@ -147,4 +240,207 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_disposition_rejected_info() -> Result<(), AmqpCodecError> {
+        let mut info = HashMap::default();
+        info.insert(Symbol::from("code"), Variant::Int(42));
+
+        let frame = AmqpFrame::new(
+            0,
+            Frame::Disposition(Disposition {
+                role: Role::Receiver,
+                first: 1,
+                last: None,
+                settled: true,
+                state: Some(DeliveryState::Rejected(Rejected {
+                    error: Some(Error {
+                        condition: AmqpError::InternalError.into(),
+                        description: Some(ByteString::from_static("failed")),
+                        info: Some(info.clone()),
+                    }),
+                })),
+                batchable: false,
+            }),
+        );
+
+        let mut buf = BytesMut::new();
+        buf.reserve(frame.encoded_size());
+        frame.encode(&mut buf);
+        let _ = buf.split_to(4);
+
+        let (remainder, decoded) = AmqpFrame::decode(buf.as_ref())?;
+        assert!(remainder.is_empty());
+        assert_eq!(decoded, frame);
+
+        match decoded.performative() {
+            Frame::Disposition(Disposition {
+                state: Some(DeliveryState::Rejected(rejected)),
+                ..
+            }) => {
+                assert_eq!(rejected.error.as_ref().unwrap().info, Some(info));
+            }
+            _ => panic!("error"),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_disposition_transactional_state() -> Result<(), AmqpCodecError> {
+        let frame = AmqpFrame::new(
+            0,
+            Frame::Disposition(Disposition {
+                role: Role::Receiver,
+                first: 1,
+                last: None,
+                settled: true,
+                state: Some(DeliveryState::TransactionalState(TransactionalState {
+                    txn_id: Bytes::from_static(b"txn-1"),
+                    outcome: Some(Outcome::Accepted(Accepted {})),
+                })),
+                batchable: false,
+            }),
+        );
+
+        let mut buf = BytesMut::new();
+        buf.reserve(frame.encoded_size());
+        frame.encode(&mut buf);
+        assert_eq!(buf.len(), frame.encoded_size());
+        let _ = buf.split_to(4);
+
+        let (remainder, decoded) = AmqpFrame::decode(buf.as_ref())?;
+        assert!(remainder.is_empty());
+        assert_eq!(decoded, frame);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lifetime_policy() -> Result<(), AmqpCodecError> {
+        let policies = [
+            (
+                LifetimePolicy::DeleteOnClose,
+                0x2b,
+                "amqp:delete-on-close:list",
+            ),
+            (
+                LifetimePolicy::DeleteOnNoLinks,
+                0x2c,
+                "amqp:delete-on-no-links:list",
+            ),
+            (
+                LifetimePolicy::DeleteOnNoMessages,
+                0x2d,
+                "amqp:delete-on-no-messages:list",
+            ),
+            (
+                LifetimePolicy::DeleteOnNoLinksOrMessages,
+                0x2e,
+                "amqp:delete-on-no-links-or-messages:list",
+            ),
+        ];
+
+        for (policy, code, name) in policies.iter() {
+            // described empty list with small ulong descriptor
+            let mut buf = BytesMut::new();
+            policy.encode(&mut buf);
+            assert_eq!(buf.len(), policy.encoded_size());
+            assert_eq!(&buf[..], &[0x00, 0x53, *code, 0x45][..]);
+
+            let (remainder, decoded) = LifetimePolicy::decode(&buf)?;
+            assert!(remainder.is_empty());
+            assert_eq!(decoded, *policy);
+
+            // symbolic descriptor and list8 body
+            let mut buf = BytesMut::new();
+            Descriptor::Symbol(Symbol::from_static(name)).encode(&mut buf);
+            buf.extend_from_slice(&[0xc0, 0x01, 0x00]);
+            let (remainder, decoded) = LifetimePolicy::decode(&buf)?;
+            assert!(remainder.is_empty());
+            assert_eq!(decoded, *policy);
+
+            // node property value
+            let (_, value) = Variant::decode(&buf)?;
+            assert_eq!(LifetimePolicy::from_variant(&value), Some(*policy));
+            assert_eq!(
+                LifetimePolicy::from_variant(&Variant::from(*policy)),
+                Some(*policy)
+            );
+        }
+
+        let unknown = b"\x00\x53\x2f\x45";
+        assert!(LifetimePolicy::decode(&unknown[..]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dynamic_node_properties() -> Result<(), AmqpCodecError> {
+        let mut props = HashMap::default();
+        props.insert(
+            Symbol::from_static("lifetime-policy"),
+            Variant::from(LifetimePolicy::DeleteOnNoLinks),
+        );
+        props.insert(
+            Symbol::from_static("supported-dist-modes"),
+            Variant::from(vec![
+                Variant::Symbol(Symbol::from_static("move")),
+                Variant::Symbol(Symbol::from_static("copy")),
+            ]),
+        );
+        let target = Target {
+            address: None,
+            durable: TerminusDurability::None,
+            expiry_policy: TerminusExpiryPolicy::LinkDetach,
+            timeout: 0,
+            dynamic: true,
+            dynamic_node_properties: Some(props),
+            capabilities: None,
+        };
+
+        let mut buf = BytesMut::new();
+        target.encode(&mut buf);
+        assert_eq!(buf.len(), target.encoded_size());
+
+        let (remainder, decoded) = Target::decode(&buf)?;
+        assert!(remainder.is_empty());
+        assert_eq!(decoded, target);
+        let props = decoded.dynamic_node_properties.unwrap();
+        assert_eq!(
+            LifetimePolicy::from_variant(&props[&Symbol::from_static("lifetime-policy")]),
+            Some(LifetimePolicy::DeleteOnNoLinks)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_properties_map_key() -> Result<(), AmqpCodecError> {
+        // open with properties {x: {{1: "a"}: null}}
+        let data = b"\x02\0\0\0\0S\x10\xc0\x1e\x0a\xa1\x01c@@@@@@@@\xc1\x10\x02\xa3\x01x\xc1\x0a\x02\xc1\x06\x02R\x01\xa1\x01a@";
+
+        let (remainder, frame) = AmqpFrame::decode(data.as_ref())?;
+        assert!(remainder.is_empty());
+        let open = match frame.performative() {
+            Frame::Open(open) => open,
+            _ => panic!("expected open frame"),
+        };
+
+        let mut key = HashMap::default();
+        key.insert(Variant::Uint(1), Variant::from("a"));
+        let mut value = HashMap::default();
+        value.insert(Variant::Map(VariantMap::new(key)), Variant::Null);
+        assert_eq!(
+            open.properties().unwrap()[&Symbol::from_static("x")],
+            Variant::Map(VariantMap::new(value))
+        );
+
+        let mut buf = BytesMut::new();
+        frame.encode(&mut buf);
+        let (_, decoded) = AmqpFrame::decode(&buf[4..])?;
+        assert_eq!(decoded.performative(), frame.performative());
+
+        Ok(())
+    }
 }
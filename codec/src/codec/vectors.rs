@@ -0,0 +1,266 @@
+//! Encoded frame test vectors.
+//!
+//! Every vector is a complete frame, including size prefix, and the
+//! structure it is expected to decode to. Encoding the structure must
+//! produce exactly the same bytes.
+use bytes::{Bytes, BytesMut};
+use bytestring::ByteString;
+
+use crate::codec::{Decode, Encode};
+use crate::framing::AmqpFrame;
+use crate::protocol::{
+    Accepted, Attach, Begin, Close, ConnectionError, DeliveryState, Detach, Disposition, End,
+    Error, Flow, Frame, LinkError, Open, ReceiverSettleMode, Role, SenderSettleMode, Source,
+    Target, TerminusDurability, TerminusExpiryPolicy, Transfer, TransferBody,
+};
+use crate::types::{Multiple, Symbol};
+
+pub(crate) struct FrameVector {
+    pub(crate) name: &'static str,
+    pub(crate) hex: &'static str,
+    pub(crate) frame: AmqpFrame,
+}
+
+pub(crate) fn frame_vectors() -> Vec<FrameVector> {
+    vec![
+        FrameVector {
+            name: "open",
+            hex: "0000004802000000005310c03b0aa10b636f6e7461696e65722d31a1096c6f63\
+                  616c686f73747000010000600400700001d4c04040a30f414e4f4e594d4f5553\
+                  2d52454c41594040",
+            frame: AmqpFrame::new(
+                0,
+                Frame::Open(Open {
+                    container_id: ByteString::from_static("container-1"),
+                    hostname: Some(ByteString::from_static("localhost")),
+                    max_frame_size: 65536,
+                    channel_max: 1024,
+                    idle_time_out: Some(120_000),
+                    outgoing_locales: None,
+                    incoming_locales: None,
+                    offered_capabilities: Some(Multiple(vec![Symbol::from_static(
+                        "ANONYMOUS-RELAY",
+                    )])),
+                    desired_capabilities: None,
+                    properties: None,
+                }),
+            ),
+        },
+        // fields in order: container-id, hostname, max-frame-size, channel-max,
+        // idle-time-out, outgoing-locales, incoming-locales (array of two),
+        // offered-capabilities, desired-capabilities, properties
+        FrameVector {
+            name: "open-locales-capabilities",
+            hex: "0000004502000000005310c0380aa102633140700000020060040040a305656e\
+                  2d5553e01102b300000005656e2d5553000000026465a3056361702d61a30563\
+                  61702d6240",
+            frame: AmqpFrame::new(
+                0,
+                Frame::Open(Open {
+                    container_id: ByteString::from_static("c1"),
+                    hostname: None,
+                    max_frame_size: 512,
+                    channel_max: 1024,
+                    idle_time_out: None,
+                    outgoing_locales: Some(Multiple(vec![Symbol::from_static("en-US")])),
+                    incoming_locales: Some(Multiple(vec![
+                        Symbol::from_static("en-US"),
+                        Symbol::from_static("de"),
+                    ])),
+                    offered_capabilities: Some(Multiple(vec![Symbol::from_static("cap-a")])),
+                    desired_capabilities: Some(Multiple(vec![Symbol::from_static("cap-b")])),
+                    properties: None,
+                }),
+            ),
+        },
+        FrameVector {
+            name: "begin",
+            hex: "0000002202000001005311c0150860000052017000000800700000080052ff40\
+                  4040",
+            frame: AmqpFrame::new(
+                1,
+                Frame::Begin(Begin {
+                    remote_channel: Some(0),
+                    next_outgoing_id: 1,
+                    incoming_window: 2048,
+                    outgoing_window: 2048,
+                    handle_max: 255,
+                    offered_capabilities: None,
+                    desired_capabilities: None,
+                    properties: None,
+                }),
+            ),
+        },
+        FrameVector {
+            name: "attach",
+            hex: "0000006802000001005312c05b0ea1066c696e6b2d31434250025000005328c0\
+                  180b4043a30b73657373696f6e2d656e644342404040404040005329c01b07a1\
+                  0671756575653143a30b73657373696f6e2d656e644342404040424380000000\
+                  0000040000404040",
+            frame: AmqpFrame::new(
+                1,
+                Frame::Attach(Attach {
+                    name: ByteString::from_static("link-1"),
+                    handle: 0,
+                    role: Role::Sender,
+                    snd_settle_mode: SenderSettleMode::Mixed,
+                    rcv_settle_mode: ReceiverSettleMode::First,
+                    source: Some(Source {
+                        address: None,
+                        durable: TerminusDurability::None,
+                        expiry_policy: TerminusExpiryPolicy::SessionEnd,
+                        timeout: 0,
+                        dynamic: false,
+                        dynamic_node_properties: None,
+                        distribution_mode: None,
+                        filter: None,
+                        default_outcome: None,
+                        outcomes: None,
+                        capabilities: None,
+                    }),
+                    target: Some(Target {
+                        address: Some(ByteString::from_static("queue1")),
+                        durable: TerminusDurability::None,
+                        expiry_policy: TerminusExpiryPolicy::SessionEnd,
+                        timeout: 0,
+                        dynamic: false,
+                        dynamic_node_properties: None,
+                        capabilities: None,
+                    }),
+                    unsettled: None,
+                    incomplete_unsettled: false,
+                    initial_delivery_count: Some(0),
+                    max_message_size: Some(262_144),
+                    offered_capabilities: None,
+                    desired_capabilities: None,
+                    properties: None,
+                }),
+            ),
+        },
+        FrameVector {
+            name: "flow",
+            hex: "0000002402000001005313c0170b520170000008005201700000080043435264\
+                  40424240",
+            frame: AmqpFrame::new(
+                1,
+                Frame::Flow(Flow {
+                    next_incoming_id: Some(1),
+                    incoming_window: 2048,
+                    next_outgoing_id: 1,
+                    outgoing_window: 2048,
+                    handle: Some(0),
+                    delivery_count: Some(0),
+                    link_credit: Some(100),
+                    available: None,
+                    drain: false,
+                    echo: false,
+                    properties: None,
+                }),
+            ),
+        },
+        FrameVector {
+            name: "transfer",
+            hex: "0000002902000001005314c0120b435201a00400000001434242404042424200\
+                  5375a00568656c6c6f",
+            frame: AmqpFrame::new(
+                1,
+                Frame::Transfer(Transfer {
+                    handle: 0,
+                    delivery_id: Some(1),
+                    delivery_tag: Some(Bytes::from_static(b"\x00\x00\x00\x01")),
+                    message_format: Some(0),
+                    settled: Some(false),
+                    more: false,
+                    rcv_settle_mode: None,
+                    state: None,
+                    resume: false,
+                    aborted: false,
+                    batchable: false,
+                    body: Some(TransferBody::Data(Bytes::from_static(
+                        b"\x00\x53\x75\xa0\x05hello",
+                    ))),
+                }),
+            ),
+        },
+        FrameVector {
+            name: "disposition",
+            hex: "0000001b02000001005315c00e06415201520341005324c0010042",
+            frame: AmqpFrame::new(
+                1,
+                Frame::Disposition(Disposition {
+                    role: Role::Receiver,
+                    first: 1,
+                    last: Some(3),
+                    settled: true,
+                    state: Some(DeliveryState::Accepted(Accepted {})),
+                    batchable: false,
+                }),
+            ),
+        },
+        FrameVector {
+            name: "detach",
+            hex: "0000003802000001005316c02b03434100531dc02303a317616d71703a6c696e\
+                  6b3a6465746163682d666f72636564a106666f7263656440",
+            frame: AmqpFrame::new(
+                1,
+                Frame::Detach(Detach {
+                    handle: 0,
+                    closed: true,
+                    error: Some(Error {
+                        condition: LinkError::DetachForced.into(),
+                        description: Some(ByteString::from_static("forced")),
+                        info: None,
+                    }),
+                }),
+            ),
+        },
+        FrameVector {
+            name: "end",
+            hex: "0000000f02000001005317c0020140",
+            frame: AmqpFrame::new(1, Frame::End(End { error: None })),
+        },
+        FrameVector {
+            name: "close",
+            hex: "0000002e02000000005318c0210100531dc01b03a316616d71703a636f6e6e65\
+                  6374696f6e3a666f726365644040",
+            frame: AmqpFrame::new(
+                0,
+                Frame::Close(Close {
+                    error: Some(Error {
+                        condition: ConnectionError::ConnectionForced.into(),
+                        description: None,
+                        info: None,
+                    }),
+                }),
+            ),
+        },
+    ]
+}
+
+pub(crate) fn from_hex(hex: &str) -> Vec<u8> {
+    let hex: Vec<u8> = hex.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+    assert_eq!(hex.len() % 2, 0, "odd number of hex digits");
+    hex.chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).unwrap(), 16).unwrap())
+        .collect()
+}
+
+#[test]
+fn test_frame_vectors() {
+    for vector in frame_vectors() {
+        let data = from_hex(vector.hex);
+
+        // size prefix covers whole frame
+        let size = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+        assert_eq!(size as usize, data.len(), "{}: frame size", vector.name);
+
+        let (remainder, frame) = AmqpFrame::decode(&data[4..])
+            .unwrap_or_else(|e| panic!("{}: decode error {:?}", vector.name, e));
+        assert!(remainder.is_empty(), "{}: remainder", vector.name);
+        assert_eq!(frame, vector.frame, "{}: decoded frame", vector.name);
+
+        let mut buf = BytesMut::with_capacity(vector.frame.encoded_size());
+        vector.frame.encode(&mut buf);
+        assert_eq!(&buf[..], &data[..], "{}: encoded bytes", vector.name);
+    }
+}
@@ -29,6 +29,9 @@ pub enum AmqpParseError {
     #[display(fmt = "Unexpected type: '{:?}'", "_0")]
     UnexpectedType(&'static str),
     Utf8Error(std::str::Utf8Error),
+    #[from(ignore)]
+    #[display(fmt = "Array elements are of different types")]
+    ArrayTypeMismatch,
 }
 
 #[derive(Debug, Display, From, Clone)]
@@ -36,8 +39,16 @@ pub enum AmqpCodecError {
     ParseError(AmqpParseError),
     #[display(fmt = "bytes left unparsed at the frame trail")]
     UnparsedBytesLeft,
-    #[display(fmt = "max inbound frame size exceeded")]
-    MaxSizeExceeded,
+    #[from(ignore)]
+    #[display(fmt = "frame size {} exceeds max inbound frame size {}", _0, _1)]
+    MaxSizeExceeded(usize, usize),
+    #[from(ignore)]
+    #[display(fmt = "invalid frame size {}", _0)]
+    InvalidFrameSize(usize),
+    /// Encoded frame size and max outbound frame size
+    #[from(ignore)]
+    #[display(fmt = "frame size {} exceeds max outbound frame size {}", _0, _1)]
+    FrameTooLarge(usize, usize),
 }
 
 #[derive(Debug, Display, From, Clone)]
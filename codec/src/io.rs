@@ -1,4 +1,4 @@
-use std::{cell::Cell, marker::PhantomData};
+use std::{cell::Cell, marker::PhantomData, sync::Arc};
 
 use byteorder::{BigEndian, ByteOrder};
 use bytes::{Buf, BufMut, BytesMut};
@@ -7,15 +7,27 @@ use ntex_codec::{Decoder, Encoder};
 use super::error::{AmqpCodecError, ProtocolIdError};
 use super::framing::HEADER_LEN;
 use crate::codec::{Decode, Encode};
-use crate::protocol::ProtocolId;
+use crate::protocol::{ProtocolHeader, ProtocolId, ProtocolVersion};
+use crate::table::{FrameTable, TableCodec};
 
 const SIZE_LOW_WM: usize = 4096;
 const SIZE_HIGH_WM: usize = 32768;
 
+/// Max inbound frame size until peer has seen local `Open`.
+///
+/// Spec minimum of max frame size is 512 bytes, the rest is headroom
+/// for large `Open` and sasl frames.
+pub const PRE_OPEN_MAX_SIZE: usize = 8192;
+
+/// Smallest max frame size peer could advertise, #2.7.1
+pub const MIN_MAX_FRAME_SIZE: usize = 512;
+
 #[derive(Debug)]
 pub struct AmqpCodec<T: Decode + Encode> {
     state: Cell<DecodeState>,
     max_size: usize,
+    max_outbound_size: usize,
+    table: Option<Arc<FrameTable>>,
     phantom: PhantomData<T>,
 }
 
@@ -36,10 +48,21 @@ impl<T: Decode + Encode> AmqpCodec<T> {
         AmqpCodec {
             state: Cell::new(DecodeState::FrameHeader),
             max_size: 0,
+            max_outbound_size: 0,
+            table: None,
             phantom: PhantomData,
         }
     }
 
+    /// Set alternate performative codecs of non-default protocol version.
+    ///
+    /// Performatives without alternate codec use standard encoding.
+    /// By default frame table is not set
+    pub fn frame_table(mut self, table: Option<Arc<FrameTable>>) -> Self {
+        self.table = table;
+        self
+    }
+
     /// Set max inbound frame size.
     ///
     /// If max size is set to `0`, size is unlimited.
@@ -56,9 +79,27 @@ impl<T: Decode + Encode> AmqpCodec<T> {
     pub fn set_max_size(&mut self, size: usize) {
         self.max_size = size;
     }
+
+    /// Set max outbound frame size, usually max frame size of peer.
+    ///
+    /// Larger frames are not encoded, encoding fails with
+    /// `AmqpCodecError::FrameTooLarge`. If max size is set to `0`,
+    /// size is unlimited. By default max size is set to `0`
+    pub fn max_outbound_size(mut self, size: usize) -> Self {
+        self.max_outbound_size = size;
+        self
+    }
+
+    /// Set max outbound frame size.
+    ///
+    /// If max size is set to `0`, size is unlimited.
+    /// By default max size is set to `0`
+    pub fn set_max_outbound_size(&mut self, size: usize) {
+        self.max_outbound_size = size;
+    }
 }
 
-impl<T: Decode + Encode> Decoder for AmqpCodec<T> {
+impl<T: TableCodec> Decoder for AmqpCodec<T> {
     type Item = T;
     type Error = AmqpCodecError;
 
@@ -71,18 +112,23 @@ impl<T: Decode + Encode> Decoder for AmqpCodec<T> {
                         return Ok(None);
                     }
 
-                    // read frame size
+                    // read frame size, it is validated before any buffering
                     let size = BigEndian::read_u32(src.as_ref()) as usize;
+                    if size < HEADER_LEN {
+                        return Err(AmqpCodecError::InvalidFrameSize(size));
+                    }
                     if self.max_size != 0 && size > self.max_size {
-                        return Err(AmqpCodecError::MaxSizeExceeded);
+                        return Err(AmqpCodecError::MaxSizeExceeded(size, self.max_size));
                     }
                     self.state.set(DecodeState::Frame(size - 4));
                     src.advance(4);
 
                     if len < size {
-                        // extend receiving buffer to fit the whole frame
-                        if src.remaining_mut() < std::cmp::max(SIZE_LOW_WM, size + HEADER_LEN) {
-                            src.reserve(SIZE_HIGH_WM);
+                        // extend receiving buffer to fit the whole frame,
+                        // growth is capped, buffer grows as data arrives
+                        let missing = size - len;
+                        if src.capacity() - src.len() < missing {
+                            src.reserve(std::cmp::min(missing, SIZE_HIGH_WM));
                         }
                         return Ok(None);
                     }
@@ -93,7 +139,10 @@ impl<T: Decode + Encode> Decoder for AmqpCodec<T> {
                     }
 
                     let frame_buf = src.split_to(size);
-                    let (remainder, frame) = T::decode(frame_buf.as_ref())?;
+                    let (remainder, frame) = match self.table {
+                        None => T::decode(frame_buf.as_ref())?,
+                        Some(ref table) => T::decode_with(frame_buf.as_ref(), table)?,
+                    };
                     if !remainder.is_empty() {
                         // todo: could it really happen?
                         return Err(AmqpCodecError::UnparsedBytesLeft);
@@ -106,19 +155,29 @@ impl<T: Decode + Encode> Decoder for AmqpCodec<T> {
     }
 }
 
-impl<T: Decode + Encode + ::std::fmt::Debug> Encoder for AmqpCodec<T> {
+impl<T: TableCodec + ::std::fmt::Debug> Encoder for AmqpCodec<T> {
     type Item = T;
     type Error = AmqpCodecError;
 
     fn encode(&self, item: Self::Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
-        let size = item.encoded_size();
+        let size = match self.table {
+            None => item.encoded_size(),
+            Some(ref table) => item.encoded_size_with(table),
+        };
+        // frame is validated before anything is written
+        if self.max_outbound_size != 0 && size > self.max_outbound_size {
+            return Err(AmqpCodecError::FrameTooLarge(size, self.max_outbound_size));
+        }
         let need = std::cmp::max(SIZE_LOW_WM, size);
         if dst.remaining_mut() < need {
             dst.reserve(std::cmp::max(need, SIZE_HIGH_WM));
         }
 
         let len = dst.len();
-        item.encode(dst);
+        match self.table {
+            None => item.encode(dst),
+            Some(ref table) => item.encode_with(table, dst),
+        }
         debug_assert!(dst.len() - len == size);
 
         Ok(())
@@ -170,3 +229,270 @@ impl Encoder for ProtocolIdCodec {
         Ok(())
     }
 }
+
+/// Protocol header codec, accepts any protocol version
+///
+/// Version has to be validated by caller, `ProtocolIdCodec` accepts
+/// amqp 1.0.0 only.
+#[derive(Default, Debug)]
+pub struct ProtocolHeaderCodec;
+
+impl Decoder for ProtocolHeaderCodec {
+    type Item = ProtocolHeader;
+    type Error = ProtocolIdError;
+
+    fn decode(&self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if src.len() < PROTOCOL_HEADER_LEN {
+            Ok(None)
+        } else {
+            let src = src.split_to(8);
+            if &src[0..4] != PROTOCOL_HEADER_PREFIX {
+                return Err(ProtocolIdError::InvalidHeader);
+            }
+            let id = match src[4] {
+                0 => ProtocolId::Amqp,
+                2 => ProtocolId::AmqpTls,
+                3 => ProtocolId::AmqpSasl,
+                _ => return Err(ProtocolIdError::Unknown),
+            };
+            Ok(Some(ProtocolHeader {
+                id,
+                version: ProtocolVersion::new(src[5], src[6], src[7]),
+            }))
+        }
+    }
+}
+
+impl Encoder for ProtocolHeaderCodec {
+    type Item = ProtocolHeader;
+    type Error = ProtocolIdError;
+
+    fn encode(&self, item: Self::Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        dst.reserve(PROTOCOL_HEADER_LEN);
+        dst.put_slice(PROTOCOL_HEADER_PREFIX);
+        dst.put_u8(item.id as u8);
+        dst.put_slice(&[
+            item.version.major,
+            item.version.minor,
+            item.version.revision,
+        ]);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::BufMut;
+
+    use super::*;
+    use crate::AmqpFrame;
+
+    fn header(size: u32) -> BytesMut {
+        let mut buf = BytesMut::with_capacity(HEADER_LEN);
+        buf.put_u32(size);
+        buf.put_slice(&[2, 0, 0, 0]);
+        buf
+    }
+
+    /// Transfer frame of `size` bytes
+    fn transfer(size: usize) -> AmqpFrame {
+        let frame = |len| {
+            AmqpFrame::new(
+                0,
+                crate::protocol::Frame::Transfer(crate::protocol::Transfer {
+                    handle: 0,
+                    delivery_id: Some(1),
+                    delivery_tag: Some(bytes::Bytes::from_static(b"tag")),
+                    message_format: None,
+                    settled: Some(false),
+                    more: false,
+                    rcv_settle_mode: None,
+                    state: None,
+                    resume: false,
+                    aborted: false,
+                    batchable: false,
+                    body: Some(crate::protocol::TransferBody::Data(bytes::Bytes::from(
+                        vec![0; len],
+                    ))),
+                }),
+            )
+        };
+        let overhead = frame(300).encoded_size() - 300;
+        let frame = frame(size - overhead);
+        assert_eq!(frame.encoded_size(), size);
+        frame
+    }
+
+    #[test]
+    fn test_max_outbound_size() {
+        for limit in &[MIN_MAX_FRAME_SIZE, 4096] {
+            let codec = AmqpCodec::<AmqpFrame>::new().max_outbound_size(*limit);
+
+            // frame exactly at the limit
+            let mut buf = BytesMut::new();
+            codec.encode(transfer(*limit), &mut buf).unwrap();
+            assert_eq!(buf.len(), *limit);
+            assert_eq!(BigEndian::read_u32(&buf) as usize, *limit);
+
+            // one byte over, nothing is written
+            let mut buf = BytesMut::new();
+            match codec.encode(transfer(*limit + 1), &mut buf) {
+                Err(AmqpCodecError::FrameTooLarge(size, max)) => {
+                    assert_eq!((size, max), (*limit + 1, *limit))
+                }
+                res => panic!("unexpected result: {:?}", res),
+            }
+            assert!(buf.is_empty());
+        }
+
+        // inbound limit does not apply to encoding
+        let codec = AmqpCodec::<AmqpFrame>::new().max_size(MIN_MAX_FRAME_SIZE);
+        let mut buf = BytesMut::new();
+        codec.encode(transfer(4096), &mut buf).unwrap();
+        assert!(matches!(
+            codec.decode(&mut buf),
+            Err(AmqpCodecError::MaxSizeExceeded(4096, MIN_MAX_FRAME_SIZE))
+        ));
+    }
+
+    #[test]
+    fn test_invalid_size() {
+        let codec = AmqpCodec::<AmqpFrame>::new();
+        for size in 0..HEADER_LEN as u32 {
+            let mut buf = header(size);
+            assert!(matches!(
+                codec.decode(&mut buf),
+                Err(AmqpCodecError::InvalidFrameSize(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_protocol_header() {
+        let codec = ProtocolHeaderCodec;
+        let header = ProtocolHeader {
+            id: ProtocolId::AmqpSasl,
+            version: ProtocolVersion::new(1, 0, 1),
+        };
+        let mut buf = BytesMut::new();
+        codec.encode(header, &mut buf).unwrap();
+        assert_eq!(&buf[..], b"AMQP\x03\x01\x00\x01");
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(header));
+
+        // standard header decodes with both codecs
+        let mut buf = BytesMut::new();
+        ProtocolIdCodec.encode(ProtocolId::Amqp, &mut buf).unwrap();
+        let header = codec.decode(&mut buf.clone()).unwrap().unwrap();
+        assert_eq!(header.version, ProtocolVersion::V1_0_0);
+        assert_eq!(header.version.to_string(), "1.0.0");
+
+        let mut buf = BytesMut::from(&b"AMQP\x00\x01\x00\x01"[..]);
+        assert!(matches!(
+            ProtocolIdCodec.decode(&mut buf),
+            Err(ProtocolIdError::Incompatible)
+        ));
+    }
+
+    #[test]
+    fn test_buffer_growth() {
+        // unlimited size, buffer grows only as data arrives
+        let codec = AmqpCodec::<AmqpFrame>::new();
+        let mut buf = header(0x8000_0000);
+        for _ in 0..4 {
+            assert!(codec.decode(&mut buf).unwrap().is_none());
+            assert!(buf.capacity() <= HEADER_LEN + SIZE_HIGH_WM);
+        }
+        let cap = buf.capacity();
+        buf.put_slice(&vec![0; cap - buf.len()]);
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+        assert!(buf.capacity() <= 2 * cap + SIZE_HIGH_WM);
+    }
+
+    fn open() -> AmqpFrame {
+        AmqpFrame::new(
+            0,
+            crate::protocol::Frame::Open(crate::protocol::Open {
+                container_id: "container".into(),
+                hostname: Some("localhost".into()),
+                max_frame_size: 65536,
+                channel_max: 1024,
+                idle_time_out: Some(30_000),
+                outgoing_locales: None,
+                incoming_locales: None,
+                offered_capabilities: None,
+                desired_capabilities: None,
+                properties: None,
+            }),
+        )
+    }
+
+    #[test]
+    fn test_partial_reads() {
+        let codec = AmqpCodec::<AmqpFrame>::new();
+        let mut data = BytesMut::new();
+        codec.encode(open(), &mut data).unwrap();
+
+        // frame arrives one byte at a time
+        let mut buf = BytesMut::new();
+        for (idx, b) in data.iter().enumerate() {
+            buf.put_u8(*b);
+            let res = codec.decode(&mut buf).unwrap();
+            if idx + 1 < data.len() {
+                assert!(res.is_none(), "frame is decoded at byte {}", idx);
+            } else {
+                assert_eq!(res, Some(open()));
+            }
+        }
+        assert!(buf.is_empty());
+
+        // frames split at every position, next frame starts in same read
+        let mut stream = BytesMut::new();
+        codec.encode(open(), &mut stream).unwrap();
+        codec
+            .encode(
+                AmqpFrame::new(0, crate::protocol::Frame::Empty),
+                &mut stream,
+            )
+            .unwrap();
+        codec.encode(open(), &mut stream).unwrap();
+        for split in 1..stream.len() {
+            let mut buf = BytesMut::from(&stream[..split]);
+            let mut frames = Vec::new();
+            while let Some(frame) = codec.decode(&mut buf).unwrap() {
+                frames.push(frame);
+            }
+            buf.put_slice(&stream[split..]);
+            while let Some(frame) = codec.decode(&mut buf).unwrap() {
+                frames.push(frame);
+            }
+            assert_eq!(frames.len(), 3, "split at {}", split);
+            assert_eq!(frames[0], open());
+            assert_eq!(frames[2], open());
+            assert!(buf.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_partial_protocol_header() {
+        let mut data = BytesMut::new();
+        ProtocolIdCodec
+            .encode(ProtocolId::AmqpSasl, &mut data)
+            .unwrap();
+
+        let mut buf = BytesMut::new();
+        for b in &data[..data.len() - 1] {
+            buf.put_u8(*b);
+            assert_eq!(ProtocolIdCodec.decode(&mut buf).unwrap(), None);
+            assert_eq!(ProtocolHeaderCodec.decode(&mut buf).unwrap(), None);
+        }
+        buf.put_u8(data[data.len() - 1]);
+        assert_eq!(
+            ProtocolIdCodec.decode(&mut buf.clone()).unwrap(),
+            Some(ProtocolId::AmqpSasl)
+        );
+        assert_eq!(
+            ProtocolHeaderCodec.decode(&mut buf).unwrap().map(|h| h.id),
+            Some(ProtocolId::AmqpSasl)
+        );
+    }
+}
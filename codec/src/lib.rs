@@ -10,13 +10,17 @@ mod framing;
 mod io;
 mod message;
 pub mod protocol;
+mod table;
 pub mod types;
 
 pub use self::codec::{Decode, Encode};
 pub use self::error::{AmqpCodecError, AmqpParseError, ProtocolIdError};
 pub use self::framing::{AmqpFrame, SaslFrame};
-pub use self::io::{AmqpCodec, ProtocolIdCodec};
-pub use self::message::{Message, MessageBody};
+pub use self::io::{
+    AmqpCodec, ProtocolHeaderCodec, ProtocolIdCodec, MIN_MAX_FRAME_SIZE, PRE_OPEN_MAX_SIZE,
+};
+pub use self::message::{Body, Message, MessageBody, MessageBuilder, MESSAGE_FORMAT_BATCH};
+pub use self::table::{FrameTable, FrameTables, PerformativeCodec, TableCodec};
 
 /// A `HashMap` using a ahash::RandomState hasher.
 type HashMap<K, V> = std::collections::HashMap<K, V, ahash::RandomState>;
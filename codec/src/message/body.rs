@@ -6,6 +6,51 @@ use crate::types::{Descriptor, List, Variant};
 
 use super::SECTION_PREFIX_LENGTH;
 
+/// Typed message body, see `Message::typed_body()`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Body {
+    /// Data sections, message without body has no sections
+    Data(Vec<Bytes>),
+    /// AmqpSequence sections
+    Sequence(Vec<List>),
+    /// AmqpValue section
+    Value(Variant),
+}
+
+impl Body {
+    /// Content of data sections, `None` for sequence and value bodies.
+    ///
+    /// Sections are copied only if there is more than one.
+    pub fn to_bytes(&self) -> Option<Bytes> {
+        if let Body::Data(sections) = self {
+            Some(match sections.len() {
+                0 => Bytes::new(),
+                1 => sections[0].clone(),
+                _ => {
+                    let len = sections.iter().map(|s| s.len()).sum();
+                    let mut buf = BytesMut::with_capacity(len);
+                    sections.iter().for_each(|s| buf.extend_from_slice(s));
+                    buf.freeze()
+                }
+            })
+        } else {
+            None
+        }
+    }
+}
+
+impl From<Body> for MessageBody {
+    fn from(body: Body) -> MessageBody {
+        let mut result = MessageBody::default();
+        match body {
+            Body::Data(data) => result.data = data,
+            Body::Sequence(sequence) => result.sequence = sequence,
+            Body::Value(value) => result.value = Some(value),
+        }
+        result
+    }
+}
+
 #[derive(Debug, Clone, Default, PartialEq)]
 pub struct MessageBody {
     pub data: Vec<Bytes>,
@@ -31,6 +76,25 @@ impl MessageBody {
         self.data.clear();
         self.data.push(data);
     }
+
+    /// Typed body, data sections are shared with this body.
+    ///
+    /// Nested messages are encoded as data sections.
+    pub fn to_body(&self) -> Body {
+        if let Some(ref value) = self.value {
+            Body::Value(value.clone())
+        } else if !self.sequence.is_empty() {
+            Body::Sequence(self.sequence.clone())
+        } else {
+            let mut data = self.data.clone();
+            data.extend(self.messages.iter().map(|m| {
+                let mut buf = BytesMut::with_capacity(m.encoded_size());
+                m.encode(&mut buf);
+                buf.freeze()
+            }));
+            Body::Data(data)
+        }
+    }
 }
 
 impl Encode for MessageBody {
@@ -4,12 +4,18 @@ use bytes::{Bytes, BytesMut};
 
 use crate::codec::{Decode, Encode};
 use crate::error::AmqpParseError;
-use crate::protocol::{Annotations, Header, MessageFormat, Properties, Section, TransferBody};
-use crate::types::{Descriptor, Str, Symbol, Variant, VecStringMap, VecSymbolMap};
+use crate::protocol::{
+    Address, Annotations, Header, MessageFormat, MessageId, Milliseconds, Properties, Section,
+    TransferBody,
+};
+use crate::types::{Descriptor, List, Str, Symbol, Variant, VecStringMap, VecSymbolMap};
 
-use super::body::MessageBody;
+use super::body::{Body, MessageBody};
 use super::SECTION_PREFIX_LENGTH;
 
+/// Message format of batched messages, i.e. Azure Service Bus and Event Hubs
+pub const MESSAGE_FORMAT_BATCH: MessageFormat = 0x8001_3700;
+
 #[derive(Debug, Clone, Default, PartialEq)]
 pub struct Message {
     pub message_format: Option<MessageFormat>,
@@ -24,6 +30,16 @@ pub struct Message {
 }
 
 impl Message {
+    /// Create message builder
+    pub fn build() -> MessageBuilder {
+        MessageBuilder {
+            msg: Message {
+                message_format: Some(0),
+                ..Message::default()
+            },
+        }
+    }
+
     /// Create new message and set body
     pub fn with_body(body: Bytes) -> Message {
         let mut msg = Message::default();
@@ -40,6 +56,58 @@ impl Message {
         msg
     }
 
+    /// Create batch of messages.
+    ///
+    /// Each message is encoded to its own data section. Properties and
+    /// message annotations of the first message are copied to the batch,
+    /// brokers use them for message id and partition key of the batch.
+    pub fn batch(messages: Vec<Message>) -> Message {
+        let mut msg = Message::default();
+        if let Some(first) = messages.first() {
+            msg.properties = first.properties.clone();
+            msg.message_annotations = first.message_annotations.clone();
+        }
+        msg.body.messages = messages.into_iter().map(TransferBody::from).collect();
+        msg.message_format = Some(MESSAGE_FORMAT_BATCH);
+        msg
+    }
+
+    /// Messages of the batch, each data section is decoded as message.
+    ///
+    /// Returns `None` if message format is not `MESSAGE_FORMAT_BATCH`
+    /// or one of data sections is not a valid message. Message format of
+    /// received message is set by `Transfer::message()`.
+    pub fn unbatch(&self) -> Option<Vec<Message>> {
+        if self.message_format != Some(MESSAGE_FORMAT_BATCH) {
+            return None;
+        }
+        let data = self.body.data.iter();
+        let nested = self.body.messages.iter().map(|m| match m {
+            TransferBody::Data(data) => Message::decode(data).map(|(_, msg)| msg),
+            TransferBody::Message(msg) => Ok(msg.as_ref().clone()),
+        });
+        data.map(|data| Message::decode(data).map(|(_, msg)| msg))
+            .chain(nested)
+            .collect::<Result<_, _>>()
+            .ok()
+    }
+
+    /// Create new message with AmqpValue body
+    pub fn with_value<V: Into<Variant>>(value: V) -> Message {
+        let mut msg = Message::default();
+        msg.body.value = Some(value.into());
+        msg.message_format = Some(0);
+        msg
+    }
+
+    /// Create new message with AmqpSequence body
+    pub fn with_sequence(sequence: Vec<List>) -> Message {
+        let mut msg = Message::default();
+        msg.body.sequence = sequence;
+        msg.message_format = Some(0);
+        msg
+    }
+
     /// Header
     pub fn header(&self) -> Option<&Header> {
         self.header.as_ref()
@@ -83,6 +151,39 @@ impl Message {
         self
     }
 
+    /// Message id property
+    pub fn message_id(&self) -> Option<&MessageId> {
+        self.properties.as_ref().and_then(|p| p.message_id.as_ref())
+    }
+
+    /// Correlation id property
+    pub fn correlation_id(&self) -> Option<&MessageId> {
+        self.properties
+            .as_ref()
+            .and_then(|p| p.correlation_id.as_ref())
+    }
+
+    /// Reply-to property
+    pub fn reply_to(&self) -> Option<&Address> {
+        self.properties.as_ref().and_then(|p| p.reply_to.as_ref())
+    }
+
+    /// Time to live from message header
+    pub fn ttl(&self) -> Option<Milliseconds> {
+        self.header.as_ref().and_then(|h| h.ttl)
+    }
+
+    /// First-acquirer flag from message header.
+    ///
+    /// `true` if message was not acquired by other link before,
+    /// i.e. it was not delivered earlier. Browsing does not acquire message.
+    pub fn first_acquirer(&self) -> bool {
+        self.header
+            .as_ref()
+            .map(|h| h.first_acquirer)
+            .unwrap_or(false)
+    }
+
     /// Get application property
     pub fn app_properties(&self) -> Option<&VecStringMap> {
         self.application_properties.as_ref()
@@ -90,13 +191,9 @@ impl Message {
 
     /// Get application property
     pub fn app_property(&self, key: &str) -> Option<&Variant> {
-        if let Some(ref props) = self.application_properties {
-            props
-                .iter()
-                .find_map(|item| if &item.0 == key { Some(&item.1) } else { None })
-        } else {
-            None
-        }
+        self.application_properties
+            .as_ref()
+            .and_then(|props| props.get(key))
     }
 
     /// Add application property
@@ -105,13 +202,9 @@ impl Message {
         K: Into<Str>,
         V: Into<Variant>,
     {
-        if let Some(ref mut props) = self.application_properties {
-            props.push((key.into(), value.into()));
-        } else {
-            let mut props = VecStringMap::default();
-            props.push((key.into(), value.into()));
-            self.application_properties = Some(props);
-        }
+        self.application_properties
+            .get_or_insert_with(VecStringMap::default)
+            .insert(key.into(), value.into());
         self.size.set(0);
         self
     }
@@ -144,6 +237,11 @@ impl Message {
         self
     }
 
+    /// Message annotations
+    pub fn message_annotations(&self) -> Option<&VecSymbolMap> {
+        self.message_annotations.as_ref()
+    }
+
     /// Delivery annotations
     pub fn delivery_annotations(&self) -> Option<&VecSymbolMap> {
         self.delivery_annotations.as_ref()
@@ -154,6 +252,11 @@ impl Message {
         self.delivery_annotations.as_mut()
     }
 
+    /// Message footer
+    pub fn footer(&self) -> Option<&Annotations> {
+        self.footer.as_ref()
+    }
+
     /// Call closure with message reference
     pub fn update<F>(self, f: F) -> Self
     where
@@ -181,6 +284,11 @@ impl Message {
         &self.body
     }
 
+    /// Message body as `Data`, `Sequence` or `Value`
+    pub fn typed_body(&self) -> Body {
+        self.body.to_body()
+    }
+
     /// Message value
     pub fn value(&self) -> Option<&Variant> {
         self.body.value.as_ref()
@@ -209,6 +317,160 @@ impl Message {
             msg
         })
     }
+
+    /// Encode bare message, immutable part of the message produced by sender.
+    ///
+    /// Bare message consists of properties, application properties and body
+    /// sections. Header, annotations and footer are not encoded.
+    pub fn encode_bare(&self) -> Bytes {
+        let mut size = self.body.encoded_size();
+        if let Some(ref p) = self.properties {
+            size += p.encoded_size();
+        }
+        if let Some(ref ap) = self.application_properties {
+            size += ap.encoded_size() + SECTION_PREFIX_LENGTH;
+        }
+
+        let mut buf = BytesMut::with_capacity(size);
+        if let Some(ref p) = self.properties {
+            p.encode(&mut buf);
+        }
+        if let Some(ref ap) = self.application_properties {
+            Descriptor::Ulong(116).encode(&mut buf);
+            ap.encode(&mut buf);
+        }
+        self.body.encode(&mut buf);
+        buf.freeze()
+    }
+
+    /// Copy pre-encoded message and overwrite bytes at `offset` with `data`.
+    ///
+    /// Only fixed width values could be patched this way, for example
+    /// part of binary body or binary message id.
+    ///
+    /// Panics if `data` does not fit into encoded message.
+    pub fn splice(encoded: &[u8], offset: usize, data: &[u8]) -> Bytes {
+        assert!(
+            offset + data.len() <= encoded.len(),
+            "spliced data is out of encoded message bounds"
+        );
+        let mut buf = BytesMut::from(encoded);
+        buf[offset..offset + data.len()].copy_from_slice(data);
+        buf.freeze()
+    }
+}
+
+/// Message builder, only sections with values set are encoded
+#[derive(Debug)]
+pub struct MessageBuilder {
+    msg: Message,
+}
+
+impl MessageBuilder {
+    /// Set message body data
+    pub fn body(mut self, body: Bytes) -> Self {
+        self.msg.body.set_data(body);
+        self
+    }
+
+    /// Set message body value
+    pub fn value<V: Into<Variant>>(mut self, value: V) -> Self {
+        self.msg.set_value(value);
+        self
+    }
+
+    pub fn message_id<V: Into<MessageId>>(mut self, id: V) -> Self {
+        self.msg.properties_mut().message_id = Some(id.into());
+        self
+    }
+
+    pub fn correlation_id<V: Into<MessageId>>(mut self, id: V) -> Self {
+        self.msg.properties_mut().correlation_id = Some(id.into());
+        self
+    }
+
+    pub fn reply_to<V: Into<Address>>(mut self, address: V) -> Self {
+        self.msg.properties_mut().reply_to = Some(address.into());
+        self
+    }
+
+    /// Set time to live in milliseconds, header is created if needed
+    pub fn ttl(mut self, ttl: Milliseconds) -> Self {
+        let header = self.msg.header.get_or_insert(Header {
+            durable: false,
+            priority: 4,
+            ttl: None,
+            first_acquirer: false,
+            delivery_count: 0,
+        });
+        header.ttl = Some(ttl);
+        self
+    }
+
+    /// Set first-acquirer flag, header is created if needed
+    pub fn first_acquirer(mut self, first_acquirer: bool) -> Self {
+        let header = self.msg.header.get_or_insert(Header {
+            durable: false,
+            priority: 4,
+            ttl: None,
+            first_acquirer: false,
+            delivery_count: 0,
+        });
+        header.first_acquirer = first_acquirer;
+        self
+    }
+
+    /// Add application property
+    pub fn app_property<K, V>(mut self, key: K, value: V) -> Self
+    where
+        K: Into<Str>,
+        V: Into<Variant>,
+    {
+        self.msg.set_app_property(key, value);
+        self
+    }
+
+    /// Add message annotation
+    pub fn annotation<K, V>(mut self, key: K, value: V) -> Self
+    where
+        K: Into<Symbol>,
+        V: Into<Variant>,
+    {
+        self.msg.add_message_annotation(key, value);
+        self
+    }
+
+    /// Add delivery annotation
+    pub fn delivery_annotation<K, V>(mut self, key: K, value: V) -> Self
+    where
+        K: Into<Symbol>,
+        V: Into<Variant>,
+    {
+        self.msg
+            .delivery_annotations
+            .get_or_insert_with(VecSymbolMap::default)
+            .push((key.into(), value.into()));
+        self
+    }
+
+    /// Add footer entry
+    pub fn footer<K, V>(mut self, key: K, value: V) -> Self
+    where
+        K: Into<Symbol>,
+        V: Into<Variant>,
+    {
+        self.msg
+            .footer
+            .get_or_insert_with(Annotations::default)
+            .insert(key.into(), value.into());
+        self
+    }
+
+    /// Finish building message
+    pub fn done(self) -> Message {
+        self.msg.size.set(0);
+        self.msg
+    }
 }
 
 impl Decode for Message {
@@ -327,10 +589,10 @@ mod tests {
 
     use crate::codec::{Decode, Encode};
     use crate::error::AmqpCodecError;
-    use crate::protocol::Header;
-    use crate::types::Variant;
+    use crate::protocol::{Header, MessageId};
+    use crate::types::{Descriptor, List, Symbol, Variant};
 
-    use super::Message;
+    use super::{Body, Message, MESSAGE_FORMAT_BATCH};
 
     #[test]
     fn test_properties() -> Result<(), AmqpCodecError> {
@@ -407,6 +669,64 @@ mod tests {
         Ok(())
     }
 
+    fn roundtrip(msg: &Message) -> Result<Message, AmqpCodecError> {
+        let mut buf = BytesMut::with_capacity(msg.encoded_size());
+        msg.encode(&mut buf);
+        assert_eq!(buf.len(), msg.encoded_size());
+        let (remainder, msg) = Message::decode(&buf)?;
+        assert!(remainder.is_empty());
+        Ok(msg)
+    }
+
+    #[test]
+    fn test_typed_body() -> Result<(), AmqpCodecError> {
+        // empty body
+        let msg = roundtrip(&Message::default())?;
+        assert_eq!(msg.typed_body(), Body::Data(Vec::new()));
+        assert_eq!(msg.typed_body().to_bytes(), Some(Bytes::new()));
+
+        // single data section is not copied
+        let msg = roundtrip(&Message::with_body(Bytes::from_static(b"data")))?;
+        let body = msg.typed_body();
+        assert_eq!(body, Body::Data(vec![Bytes::from_static(b"data")]));
+        assert_eq!(body.to_bytes().unwrap().as_ptr(), msg.body.data[0].as_ptr());
+
+        // multiple data sections
+        let mut msg = Message::default();
+        msg.set_body(|body| {
+            body.data.push(Bytes::from_static(b"one "));
+            body.data.push(Bytes::from_static(b"two"));
+        });
+        let body = roundtrip(&msg)?.typed_body();
+        assert_eq!(
+            body,
+            Body::Data(vec![
+                Bytes::from_static(b"one "),
+                Bytes::from_static(b"two")
+            ])
+        );
+        assert_eq!(body.to_bytes(), Some(Bytes::from_static(b"one two")));
+
+        // sequence
+        let sequence = vec![
+            List(vec![Variant::Int(1), Variant::from("a")]),
+            List(vec![Variant::Null]),
+        ];
+        let msg = roundtrip(&Message::with_sequence(sequence.clone()))?;
+        assert_eq!(msg.typed_body(), Body::Sequence(sequence));
+        assert_eq!(msg.typed_body().to_bytes(), None);
+
+        // value
+        let msg = roundtrip(&Message::with_value(Variant::Ulong(7)))?;
+        assert_eq!(msg.typed_body(), Body::Value(Variant::Ulong(7)));
+
+        // typed body converts back
+        let mut msg = Message::default();
+        msg.set_body(|body| *body = Body::Value(Variant::from("v")).into());
+        assert_eq!(msg.value(), Some(&Variant::from("v")));
+        Ok(())
+    }
+
     #[test]
     fn test_messages() -> Result<(), AmqpCodecError> {
         let mut msg1 = Message::default();
@@ -430,4 +750,181 @@ mod tests {
         assert_eq!(msg2.properties, msg5.properties);
         Ok(())
     }
+
+    #[test]
+    fn test_batch() -> Result<(), AmqpCodecError> {
+        let messages: Vec<_> = (1..4)
+            .map(|i| {
+                Message::build()
+                    .message_id(i)
+                    .body(Bytes::from(format!("msg{}", i)))
+                    .done()
+            })
+            .collect();
+        let batch = Message::batch(messages.clone());
+        assert_eq!(batch.message_format, Some(MESSAGE_FORMAT_BATCH));
+        assert_eq!(batch.message_id(), Some(&MessageId::Ulong(1)));
+        let unbatched = batch.unbatch().unwrap();
+        assert_eq!(unbatched.len(), 3);
+        assert!(messages[0].unbatch().is_none());
+
+        let mut buf = BytesMut::with_capacity(batch.encoded_size());
+        batch.encode(&mut buf);
+        let mut decoded = Message::decode(&buf)?.1;
+        assert_eq!(decoded.body.data.len(), 3);
+        assert!(decoded.unbatch().is_none());
+
+        decoded.message_format = Some(MESSAGE_FORMAT_BATCH);
+        let unbatched = decoded.unbatch().unwrap();
+        assert_eq!(unbatched.len(), messages.len());
+        for (msg, item) in messages.iter().zip(unbatched) {
+            assert_eq!(msg.properties, item.properties);
+            assert_eq!(msg.body.data, item.body.data);
+        }
+
+        decoded.body.data.push(Bytes::from_static(b"\x00\x53"));
+        assert!(decoded.unbatch().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_builder() -> Result<(), AmqpCodecError> {
+        let msg = Message::build()
+            .body(Bytes::from_static(b"data"))
+            .message_id(1)
+            .correlation_id(ByteString::from_static("corr"))
+            .reply_to("reply")
+            .ttl(1000)
+            .first_acquirer(true)
+            .app_property(ByteString::from_static("key"), 2)
+            .annotation(Symbol::from_static("x-opt"), true)
+            .delivery_annotation(Symbol::from_static("x-delivery"), 3)
+            .footer(Symbol::from_static("x-footer"), 4)
+            .done();
+
+        let mut buf = BytesMut::with_capacity(msg.encoded_size());
+        msg.encode(&mut buf);
+        assert_eq!(buf.len(), msg.encoded_size());
+
+        let msg2 = Message::decode(&buf)?.1;
+        let mut buf2 = BytesMut::with_capacity(msg2.encoded_size());
+        msg2.encode(&mut buf2);
+        assert_eq!(buf, buf2);
+        assert_eq!(msg2.message_id(), Some(&MessageId::Ulong(1)));
+        assert_eq!(
+            msg2.correlation_id(),
+            Some(&MessageId::String(ByteString::from_static("corr")))
+        );
+        assert_eq!(msg2.reply_to().unwrap(), "reply");
+        assert_eq!(msg2.ttl(), Some(1000));
+        assert!(msg2.first_acquirer());
+        assert_eq!(msg2.app_property("key"), Some(&Variant::from(2)));
+        assert_eq!(msg2.message_annotation("x-opt"), Some(&Variant::from(true)));
+        assert_eq!(msg2.delivery_annotations().unwrap().len(), 1);
+        assert_eq!(
+            msg2.footer().unwrap()[&Symbol::from_static("x-footer")],
+            Variant::from(4)
+        );
+        assert_eq!(msg2.body.data().unwrap(), &Bytes::from_static(b"data"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_builder_sections() -> Result<(), AmqpCodecError> {
+        // only body section is encoded
+        let msg = Message::build().body(Bytes::from_static(b"data")).done();
+        let mut buf = BytesMut::with_capacity(msg.encoded_size());
+        msg.encode(&mut buf);
+        assert_eq!(&buf[..], &b"\x00\x53\x75\xa0\x04data"[..]);
+
+        // properties section is not created by reader
+        let msg2 = Message::decode(&buf)?.1;
+        assert!(msg2.properties().is_none());
+        assert!(msg2.message_id().is_none());
+        assert!(msg2.ttl().is_none());
+        assert!(!msg2.first_acquirer());
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_sections_order() -> Result<(), AmqpCodecError> {
+        let props = Message::build().message_id(1).done();
+        let app_props = Message::build().app_property("key", 2).done();
+        let body = Message::build().body(Bytes::from_static(b"data")).done();
+
+        // body, application properties and properties in reverse order
+        let mut buf = BytesMut::new();
+        for msg in &[&body, &app_props, &props] {
+            buf.reserve(msg.encoded_size());
+            msg.encode(&mut buf);
+        }
+
+        let msg = Message::decode(&buf)?.1;
+        assert_eq!(msg.message_id(), Some(&MessageId::Ulong(1)));
+        assert_eq!(msg.app_property("key"), Some(&Variant::from(2)));
+        assert_eq!(msg.body.data().unwrap(), &Bytes::from_static(b"data"));
+
+        // footer before header
+        let mut buf = BytesMut::new();
+        Descriptor::Ulong(120).encode(&mut buf);
+        Message::build()
+            .footer(Symbol::from_static("x-footer"), 1)
+            .done()
+            .footer()
+            .unwrap()
+            .encode(&mut buf);
+        let header = Message::build().ttl(10).done();
+        buf.reserve(header.encoded_size());
+        header.encode(&mut buf);
+
+        let msg = Message::decode(&buf)?.1;
+        assert_eq!(msg.ttl(), Some(10));
+        assert!(msg.footer().is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn test_encode_bare() -> Result<(), AmqpCodecError> {
+        let msg = Message::build()
+            .body(Bytes::from_static(b"counter:00000000"))
+            .message_id(1)
+            .app_property("key", 2)
+            .ttl(1000)
+            .annotation(Symbol::from_static("x-opt"), true)
+            .done();
+
+        let bare = msg.encode_bare();
+        let msg2 = Message::decode(&bare)?.1;
+        assert!(msg2.header.is_none());
+        assert!(msg2.message_annotations().is_none());
+        assert_eq!(msg2.message_id(), Some(&MessageId::Ulong(1)));
+        assert_eq!(msg2.app_property("key"), Some(&Variant::from(2)));
+
+        // bare message is the same as message without header and annotations
+        let mut msg3 = msg.clone();
+        msg3.header = None;
+        msg3.message_annotations = None;
+        let mut buf = BytesMut::with_capacity(msg3.encoded_size());
+        msg3.encode(&mut buf);
+        assert_eq!(&buf[..], &bare[..]);
+
+        // patch counter in body
+        let offset = bare.windows(8).position(|w| w == b"00000000").unwrap();
+        let spliced = Message::splice(&bare, offset, b"00000042");
+        assert_eq!(spliced.len(), bare.len());
+        let msg4 = Message::decode(&spliced)?.1;
+        assert_eq!(
+            msg4.body.data().unwrap(),
+            &Bytes::from_static(b"counter:00000042")
+        );
+        assert_eq!(msg4.properties, msg2.properties);
+        Ok(())
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_splice_out_of_bounds() {
+        let bare = Message::with_body(Bytes::from_static(b"data")).encode_bare();
+        Message::splice(&bare, bare.len() - 2, b"abc");
+    }
 }
@@ -3,7 +3,7 @@ mod body;
 #[allow(clippy::module_inception)]
 mod message;
 
-pub use self::body::MessageBody;
-pub use self::message::Message;
+pub use self::body::{Body, MessageBody};
+pub use self::message::{Message, MessageBuilder, MESSAGE_FORMAT_BATCH};
 
 pub(self) const SECTION_PREFIX_LENGTH: usize = 3;
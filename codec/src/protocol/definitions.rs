@@ -462,6 +462,8 @@ pub enum DeliveryState {
     Released(Released),
 
     Modified(Modified),
+
+    TransactionalState(TransactionalState),
 }
 
 impl DecodeFormatted for DeliveryState {
@@ -489,6 +491,9 @@ impl DecodeFormatted for DeliveryState {
                 decode_modified_inner(input).map(|(i, r)| (i, DeliveryState::Modified(r)))
             }
 
+            Descriptor::Ulong(52) => decode_transactional_state_inner(input)
+                .map(|(i, r)| (i, DeliveryState::TransactionalState(r))),
+
             Descriptor::Symbol(ref a) if a.as_str() == "amqp:received:list" => {
                 decode_received_inner(input).map(|(i, r)| (i, DeliveryState::Received(r)))
             }
@@ -509,6 +514,11 @@ impl DecodeFormatted for DeliveryState {
                 decode_modified_inner(input).map(|(i, r)| (i, DeliveryState::Modified(r)))
             }
 
+            Descriptor::Symbol(ref a) if a.as_str() == "amqp:transactional-state:list" => {
+                decode_transactional_state_inner(input)
+                    .map(|(i, r)| (i, DeliveryState::TransactionalState(r)))
+            }
+
             _ => Err(AmqpParseError::InvalidDescriptor(descriptor)),
         }
     }
@@ -526,6 +536,8 @@ impl Encode for DeliveryState {
             DeliveryState::Released(ref v) => encoded_size_released_inner(v),
 
             DeliveryState::Modified(ref v) => encoded_size_modified_inner(v),
+
+            DeliveryState::TransactionalState(ref v) => encoded_size_transactional_state_inner(v),
         }
     }
     fn encode(&self, buf: &mut BytesMut) {
@@ -539,6 +551,8 @@ impl Encode for DeliveryState {
             DeliveryState::Released(ref v) => encode_released_inner(v, buf),
 
             DeliveryState::Modified(ref v) => encode_modified_inner(v, buf),
+
+            DeliveryState::TransactionalState(ref v) => encode_transactional_state_inner(v, buf),
         }
     }
 }
@@ -5760,3 +5774,112 @@ impl Encode for Modified {
         encode_modified_inner(self, buf)
     }
 }
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TransactionalState {
+    pub txn_id: Bytes,
+
+    pub outcome: Option<Outcome>,
+}
+
+impl TransactionalState {
+    pub fn txn_id(&self) -> &Bytes {
+        &self.txn_id
+    }
+
+    pub fn outcome(&self) -> Option<&Outcome> {
+        self.outcome.as_ref()
+    }
+
+    #[allow(clippy::identity_op)]
+    const FIELD_COUNT: usize = 0 + 1 + 1;
+}
+#[allow(unused_mut)]
+fn decode_transactional_state_inner(
+    input: &[u8],
+) -> Result<(&[u8], TransactionalState), AmqpParseError> {
+    let (input, format) = decode_format_code(input)?;
+    let (input, header) = decode_list_header(input, format)?;
+    let size = header.size as usize;
+    decode_check_len!(input, size);
+
+    let (mut input, mut remainder) = input.split_at(size);
+    let mut count = header.count;
+
+    let txn_id: Bytes;
+    if count > 0 {
+        let (in1, decoded) = Bytes::decode(input)?;
+        txn_id = decoded;
+
+        input = in1;
+        count -= 1;
+    } else {
+        return Err(AmqpParseError::RequiredFieldOmitted("txn_id"));
+    }
+
+    let outcome: Option<Outcome>;
+    if count > 0 {
+        let decoded = Option::<Outcome>::decode(input)?;
+        input = decoded.0;
+        outcome = decoded.1;
+        count -= 1;
+    } else {
+        outcome = None;
+    }
+
+    Ok((remainder, TransactionalState { txn_id, outcome }))
+}
+
+fn encoded_size_transactional_state_inner(list: &TransactionalState) -> usize {
+    #[allow(clippy::identity_op)]
+    let content_size = 0 + list.txn_id.encoded_size() + list.outcome.encoded_size();
+    // header: 0x00 0x53 <descriptor code> format_code size count
+    (if content_size + 1 > u8::MAX as usize {
+        12
+    } else {
+        6
+    }) + content_size
+}
+fn encode_transactional_state_inner(list: &TransactionalState, buf: &mut BytesMut) {
+    Descriptor::Ulong(52).encode(buf);
+    #[allow(clippy::identity_op)]
+    let content_size = 0 + list.txn_id.encoded_size() + list.outcome.encoded_size();
+    if content_size + 1 > u8::MAX as usize {
+        buf.put_u8(codec::FORMATCODE_LIST32);
+        buf.put_u32((content_size + 4) as u32); // +4 for 4 byte count
+        buf.put_u32(TransactionalState::FIELD_COUNT as u32);
+    } else {
+        buf.put_u8(codec::FORMATCODE_LIST8);
+        buf.put_u8((content_size + 1) as u8);
+        buf.put_u8(TransactionalState::FIELD_COUNT as u8);
+    }
+
+    list.txn_id.encode(buf);
+    list.outcome.encode(buf);
+}
+
+impl DecodeFormatted for TransactionalState {
+    fn decode_with_format(input: &[u8], fmt: u8) -> Result<(&[u8], Self), AmqpParseError> {
+        validate_code!(fmt, codec::FORMATCODE_DESCRIBED);
+        let (input, descriptor) = Descriptor::decode(input)?;
+        let is_match = match descriptor {
+            Descriptor::Ulong(val) => val == 52,
+            Descriptor::Symbol(ref sym) => sym.as_bytes() == b"amqp:transactional-state:list",
+        };
+        if !is_match {
+            Err(AmqpParseError::InvalidDescriptor(descriptor))
+        } else {
+            decode_transactional_state_inner(input)
+        }
+    }
+}
+
+impl Encode for TransactionalState {
+    fn encoded_size(&self) -> usize {
+        encoded_size_transactional_state_inner(self)
+    }
+
+    fn encode(&self, buf: &mut BytesMut) {
+        encode_transactional_state_inner(self, buf)
+    }
+}
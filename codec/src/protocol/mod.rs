@@ -6,7 +6,7 @@ use chrono::{DateTime, Utc};
 use derive_more::From;
 use uuid::Uuid;
 
-use super::codec::{self, DecodeFormatted, Encode};
+use super::codec::{self, Decode, DecodeFormatted, Encode};
 use super::error::AmqpParseError;
 use super::message::Message;
 use super::types::*;
@@ -37,6 +37,46 @@ pub enum ProtocolId {
     AmqpSasl = 3,
 }
 
+/// Protocol version of the protocol header, major, minor and revision
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, PartialOrd, Ord)]
+pub struct ProtocolVersion {
+    pub major: u8,
+    pub minor: u8,
+    pub revision: u8,
+}
+
+impl ProtocolVersion {
+    /// Amqp 1.0.0, the only version defined by specification
+    pub const V1_0_0: ProtocolVersion = ProtocolVersion::new(1, 0, 0);
+
+    pub const fn new(major: u8, minor: u8, revision: u8) -> ProtocolVersion {
+        ProtocolVersion {
+            major,
+            minor,
+            revision,
+        }
+    }
+}
+
+impl Default for ProtocolVersion {
+    fn default() -> ProtocolVersion {
+        ProtocolVersion::V1_0_0
+    }
+}
+
+impl fmt::Display for ProtocolVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.revision)
+    }
+}
+
+/// Protocol header, protocol id and version
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct ProtocolHeader {
+    pub id: ProtocolId,
+    pub version: ProtocolVersion,
+}
+
 pub type Map = HashMap<Variant, Variant>;
 pub type StringVariantMap = HashMap<Str, Variant>;
 pub type Fields = HashMap<Symbol, Variant>;
@@ -54,7 +94,7 @@ pub type Annotations = HashMap<Symbol, Variant>;
 mod definitions;
 pub use self::definitions::*;
 
-#[derive(Debug, Eq, PartialEq, Clone, From, Display)]
+#[derive(Debug, Eq, PartialEq, Hash, Clone, From, Display)]
 pub enum MessageId {
     #[display(fmt = "{}", _0)]
     Ulong(u64),
@@ -206,6 +246,108 @@ impl Encode for DistributionMode {
     }
 }
 
+impl DistributionMode {
+    /// Symbolic value of distribution mode
+    pub fn to_symbol(&self) -> Symbol {
+        match *self {
+            DistributionMode::Move => Symbol::from_static("move"),
+            DistributionMode::Copy => Symbol::from_static("copy"),
+            DistributionMode::Custom(ref v) => v.clone(),
+        }
+    }
+}
+
+/// Lifetime policy of dynamically created node.
+///
+/// Value of `lifetime-policy` node property, encoded as described empty list.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LifetimePolicy {
+    /// `amqp:delete-on-close:list`, node is deleted when creating link is closed
+    DeleteOnClose,
+    /// `amqp:delete-on-no-links:list`, node is deleted when there are no links
+    DeleteOnNoLinks,
+    /// `amqp:delete-on-no-messages:list`, node is deleted when it holds no messages
+    DeleteOnNoMessages,
+    /// `amqp:delete-on-no-links-or-messages:list`
+    DeleteOnNoLinksOrMessages,
+}
+
+impl LifetimePolicy {
+    pub fn descriptor_code(self) -> u64 {
+        match self {
+            LifetimePolicy::DeleteOnClose => 0x0000_0000_0000_002b,
+            LifetimePolicy::DeleteOnNoLinks => 0x0000_0000_0000_002c,
+            LifetimePolicy::DeleteOnNoMessages => 0x0000_0000_0000_002d,
+            LifetimePolicy::DeleteOnNoLinksOrMessages => 0x0000_0000_0000_002e,
+        }
+    }
+
+    pub fn descriptor_name(self) -> &'static str {
+        match self {
+            LifetimePolicy::DeleteOnClose => "amqp:delete-on-close:list",
+            LifetimePolicy::DeleteOnNoLinks => "amqp:delete-on-no-links:list",
+            LifetimePolicy::DeleteOnNoMessages => "amqp:delete-on-no-messages:list",
+            LifetimePolicy::DeleteOnNoLinksOrMessages => "amqp:delete-on-no-links-or-messages:list",
+        }
+    }
+
+    /// Policy for descriptor, either numeric or symbolic
+    pub fn from_descriptor(descriptor: &Descriptor) -> Option<Self> {
+        const ALL: [LifetimePolicy; 4] = [
+            LifetimePolicy::DeleteOnClose,
+            LifetimePolicy::DeleteOnNoLinks,
+            LifetimePolicy::DeleteOnNoMessages,
+            LifetimePolicy::DeleteOnNoLinksOrMessages,
+        ];
+
+        let code = descriptor.code()?;
+        ALL.iter().copied().find(|p| p.descriptor_code() == code)
+    }
+
+    /// Policy from node property value
+    pub fn from_variant(value: &Variant) -> Option<Self> {
+        match value {
+            Variant::Described((descriptor, _)) => Self::from_descriptor(descriptor),
+            _ => None,
+        }
+    }
+}
+
+impl From<LifetimePolicy> for Variant {
+    fn from(policy: LifetimePolicy) -> Variant {
+        Variant::Described((
+            Descriptor::Ulong(policy.descriptor_code()),
+            Box::new(Variant::List(List(Vec::new()))),
+        ))
+    }
+}
+
+impl DecodeFormatted for LifetimePolicy {
+    fn decode_with_format(input: &[u8], fmt: u8) -> Result<(&[u8], Self), AmqpParseError> {
+        if fmt != codec::FORMATCODE_DESCRIBED {
+            return Err(AmqpParseError::InvalidFormatCode(fmt));
+        }
+        let (input, descriptor) = Descriptor::decode(input)?;
+        // policies do not define fields
+        let (input, _) = List::decode(input)?;
+        match Self::from_descriptor(&descriptor) {
+            Some(policy) => Ok((input, policy)),
+            None => Err(AmqpParseError::InvalidDescriptor(descriptor)),
+        }
+    }
+}
+
+impl Encode for LifetimePolicy {
+    fn encoded_size(&self) -> usize {
+        Descriptor::Ulong(self.descriptor_code()).encoded_size() + 1
+    }
+
+    fn encode(&self, buf: &mut BytesMut) {
+        Descriptor::Ulong(self.descriptor_code()).encode(buf);
+        buf.put_u8(codec::FORMATCODE_LIST0);
+    }
+}
+
 impl SaslInit {
     pub fn prepare_response(authz_id: &str, authn_id: &str, password: &str) -> Bytes {
         Bytes::from(format!("{}\x00{}\x00{}", authz_id, authn_id, password))
@@ -253,6 +395,24 @@ impl TransferBody {
     }
 }
 
+impl Transfer {
+    /// Decode message of transfer.
+    ///
+    /// Message format of the transfer is kept in the message, use
+    /// `Message::unbatch()` to unpack batched messages.
+    pub fn message(&self) -> Result<Message, AmqpParseError> {
+        let mut msg = match self.body {
+            Some(TransferBody::Data(ref data)) => Message::decode(data)?.1,
+            Some(TransferBody::Message(ref msg)) => msg.as_ref().clone(),
+            None => Message::default(),
+        };
+        if self.message_format.is_some() {
+            msg.message_format = self.message_format;
+        }
+        Ok(msg)
+    }
+}
+
 impl From<Message> for TransferBody {
     fn from(msg: Message) -> Self {
         Self::Message(Box::new(msg))
@@ -0,0 +1,265 @@
+use std::{fmt, sync::Arc};
+
+use bytes::{BufMut, BytesMut};
+
+use crate::codec::{self, decode_frame_header, Decode, Encode};
+use crate::error::AmqpParseError;
+use crate::framing::{self, AmqpFrame, SaslFrame};
+use crate::protocol::{Frame, ProtocolVersion};
+use crate::types::Descriptor;
+
+/// Alternate encoding of a single performative.
+///
+/// Used by protocol versions that differ from amqp 1.0.0 in layout
+/// of some performatives. Input of `decode()` starts with performative
+/// descriptor, `encode()` must write exactly `encoded_size()` bytes.
+pub trait PerformativeCodec: Send + Sync {
+    fn decode<'a>(&self, input: &'a [u8]) -> Result<(&'a [u8], Frame), AmqpParseError>;
+
+    fn encoded_size(&self, frame: &Frame) -> usize;
+
+    fn encode(&self, frame: &Frame, buf: &mut BytesMut);
+}
+
+/// Performative codecs of one protocol version, keyed by descriptor code
+#[derive(Clone, Default)]
+pub struct FrameTable {
+    codecs: Vec<(u64, Arc<dyn PerformativeCodec>)>,
+}
+
+impl fmt::Debug for FrameTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.codecs.iter().map(|(code, _)| code))
+            .finish()
+    }
+}
+
+impl FrameTable {
+    /// Alternate codec of performative with descriptor code
+    pub fn get(&self, code: u64) -> Option<&dyn PerformativeCodec> {
+        self.codecs
+            .iter()
+            .find(|(c, _)| *c == code)
+            .map(|(_, codec)| codec.as_ref())
+    }
+
+    fn insert(&mut self, code: u64, codec: Arc<dyn PerformativeCodec>) {
+        self.codecs.retain(|(c, _)| *c != code);
+        self.codecs.push((code, codec));
+    }
+
+    /// Alternate codec for encoded performative
+    fn for_input(&self, input: &[u8]) -> Option<&dyn PerformativeCodec> {
+        if input.first() != Some(&codec::FORMATCODE_DESCRIBED) {
+            return None;
+        }
+        let (_, descriptor) = Descriptor::decode(&input[1..]).ok()?;
+        self.get(descriptor.code()?)
+    }
+
+    /// Alternate codec for performative
+    fn for_frame(&self, frame: &Frame) -> Option<&dyn PerformativeCodec> {
+        let code = match frame {
+            Frame::Open(_) => 0x10,
+            Frame::Begin(_) => 0x11,
+            Frame::Attach(_) => 0x12,
+            Frame::Flow(_) => 0x13,
+            Frame::Transfer(_) => 0x14,
+            Frame::Disposition(_) => 0x15,
+            Frame::Detach(_) => 0x16,
+            Frame::End(_) => 0x17,
+            Frame::Close(_) => 0x18,
+            Frame::Empty => return None,
+        };
+        self.get(code)
+    }
+}
+
+/// Registry of frame tables, keyed by protocol version
+///
+/// Versions without registered codecs use standard encoding.
+#[derive(Clone, Debug, Default)]
+pub struct FrameTables {
+    tables: Vec<(ProtocolVersion, Arc<FrameTable>)>,
+}
+
+impl FrameTables {
+    /// Register alternate codec of performative for protocol version
+    pub fn register<C>(&mut self, version: ProtocolVersion, code: u64, codec: C)
+    where
+        C: PerformativeCodec + 'static,
+    {
+        let codec = Arc::new(codec);
+        if let Some((_, table)) = self.tables.iter_mut().find(|(v, _)| *v == version) {
+            Arc::make_mut(table).insert(code, codec);
+        } else {
+            let mut table = FrameTable::default();
+            table.insert(code, codec);
+            self.tables.push((version, Arc::new(table)));
+        }
+    }
+
+    /// Frame table of protocol version, `None` if version uses standard encoding
+    pub fn get(&self, version: ProtocolVersion) -> Option<Arc<FrameTable>> {
+        self.tables
+            .iter()
+            .find(|(v, _)| *v == version)
+            .map(|(_, table)| table.clone())
+    }
+}
+
+/// Frame that could be encoded with alternate frame table
+pub trait TableCodec: Decode + Encode {
+    fn decode_with<'a>(
+        input: &'a [u8],
+        _table: &FrameTable,
+    ) -> Result<(&'a [u8], Self), AmqpParseError> {
+        Self::decode(input)
+    }
+
+    fn encoded_size_with(&self, _table: &FrameTable) -> usize {
+        self.encoded_size()
+    }
+
+    fn encode_with(&self, _table: &FrameTable, buf: &mut BytesMut) {
+        self.encode(buf)
+    }
+}
+
+impl TableCodec for SaslFrame {}
+
+impl TableCodec for AmqpFrame {
+    fn decode_with<'a>(
+        input: &'a [u8],
+        table: &FrameTable,
+    ) -> Result<(&'a [u8], Self), AmqpParseError> {
+        let (input, channel_id) = decode_frame_header(input, framing::FRAME_TYPE_AMQP)?;
+        let (input, performative) = match table.for_input(input) {
+            Some(codec) => codec.decode(input)?,
+            None => Frame::decode(input)?,
+        };
+        Ok((input, AmqpFrame::new(channel_id, performative)))
+    }
+
+    fn encoded_size_with(&self, table: &FrameTable) -> usize {
+        match table.for_frame(self.performative()) {
+            Some(codec) => framing::HEADER_LEN + codec.encoded_size(self.performative()),
+            None => self.encoded_size(),
+        }
+    }
+
+    fn encode_with(&self, table: &FrameTable, buf: &mut BytesMut) {
+        if let Some(codec) = table.for_frame(self.performative()) {
+            buf.put_u32(self.encoded_size_with(table) as u32);
+            buf.put_u8((framing::HEADER_LEN / 4) as u8);
+            buf.put_u8(framing::FRAME_TYPE_AMQP);
+            buf.put_u16(self.channel_id());
+            codec.encode(self.performative(), buf);
+        } else {
+            self.encode(buf)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use ntex_codec::{Decoder, Encoder};
+
+    use super::*;
+    use crate::io::AmqpCodec;
+    use crate::protocol::Begin;
+
+    /// Dialect with swapped order of session windows
+    struct SwappedBegin(Arc<AtomicUsize>);
+
+    fn swap(frame: &Frame) -> Frame {
+        match frame {
+            Frame::Begin(begin) => {
+                let mut begin = begin.clone();
+                std::mem::swap(&mut begin.incoming_window, &mut begin.outgoing_window);
+                Frame::Begin(begin)
+            }
+            frame => frame.clone(),
+        }
+    }
+
+    impl PerformativeCodec for SwappedBegin {
+        fn decode<'a>(&self, input: &'a [u8]) -> Result<(&'a [u8], Frame), AmqpParseError> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            let (input, frame) = Frame::decode(input)?;
+            Ok((input, swap(&frame)))
+        }
+
+        fn encoded_size(&self, frame: &Frame) -> usize {
+            swap(frame).encoded_size()
+        }
+
+        fn encode(&self, frame: &Frame, buf: &mut BytesMut) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            swap(frame).encode(buf)
+        }
+    }
+
+    fn begin() -> AmqpFrame {
+        AmqpFrame::new(
+            1,
+            Frame::Begin(Begin {
+                remote_channel: None,
+                next_outgoing_id: 1,
+                incoming_window: 10,
+                outgoing_window: 20,
+                handle_max: u32::MAX,
+                offered_capabilities: None,
+                desired_capabilities: None,
+                properties: None,
+            }),
+        )
+    }
+
+    #[test]
+    fn test_frame_table() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let version = ProtocolVersion::new(1, 0, 1);
+        let mut tables = FrameTables::default();
+        tables.register(version, 0x11, SwappedBegin(calls.clone()));
+        assert!(tables.get(ProtocolVersion::V1_0_0).is_none());
+
+        let table = tables.get(version).unwrap();
+        assert!(table.get(0x11).is_some());
+        assert!(table.get(0x10).is_none());
+
+        let dialect = AmqpCodec::<AmqpFrame>::new().frame_table(Some(table));
+        let standard = AmqpCodec::<AmqpFrame>::new();
+
+        // dialect encoding differs from standard one
+        let mut buf = BytesMut::new();
+        dialect.encode(begin(), &mut buf).unwrap();
+        let mut std_buf = BytesMut::new();
+        standard.encode(begin(), &mut std_buf).unwrap();
+        assert_ne!(buf, std_buf);
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+
+        let frame = standard.decode(&mut buf.clone()).unwrap().unwrap();
+        match frame.performative() {
+            Frame::Begin(begin) => {
+                assert_eq!((begin.incoming_window, begin.outgoing_window), (20, 10))
+            }
+            frame => panic!("unexpected frame: {:?}", frame),
+        }
+
+        // roundtrip with same table
+        let frame = dialect.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(frame, begin());
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+
+        // performatives without alternate codec use standard encoding
+        let close = AmqpFrame::new(0, Frame::Close(crate::protocol::Close { error: None }));
+        let mut buf = BytesMut::new();
+        dialect.encode(close.clone(), &mut buf).unwrap();
+        assert_eq!(standard.decode(&mut buf).unwrap().unwrap(), close);
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+    }
+}
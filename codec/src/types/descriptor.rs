@@ -0,0 +1,176 @@
+use super::Descriptor;
+
+/// Well-known descriptors, numeric code and symbolic name
+///
+/// Decoder keeps descriptor of `Variant::Described` as it was sent, symbolic
+/// and numeric forms of a descriptor are not normalized and such values
+/// compare unequal. Use `Descriptor::code()`, `Descriptor::name()` or
+/// `Descriptor::is_same()` to recognize the type of described value.
+/// Performatives, outcomes and message sections are decoded by generated
+/// definitions, the registry is not consulted there.
+pub static WELL_KNOWN_DESCRIPTORS: &[(u64, &str)] = &[
+    // performatives, #2.7
+    (0x0000_0000_0000_0010, "amqp:open:list"),
+    (0x0000_0000_0000_0011, "amqp:begin:list"),
+    (0x0000_0000_0000_0012, "amqp:attach:list"),
+    (0x0000_0000_0000_0013, "amqp:flow:list"),
+    (0x0000_0000_0000_0014, "amqp:transfer:list"),
+    (0x0000_0000_0000_0015, "amqp:disposition:list"),
+    (0x0000_0000_0000_0016, "amqp:detach:list"),
+    (0x0000_0000_0000_0017, "amqp:end:list"),
+    (0x0000_0000_0000_0018, "amqp:close:list"),
+    (0x0000_0000_0000_001d, "amqp:error:list"),
+    // delivery states and outcomes, #3.4
+    (0x0000_0000_0000_0023, "amqp:received:list"),
+    (0x0000_0000_0000_0024, "amqp:accepted:list"),
+    (0x0000_0000_0000_0025, "amqp:rejected:list"),
+    (0x0000_0000_0000_0026, "amqp:released:list"),
+    (0x0000_0000_0000_0027, "amqp:modified:list"),
+    // terminus, #3.5
+    (0x0000_0000_0000_0028, "amqp:source:list"),
+    (0x0000_0000_0000_0029, "amqp:target:list"),
+    (0x0000_0000_0000_002b, "amqp:delete-on-close:list"),
+    (0x0000_0000_0000_002c, "amqp:delete-on-no-links:list"),
+    (0x0000_0000_0000_002d, "amqp:delete-on-no-messages:list"),
+    (
+        0x0000_0000_0000_002e,
+        "amqp:delete-on-no-links-or-messages:list",
+    ),
+    // transactions, #4.5
+    (0x0000_0000_0000_0030, "amqp:coordinator:list"),
+    (0x0000_0000_0000_0031, "amqp:declare:list"),
+    (0x0000_0000_0000_0032, "amqp:discharge:list"),
+    (0x0000_0000_0000_0033, "amqp:declared:list"),
+    (0x0000_0000_0000_0034, "amqp:transactional-state:list"),
+    // sasl, #5.3
+    (0x0000_0000_0000_0040, "amqp:sasl-mechanisms:list"),
+    (0x0000_0000_0000_0041, "amqp:sasl-init:list"),
+    (0x0000_0000_0000_0042, "amqp:sasl-challenge:list"),
+    (0x0000_0000_0000_0043, "amqp:sasl-response:list"),
+    (0x0000_0000_0000_0044, "amqp:sasl-outcome:list"),
+    // message sections, #3.2
+    (0x0000_0000_0000_0070, "amqp:header:list"),
+    (0x0000_0000_0000_0071, "amqp:delivery-annotations:map"),
+    (0x0000_0000_0000_0072, "amqp:message-annotations:map"),
+    (0x0000_0000_0000_0073, "amqp:properties:list"),
+    (0x0000_0000_0000_0074, "amqp:application-properties:map"),
+    (0x0000_0000_0000_0075, "amqp:data:binary"),
+    (0x0000_0000_0000_0076, "amqp:amqp-sequence:list"),
+    (0x0000_0000_0000_0077, "amqp:amqp-value:*"),
+    (0x0000_0000_0000_0078, "amqp:footer:map"),
+    // filters, amqp filter registry
+    (
+        0x0000_468c_0000_0000,
+        "apache.org:legacy-amqp-direct-binding:string",
+    ),
+    (
+        0x0000_468c_0000_0001,
+        "apache.org:legacy-amqp-topic-binding:string",
+    ),
+    (
+        0x0000_468c_0000_0002,
+        "apache.org:legacy-amqp-headers-binding:map",
+    ),
+    (0x0000_468c_0000_0003, "apache.org:no-local-filter:list"),
+    (0x0000_468c_0000_0004, "apache.org:selector-filter:string"),
+];
+
+/// Numeric code of well-known descriptor name
+pub fn descriptor_code(name: &str) -> Option<u64> {
+    WELL_KNOWN_DESCRIPTORS
+        .iter()
+        .find(|(_, n)| *n == name)
+        .map(|(code, _)| *code)
+}
+
+/// Symbolic name of well-known descriptor code
+pub fn descriptor_name(code: u64) -> Option<&'static str> {
+    WELL_KNOWN_DESCRIPTORS
+        .iter()
+        .find(|(c, _)| *c == code)
+        .map(|(_, name)| *name)
+}
+
+impl Descriptor {
+    /// Numeric code, symbolic descriptors are resolved with well-known registry
+    pub fn code(&self) -> Option<u64> {
+        match self {
+            Descriptor::Ulong(code) => Some(*code),
+            Descriptor::Symbol(name) => descriptor_code(name.as_str()),
+        }
+    }
+
+    /// Symbolic name, numeric descriptors are resolved with well-known registry
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            Descriptor::Ulong(code) => descriptor_name(*code),
+            Descriptor::Symbol(name) => Some(name.as_str()),
+        }
+    }
+
+    /// Check if descriptors identify same type, regardless of representation
+    pub fn is_same(&self, other: &Descriptor) -> bool {
+        match (self, other) {
+            (Descriptor::Ulong(a), Descriptor::Ulong(b)) => a == b,
+            (Descriptor::Symbol(a), Descriptor::Symbol(b)) => a == b,
+            _ => self.code().is_some() && self.code() == other.code(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::{Decode, Encode};
+    use crate::types::{List, Symbol, Variant};
+
+    #[test]
+    fn resolve_descriptors() {
+        assert_eq!(descriptor_code("amqp:accepted:list"), Some(0x24));
+        assert_eq!(descriptor_name(0x24), Some("amqp:accepted:list"));
+        assert_eq!(
+            descriptor_code("apache.org:selector-filter:string"),
+            Some(0x0000_468c_0000_0004)
+        );
+        assert_eq!(descriptor_code("amqp:unknown:list"), None);
+        assert_eq!(descriptor_name(0x1000), None);
+
+        let sym = Descriptor::Symbol(Symbol::from_static("amqp:transactional-state:list"));
+        let code = Descriptor::Ulong(0x34);
+        assert_eq!(sym.code(), Some(0x34));
+        assert_eq!(code.name(), Some("amqp:transactional-state:list"));
+        assert!(sym.is_same(&code));
+        assert!(code.is_same(&sym));
+        assert!(!code.is_same(&Descriptor::Ulong(0x33)));
+
+        // unknown descriptors keep their own representation
+        let custom = Descriptor::Symbol(Symbol::from_static("com.example:custom"));
+        assert_eq!(custom.code(), None);
+        assert_eq!(custom.name(), Some("com.example:custom"));
+        assert!(!custom.is_same(&Descriptor::Ulong(0x1000)));
+
+        // decoded described values keep descriptor representation
+        let mut buf = bytes::BytesMut::new();
+        Variant::Described((sym.clone(), Box::new(Variant::List(List(Vec::new())))))
+            .encode(&mut buf);
+        let decoded = Variant::decode(&buf).unwrap().1;
+        match decoded {
+            Variant::Described((ref descriptor, _)) => {
+                assert_eq!(descriptor, &sym);
+                assert_eq!(descriptor.code(), Some(0x34));
+                assert!(descriptor.is_same(&code));
+            }
+            _ => panic!("unexpected value: {:?}", decoded),
+        }
+        assert_ne!(
+            decoded,
+            Variant::Described((code.clone(), Box::new(Variant::List(List(Vec::new())))))
+        );
+
+        // registry is consistent in both directions
+        for (code, name) in WELL_KNOWN_DESCRIPTORS {
+            assert_eq!(descriptor_name(*code), Some(*name));
+            assert_eq!(descriptor_code(name), Some(*code));
+        }
+    }
+}
@@ -2,11 +2,17 @@ use std::{borrow, fmt, hash, ops, str};
 
 use bytestring::ByteString;
 
+mod descriptor;
+#[cfg(feature = "serde")]
+pub mod serde;
 mod symbol;
 mod variant;
 
+pub use self::descriptor::{descriptor_code, descriptor_name, WELL_KNOWN_DESCRIPTORS};
 pub use self::symbol::{StaticSymbol, Symbol};
-pub use self::variant::{Variant, VariantMap, VecStringMap, VecSymbolMap};
+pub use self::variant::{
+    Variant, VariantArray, VariantMap, VariantMapBuilder, VecStringMap, VecSymbolMap,
+};
 
 #[derive(Debug, PartialEq, Eq, Clone, Hash, Display)]
 pub enum Descriptor {
@@ -51,6 +57,37 @@ impl<T> ops::DerefMut for Multiple<T> {
     }
 }
 
+/// 32-bit decimal number (IEEE 754-2008 decimal32), raw encoded bytes
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, From)]
+pub struct Decimal32(pub [u8; 4]);
+
+/// 64-bit decimal number (IEEE 754-2008 decimal64), raw encoded bytes
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, From)]
+pub struct Decimal64(pub [u8; 8]);
+
+/// 128-bit decimal number (IEEE 754-2008 decimal128), raw encoded bytes
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, From)]
+pub struct Decimal128(pub [u8; 16]);
+
+macro_rules! decimal_display {
+    ($name:ident) => {
+        /// Encoded bytes in hex, value is not decoded
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("0x")?;
+                for b in self.0.iter() {
+                    write!(f, "{:02x}", b)?;
+                }
+                Ok(())
+            }
+        }
+    };
+}
+
+decimal_display!(Decimal32);
+decimal_display!(Decimal64);
+decimal_display!(Decimal128);
+
 #[derive(Debug, PartialEq, Eq, Clone, Hash)]
 pub struct List(pub Vec<Variant>);
 
@@ -0,0 +1,529 @@
+//! Serde support for AMQP values.
+//!
+//! `Variant` is represented as an externally tagged enum named after
+//! the AMQP type, i.e. `{"uint": 10}` or `{"symbol": "amqp:link"}`, so
+//! values round-trip without losing their AMQP type. `Null` is a unit
+//! variant. Maps are sequences of `[key, value]` pairs because keys could
+//! be any AMQP value. `StaticSymbol` is serialized as `symbol` and comes
+//! back as `Symbol`.
+//!
+//! Binary data (`binary` and decimals) is base64 encoded for human-readable
+//! formats and raw bytes for compact ones, uuids are strings or 16 bytes.
+//! Timestamps are milliseconds since unix epoch.
+//!
+//! `to_variant` and `from_variant` convert plain Rust types to and from
+//! `Variant`, i.e. to build application properties from a struct.
+use std::{convert::TryFrom, fmt, marker::PhantomData};
+
+use bytes::Bytes;
+use chrono::{TimeZone, Utc};
+use ordered_float::OrderedFloat;
+use serde::de::{self, Deserializer, EnumAccess, MapAccess, SeqAccess, VariantAccess, Visitor};
+use serde::ser::{SerializeMap, SerializeSeq, Serializer};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::{
+    Decimal128, Decimal32, Decimal64, Descriptor, List, Str, Symbol, Variant, VariantMap,
+    VecStringMap, VecSymbolMap,
+};
+use crate::HashMap;
+
+mod value;
+
+pub use self::value::{as_binary, as_timestamp, as_uuid, from_variant, to_variant, Error};
+
+const VARIANTS: &[&str] = &[
+    "null",
+    "boolean",
+    "ubyte",
+    "ushort",
+    "uint",
+    "ulong",
+    "byte",
+    "short",
+    "int",
+    "long",
+    "float",
+    "double",
+    "decimal32",
+    "decimal64",
+    "decimal128",
+    "char",
+    "timestamp",
+    "uuid",
+    "binary",
+    "string",
+    "symbol",
+    "list",
+    "map",
+    "array",
+    "described",
+];
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Kind {
+    Null,
+    Boolean,
+    Ubyte,
+    Ushort,
+    Uint,
+    Ulong,
+    Byte,
+    Short,
+    Int,
+    Long,
+    Float,
+    Double,
+    Decimal32,
+    Decimal64,
+    Decimal128,
+    Char,
+    Timestamp,
+    Uuid,
+    Binary,
+    String,
+    Symbol,
+    List,
+    Map,
+    Array,
+    Described,
+}
+
+impl Serialize for Variant {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        macro_rules! tagged {
+            ($idx:expr, $value:expr) => {
+                serializer.serialize_newtype_variant("Variant", $idx, VARIANTS[$idx], $value)
+            };
+        }
+
+        match self {
+            Variant::Null => serializer.serialize_unit_variant("Variant", 0, VARIANTS[0]),
+            Variant::Boolean(v) => tagged!(1, v),
+            Variant::Ubyte(v) => tagged!(2, v),
+            Variant::Ushort(v) => tagged!(3, v),
+            Variant::Uint(v) => tagged!(4, v),
+            Variant::Ulong(v) => tagged!(5, v),
+            Variant::Byte(v) => tagged!(6, v),
+            Variant::Short(v) => tagged!(7, v),
+            Variant::Int(v) => tagged!(8, v),
+            Variant::Long(v) => tagged!(9, v),
+            Variant::Float(v) => tagged!(10, &v.into_inner()),
+            Variant::Double(v) => tagged!(11, &v.into_inner()),
+            Variant::Decimal32(v) => tagged!(12, &BinaryRef(&v.0)),
+            Variant::Decimal64(v) => tagged!(13, &BinaryRef(&v.0)),
+            Variant::Decimal128(v) => tagged!(14, &BinaryRef(&v.0)),
+            Variant::Char(v) => tagged!(15, v),
+            Variant::Timestamp(v) => tagged!(16, &v.timestamp_millis()),
+            Variant::Uuid(v) => tagged!(17, &UuidRepr(*v)),
+            Variant::Binary(v) => tagged!(18, &BinaryRef(v)),
+            Variant::String(v) => tagged!(19, v.as_str()),
+            Variant::Symbol(v) => tagged!(20, v.as_str()),
+            Variant::StaticSymbol(v) => tagged!(20, v.0),
+            Variant::List(v) => tagged!(21, &v.0),
+            Variant::Map(v) => tagged!(22, v),
+            Variant::Array(v) => tagged!(23, &**v),
+            Variant::Described((descriptor, value)) => {
+                tagged!(24, &(DescriptorRepr::from(descriptor), value))
+            }
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Variant {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_enum("Variant", VARIANTS, VariantVisitor)
+    }
+}
+
+struct VariantVisitor;
+
+impl<'de> Visitor<'de> for VariantVisitor {
+    type Value = Variant;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("AMQP value")
+    }
+
+    fn visit_enum<A: EnumAccess<'de>>(self, data: A) -> Result<Variant, A::Error> {
+        let (kind, value) = data.variant::<Kind>()?;
+        Ok(match kind {
+            Kind::Null => {
+                value.unit_variant()?;
+                Variant::Null
+            }
+            Kind::Boolean => Variant::Boolean(value.newtype_variant()?),
+            Kind::Ubyte => Variant::Ubyte(value.newtype_variant()?),
+            Kind::Ushort => Variant::Ushort(value.newtype_variant()?),
+            Kind::Uint => Variant::Uint(value.newtype_variant()?),
+            Kind::Ulong => Variant::Ulong(value.newtype_variant()?),
+            Kind::Byte => Variant::Byte(value.newtype_variant()?),
+            Kind::Short => Variant::Short(value.newtype_variant()?),
+            Kind::Int => Variant::Int(value.newtype_variant()?),
+            Kind::Long => Variant::Long(value.newtype_variant()?),
+            Kind::Float => Variant::Float(OrderedFloat(value.newtype_variant()?)),
+            Kind::Double => Variant::Double(OrderedFloat(value.newtype_variant()?)),
+            Kind::Decimal32 => Variant::Decimal32(Decimal32(fixed(value.newtype_variant()?)?)),
+            Kind::Decimal64 => Variant::Decimal64(Decimal64(fixed(value.newtype_variant()?)?)),
+            Kind::Decimal128 => Variant::Decimal128(Decimal128(fixed(value.newtype_variant()?)?)),
+            Kind::Char => Variant::Char(value.newtype_variant()?),
+            Kind::Timestamp => {
+                let millis: i64 = value.newtype_variant()?;
+                let ts = Utc.timestamp_millis_opt(millis).single().ok_or_else(|| {
+                    de::Error::invalid_value(de::Unexpected::Signed(millis), &"timestamp")
+                })?;
+                Variant::Timestamp(ts)
+            }
+            Kind::Uuid => Variant::Uuid(value.newtype_variant::<UuidRepr>()?.0),
+            Kind::Binary => Variant::Binary(value.newtype_variant::<Binary>()?.0),
+            Kind::String => Variant::String(Str::String(value.newtype_variant()?)),
+            Kind::Symbol => Variant::Symbol(Symbol(Str::String(value.newtype_variant()?))),
+            Kind::List => Variant::List(List(value.newtype_variant()?)),
+            Kind::Map => Variant::Map(value.newtype_variant()?),
+            Kind::Array => Variant::from(value.newtype_variant::<Vec<Variant>>()?),
+            Kind::Described => {
+                let (descriptor, value): (DescriptorRepr, Variant) = value.newtype_variant()?;
+                Variant::Described((descriptor.into(), Box::new(value)))
+            }
+        })
+    }
+}
+
+fn fixed<T, E>(data: Binary) -> Result<T, E>
+where
+    T: for<'a> TryFrom<&'a [u8]>,
+    E: de::Error,
+{
+    T::try_from(&data.0[..]).map_err(|_| E::invalid_length(data.0.len(), &"decimal"))
+}
+
+impl Serialize for VariantMap {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.map.len()))?;
+        for entry in self.map.iter() {
+            seq.serialize_element(&entry)?;
+        }
+        seq.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for VariantMap {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct MapVisitor;
+
+        impl<'de> Visitor<'de> for MapVisitor {
+            type Value = VariantMap;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("sequence of key-value pairs")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<VariantMap, A::Error> {
+                let mut map = HashMap::with_capacity_and_hasher(
+                    seq.size_hint().unwrap_or(0),
+                    Default::default(),
+                );
+                while let Some((key, value)) = seq.next_element::<(Variant, Variant)>()? {
+                    map.insert(key, value);
+                }
+                Ok(VariantMap::new(map))
+            }
+        }
+
+        deserializer.deserialize_seq(MapVisitor)
+    }
+}
+
+impl Serialize for VecSymbolMap {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_vec_map(
+            self.iter().map(|(k, v)| (k.as_str(), v)),
+            self.len(),
+            serializer,
+        )
+    }
+}
+
+impl<'de> Deserialize<'de> for VecSymbolMap {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer
+            .deserialize_map(VecMapVisitor(PhantomData))
+            .map(VecSymbolMap)
+    }
+}
+
+impl Serialize for VecStringMap {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_vec_map(
+            self.iter().map(|(k, v)| (k.as_str(), v)),
+            self.len(),
+            serializer,
+        )
+    }
+}
+
+impl<'de> Deserialize<'de> for VecStringMap {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer
+            .deserialize_map(VecMapVisitor(PhantomData))
+            .map(VecStringMap)
+    }
+}
+
+/// Keys of vec maps are strings, entries keep their order
+fn serialize_vec_map<'a, I, S>(entries: I, len: usize, serializer: S) -> Result<S::Ok, S::Error>
+where
+    I: Iterator<Item = (&'a str, &'a Variant)>,
+    S: Serializer,
+{
+    let mut map = serializer.serialize_map(Some(len))?;
+    for (key, value) in entries {
+        map.serialize_entry(key, value)?;
+    }
+    map.end()
+}
+
+struct VecMapVisitor<K>(PhantomData<K>);
+
+impl<'de, K: From<String>> Visitor<'de> for VecMapVisitor<K> {
+    type Value = Vec<(K, Variant)>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("map with string keys")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut entries = Vec::with_capacity(map.size_hint().unwrap_or(8));
+        while let Some((key, value)) = map.next_entry::<String, Variant>()? {
+            entries.push((K::from(key), value));
+        }
+        Ok(entries)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum DescriptorRepr {
+    Ulong(u64),
+    Symbol(String),
+}
+
+impl<'a> From<&'a Descriptor> for DescriptorRepr {
+    fn from(d: &'a Descriptor) -> Self {
+        match d {
+            Descriptor::Ulong(code) => DescriptorRepr::Ulong(*code),
+            Descriptor::Symbol(name) => DescriptorRepr::Symbol(name.as_str().to_string()),
+        }
+    }
+}
+
+impl From<DescriptorRepr> for Descriptor {
+    fn from(d: DescriptorRepr) -> Self {
+        match d {
+            DescriptorRepr::Ulong(code) => Descriptor::Ulong(code),
+            DescriptorRepr::Symbol(name) => Descriptor::Symbol(Symbol::from(name)),
+        }
+    }
+}
+
+struct BinaryRef<'a>(&'a [u8]);
+
+impl<'a> Serialize for BinaryRef<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&base64::encode(self.0))
+        } else {
+            serializer.serialize_bytes(self.0)
+        }
+    }
+}
+
+struct Binary(Bytes);
+
+impl<'de> Deserialize<'de> for Binary {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct BinaryVisitor;
+
+        impl<'de> Visitor<'de> for BinaryVisitor {
+            type Value = Binary;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("bytes or base64 string")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Binary, E> {
+                base64::decode(v)
+                    .map(|data| Binary(Bytes::from(data)))
+                    .map_err(|_| E::invalid_value(de::Unexpected::Str(v), &self))
+            }
+
+            fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Binary, E> {
+                Ok(Binary(Bytes::copy_from_slice(v)))
+            }
+
+            fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<Binary, E> {
+                Ok(Binary(Bytes::from(v)))
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Binary, A::Error> {
+                let mut data = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+                while let Some(b) = seq.next_element()? {
+                    data.push(b);
+                }
+                Ok(Binary(Bytes::from(data)))
+            }
+        }
+
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_str(BinaryVisitor)
+        } else {
+            deserializer.deserialize_byte_buf(BinaryVisitor)
+        }
+    }
+}
+
+struct UuidRepr(Uuid);
+
+impl Serialize for UuidRepr {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&self.0.to_hyphenated().to_string())
+        } else {
+            serializer.serialize_bytes(self.0.as_bytes())
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for UuidRepr {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            let s = String::deserialize(deserializer)?;
+            Uuid::parse_str(&s)
+                .map(UuidRepr)
+                .map_err(|_| de::Error::invalid_value(de::Unexpected::Str(&s), &"uuid"))
+        } else {
+            let data = Binary::deserialize(deserializer)?;
+            Uuid::from_slice(&data.0)
+                .map(UuidRepr)
+                .map_err(|_| de::Error::invalid_length(data.0.len(), &"16 bytes"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nested() -> Variant {
+        let mut inner = HashMap::default();
+        inner.insert(Variant::Uint(1), Variant::Int(1));
+        inner.insert(Variant::Symbol(Symbol::from("sym")), Variant::from("str"));
+        inner.insert(
+            Variant::from("bin"),
+            Variant::Binary(Bytes::from_static(b"\x00\x01\xfe\xff")),
+        );
+
+        let mut map = HashMap::default();
+        map.insert(Variant::from("inner"), Variant::Map(VariantMap::new(inner)));
+        map.insert(Variant::Null, Variant::Ulong(u64::MAX));
+        map.insert(
+            Variant::Long(-1),
+            Variant::List(List(vec![
+                Variant::Boolean(true),
+                Variant::Ubyte(1),
+                Variant::Ushort(2),
+                Variant::Byte(-1),
+                Variant::Short(-2),
+                Variant::Float(OrderedFloat(1.5)),
+                Variant::Double(OrderedFloat(-0.25)),
+                Variant::Char('ü'),
+                Variant::Timestamp(Utc.timestamp_millis(1_311_704_463_521)),
+                Variant::Uuid(Uuid::new_v4()),
+                Variant::Decimal32(Decimal32([1, 2, 3, 4])),
+                Variant::Decimal64(Decimal64([1; 8])),
+                Variant::Decimal128(Decimal128([2; 16])),
+            ])),
+        );
+        map.insert(
+            Variant::from("array"),
+            Variant::from(vec![Variant::Int(1), Variant::Int(2)]),
+        );
+        map.insert(
+            Variant::from("described"),
+            Variant::Described((
+                Descriptor::Symbol(Symbol::from("amqp:test")),
+                Box::new(Variant::Described((
+                    Descriptor::Ulong(0x70),
+                    Box::new(Variant::Null),
+                ))),
+            )),
+        );
+        Variant::Map(VariantMap::new(map))
+    }
+
+    #[test]
+    fn test_json_roundtrip() {
+        let value = nested();
+        let json = serde_json::to_string(&value).unwrap();
+        let decoded: Variant = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, value);
+    }
+
+    #[test]
+    fn test_json_tags() {
+        let json = |v: &Variant| serde_json::to_string(v).unwrap();
+        assert_eq!(json(&Variant::Null), r#""null""#);
+        assert_eq!(json(&Variant::Uint(5)), r#"{"uint":5}"#);
+        assert_eq!(json(&Variant::Int(5)), r#"{"int":5}"#);
+        assert_eq!(json(&Variant::from("a")), r#"{"string":"a"}"#);
+        assert_eq!(
+            json(&Variant::Symbol(Symbol::from("a"))),
+            r#"{"symbol":"a"}"#
+        );
+        assert_eq!(
+            json(&Variant::Binary(Bytes::from_static(b"hello"))),
+            r#"{"binary":"aGVsbG8="}"#
+        );
+
+        let decoded: Variant = serde_json::from_str(r#"{"symbol":"a"}"#).unwrap();
+        assert_eq!(decoded, Variant::Symbol(Symbol::from("a")));
+        let decoded: Variant = serde_json::from_str(r#"{"uint":5}"#).unwrap();
+        assert_eq!(decoded, Variant::Uint(5));
+        assert!(serde_json::from_str::<Variant>(r#"{"uint":-5}"#).is_err());
+        assert!(serde_json::from_str::<Variant>(r#"{"binary":"a"}"#).is_err());
+        assert!(serde_json::from_str::<Variant>(r#"{"decimal32":"AQID"}"#).is_err());
+    }
+
+    #[test]
+    fn test_vec_maps() {
+        let map = VecSymbolMap(vec![
+            (Symbol::from("b"), Variant::Uint(1)),
+            (Symbol::from("a"), Variant::from("x")),
+        ]);
+        let json = serde_json::to_string(&map).unwrap();
+        assert_eq!(json, r#"{"b":{"uint":1},"a":{"string":"x"}}"#);
+        assert_eq!(serde_json::from_str::<VecSymbolMap>(&json).unwrap(), map);
+
+        let map = VecStringMap(vec![(Str::from("k"), nested())]);
+        let json = serde_json::to_string(&map).unwrap();
+        assert_eq!(serde_json::from_str::<VecStringMap>(&json).unwrap(), map);
+    }
+
+    #[test]
+    fn test_base64() {
+        for data in [&b""[..], b"f", b"fo", b"foo", b"foob", b"fooba", b"foobar"].iter() {
+            let value = Variant::Binary(Bytes::from_static(data));
+            let json = serde_json::to_string(&value).unwrap();
+            assert_eq!(serde_json::from_str::<Variant>(&json).unwrap(), value);
+        }
+        let value = Variant::Binary(Bytes::from_static(b"foob"));
+        assert_eq!(
+            serde_json::to_string(&value).unwrap(),
+            r#"{"binary":"Zm9vYg=="}"#
+        );
+        assert!(serde_json::from_str::<Variant>(r#"{"binary":"Zm9v=mFy"}"#).is_err());
+        assert!(serde_json::from_str::<Variant>(r#"{"binary":"Zm9"}"#).is_err());
+    }
+}
@@ -0,0 +1,907 @@
+//! Conversion of serde data model to and from `Variant`
+use std::{collections::hash_map, convert::TryFrom, fmt};
+
+use bytes::Bytes;
+use chrono::{DateTime, TimeZone, Utc};
+use ordered_float::OrderedFloat;
+use serde::de::{self, DeserializeOwned, DeserializeSeed, IntoDeserializer, Unexpected, Visitor};
+use serde::ser::{self, Serialize};
+use uuid::Uuid;
+
+use crate::types::{List, Str, Variant, VariantMap};
+use crate::HashMap;
+
+const TIMESTAMP_TOKEN: &str = "$amqp::timestamp";
+const UUID_TOKEN: &str = "$amqp::uuid";
+
+/// Error of conversion between `Variant` and serde types
+#[derive(Debug, Display, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// Map keys must serialize to strings
+    #[display(fmt = "Map key must be a string, got: {}", _0)]
+    KeyMustBeString(String),
+    #[display(fmt = "{}", _0)]
+    Custom(String),
+}
+
+impl std::error::Error for Error {}
+
+impl ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error::Custom(msg.to_string())
+    }
+}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error::Custom(msg.to_string())
+    }
+}
+
+/// Convert serializable value to `Variant`.
+///
+/// Structs and maps become maps with string keys, sequences become lists,
+/// integers use the narrowest AMQP integer type that fits the value.
+pub fn to_variant<T: Serialize + ?Sized>(value: &T) -> Result<Variant, Error> {
+    value.serialize(VariantSerializer)
+}
+
+/// Convert `Variant` to deserializable value
+pub fn from_variant<T: DeserializeOwned>(value: &Variant) -> Result<T, Error> {
+    T::deserialize(VariantDeserializer(value))
+}
+
+/// Serialize `DateTime<Utc>` as AMQP timestamp.
+///
+/// Use with `#[serde(with = "as_timestamp")]`, other formats
+/// see milliseconds since unix epoch.
+pub mod as_timestamp {
+    use chrono::{DateTime, Utc};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        value: &DateTime<Utc>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_newtype_struct(super::TIMESTAMP_TOKEN, &value.timestamp_millis())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<DateTime<Utc>, D::Error> {
+        super::timestamp(i64::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Serialize `Uuid` as AMQP uuid.
+///
+/// Use with `#[serde(with = "as_uuid")]`, other formats see hyphenated string.
+pub mod as_uuid {
+    use std::fmt;
+
+    use serde::{de, Deserializer, Serializer};
+    use uuid::Uuid;
+
+    pub fn serialize<S: Serializer>(value: &Uuid, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_newtype_struct(
+            super::UUID_TOKEN,
+            value
+                .to_hyphenated_ref()
+                .encode_lower(&mut Uuid::encode_buffer()),
+        )
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Uuid, D::Error> {
+        deserializer.deserialize_str(UuidVisitor)
+    }
+
+    struct UuidVisitor;
+
+    impl<'de> de::Visitor<'de> for UuidVisitor {
+        type Value = Uuid;
+
+        fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("uuid string or 16 bytes")
+        }
+
+        fn visit_str<E: de::Error>(self, v: &str) -> Result<Uuid, E> {
+            Uuid::parse_str(v).map_err(E::custom)
+        }
+
+        fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Uuid, E> {
+            Uuid::from_slice(v).map_err(E::custom)
+        }
+    }
+}
+
+/// Serialize bytes as AMQP binary instead of list of ubytes.
+///
+/// Use with `#[serde(with = "as_binary")]` for `Vec<u8>` and `Bytes` fields.
+pub mod as_binary {
+    use std::fmt;
+
+    use serde::{de, Deserializer, Serializer};
+
+    pub fn serialize<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: AsRef<[u8]>,
+        S: Serializer,
+    {
+        serializer.serialize_bytes(value.as_ref())
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: From<Vec<u8>>,
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_byte_buf(BytesVisitor).map(T::from)
+    }
+
+    struct BytesVisitor;
+
+    impl<'de> de::Visitor<'de> for BytesVisitor {
+        type Value = Vec<u8>;
+
+        fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("bytes")
+        }
+
+        fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Vec<u8>, E> {
+            Ok(v.to_vec())
+        }
+
+        fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<Vec<u8>, E> {
+            Ok(v)
+        }
+
+        fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<u8>, A::Error> {
+            let mut data = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+            while let Some(b) = seq.next_element()? {
+                data.push(b);
+            }
+            Ok(data)
+        }
+    }
+}
+
+fn timestamp(millis: i64) -> Result<DateTime<Utc>, Error> {
+    Utc.timestamp_millis_opt(millis)
+        .single()
+        .ok_or_else(|| Error::Custom(format!("Invalid timestamp: {}", millis)))
+}
+
+fn string(value: &str) -> Variant {
+    Variant::String(Str::from(value.to_string()))
+}
+
+struct VariantSerializer;
+
+impl ser::Serializer for VariantSerializer {
+    type Ok = Variant;
+    type Error = Error;
+    type SerializeSeq = SeqSerializer;
+    type SerializeTuple = SeqSerializer;
+    type SerializeTupleStruct = SeqSerializer;
+    type SerializeTupleVariant = SeqSerializer;
+    type SerializeMap = MapSerializer;
+    type SerializeStruct = MapSerializer;
+    type SerializeStructVariant = MapSerializer;
+
+    fn serialize_bool(self, v: bool) -> Result<Variant, Error> {
+        Ok(Variant::Boolean(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Variant, Error> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Variant, Error> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Variant, Error> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Variant, Error> {
+        Ok(if let Ok(v) = i8::try_from(v) {
+            Variant::Byte(v)
+        } else if let Ok(v) = i16::try_from(v) {
+            Variant::Short(v)
+        } else if let Ok(v) = i32::try_from(v) {
+            Variant::Int(v)
+        } else {
+            Variant::Long(v)
+        })
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Variant, Error> {
+        self.serialize_u64(v.into())
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Variant, Error> {
+        self.serialize_u64(v.into())
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Variant, Error> {
+        self.serialize_u64(v.into())
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Variant, Error> {
+        Ok(if let Ok(v) = u8::try_from(v) {
+            Variant::Ubyte(v)
+        } else if let Ok(v) = u16::try_from(v) {
+            Variant::Ushort(v)
+        } else if let Ok(v) = u32::try_from(v) {
+            Variant::Uint(v)
+        } else {
+            Variant::Ulong(v)
+        })
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Variant, Error> {
+        Ok(Variant::Float(OrderedFloat(v)))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Variant, Error> {
+        Ok(Variant::Double(OrderedFloat(v)))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Variant, Error> {
+        Ok(Variant::Char(v))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Variant, Error> {
+        Ok(string(v))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Variant, Error> {
+        Ok(Variant::Binary(Bytes::copy_from_slice(v)))
+    }
+
+    fn serialize_none(self) -> Result<Variant, Error> {
+        Ok(Variant::Null)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Variant, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Variant, Error> {
+        Ok(Variant::Null)
+    }
+
+    fn serialize_unit_struct(self, _: &'static str) -> Result<Variant, Error> {
+        Ok(Variant::Null)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        variant: &'static str,
+    ) -> Result<Variant, Error> {
+        Ok(Variant::String(Str::from_static(variant)))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<Variant, Error> {
+        let value = value.serialize(self)?;
+        match (name, value) {
+            (TIMESTAMP_TOKEN, value) => match value {
+                Variant::Byte(v) => timestamp(v.into()),
+                Variant::Short(v) => timestamp(v.into()),
+                Variant::Int(v) => timestamp(v.into()),
+                Variant::Long(v) => timestamp(v),
+                value => Err(Error::Custom(format!("Invalid timestamp: {:?}", value))),
+            }
+            .map(Variant::Timestamp),
+            (UUID_TOKEN, Variant::String(s)) => Uuid::parse_str(s.as_str())
+                .map(Variant::Uuid)
+                .map_err(|e| Error::Custom(e.to_string())),
+            (_, value) => Ok(value),
+        }
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        _: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Variant, Error> {
+        let mut map = HashMap::default();
+        map.insert(
+            Variant::String(Str::from_static(variant)),
+            to_variant(value)?,
+        );
+        Ok(Variant::Map(VariantMap::new(map)))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SeqSerializer, Error> {
+        Ok(SeqSerializer {
+            variant: None,
+            items: Vec::with_capacity(len.unwrap_or(0)),
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SeqSerializer, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _: &'static str, len: usize) -> Result<SeqSerializer, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SeqSerializer, Error> {
+        Ok(SeqSerializer {
+            variant: Some(variant),
+            items: Vec::with_capacity(len),
+        })
+    }
+
+    fn serialize_map(self, _: Option<usize>) -> Result<MapSerializer, Error> {
+        Ok(MapSerializer {
+            variant: None,
+            map: HashMap::default(),
+            key: None,
+        })
+    }
+
+    fn serialize_struct(self, _: &'static str, _: usize) -> Result<MapSerializer, Error> {
+        self.serialize_map(None)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        variant: &'static str,
+        _: usize,
+    ) -> Result<MapSerializer, Error> {
+        Ok(MapSerializer {
+            variant: Some(variant),
+            map: HashMap::default(),
+            key: None,
+        })
+    }
+}
+
+/// Wrap value of enum variant to single entry map
+fn variant_map(variant: Option<&'static str>, value: Variant) -> Variant {
+    match variant {
+        Some(variant) => {
+            let mut map = HashMap::default();
+            map.insert(Variant::String(Str::from_static(variant)), value);
+            Variant::Map(VariantMap::new(map))
+        }
+        None => value,
+    }
+}
+
+struct SeqSerializer {
+    variant: Option<&'static str>,
+    items: Vec<Variant>,
+}
+
+impl SeqSerializer {
+    fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.items.push(to_variant(value)?);
+        Ok(())
+    }
+
+    fn finish(self) -> Variant {
+        variant_map(self.variant, Variant::List(List(self.items)))
+    }
+}
+
+impl ser::SerializeSeq for SeqSerializer {
+    type Ok = Variant;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Variant, Error> {
+        Ok(self.finish())
+    }
+}
+
+impl ser::SerializeTuple for SeqSerializer {
+    type Ok = Variant;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Variant, Error> {
+        Ok(self.finish())
+    }
+}
+
+impl ser::SerializeTupleStruct for SeqSerializer {
+    type Ok = Variant;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Variant, Error> {
+        Ok(self.finish())
+    }
+}
+
+impl ser::SerializeTupleVariant for SeqSerializer {
+    type Ok = Variant;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Variant, Error> {
+        Ok(self.finish())
+    }
+}
+
+struct MapSerializer {
+    variant: Option<&'static str>,
+    map: HashMap<Variant, Variant>,
+    key: Option<Variant>,
+}
+
+impl MapSerializer {
+    fn finish(self) -> Variant {
+        variant_map(self.variant, Variant::Map(VariantMap::new(self.map)))
+    }
+}
+
+impl ser::SerializeMap for MapSerializer {
+    type Ok = Variant;
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+        match to_variant(key)? {
+            key @ Variant::String(_) => {
+                self.key = Some(key);
+                Ok(())
+            }
+            key => Err(Error::KeyMustBeString(format!("{:?}", key))),
+        }
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        let key = self
+            .key
+            .take()
+            .ok_or_else(|| Error::Custom("Map value without key".to_string()))?;
+        self.map.insert(key, to_variant(value)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Variant, Error> {
+        Ok(self.finish())
+    }
+}
+
+impl ser::SerializeStruct for MapSerializer {
+    type Ok = Variant;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.map
+            .insert(Variant::String(Str::from_static(key)), to_variant(value)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Variant, Error> {
+        Ok(self.finish())
+    }
+}
+
+impl ser::SerializeStructVariant for MapSerializer {
+    type Ok = Variant;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        ser::SerializeStruct::serialize_field(self, key, value)
+    }
+
+    fn end(self) -> Result<Variant, Error> {
+        Ok(self.finish())
+    }
+}
+
+struct VariantDeserializer<'a>(&'a Variant);
+
+impl<'de, 'a> de::Deserializer<'de> for VariantDeserializer<'a> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            Variant::Null => visitor.visit_unit(),
+            Variant::Boolean(v) => visitor.visit_bool(*v),
+            Variant::Ubyte(v) => visitor.visit_u8(*v),
+            Variant::Ushort(v) => visitor.visit_u16(*v),
+            Variant::Uint(v) => visitor.visit_u32(*v),
+            Variant::Ulong(v) => visitor.visit_u64(*v),
+            Variant::Byte(v) => visitor.visit_i8(*v),
+            Variant::Short(v) => visitor.visit_i16(*v),
+            Variant::Int(v) => visitor.visit_i32(*v),
+            Variant::Long(v) => visitor.visit_i64(*v),
+            Variant::Float(v) => visitor.visit_f32(v.into_inner()),
+            Variant::Double(v) => visitor.visit_f64(v.into_inner()),
+            Variant::Decimal32(v) => visitor.visit_bytes(&v.0),
+            Variant::Decimal64(v) => visitor.visit_bytes(&v.0),
+            Variant::Decimal128(v) => visitor.visit_bytes(&v.0),
+            Variant::Char(v) => visitor.visit_char(*v),
+            Variant::Timestamp(v) => visitor.visit_i64(v.timestamp_millis()),
+            Variant::Uuid(v) => visitor.visit_bytes(v.as_bytes()),
+            Variant::Binary(v) => visitor.visit_bytes(v),
+            Variant::String(v) => visitor.visit_str(v.as_str()),
+            Variant::Symbol(v) => visitor.visit_str(v.as_str()),
+            Variant::StaticSymbol(v) => visitor.visit_str(v.0),
+            Variant::List(v) => visitor.visit_seq(SeqDeserializer(v.iter())),
+            Variant::Array(v) => visitor.visit_seq(SeqDeserializer(v.iter())),
+            Variant::Map(v) => visitor.visit_map(MapDeserializer {
+                iter: v.map.iter(),
+                value: None,
+            }),
+            Variant::Described((_, v)) => VariantDeserializer(v).deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            Variant::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            Variant::Binary(v) => {
+                visitor.visit_seq(de::value::SeqDeserializer::new(v.iter().copied()))
+            }
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        _: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match self.0 {
+            Variant::String(s) => visitor.visit_enum(s.as_str().into_deserializer()),
+            Variant::Symbol(s) => visitor.visit_enum(s.as_str().into_deserializer()),
+            Variant::Map(map) if map.map.len() == 1 => {
+                let (variant, value) = map.map.iter().next().unwrap();
+                visitor.visit_enum(EnumDeserializer { variant, value })
+            }
+            _ => Err(de::Error::invalid_type(
+                Unexpected::Other(&format!("{:?}", self.0)),
+                &"string or map with single entry",
+            )),
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct tuple tuple_struct map struct
+        identifier ignored_any
+    }
+}
+
+struct SeqDeserializer<'a>(std::slice::Iter<'a, Variant>);
+
+impl<'de, 'a> de::SeqAccess<'de> for SeqDeserializer<'a> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Error> {
+        self.0
+            .next()
+            .map(|v| seed.deserialize(VariantDeserializer(v)))
+            .transpose()
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.0.len())
+    }
+}
+
+struct MapDeserializer<'a> {
+    iter: hash_map::Iter<'a, Variant, Variant>,
+    value: Option<&'a Variant>,
+}
+
+impl<'de, 'a> de::MapAccess<'de> for MapDeserializer<'a> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Error> {
+        match self.iter.next() {
+            Some((key, value)) => {
+                self.value = Some(value);
+                seed.deserialize(VariantDeserializer(key)).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        let value = self
+            .value
+            .take()
+            .ok_or_else(|| Error::Custom("Map value without key".to_string()))?;
+        seed.deserialize(VariantDeserializer(value))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+struct EnumDeserializer<'a> {
+    variant: &'a Variant,
+    value: &'a Variant,
+}
+
+impl<'de, 'a> de::EnumAccess<'de> for EnumDeserializer<'a> {
+    type Error = Error;
+    type Variant = VariantDeserializer<'a>;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, VariantDeserializer<'a>), Error> {
+        let variant = seed.deserialize(VariantDeserializer(self.variant))?;
+        Ok((variant, VariantDeserializer(self.value)))
+    }
+}
+
+impl<'de, 'a> de::VariantAccess<'de> for VariantDeserializer<'a> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        de::Deserialize::deserialize(self)
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, Error> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _: usize, visitor: V) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_seq(self, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_map(self, visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+    use crate::types::Symbol;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    enum Kind {
+        Plain,
+        Sized(u32),
+        Point { x: i16, y: i16 },
+        Pair(String, bool),
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Props {
+        id: u64,
+        name: String,
+        count: i32,
+        ratio: f64,
+        tags: Vec<String>,
+        parent: Option<String>,
+        #[serde(with = "as_binary")]
+        payload: Vec<u8>,
+        kind: Kind,
+        extra: std::collections::BTreeMap<String, i64>,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Event {
+        #[serde(with = "as_timestamp")]
+        time: DateTime<Utc>,
+        #[serde(with = "as_uuid")]
+        id: Uuid,
+        seq: (u8, i8, char),
+    }
+
+    fn kind() -> impl Strategy<Value = Kind> {
+        prop_oneof![
+            Just(Kind::Plain),
+            any::<u32>().prop_map(Kind::Sized),
+            (any::<i16>(), any::<i16>()).prop_map(|(x, y)| Kind::Point { x, y }),
+            (".*", any::<bool>()).prop_map(|(s, b)| Kind::Pair(s, b)),
+        ]
+    }
+
+    fn props() -> impl Strategy<Value = Props> {
+        (
+            (any::<u64>(), ".*", any::<i32>(), -1e12..1e12f64),
+            (
+                prop::collection::vec(".*", 0..4),
+                prop::option::of(".*"),
+                prop::collection::vec(any::<u8>(), 0..16),
+            ),
+            (
+                kind(),
+                prop::collection::btree_map(".*", any::<i64>(), 0..4),
+            ),
+        )
+            .prop_map(
+                |((id, name, count, ratio), (tags, parent, payload), (kind, extra))| Props {
+                    id,
+                    name,
+                    count,
+                    ratio,
+                    tags,
+                    parent,
+                    payload,
+                    kind,
+                    extra,
+                },
+            )
+    }
+
+    proptest! {
+        #[test]
+        fn props_roundtrip(props in props()) {
+            let value = to_variant(&props).unwrap();
+            prop_assert!(matches!(value, Variant::Map(_)));
+            prop_assert_eq!(from_variant::<Props>(&value).unwrap(), props);
+        }
+
+        #[test]
+        fn event_roundtrip(
+            millis in -8_000_000_000_000i64..8_000_000_000_000,
+            id in any::<u128>(),
+            seq in (any::<u8>(), any::<i8>(), any::<char>()),
+        ) {
+            let event = Event {
+                time: timestamp(millis).unwrap(),
+                id: Uuid::from_u128(id),
+                seq,
+            };
+            let value = to_variant(&event).unwrap();
+            prop_assert_eq!(from_variant::<Event>(&value).unwrap(), event);
+        }
+
+        #[test]
+        fn narrowest_integer(v in any::<i64>()) {
+            let expected = if i8::try_from(v).is_ok() {
+                "byte"
+            } else if i16::try_from(v).is_ok() {
+                "short"
+            } else if i32::try_from(v).is_ok() {
+                "int"
+            } else {
+                "long"
+            };
+            let value = to_variant(&v).unwrap();
+            let json = serde_json::to_value(&value).unwrap();
+            prop_assert!(json.get(expected).is_some());
+            prop_assert_eq!(from_variant::<i64>(&value).unwrap(), v);
+        }
+    }
+
+    #[test]
+    fn test_mapping() {
+        let event = Event {
+            time: timestamp(1_311_704_463_521).unwrap(),
+            id: Uuid::nil(),
+            seq: (1, -1, 'a'),
+        };
+        let value = to_variant(&event).unwrap();
+        let map = match value {
+            Variant::Map(ref map) => map,
+            _ => panic!("expected map, got {:?}", value),
+        };
+        assert_eq!(map.get("time"), Some(&Variant::Timestamp(event.time)));
+        assert_eq!(map.get("id"), Some(&Variant::Uuid(Uuid::nil())));
+        assert_eq!(
+            map.get("seq"),
+            Some(&Variant::List(List(vec![
+                Variant::Ubyte(1),
+                Variant::Byte(-1),
+                Variant::Char('a')
+            ])))
+        );
+
+        assert_eq!(to_variant(&300u64).unwrap(), Variant::Ushort(300));
+        assert_eq!(to_variant(&-300i64).unwrap(), Variant::Short(-300));
+        assert_eq!(to_variant(&Kind::Plain).unwrap(), Variant::from("Plain"));
+        assert_eq!(
+            to_variant(&Some(u64::MAX)).unwrap(),
+            Variant::Ulong(u64::MAX)
+        );
+        assert_eq!(to_variant(&Option::<u8>::None).unwrap(), Variant::Null);
+
+        // values built by hand, integer width and string kind do not matter
+        let value = Variant::Map(
+            VariantMap::builder()
+                .insert("time", Variant::Long(1_311_704_463_521))
+                .insert("id", Variant::from("00000000-0000-0000-0000-000000000000"))
+                .insert(
+                    "seq",
+                    Variant::List(List(vec![
+                        Variant::Ulong(1),
+                        Variant::Long(-1),
+                        Variant::Char('a'),
+                    ])),
+                )
+                .build(),
+        );
+        assert_eq!(from_variant::<Event>(&value).unwrap(), event);
+        let kind = Variant::Symbol(Symbol::from("Plain"));
+        assert_eq!(from_variant::<Kind>(&kind).unwrap(), Kind::Plain);
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut map = std::collections::BTreeMap::new();
+        map.insert(1u32, "a");
+        assert_eq!(
+            to_variant(&map),
+            Err(Error::KeyMustBeString("Ubyte(1)".to_string()))
+        );
+
+        assert!(from_variant::<u8>(&Variant::Int(256)).is_err());
+        assert!(from_variant::<String>(&Variant::Int(1)).is_err());
+        assert!(from_variant::<Kind>(&Variant::Int(1)).is_err());
+        assert!(from_variant::<Props>(&Variant::Null).is_err());
+        let err = from_variant::<Event>(&Variant::Map(VariantMap::builder().build()));
+        assert!(err.unwrap_err().to_string().contains("missing field"));
+    }
+}
@@ -1,13 +1,17 @@
+use std::cmp::Ordering;
+use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
 
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
 use bytestring::ByteString;
 use chrono::{DateTime, Utc};
 use ordered_float::OrderedFloat;
 use uuid::Uuid;
 
+use crate::codec::{variant_array_format_code, DecodeFormatted, Encode};
+use crate::error::AmqpParseError;
 use crate::protocol::Annotations;
-use crate::types::{Descriptor, List, StaticSymbol, Str, Symbol};
+use crate::types::{Decimal128, Decimal32, Decimal64, Descriptor, List, StaticSymbol, Str, Symbol};
 use crate::HashMap;
 
 /// Represents an AMQP type for use in polymorphic collections
@@ -49,9 +53,15 @@ pub enum Variant {
     /// 64-bit floating point number (IEEE 754-2008 binary64).
     Double(OrderedFloat<f64>),
 
-    // Decimal32(d32),
-    // Decimal64(d64),
-    // Decimal128(d128),
+    /// 32-bit decimal number (IEEE 754-2008 decimal32).
+    Decimal32(Decimal32),
+
+    /// 64-bit decimal number (IEEE 754-2008 decimal64).
+    Decimal64(Decimal64),
+
+    /// 128-bit decimal number (IEEE 754-2008 decimal128).
+    Decimal128(Decimal128),
+
     /// A single Unicode character.
     Char(char),
 
@@ -84,7 +94,11 @@ pub enum Variant {
     /// Map
     Map(VariantMap),
 
-    /// Described value
+    /// Array of values of the same type
+    #[display(fmt = "Array({:?})", _0)]
+    Array(VariantArray),
+
+    /// Described value, descriptor is kept as decoded
     #[display(fmt = "Described{:?}", _0)]
     Described((Descriptor, Box<Variant>)),
 }
@@ -107,6 +121,18 @@ impl From<&'static str> for Variant {
     }
 }
 
+impl From<f32> for Variant {
+    fn from(v: f32) -> Self {
+        Variant::Float(OrderedFloat(v))
+    }
+}
+
+impl From<f64> for Variant {
+    fn from(v: f64) -> Self {
+        Variant::Double(OrderedFloat(v))
+    }
+}
+
 impl PartialEq<str> for Variant {
     fn eq(&self, other: &str) -> bool {
         match self {
@@ -118,6 +144,26 @@ impl PartialEq<str> for Variant {
 }
 
 impl Variant {
+    /// Decode value from its AMQP encoding.
+    ///
+    /// `format_code` is the constructor of the value and `data` holds
+    /// the encoded value without it. All of `data` must be consumed.
+    pub fn from_amqp_bytes(format_code: u8, data: &[u8]) -> Result<Variant, AmqpParseError> {
+        let (rest, value) = Variant::decode_with_format(data, format_code)?;
+        if rest.is_empty() {
+            Ok(value)
+        } else {
+            Err(AmqpParseError::InvalidSize)
+        }
+    }
+
+    /// Encode value to AMQP encoding, format code included.
+    pub fn to_amqp_bytes(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(self.encoded_size());
+        self.encode(&mut buf);
+        buf.freeze()
+    }
+
     pub fn as_str(&self) -> Option<&str> {
         match self {
             Variant::String(s) => Some(s.as_str()),
@@ -147,6 +193,54 @@ impl Variant {
         }
     }
 
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Variant::Boolean(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    /// Unsigned value, narrower unsigned types are widened
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Variant::Ubyte(v) => Some(*v as u64),
+            Variant::Ushort(v) => Some(*v as u64),
+            Variant::Uint(v) => Some(*v as u64),
+            Variant::Ulong(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    /// Floating point value, `float` is widened to `f64`
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Variant::Float(v) => Some(v.into_inner() as f64),
+            Variant::Double(v) => Some(v.into_inner()),
+            _ => None,
+        }
+    }
+
+    pub fn as_timestamp(&self) -> Option<DateTime<Utc>> {
+        match self {
+            Variant::Timestamp(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    pub fn as_uuid(&self) -> Option<Uuid> {
+        match self {
+            Variant::Uuid(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    pub fn as_binary(&self) -> Option<&Bytes> {
+        match self {
+            Variant::Binary(v) => Some(v),
+            _ => None,
+        }
+    }
+
     pub fn to_bytes_str(&self) -> Option<ByteString> {
         match self {
             Variant::String(s) => Some(s.to_bytes_str()),
@@ -166,12 +260,304 @@ impl VariantMap {
     pub fn new(map: HashMap<Variant, Variant>) -> VariantMap {
         VariantMap { map }
     }
+
+    /// Create map builder with string keys
+    pub fn builder() -> VariantMapBuilder {
+        VariantMapBuilder {
+            map: HashMap::default(),
+        }
+    }
+
+    /// Get value by string key, key could be stored as string or symbol
+    pub fn get(&self, key: &str) -> Option<&Variant> {
+        let s = Str::from_str(key);
+        self.map
+            .get(&Variant::String(s.clone()))
+            .or_else(|| self.map.get(&Variant::Symbol(Symbol(s))))
+            .or_else(|| {
+                self.map.iter().find_map(|(k, v)| match k {
+                    Variant::StaticSymbol(sym) if sym.0 == key => Some(v),
+                    _ => None,
+                })
+            })
+    }
+}
+
+/// Builder for `VariantMap` with string keys
+#[derive(Debug)]
+pub struct VariantMapBuilder {
+    map: HashMap<Variant, Variant>,
+}
+
+impl VariantMapBuilder {
+    /// Insert value, key is stored as string
+    pub fn insert<K, V>(mut self, key: K, value: V) -> Self
+    where
+        K: Into<Str>,
+        V: Into<Variant>,
+    {
+        self.map.insert(Variant::String(key.into()), value.into());
+        self
+    }
+
+    pub fn build(self) -> VariantMap {
+        VariantMap { map: self.map }
+    }
 }
 
 #[allow(clippy::derive_hash_xor_eq)]
 impl Hash for VariantMap {
-    fn hash<H: Hasher>(&self, _state: &mut H) {
-        unimplemented!()
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        // map iteration order is unspecified, entry hashes are combined
+        // with xor to get the same hash for equal maps
+        let hash = self.map.iter().fold(0u64, |acc, entry| {
+            let mut hasher = DefaultHasher::new();
+            entry.hash(&mut hasher);
+            acc ^ hasher.finish()
+        });
+        state.write_usize(self.map.len());
+        state.write_u64(hash);
+    }
+}
+
+impl VariantMap {
+    /// Entries sorted by key, canonical order of the map
+    fn sorted(&self) -> Vec<(&Variant, &Variant)> {
+        let mut entries: Vec<_> = self.map.iter().collect();
+        entries.sort_unstable_by(|a, b| a.0.cmp(b.0));
+        entries
+    }
+}
+
+impl PartialOrd for VariantMap {
+    fn partial_cmp(&self, other: &VariantMap) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for VariantMap {
+    fn cmp(&self, other: &VariantMap) -> Ordering {
+        self.sorted().cmp(&other.sorted())
+    }
+}
+
+/// Array of values of the same type.
+///
+/// Element constructor of decoded array is kept, array is encoded
+/// with it as long as values fit, i.e. array of `sym8` symbols is
+/// not re-encoded as `sym32`. Constructor is not part of equality.
+#[derive(Clone, Default)]
+pub struct VariantArray {
+    items: Vec<Variant>,
+    ctor: Option<u8>,
+}
+
+impl VariantArray {
+    /// Create array, items are not checked.
+    ///
+    /// Encoding panics if items are of different types or contain
+    /// described values, use `try_new()` for untrusted input.
+    pub fn new(items: Vec<Variant>) -> VariantArray {
+        VariantArray { items, ctor: None }
+    }
+
+    /// Create array, fails if items are not of the same non-described type
+    pub fn try_new(items: Vec<Variant>) -> Result<VariantArray, AmqpParseError> {
+        if let Some(first) = items.first() {
+            let code = variant_array_format_code(first);
+            if code.is_none() || items.iter().any(|i| variant_array_format_code(i) != code) {
+                return Err(AmqpParseError::ArrayTypeMismatch);
+            }
+        }
+        Ok(VariantArray::new(items))
+    }
+
+    pub(crate) fn with_ctor(items: Vec<Variant>, ctor: u8) -> VariantArray {
+        VariantArray {
+            items,
+            ctor: Some(ctor),
+        }
+    }
+
+    /// Element constructor of decoded array
+    pub fn ctor(&self) -> Option<u8> {
+        self.ctor
+    }
+
+    pub fn into_inner(self) -> Vec<Variant> {
+        self.items
+    }
+}
+
+impl std::fmt::Debug for VariantArray {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.items.iter()).finish()
+    }
+}
+
+impl std::ops::Deref for VariantArray {
+    type Target = Vec<Variant>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.items
+    }
+}
+
+impl std::ops::DerefMut for VariantArray {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.items
+    }
+}
+
+impl From<Vec<Variant>> for VariantArray {
+    fn from(items: Vec<Variant>) -> VariantArray {
+        VariantArray::new(items)
+    }
+}
+
+impl From<Vec<Variant>> for Variant {
+    fn from(items: Vec<Variant>) -> Variant {
+        Variant::Array(VariantArray::new(items))
+    }
+}
+
+impl PartialEq for VariantArray {
+    fn eq(&self, other: &VariantArray) -> bool {
+        self.items == other.items
+    }
+}
+
+impl Eq for VariantArray {}
+
+impl Hash for VariantArray {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.items.hash(state)
+    }
+}
+
+impl PartialOrd for VariantArray {
+    fn partial_cmp(&self, other: &VariantArray) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for VariantArray {
+    fn cmp(&self, other: &VariantArray) -> Ordering {
+        self.items.cmp(&other.items)
+    }
+}
+
+impl Variant {
+    /// Ordering rank of the value type and rank within it.
+    ///
+    /// Integers and floats of different width share rank,
+    /// second value orders equal numbers of different types.
+    fn rank(&self) -> (u8, u8) {
+        match self {
+            Variant::Null => (0, 0),
+            Variant::Boolean(_) => (1, 0),
+            Variant::Ubyte(_) => (2, 0),
+            Variant::Ushort(_) => (2, 1),
+            Variant::Uint(_) => (2, 2),
+            Variant::Ulong(_) => (2, 3),
+            Variant::Byte(_) => (2, 4),
+            Variant::Short(_) => (2, 5),
+            Variant::Int(_) => (2, 6),
+            Variant::Long(_) => (2, 7),
+            Variant::Float(_) => (3, 0),
+            Variant::Double(_) => (3, 1),
+            Variant::Decimal32(_) => (4, 0),
+            Variant::Decimal64(_) => (5, 0),
+            Variant::Decimal128(_) => (6, 0),
+            Variant::Char(_) => (7, 0),
+            Variant::Timestamp(_) => (8, 0),
+            Variant::Uuid(_) => (9, 0),
+            Variant::Binary(_) => (10, 0),
+            Variant::String(_) => (11, 0),
+            Variant::Symbol(_) => (12, 0),
+            Variant::StaticSymbol(_) => (12, 1),
+            Variant::List(_) => (13, 0),
+            Variant::Map(_) => (14, 0),
+            Variant::Array(_) => (15, 0),
+            Variant::Described(_) => (16, 0),
+        }
+    }
+
+    fn as_i128(&self) -> Option<i128> {
+        match self {
+            Variant::Ulong(v) => Some(i128::from(*v)),
+            _ => self.as_long().map(i128::from),
+        }
+    }
+
+    fn as_symbol_str(&self) -> Option<&str> {
+        match self {
+            Variant::Symbol(s) => Some(s.as_str()),
+            Variant::StaticSymbol(s) => Some(s.0),
+            _ => None,
+        }
+    }
+}
+
+impl PartialOrd for Variant {
+    fn partial_cmp(&self, other: &Variant) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Values of different types are ordered by type rank:
+/// null < boolean < integers < floats < decimal32 < decimal64 < decimal128
+/// < char < timestamp < uuid < binary < string < symbol < list < map
+/// < array < described.
+///
+/// Integers of any width are compared by value, as are floats. Equal
+/// numbers of different types, i.e. `Uint(1)` and `Long(1)`, are not
+/// equal and ordered by width, unsigned first. Maps are compared by
+/// entries sorted by key.
+impl Ord for Variant {
+    fn cmp(&self, other: &Variant) -> Ordering {
+        let (rank, sub_rank) = self.rank();
+        let (other_rank, other_sub_rank) = other.rank();
+
+        let ord = match (self, other) {
+            _ if rank != other_rank => rank.cmp(&other_rank),
+            (Variant::Boolean(a), Variant::Boolean(b)) => a.cmp(b),
+            (Variant::Float(a), Variant::Float(b)) => a.cmp(b),
+            (Variant::Float(a), Variant::Double(b)) => OrderedFloat(f64::from(a.0)).cmp(b),
+            (Variant::Double(a), Variant::Float(b)) => a.cmp(&OrderedFloat(f64::from(b.0))),
+            (Variant::Double(a), Variant::Double(b)) => a.cmp(b),
+            (Variant::Decimal32(a), Variant::Decimal32(b)) => a.0.cmp(&b.0),
+            (Variant::Decimal64(a), Variant::Decimal64(b)) => a.0.cmp(&b.0),
+            (Variant::Decimal128(a), Variant::Decimal128(b)) => a.0.cmp(&b.0),
+            (Variant::Char(a), Variant::Char(b)) => a.cmp(b),
+            (Variant::Timestamp(a), Variant::Timestamp(b)) => a.cmp(b),
+            (Variant::Uuid(a), Variant::Uuid(b)) => a.cmp(b),
+            (Variant::Binary(a), Variant::Binary(b)) => a.cmp(b),
+            (Variant::String(a), Variant::String(b)) => a.as_str().cmp(b.as_str()),
+            (Variant::List(a), Variant::List(b)) => a.0.cmp(&b.0),
+            (Variant::Map(a), Variant::Map(b)) => a.cmp(b),
+            (Variant::Array(a), Variant::Array(b)) => a.cmp(b),
+            (Variant::Described(a), Variant::Described(b)) => {
+                let desc = match (&a.0, &b.0) {
+                    (Descriptor::Ulong(a), Descriptor::Ulong(b)) => a.cmp(b),
+                    (Descriptor::Ulong(_), Descriptor::Symbol(_)) => Ordering::Less,
+                    (Descriptor::Symbol(_), Descriptor::Ulong(_)) => Ordering::Greater,
+                    (Descriptor::Symbol(a), Descriptor::Symbol(b)) => a.as_str().cmp(b.as_str()),
+                };
+                desc.then_with(|| a.1.cmp(&b.1))
+            }
+            _ => {
+                if let (Some(a), Some(b)) = (self.as_i128(), other.as_i128()) {
+                    a.cmp(&b)
+                } else if let (Some(a), Some(b)) = (self.as_symbol_str(), other.as_symbol_str()) {
+                    a.cmp(b)
+                } else {
+                    // null
+                    Ordering::Equal
+                }
+            }
+        };
+        ord.then(sub_rank.cmp(&other_sub_rank))
     }
 }
 
@@ -217,7 +603,7 @@ impl Default for VecStringMap {
 
 impl From<Vec<(Str, Variant)>> for VecStringMap {
     fn from(data: Vec<(Str, Variant)>) -> VecStringMap {
-        VecStringMap(data)
+        data.into_iter().collect()
     }
 }
 
@@ -227,6 +613,86 @@ impl From<HashMap<Str, Variant>> for VecStringMap {
     }
 }
 
+impl VecStringMap {
+    /// Insert value, value of existing key is replaced in place.
+    ///
+    /// Lookup is linear, use `extend` to insert many entries.
+    pub fn insert(&mut self, key: Str, value: Variant) -> Option<Variant> {
+        if let Some(item) = self.0.iter_mut().rev().find(|item| item.0 == key) {
+            Some(std::mem::replace(&mut item.1, value))
+        } else {
+            self.0.push((key, value));
+            None
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Variant> {
+        self.0
+            .iter()
+            .rev()
+            .find_map(|item| if item.0 == *key { Some(&item.1) } else { None })
+    }
+
+    /// Remove key, order of other entries is preserved
+    pub fn remove(&mut self, key: &str) -> Option<Variant> {
+        let mut value = None;
+        while let Some(idx) = self.0.iter().rposition(|item| item.0 == *key) {
+            let item = self.0.remove(idx);
+            value = value.or(Some(item.1));
+        }
+        value
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.0.iter().any(|item| item.0 == *key)
+    }
+
+    /// Entries without duplicate keys, last value of a key wins.
+    ///
+    /// Duplicates could be added through `Vec` api, they are not encoded.
+    pub(crate) fn unique(&self) -> impl Iterator<Item = &(Str, Variant)> {
+        let last: HashMap<&Str, usize> = self
+            .0
+            .iter()
+            .enumerate()
+            .map(|(idx, item)| (&item.0, idx))
+            .collect();
+        self.0
+            .iter()
+            .enumerate()
+            .filter(move |(idx, item)| last[&item.0] == *idx)
+            .map(|(_, item)| item)
+    }
+}
+
+impl std::iter::FromIterator<(Str, Variant)> for VecStringMap {
+    fn from_iter<T: IntoIterator<Item = (Str, Variant)>>(iter: T) -> Self {
+        let mut map = VecStringMap::default();
+        map.extend(iter);
+        map
+    }
+}
+
+impl Extend<(Str, Variant)> for VecStringMap {
+    fn extend<T: IntoIterator<Item = (Str, Variant)>>(&mut self, iter: T) {
+        // position of last entry of each key
+        let mut index: HashMap<Str, usize> = self
+            .0
+            .iter()
+            .enumerate()
+            .map(|(idx, item)| (item.0.clone(), idx))
+            .collect();
+        for (key, value) in iter {
+            if let Some(idx) = index.get(&key) {
+                self.0[*idx].1 = value;
+            } else {
+                index.insert(key.clone(), self.0.len());
+                self.0.push((key, value));
+            }
+        }
+    }
+}
+
 impl std::ops::Deref for VecStringMap {
     type Target = Vec<(Str, Variant)>;
 
@@ -244,6 +710,9 @@ impl std::ops::DerefMut for VecStringMap {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use chrono::TimeZone;
+
+    use crate::codec::Decode;
 
     #[test]
     fn bytes_eq() {
@@ -272,4 +741,594 @@ mod tests {
         assert_eq!(Variant::Symbol(Symbol::from("hello")), a);
         assert!(a != b);
     }
+
+    fn from_amqp(code: u8, data: &[u8]) -> Variant {
+        Variant::from_amqp_bytes(code, data).unwrap()
+    }
+
+    #[test]
+    fn from_amqp_bytes_fixed() {
+        use chrono::TimeZone;
+
+        assert_eq!(from_amqp(0x40, &[]), Variant::Null);
+        assert_eq!(from_amqp(0x56, &[0x01]), Variant::Boolean(true));
+        assert_eq!(from_amqp(0x56, &[0x00]), Variant::Boolean(false));
+        assert_eq!(from_amqp(0x41, &[]), Variant::Boolean(true));
+        assert_eq!(from_amqp(0x42, &[]), Variant::Boolean(false));
+        assert_eq!(from_amqp(0x50, &[0xff]), Variant::Ubyte(255));
+        assert_eq!(from_amqp(0x60, &[0x01, 0x5e]), Variant::Ushort(350));
+        assert_eq!(from_amqp(0x70, &[0, 0, 0x01, 0]), Variant::Uint(256));
+        assert_eq!(from_amqp(0x52, &[0x80]), Variant::Uint(128));
+        assert_eq!(from_amqp(0x43, &[]), Variant::Uint(0));
+        assert_eq!(
+            from_amqp(0x80, &[0, 0, 0, 0, 0x80, 0, 0, 0x01]),
+            Variant::Ulong(2_147_483_649)
+        );
+        assert_eq!(from_amqp(0x53, &[0x80]), Variant::Ulong(128));
+        assert_eq!(from_amqp(0x44, &[]), Variant::Ulong(0));
+        assert_eq!(from_amqp(0x51, &[0x80]), Variant::Byte(-128));
+        assert_eq!(from_amqp(0x61, &[0xff, 0x01]), Variant::Short(-255));
+        assert_eq!(
+            from_amqp(0x71, &[0xff, 0xff, 0x3c, 0xb0]),
+            Variant::Int(-50_000)
+        );
+        assert_eq!(from_amqp(0x54, &[0x80]), Variant::Int(-128));
+        assert_eq!(
+            from_amqp(0x81, &[0xff, 0xff, 0xff, 0xff, 0x80, 0, 0, 0x01]),
+            Variant::Long(-2_147_483_647)
+        );
+        assert_eq!(from_amqp(0x55, &[0x80]), Variant::Long(-128));
+        assert_eq!(
+            from_amqp(0x72, &[0x3f, 0xc0, 0, 0]),
+            Variant::Float(OrderedFloat(1.5))
+        );
+        assert_eq!(
+            from_amqp(0x82, &[0x3f, 0xf8, 0, 0, 0, 0, 0, 0]),
+            Variant::Double(OrderedFloat(1.5))
+        );
+        assert_eq!(from_amqp(0x73, &[0, 0, 0, 0x61]), Variant::Char('a'));
+        assert_eq!(
+            from_amqp(0x83, &[0, 0, 0x01, 0x31, 0x67, 0xad, 0xb8, 0xa1]),
+            Variant::Timestamp(Utc.ymd(2011, 7, 26).and_hms_milli(18, 21, 3, 521))
+        );
+        let uuid = Uuid::new_v4();
+        assert_eq!(from_amqp(0x98, uuid.as_bytes()), Variant::Uuid(uuid));
+        assert_eq!(
+            from_amqp(0x74, &[0x22, 0x50, 0, 0x01]),
+            Variant::Decimal32(Decimal32([0x22, 0x50, 0, 0x01]))
+        );
+        assert_eq!(
+            from_amqp(0x84, &[0x22, 0x38, 0, 0, 0, 0, 0, 0x01]),
+            Variant::Decimal64(Decimal64([0x22, 0x38, 0, 0, 0, 0, 0, 0x01]))
+        );
+        let mut d128 = [0; 16];
+        d128[0] = 0x22;
+        d128[1] = 0x08;
+        d128[15] = 0x01;
+        assert_eq!(
+            from_amqp(0x94, &d128),
+            Variant::Decimal128(Decimal128(d128))
+        );
+    }
+
+    #[test]
+    fn from_amqp_bytes_variable() {
+        let bin = Variant::Binary(Bytes::from_static(&[0x04, 0x05]));
+        assert_eq!(from_amqp(0xa0, &[2, 0x04, 0x05]), bin);
+        assert_eq!(from_amqp(0xb0, &[0, 0, 0, 2, 0x04, 0x05]), bin);
+
+        let s = Variant::from("hello");
+        assert_eq!(from_amqp(0xa1, b"\x05hello"), s);
+        assert_eq!(from_amqp(0xb1, b"\x00\x00\x00\x05hello"), s);
+
+        let sym = Variant::Symbol(Symbol::from("hello"));
+        assert_eq!(from_amqp(0xa3, b"\x05hello"), sym);
+        assert_eq!(from_amqp(0xb3, b"\x00\x00\x00\x05hello"), sym);
+    }
+
+    #[test]
+    fn from_amqp_bytes_compound() {
+        let empty = Variant::List(List(vec![]));
+        assert_eq!(from_amqp(0x45, &[]), empty);
+
+        let list = Variant::List(List(vec![Variant::Boolean(true), Variant::Null]));
+        assert_eq!(from_amqp(0xc0, &[3, 2, 0x41, 0x40]), list);
+        assert_eq!(from_amqp(0xd0, &[0, 0, 0, 6, 0, 0, 0, 2, 0x41, 0x40]), list);
+
+        let mut map = HashMap::default();
+        map.insert(Variant::Uint(0), Variant::Boolean(true));
+        let map = Variant::Map(VariantMap::new(map));
+        assert_eq!(from_amqp(0xc1, &[3, 2, 0x43, 0x41]), map);
+        assert_eq!(from_amqp(0xd1, &[0, 0, 0, 6, 0, 0, 0, 2, 0x43, 0x41]), map);
+
+        assert_eq!(
+            from_amqp(0x00, &[0x53, 0x24, 0x45]),
+            Variant::Described((Descriptor::Ulong(0x24), Box::new(empty)))
+        );
+    }
+
+    #[test]
+    fn ordering() {
+        let mut values = vec![
+            Variant::from("b"),
+            Variant::Symbol(Symbol::from("a")),
+            Variant::Double(OrderedFloat(1.5)),
+            Variant::Long(-3),
+            Variant::Ulong(u64::MAX),
+            Variant::Uint(2),
+            Variant::Byte(2),
+            Variant::Boolean(true),
+            Variant::Null,
+            Variant::Float(OrderedFloat(-1.0)),
+            Variant::from("a"),
+            Variant::Boolean(false),
+            Variant::List(List(vec![Variant::Uint(1)])),
+            Variant::Binary(Bytes::from_static(b"a")),
+            Variant::Char('x'),
+        ];
+        values.sort();
+        assert_eq!(
+            values,
+            vec![
+                Variant::Null,
+                Variant::Boolean(false),
+                Variant::Boolean(true),
+                Variant::Long(-3),
+                Variant::Uint(2),
+                Variant::Byte(2),
+                Variant::Ulong(u64::MAX),
+                Variant::Float(OrderedFloat(-1.0)),
+                Variant::Double(OrderedFloat(1.5)),
+                Variant::Char('x'),
+                Variant::Binary(Bytes::from_static(b"a")),
+                Variant::from("a"),
+                Variant::from("b"),
+                Variant::Symbol(Symbol::from("a")),
+                Variant::List(List(vec![Variant::Uint(1)])),
+            ]
+        );
+    }
+
+    #[test]
+    fn ordering_consistent_with_eq() {
+        let values = vec![
+            Variant::Uint(1),
+            Variant::Long(1),
+            Variant::Float(OrderedFloat(1.0)),
+            Variant::Double(OrderedFloat(1.0)),
+            Variant::Symbol(Symbol::from("a")),
+            Variant::StaticSymbol(StaticSymbol("a")),
+            Variant::String(Str::from("a")),
+            Variant::String(Str::from_str("a")),
+        ];
+        for a in &values {
+            for b in &values {
+                assert_eq!(a == b, a.cmp(b) == Ordering::Equal, "{:?} {:?}", a, b);
+                assert_eq!(a.cmp(b), b.cmp(a).reverse());
+            }
+        }
+
+        let mut map1 = HashMap::default();
+        let mut map2 = HashMap::default();
+        for i in 0..16 {
+            map1.insert(Variant::Uint(i), Variant::Null);
+            map2.insert(Variant::Uint(15 - i), Variant::Null);
+        }
+        let map1 = VariantMap::new(map1);
+        let mut map2 = VariantMap::new(map2);
+        assert_eq!(map1.cmp(&map2), Ordering::Equal);
+        map2.map.insert(Variant::Uint(16), Variant::Null);
+        assert_eq!(map1.cmp(&map2), Ordering::Less);
+    }
+
+    #[test]
+    fn decimal_display() {
+        // 1 in densely packed decimal encoding
+        let d32 = Variant::Decimal32(Decimal32([0x22, 0x50, 0, 0x01]));
+        assert_eq!(d32.to_string(), "0x22500001");
+        let d64 = Variant::Decimal64(Decimal64([0x22, 0x38, 0, 0, 0, 0, 0, 0x01]));
+        assert_eq!(d64.to_string(), "0x2238000000000001");
+        let mut d128 = [0; 16];
+        d128[0] = 0x22;
+        d128[1] = 0x08;
+        d128[15] = 0x01;
+        assert_eq!(
+            Decimal128(d128).to_string(),
+            "0x22080000000000000000000000000001"
+        );
+    }
+
+    #[test]
+    fn typed_accessors() {
+        assert_eq!(Variant::Boolean(true).as_bool(), Some(true));
+        assert_eq!(Variant::Int(1).as_bool(), None);
+
+        assert_eq!(Variant::Ubyte(1).as_u64(), Some(1));
+        assert_eq!(Variant::Ushort(2).as_u64(), Some(2));
+        assert_eq!(Variant::Uint(3).as_u64(), Some(3));
+        assert_eq!(Variant::Ulong(std::u64::MAX).as_u64(), Some(std::u64::MAX));
+        assert_eq!(Variant::Long(4).as_u64(), None);
+
+        assert_eq!(Variant::Float(OrderedFloat(1.5)).as_f64(), Some(1.5));
+        assert_eq!(Variant::Double(OrderedFloat(2.25)).as_f64(), Some(2.25));
+        assert_eq!(Variant::Uint(1).as_f64(), None);
+
+        let ts = Utc.timestamp_millis(1_311_704_463_521);
+        assert_eq!(Variant::Timestamp(ts).as_timestamp(), Some(ts));
+        assert_eq!(Variant::Ulong(1).as_timestamp(), None);
+
+        let uuid = Uuid::from_u128(0x0102_0304);
+        assert_eq!(Variant::Uuid(uuid).as_uuid(), Some(uuid));
+        assert_eq!(Variant::from("uuid").as_uuid(), None);
+
+        let bytes = Bytes::from_static(b"data");
+        assert_eq!(Variant::Binary(bytes.clone()).as_binary(), Some(&bytes));
+        assert_eq!(Variant::from("data").as_binary(), None);
+    }
+
+    #[test]
+    fn list_encode_format_code() {
+        let list = Variant::List(List(vec![Variant::Uint(1), Variant::Null]));
+        let bytes = list.to_amqp_bytes();
+        assert_eq!(bytes[0], 0xc0);
+        assert_eq!(from_amqp(bytes[0], &bytes[1..]), list);
+    }
+
+    #[test]
+    fn array_uint_round_trip() {
+        let array = Variant::from(vec![Variant::Uint(1), Variant::Uint(0x0102_0304)]);
+        let bytes = array.to_amqp_bytes();
+        assert_eq!(bytes.len(), array.encoded_size());
+        assert_eq!(&bytes[..], &[0xe0, 10, 2, 0x70, 0, 0, 0, 1, 1, 2, 3, 4][..]);
+        assert_eq!(from_amqp(bytes[0], &bytes[1..]), array);
+    }
+
+    #[test]
+    fn array_symbol_round_trip() {
+        let array = Variant::from(vec![
+            Variant::Symbol(Symbol::from("a")),
+            Variant::StaticSymbol(StaticSymbol("bc")),
+        ]);
+        let bytes = array.to_amqp_bytes();
+        assert_eq!(bytes.len(), array.encoded_size());
+        assert_eq!(
+            &bytes[..],
+            &[0xe0, 13, 2, 0xb3, 0, 0, 0, 1, b'a', 0, 0, 0, 2, b'b', b'c'][..]
+        );
+        assert_eq!(
+            from_amqp(bytes[0], &bytes[1..]),
+            Variant::from(vec![
+                Variant::Symbol(Symbol::from("a")),
+                Variant::Symbol(Symbol::from("bc")),
+            ])
+        );
+
+        let long = Variant::from(vec![Variant::Symbol(Symbol::from("x".repeat(300)))]);
+        let bytes = long.to_amqp_bytes();
+        assert_eq!(bytes.len(), long.encoded_size());
+        assert_eq!(bytes[0], 0xf0);
+        assert_eq!(from_amqp(bytes[0], &bytes[1..]), long);
+
+        let empty = Variant::from(vec![]);
+        let bytes = empty.to_amqp_bytes();
+        assert_eq!(from_amqp(bytes[0], &bytes[1..]), empty);
+    }
+
+    #[test]
+    fn array_keeps_ctor() {
+        // capabilities array of sym8 symbols
+        let data = [0xe0, 10, 2, 0xa3, 3, b'f', b'o', b'o', 3, b'b', b'a', b'r'];
+        let array = from_amqp(data[0], &data[1..]);
+        assert_eq!(
+            array,
+            Variant::from(vec![
+                Variant::Symbol(Symbol::from("foo")),
+                Variant::Symbol(Symbol::from("bar")),
+            ])
+        );
+        assert_eq!(array.encoded_size(), data.len());
+        assert_eq!(&array.to_amqp_bytes()[..], &data[..]);
+
+        // element type of empty array is kept
+        let data = [0xe0, 2, 0, 0xb1];
+        let empty = from_amqp(data[0], &data[1..]);
+        assert_eq!(empty, Variant::from(vec![]));
+        assert_eq!(&empty.to_amqp_bytes()[..], &data[..]);
+
+        let data = [0xe0, 4, 2, 0x52, 1, 2];
+        let small = from_amqp(data[0], &data[1..]);
+        assert_eq!(&small.to_amqp_bytes()[..], &data[..]);
+
+        // value does not fit constructor of decoded array
+        let mut small = match small {
+            Variant::Array(a) => a,
+            _ => unreachable!(),
+        };
+        assert_eq!(small.ctor(), Some(0x52));
+        small.push(Variant::Uint(256));
+        let bytes = Variant::Array(small).to_amqp_bytes();
+        assert_eq!(
+            &bytes[..],
+            &[0xe0, 14, 3, 0x70, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 1, 0][..]
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn array_encode_mixed_types() {
+        Variant::from(vec![Variant::Uint(1), Variant::Ulong(1)]).to_amqp_bytes();
+    }
+
+    #[test]
+    fn array_try_new() {
+        let arr = VariantArray::try_new(vec![Variant::Uint(1), Variant::Uint(2)]).unwrap();
+        assert_eq!(arr.len(), 2);
+        assert!(VariantArray::try_new(Vec::new()).unwrap().is_empty());
+        assert!(matches!(
+            VariantArray::try_new(vec![Variant::Uint(1), Variant::Ulong(1)]),
+            Err(AmqpParseError::ArrayTypeMismatch)
+        ));
+        let described = Variant::Described((Descriptor::Ulong(0x24), Box::new(Variant::Null)));
+        assert!(matches!(
+            VariantArray::try_new(vec![described]),
+            Err(AmqpParseError::ArrayTypeMismatch)
+        ));
+    }
+
+    #[test]
+    fn array_decode_mixed_types() {
+        // all elements share single constructor (part 1, section 1.2),
+        // arrays of described values are not supported and rejected
+        let data = [4 + 6, 2, 0x00, 0x53, 0x24, 0x45, 0x53, 0x24, 0x41];
+        assert!(matches!(
+            Variant::from_amqp_bytes(0xe0, &data[..]),
+            Err(AmqpParseError::ArrayTypeMismatch)
+        ));
+    }
+
+    fn hash_of<T: Hash>(value: &T) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn map_hash_nested() {
+        let mut inner = HashMap::default();
+        inner.insert(Variant::Uint(1), Variant::from("one"));
+        let inner = Variant::Map(VariantMap::new(inner));
+
+        let mut outer = HashMap::default();
+        outer.insert(Variant::from("inner"), inner.clone());
+        outer.insert(inner.clone(), Variant::Null);
+        let outer = Variant::Map(VariantMap::new(outer));
+
+        assert_eq!(hash_of(&outer), hash_of(&outer.clone()));
+        assert_ne!(hash_of(&outer), hash_of(&inner));
+    }
+
+    #[test]
+    fn map_hash_insertion_order() {
+        let entries: Vec<_> = (0..32)
+            .map(|i| {
+                (
+                    Variant::Uint(i),
+                    Variant::Symbol(Symbol::from(format!("s{}", i))),
+                )
+            })
+            .collect();
+
+        let mut map1 = HashMap::default();
+        for (k, v) in entries.iter().cloned() {
+            map1.insert(k, v);
+        }
+        let mut map2 = HashMap::default();
+        for (k, v) in entries.iter().rev().cloned() {
+            map2.insert(k, v);
+        }
+        let map1 = VariantMap::new(map1);
+        let map2 = VariantMap::new(map2);
+
+        assert_eq!(map1, map2);
+        assert_eq!(hash_of(&map1), hash_of(&map2));
+        assert_eq!(hash_of(&Variant::Map(map1)), hash_of(&Variant::Map(map2)));
+    }
+
+    #[test]
+    fn from_amqp_bytes_errors() {
+        assert!(matches!(
+            Variant::from_amqp_bytes(0xff, &[]),
+            Err(AmqpParseError::InvalidFormatCode(0xff))
+        ));
+        assert!(matches!(
+            Variant::from_amqp_bytes(0x50, &[]),
+            Err(AmqpParseError::Incomplete(Some(1)))
+        ));
+        assert!(matches!(
+            Variant::from_amqp_bytes(0x50, &[0x01, 0x02]),
+            Err(AmqpParseError::InvalidSize)
+        ));
+    }
+
+    #[test]
+    fn to_amqp_bytes_roundtrip() {
+        let values = vec![
+            Variant::Null,
+            Variant::Boolean(true),
+            Variant::Ubyte(7),
+            Variant::Uint(70_000),
+            Variant::Long(-1),
+            Variant::Double(OrderedFloat(2.5)),
+            Variant::Char('x'),
+            Variant::Decimal32(Decimal32([0xa2, 0x50, 0, 0x0f])),
+            Variant::Decimal64(Decimal64([0x22, 0x34, 0, 0, 0, 0, 0x30, 0x39])),
+            Variant::Decimal128(Decimal128([
+                0x22, 0x08, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x07,
+            ])),
+            Variant::Binary(Bytes::from_static(b"data")),
+            Variant::from("hello"),
+            Variant::Symbol(Symbol::from("sym")),
+        ];
+
+        for value in values {
+            let buf = value.to_amqp_bytes();
+            assert_eq!(Variant::from_amqp_bytes(buf[0], &buf[1..]).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn from_primitives() {
+        let uuid = Uuid::new_v4();
+        let ts = Utc.timestamp_millis(1_311_704_463_521);
+
+        assert_eq!(Variant::from(1u8), Variant::Ubyte(1));
+        assert_eq!(Variant::from(1u16), Variant::Ushort(1));
+        assert_eq!(Variant::from(1u32), Variant::Uint(1));
+        assert_eq!(Variant::from(1u64), Variant::Ulong(1));
+        assert_eq!(Variant::from(-1i8), Variant::Byte(-1));
+        assert_eq!(Variant::from(-1i16), Variant::Short(-1));
+        assert_eq!(Variant::from(-1i32), Variant::Int(-1));
+        assert_eq!(Variant::from(-1i64), Variant::Long(-1));
+        assert_eq!(Variant::from(1.5f32), Variant::Float(OrderedFloat(1.5)));
+        assert_eq!(Variant::from(1.5f64), Variant::Double(OrderedFloat(1.5)));
+        assert_eq!(Variant::from(true), Variant::Boolean(true));
+        assert_eq!(Variant::from(uuid), Variant::Uuid(uuid));
+        assert_eq!(Variant::from(ts), Variant::Timestamp(ts));
+    }
+
+    #[test]
+    fn map_builder() {
+        let map = VariantMap::builder()
+            .insert("count", 5u32)
+            .insert(String::from("name"), "test")
+            .insert("count", 6u32)
+            .build();
+        assert_eq!(map.map.len(), 2);
+        assert_eq!(map.get("count"), Some(&Variant::Uint(6)));
+        assert_eq!(map.get("name"), Some(&Variant::from("test")));
+        assert_eq!(map.get("missing"), None);
+    }
+
+    #[test]
+    fn map_get_by_str() {
+        let mut map = HashMap::default();
+        map.insert(Variant::from("string"), Variant::Uint(1));
+        map.insert(Variant::Symbol(Symbol::from("symbol")), Variant::Uint(2));
+        map.insert(
+            Variant::StaticSymbol(StaticSymbol("static")),
+            Variant::Uint(3),
+        );
+        map.insert(Variant::Uint(4), Variant::Uint(4));
+        let map = VariantMap::new(map);
+
+        assert_eq!(map.get("string"), Some(&Variant::Uint(1)));
+        assert_eq!(map.get("symbol"), Some(&Variant::Uint(2)));
+        assert_eq!(map.get("static"), Some(&Variant::Uint(3)));
+        assert_eq!(map.get("4"), None);
+    }
+
+    #[test]
+    fn vec_string_map_api() {
+        let mut map = VecStringMap::default();
+        assert_eq!(map.insert(Str::from("a"), Variant::Uint(1)), None);
+        assert_eq!(map.insert(Str::from("b"), Variant::Uint(2)), None);
+        assert_eq!(map.insert(Str::from("c"), Variant::Uint(3)), None);
+        assert_eq!(
+            map.insert(Str::from("a"), Variant::Uint(10)),
+            Some(Variant::Uint(1))
+        );
+        let keys: Vec<_> = map.iter().map(|(k, _)| k.as_str()).collect();
+        assert_eq!(keys, ["a", "b", "c"]);
+        assert_eq!(map.get("a"), Some(&Variant::Uint(10)));
+        assert_eq!(map.get("d"), None);
+        assert!(map.contains_key("b"));
+
+        assert_eq!(map.remove("b"), Some(Variant::Uint(2)));
+        assert_eq!(map.remove("b"), None);
+        assert!(!map.contains_key("b"));
+        let keys: Vec<_> = map.iter().map(|(k, _)| k.as_str()).collect();
+        assert_eq!(keys, ["a", "c"]);
+
+        let map: VecStringMap = vec![
+            (Str::from("x"), Variant::Uint(1)),
+            (Str::from("y"), Variant::Uint(2)),
+            (Str::from("x"), Variant::Uint(3)),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(
+            map.0,
+            vec![
+                (Str::from("x"), Variant::Uint(3)),
+                (Str::from("y"), Variant::Uint(2))
+            ]
+        );
+
+        let mut map = VecStringMap::from(vec![(Str::from("x"), Variant::Uint(1))]);
+        map.extend(vec![
+            (Str::from("y"), Variant::Uint(2)),
+            (Str::from("x"), Variant::Uint(3)),
+        ]);
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.get("x"), Some(&Variant::Uint(3)));
+    }
+
+    #[test]
+    fn vec_string_map_many_keys() {
+        // every key twice, second value wins, order of first insert is kept
+        let keys: Vec<_> = (0..20_000).map(|i| Str::from(i.to_string())).collect();
+        let map: VecStringMap = keys
+            .iter()
+            .chain(keys.iter())
+            .enumerate()
+            .map(|(i, k)| (k.clone(), Variant::Uint(i as u32)))
+            .collect();
+        assert_eq!(map.len(), keys.len());
+        assert_eq!(map[1].0, keys[1]);
+        assert_eq!(map.get("1"), Some(&Variant::Uint(20_001)));
+
+        // duplicates added through `Vec` api are skipped, last value wins
+        let mut map = map;
+        map.0
+            .extend(keys.iter().map(|k| (k.clone(), Variant::Null)));
+        let unique: Vec<_> = map.unique().collect();
+        assert_eq!(unique.len(), keys.len());
+        assert!(unique.iter().all(|item| item.1 == Variant::Null));
+        assert_eq!(unique[0].0, keys[0]);
+    }
+
+    #[test]
+    fn vec_string_map_codec() {
+        let map: VecStringMap = vec![
+            (Str::from("b"), Variant::Uint(1)),
+            (Str::from("a"), Variant::from("value")),
+        ]
+        .into_iter()
+        .collect();
+        let mut buf = BytesMut::with_capacity(map.encoded_size());
+        map.encode(&mut buf);
+        assert_eq!(buf.len(), map.encoded_size());
+        let decoded = VecStringMap::decode(&buf).unwrap().1;
+        assert_eq!(decoded, map);
+
+        // duplicates added through vec api are not encoded, last value wins
+        let mut dup = map.clone();
+        dup.push((Str::from("b"), Variant::Uint(2)));
+        let mut buf = BytesMut::with_capacity(dup.encoded_size());
+        dup.encode(&mut buf);
+        assert_eq!(buf.len(), dup.encoded_size());
+        let decoded = VecStringMap::decode(&buf).unwrap().1;
+        assert_eq!(decoded.len(), 2);
+        assert_eq!(decoded.get("b"), Some(&Variant::Uint(2)));
+
+        // decoded duplicate keys, last value wins
+        let mut buf = BytesMut::new();
+        buf.extend_from_slice(&[0xc1, 11, 4]);
+        for value in &[1u8, 2] {
+            Str::from("k").encode(&mut buf);
+            Variant::Ubyte(*value).encode(&mut buf);
+        }
+        let decoded = VecStringMap::decode(&buf).unwrap().1;
+        assert_eq!(decoded.0, vec![(Str::from("k"), Variant::Ubyte(2))]);
+    }
 }
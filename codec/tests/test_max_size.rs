@@ -0,0 +1,141 @@
+//! Inbound frame size limits.
+//!
+//! Test binary installs allocator recording the largest allocation,
+//! decoder must not buffer announced size of oversized frames.
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use bytes::{BufMut, Bytes, BytesMut};
+use ntex_amqp_codec::protocol::{Frame, Transfer, TransferBody};
+use ntex_amqp_codec::{
+    AmqpCodec, AmqpCodecError, AmqpFrame, Encode, MIN_MAX_FRAME_SIZE, PRE_OPEN_MAX_SIZE,
+};
+use ntex_codec::{Decoder, Encoder};
+
+/// Frame header size
+const HEADER_LEN: usize = 8;
+
+/// Read buffer high watermark of the decoder
+const SIZE_HIGH_WM: usize = 32768;
+
+/// Allocator recording the largest allocation of current thread
+struct CountingAlloc;
+
+thread_local! {
+    static LARGEST: Cell<usize> = Cell::new(0);
+}
+
+fn record(size: usize) {
+    let _ = LARGEST.try_with(|largest| {
+        if size > largest.get() {
+            largest.set(size)
+        }
+    });
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        record(layout.size());
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        record(new_size);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOC: CountingAlloc = CountingAlloc;
+
+/// Size of the largest allocation made by `f`
+fn largest_alloc<R>(f: impl FnOnce() -> R) -> (R, usize) {
+    LARGEST.with(|largest| largest.set(0));
+    let res = f();
+    (res, LARGEST.with(|largest| largest.get()))
+}
+
+fn header(size: u32) -> BytesMut {
+    let mut buf = BytesMut::with_capacity(HEADER_LEN);
+    buf.put_u32(size);
+    buf.put_slice(&[2, 0, 0, 0]);
+    buf
+}
+
+/// Transfer frame of `size` bytes
+fn transfer(size: usize) -> AmqpFrame {
+    let frame = |len| {
+        AmqpFrame::new(
+            0,
+            Frame::Transfer(Transfer {
+                handle: 0,
+                delivery_id: Some(1),
+                delivery_tag: Some(Bytes::from_static(b"tag")),
+                message_format: None,
+                settled: Some(false),
+                more: false,
+                rcv_settle_mode: None,
+                state: None,
+                resume: false,
+                aborted: false,
+                batchable: false,
+                body: Some(TransferBody::Data(Bytes::from(vec![0; len]))),
+            }),
+        )
+    };
+    let overhead = frame(300).encoded_size() - 300;
+    let frame = frame(size - overhead);
+    assert_eq!(frame.encoded_size(), size);
+    frame
+}
+
+fn check_limit(limit: usize) {
+    for size in &[limit - 1, limit] {
+        let codec = AmqpCodec::<AmqpFrame>::new().max_size(limit);
+        let mut buf = header(*size as u32);
+        let (res, largest) = largest_alloc(|| codec.decode(&mut buf));
+        assert!(res.unwrap().is_none());
+        assert!(buf.capacity() <= HEADER_LEN + SIZE_HIGH_WM);
+        assert!(largest <= HEADER_LEN + SIZE_HIGH_WM, "{}", largest);
+    }
+
+    for size in &[limit + 1, 0x8000_0000, u32::MAX as usize] {
+        let codec = AmqpCodec::<AmqpFrame>::new().max_size(limit);
+        let mut buf = header(*size as u32);
+        let (res, largest) = largest_alloc(|| codec.decode(&mut buf));
+        match res {
+            Err(AmqpCodecError::MaxSizeExceeded(announced, max)) => {
+                assert_eq!((announced, max), (*size, limit))
+            }
+            res => panic!("unexpected result: {:?}", res),
+        }
+        assert_eq!(buf.capacity(), HEADER_LEN);
+        // announced size is never buffered
+        assert!(largest < MIN_MAX_FRAME_SIZE, "{}", largest);
+    }
+}
+
+#[test]
+fn test_max_size() {
+    // before open
+    check_limit(PRE_OPEN_MAX_SIZE);
+
+    // after open, negotiated max frame size
+    check_limit(65536);
+
+    // smallest max frame size, frame at the limit is decoded
+    check_limit(MIN_MAX_FRAME_SIZE);
+    let codec = AmqpCodec::<AmqpFrame>::new().max_size(MIN_MAX_FRAME_SIZE);
+    let mut buf = BytesMut::new();
+    AmqpCodec::<AmqpFrame>::new()
+        .encode(transfer(MIN_MAX_FRAME_SIZE), &mut buf)
+        .unwrap();
+    assert_eq!(
+        codec.decode(&mut buf).unwrap(),
+        Some(transfer(MIN_MAX_FRAME_SIZE))
+    );
+}
@@ -0,0 +1,347 @@
+//! Validated link addresses
+use std::{convert::TryFrom, fmt};
+
+use ntex::router::IntoPattern;
+use ntex::util::ByteString;
+
+use crate::error::AddressError;
+use crate::Message;
+
+/// Max address length accepted by default
+pub const DEFAULT_MAX_ADDRESS_LEN: usize = 1024;
+
+/// Characters allowed by strict validation in addition to ascii alphanumerics
+const STRICT_CHARS: &str = "-._~/:";
+
+/// Character set accepted by address validation
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Strictness {
+    /// Any characters except control characters
+    Lenient,
+    /// No control characters or whitespace, `%` starts percent-encoded octet
+    Standard,
+    /// Ascii alphanumerics, `-._~/:` and percent-encoded octets only
+    Strict,
+}
+
+/// Address validation and normalization options.
+///
+/// Normalization is disabled by default, address is validated as is.
+#[derive(Clone, Debug)]
+pub struct AddressOptions {
+    strictness: Strictness,
+    max_len: usize,
+    trim: bool,
+    lowercase: bool,
+}
+
+impl Default for AddressOptions {
+    fn default() -> Self {
+        AddressOptions {
+            strictness: Strictness::Standard,
+            max_len: DEFAULT_MAX_ADDRESS_LEN,
+            trim: false,
+            lowercase: false,
+        }
+    }
+}
+
+impl AddressOptions {
+    /// Set character set strictness, default is `Strictness::Standard`
+    pub fn strictness(mut self, strictness: Strictness) -> Self {
+        self.strictness = strictness;
+        self
+    }
+
+    /// Set max address length in bytes, default is 1024
+    pub fn max_len(mut self, max_len: usize) -> Self {
+        self.max_len = max_len;
+        self
+    }
+
+    /// Remove leading and trailing whitespace before validation
+    pub fn trim(mut self, trim: bool) -> Self {
+        self.trim = trim;
+        self
+    }
+
+    /// Convert address to lower case before validation
+    pub fn lowercase(mut self, lowercase: bool) -> Self {
+        self.lowercase = lowercase;
+        self
+    }
+
+    fn validate(&self, addr: &str) -> Result<(), AddressError> {
+        if addr.is_empty() {
+            return Err(AddressError::Empty);
+        }
+        if addr.len() > self.max_len {
+            return Err(AddressError::TooLong(addr.len(), self.max_len));
+        }
+
+        let bytes = addr.as_bytes();
+        for (idx, ch) in addr.char_indices() {
+            if ch.is_control() {
+                return Err(AddressError::InvalidChar(ch, idx));
+            }
+            if self.strictness == Strictness::Lenient {
+                continue;
+            }
+            if ch.is_whitespace() {
+                return Err(AddressError::InvalidChar(ch, idx));
+            }
+            if ch == '%' {
+                let encoded = bytes.len() > idx + 2
+                    && bytes[idx + 1].is_ascii_hexdigit()
+                    && bytes[idx + 2].is_ascii_hexdigit();
+                if !encoded {
+                    return Err(AddressError::InvalidEncoding(idx));
+                }
+            } else if self.strictness == Strictness::Strict
+                && !(ch.is_ascii_alphanumeric() || STRICT_CHARS.contains(ch))
+            {
+                return Err(AddressError::InvalidChar(ch, idx));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Kind of node in prefixed address forms
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AddressKind {
+    Queue,
+    Topic,
+}
+
+/// Address prefix convention of broker family
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PrefixStyle {
+    /// Plain name, node kind is defined by broker configuration
+    Plain,
+    /// `queue://name` and `topic://name`, ActiveMQ family
+    Scheme,
+    /// `/queue/name` and `/topic/name`, RabbitMQ amqp 1.0 plugin
+    Path,
+}
+
+/// Broker family settings, see `Configuration::preset()`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Preset {
+    /// Plain addresses
+    Generic,
+    /// ActiveMQ Classic and Artemis, `queue://name` and `topic://name`
+    ActiveMq,
+    /// RabbitMQ amqp 1.0 plugin, `/queue/name` and `/topic/name`
+    RabbitMq,
+}
+
+impl Preset {
+    /// Address prefix convention of broker family
+    pub fn prefix_style(self) -> PrefixStyle {
+        match self {
+            Preset::Generic => PrefixStyle::Plain,
+            Preset::ActiveMq => PrefixStyle::Scheme,
+            Preset::RabbitMq => PrefixStyle::Path,
+        }
+    }
+}
+
+/// Link source or target address.
+///
+/// Addresses created by the application are validated, addresses
+/// received from peer are kept as is. Address is never normalized
+/// unless requested by `AddressOptions`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Address(ByteString);
+
+impl Address {
+    /// Validate address with default options
+    pub fn new<T: Into<ByteString>>(addr: T) -> Result<Address, AddressError> {
+        Address::with_options(addr, &AddressOptions::default())
+    }
+
+    /// Normalize and validate address
+    pub fn with_options<T: Into<ByteString>>(
+        addr: T,
+        opts: &AddressOptions,
+    ) -> Result<Address, AddressError> {
+        let mut addr = addr.into();
+        if opts.trim && addr.trim().len() != addr.len() {
+            addr = ByteString::from(addr.trim());
+        }
+        if opts.lowercase && addr.chars().any(char::is_uppercase) {
+            addr = ByteString::from(addr.to_lowercase());
+        }
+        opts.validate(&addr)?;
+        Ok(Address(addr))
+    }
+
+    /// Create address without validation
+    pub fn unchecked<T: Into<ByteString>>(addr: T) -> Address {
+        Address(addr.into())
+    }
+
+    /// Percent-encode characters that are not allowed by strict validation
+    pub fn encode(name: &str) -> Result<Address, AddressError> {
+        let mut addr = String::with_capacity(name.len());
+        for b in name.bytes() {
+            if b.is_ascii_alphanumeric() || STRICT_CHARS.as_bytes().contains(&b) {
+                addr.push(b as char);
+            } else {
+                addr.push_str(&format!("%{:02X}", b));
+            }
+        }
+        Address::with_options(
+            addr,
+            &AddressOptions::default().strictness(Strictness::Strict),
+        )
+    }
+
+    /// Address of queue or topic in broker's prefix convention
+    pub fn prefixed(
+        kind: AddressKind,
+        name: &str,
+        style: PrefixStyle,
+    ) -> Result<Address, AddressError> {
+        Address::prefixed_with(kind, name, style, &AddressOptions::default())
+    }
+
+    pub(crate) fn prefixed_with(
+        kind: AddressKind,
+        name: &str,
+        style: PrefixStyle,
+        opts: &AddressOptions,
+    ) -> Result<Address, AddressError> {
+        if name.is_empty() {
+            return Err(AddressError::Empty);
+        }
+        let kind = match kind {
+            AddressKind::Queue => "queue",
+            AddressKind::Topic => "topic",
+        };
+        match style {
+            PrefixStyle::Plain => Address::with_options(name, opts),
+            PrefixStyle::Scheme => Address::with_options(format!("{}://{}", kind, name), opts),
+            PrefixStyle::Path => Address::with_options(format!("/{}/{}", kind, name), opts),
+        }
+    }
+
+    /// Queue address in broker's prefix convention
+    pub fn queue(name: &str, style: PrefixStyle) -> Result<Address, AddressError> {
+        Address::prefixed(AddressKind::Queue, name, style)
+    }
+
+    /// Topic address in broker's prefix convention
+    pub fn topic(name: &str, style: PrefixStyle) -> Result<Address, AddressError> {
+        Address::prefixed(AddressKind::Topic, name, style)
+    }
+
+    /// Node kind and name of prefixed address, `None` for plain address
+    pub fn kind(&self) -> Option<(AddressKind, PrefixStyle, &str)> {
+        let kinds = [("queue", AddressKind::Queue), ("topic", AddressKind::Topic)];
+        for (prefix, kind) in kinds.iter() {
+            let name = self
+                .0
+                .strip_prefix(prefix)
+                .and_then(|name| name.strip_prefix("://"))
+                .map(|name| (PrefixStyle::Scheme, name))
+                .or_else(|| {
+                    self.0
+                        .strip_prefix('/')
+                        .and_then(|name| name.strip_prefix(prefix))
+                        .and_then(|name| name.strip_prefix('/'))
+                        .map(|name| (PrefixStyle::Path, name))
+                });
+            if let Some((style, name)) = name {
+                if !name.is_empty() {
+                    return Some((*kind, style, name));
+                }
+            }
+        }
+        None
+    }
+
+    /// Percent-decoded address, `None` if encoding is malformed or not utf-8
+    pub fn decode(&self) -> Option<String> {
+        let bytes = self.0.as_bytes();
+        let mut data = Vec::with_capacity(bytes.len());
+        let mut idx = 0;
+        while idx < bytes.len() {
+            if bytes[idx] == b'%' {
+                let hex = std::str::from_utf8(bytes.get(idx + 1..idx + 3)?).ok()?;
+                data.push(u8::from_str_radix(hex, 16).ok()?);
+                idx += 3;
+            } else {
+                data.push(bytes[idx]);
+                idx += 1;
+            }
+        }
+        String::from_utf8(data).ok()
+    }
+
+    /// Validate existing address, i.e. received from peer
+    pub fn validate(&self, opts: &AddressOptions) -> Result<(), AddressError> {
+        opts.validate(&self.0)
+    }
+
+    /// `to` property of the message
+    pub fn message_to(msg: &Message) -> Option<Address> {
+        msg.properties()
+            .and_then(|props| props.to.clone())
+            .map(Address)
+    }
+
+    /// `reply-to` property of the message
+    pub fn message_reply_to(msg: &Message) -> Option<Address> {
+        msg.reply_to().cloned().map(Address)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn into_inner(self) -> ByteString {
+        self.0
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl AsRef<str> for Address {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<Address> for ByteString {
+    fn from(addr: Address) -> ByteString {
+        addr.0
+    }
+}
+
+impl<'a> TryFrom<&'a str> for Address {
+    type Error = AddressError;
+
+    fn try_from(addr: &'a str) -> Result<Address, AddressError> {
+        Address::new(addr)
+    }
+}
+
+impl TryFrom<String> for Address {
+    type Error = AddressError;
+
+    fn try_from(addr: String) -> Result<Address, AddressError> {
+        Address::new(addr)
+    }
+}
+
+impl IntoPattern for Address {
+    fn patterns(&self) -> Vec<String> {
+        vec![self.0.to_string()]
+    }
+}
@@ -19,6 +19,14 @@ impl<T> Clone for Cell<T> {
     }
 }
 
+impl<T> Clone for WeakCell<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
 impl<T> Deref for Cell<T> {
     type Target = T;
 
@@ -0,0 +1,158 @@
+use std::{cell::RefCell, future::Future, marker, pin::Pin, rc::Rc, task::Context, task::Poll};
+
+use ntex::util::Bytes;
+use ntex::Stream;
+use ntex_amqp_codec::protocol::{Accepted, DeliveryState, Released, Transfer};
+
+use crate::error::AmqpProtocolError;
+use crate::rcvlink::ReceiverLink;
+
+/// Tags of deliveries that are committed but possibly not settled.
+///
+/// Peer could redeliver such deliveries, application should reconcile
+/// them with committed data.
+#[derive(Clone, Debug, Default)]
+pub struct PossibleDuplicates(Rc<RefCell<Vec<Bytes>>>);
+
+impl PossibleDuplicates {
+    /// Check if there are no possibly duplicated deliveries
+    pub fn is_empty(&self) -> bool {
+        self.0.borrow().is_empty()
+    }
+
+    /// Tags of possibly duplicated deliveries
+    pub fn tags(&self) -> Vec<Bytes> {
+        self.0.borrow().clone()
+    }
+
+    /// Take tags of possibly duplicated deliveries
+    pub fn take(&self) -> Vec<Bytes> {
+        std::mem::take(&mut *self.0.borrow_mut())
+    }
+}
+
+/// At-least-once processing of receiver link deliveries.
+///
+/// Delivery is accepted only after handler's commit future succeeds,
+/// failed commit releases delivery. Deliveries are processed one by one,
+/// next handler call starts after previous delivery is settled.
+pub struct Checkpoint<F, E> {
+    link: ReceiverLink,
+    handler: F,
+    error_outcome: Box<dyn Fn(&E) -> DeliveryState>,
+    duplicates: PossibleDuplicates,
+    _t: marker::PhantomData<E>,
+}
+
+impl<F, Fut, E> Checkpoint<F, E>
+where
+    F: Fn(Transfer) -> Fut,
+    Fut: Future<Output = Result<(), E>>,
+{
+    pub(crate) fn new(link: ReceiverLink, handler: F) -> Self {
+        Checkpoint {
+            link,
+            handler,
+            error_outcome: Box::new(|_| DeliveryState::Released(Released {})),
+            duplicates: PossibleDuplicates::default(),
+            _t: marker::PhantomData,
+        }
+    }
+
+    /// Map commit error to delivery outcome.
+    ///
+    /// By default failed deliveries are released
+    pub fn error_outcome<M>(mut self, f: M) -> Self
+    where
+        M: Fn(&E) -> DeliveryState + 'static,
+    {
+        self.error_outcome = Box::new(f);
+        self
+    }
+
+    /// Possibly duplicated deliveries.
+    ///
+    /// Delivery is reported if commit succeeded but settlement failed,
+    /// or if processing is dropped while commit is in progress.
+    pub fn possible_duplicates(&self) -> PossibleDuplicates {
+        self.duplicates.clone()
+    }
+
+    /// Process deliveries until link is detached.
+    ///
+    /// Dropping returned future stops processing.
+    pub async fn run(self) -> Result<(), AmqpProtocolError> {
+        let Checkpoint {
+            mut link,
+            handler,
+            error_outcome,
+            duplicates,
+            ..
+        } = self;
+
+        loop {
+            let transfer = match NextTransfer(&mut link).await {
+                Some(Ok(transfer)) => transfer,
+                Some(Err(err)) => return Err(err),
+                None => return Ok(()),
+            };
+            let delivery_id = if let Some(id) = transfer.delivery_id {
+                id
+            } else {
+                log::error!("Delivery id is not set, skip delivery");
+                continue;
+            };
+            let settled = transfer.settled.unwrap_or(false);
+            let epoch = link.epoch();
+            let mut gap = Gap {
+                tag: transfer.delivery_tag.clone(),
+                duplicates: &duplicates,
+            };
+
+            let state = match handler(transfer).await {
+                Ok(()) => DeliveryState::Accepted(Accepted {}),
+                Err(err) => {
+                    // nothing is committed, peer could redeliver
+                    gap.disarm();
+                    error_outcome(&err)
+                }
+            };
+            // link is migrated, peer redelivers delivery
+            if !settled && !link.inner.get_mut().settle(epoch, delivery_id, state)? {
+                continue;
+            }
+            gap.disarm();
+        }
+    }
+}
+
+/// Committed delivery that is not settled yet, tag is reported on drop
+struct Gap<'a> {
+    tag: Option<Bytes>,
+    duplicates: &'a PossibleDuplicates,
+}
+
+impl<'a> Gap<'a> {
+    fn disarm(&mut self) {
+        self.tag = None;
+    }
+}
+
+impl<'a> Drop for Gap<'a> {
+    fn drop(&mut self) {
+        if let Some(tag) = self.tag.take() {
+            log::trace!("Delivery {:?} is possibly duplicated", tag);
+            self.duplicates.0.borrow_mut().push(tag);
+        }
+    }
+}
+
+struct NextTransfer<'a>(&'a mut ReceiverLink);
+
+impl<'a> Future for NextTransfer<'a> {
+    type Output = Option<Result<Transfer, AmqpProtocolError>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut *self.0).poll_next(cx)
+    }
+}
@@ -1,11 +1,18 @@
+use std::{fmt, future::Future};
+
 use ntex::codec::{AsyncRead, AsyncWrite};
+use ntex::connect::Address;
 use ntex::framed::{Dispatcher as IoDispatcher, State as IoState, Timer};
-use ntex::service::{fn_service, Service};
-use ntex::util::Ready;
+use ntex::rt::net::TcpStream;
+use ntex::service::{fn_service, IntoServiceFactory, Service, ServiceFactory};
+use ntex::util::{Bytes, Ready};
 
 use crate::codec::{AmqpCodec, AmqpFrame};
-use crate::error::{DispatcherError, LinkError};
-use crate::{dispatcher::Dispatcher, Configuration, Connection, State};
+use crate::error::{DispatcherError, Error, LinkError};
+use crate::protocol::Open;
+use crate::{dispatcher::Dispatcher, types, Configuration, Connection, State};
+
+use super::{error::ConnectError, tls::TlsConnector, Connector};
 
 /// Mqtt client
 pub struct Client<Io, St = ()> {
@@ -13,9 +20,10 @@ pub struct Client<Io, St = ()> {
     state: IoState,
     codec: AmqpCodec<AmqpFrame>,
     connection: Connection,
-    keepalive: u16,
     remote_config: Configuration,
+    remote_open: Open,
     timer: Timer,
+    sasl_data: Option<Bytes>,
     st: State<St>,
 }
 
@@ -29,8 +37,8 @@ where
         state: IoState,
         codec: AmqpCodec<AmqpFrame>,
         connection: Connection,
-        keepalive: u16,
         remote_config: Configuration,
+        remote_open: Open,
         timer: Timer,
     ) -> Self {
         Client {
@@ -38,14 +46,35 @@ where
             state,
             codec,
             connection,
-            keepalive,
             remote_config,
+            remote_open,
             timer,
+            sasl_data: None,
             st: State::new(()),
         }
     }
 }
 
+impl<Io> Client<Io, ()>
+where
+    Io: AsyncRead + AsyncWrite + Unpin + 'static,
+{
+    /// Connect to amqp server over tls with default connector settings.
+    ///
+    /// See `Connector::connect_tls()`
+    pub fn connect_tls<A, C>(
+        address: A,
+        domain: &str,
+        connector: C,
+    ) -> impl Future<Output = Result<Self, ConnectError>>
+    where
+        A: Address,
+        C: TlsConnector<TcpStream, Stream = Io>,
+    {
+        Connector::new().connect_tls(address, domain, connector)
+    }
+}
+
 impl<Io, St> Client<Io, St>
 where
     St: 'static,
@@ -57,6 +86,29 @@ where
         self.connection.clone()
     }
 
+    #[inline]
+    /// Get `Open` frame received from remote peer
+    pub fn remote_open(&self) -> &Open {
+        &self.remote_open
+    }
+
+    #[inline]
+    /// Get remote configuration, derived from peer's `Open` frame
+    pub fn remote_config(&self) -> &Configuration {
+        &self.remote_config
+    }
+
+    #[inline]
+    /// Get additional data of sasl `Outcome` frame, sent by server on successful
+    /// authentication
+    pub fn sasl_additional_data(&self) -> Option<&Bytes> {
+        self.sasl_data.as_ref()
+    }
+
+    pub(super) fn set_sasl_additional_data(&mut self, data: Option<Bytes>) {
+        self.sasl_data = data;
+    }
+
     #[inline]
     /// Set connection state
     pub fn state<T: 'static>(self, st: T) -> Client<Io, T> {
@@ -65,9 +117,10 @@ where
             state: self.state,
             codec: self.codec,
             connection: self.connection,
-            keepalive: self.keepalive,
             remote_config: self.remote_config,
+            remote_open: self.remote_open,
             timer: self.timer,
+            sasl_data: self.sasl_data,
             st: State::new(st),
         }
     }
@@ -76,21 +129,49 @@ where
     ///
     /// Default handler closes connection on any control message.
     pub async fn start_default(self) -> Result<(), DispatcherError> {
+        self.run(fn_service(|_| {
+            Ready::<_, LinkError>::Err(LinkError::force_detach())
+        }))
+        .await
+    }
+
+    /// Run client with link service.
+    ///
+    /// Service handles links attached by server, i.e. `server::Router`,
+    /// control messages are handled by default handler.
+    pub async fn start<F, Pb>(self, service: F) -> Result<(), DispatcherError>
+    where
+        F: IntoServiceFactory<Pb>,
+        Pb: ServiceFactory<Config = State<St>, Request = types::Link<St>, Response = ()> + 'static,
+        Pb::InitError: fmt::Debug,
+        Error: From<Pb::Error>,
+    {
+        let service = service
+            .into_factory()
+            .new_service(self.st.clone())
+            .await
+            .map_err(|e| {
+                error!("Link service init error: {:?}", e);
+                DispatcherError::Service
+            })?;
+        self.run(service).await
+    }
+
+    async fn run<S>(self, service: S) -> Result<(), DispatcherError>
+    where
+        S: Service<Request = types::Link<St>, Response = ()> + 'static,
+        Error: From<S::Error>,
+    {
         let dispatcher = Dispatcher::new(
             self.st,
             self.connection,
-            fn_service(|_| Ready::<_, LinkError>::Err(LinkError::force_detach())),
+            service,
             fn_service(|_| Ready::<_, LinkError>::Ok(())),
-            self.remote_config.timeout_remote_secs(),
         )
         .map(|_| Option::<AmqpFrame>::None);
 
         IoDispatcher::new(self.io, self.codec, self.state, dispatcher, self.timer)
-            .keepalive_timeout(if self.keepalive != 0 {
-                self.keepalive + 5
-            } else {
-                0
-            })
+            .keepalive_timeout(0)
             .await
     }
 }
@@ -1,4 +1,4 @@
-use std::{future::Future, marker::PhantomData, time::Duration};
+use std::{future::Future, marker::PhantomData, rc::Rc, time::Duration};
 
 use ntex::codec::{AsyncRead, AsyncWrite};
 use ntex::connect::{self, Address, Connect};
@@ -13,11 +13,17 @@ use ntex::connect::openssl::{OpensslConnector, SslConnector};
 #[cfg(feature = "rustls")]
 use ntex::connect::rustls::{ClientConfig, RustlsConnector};
 
-use crate::codec::protocol::{Frame, Milliseconds, ProtocolId, SaslCode, SaslFrameBody, SaslInit};
-use crate::codec::{types::Symbol, AmqpCodec, AmqpFrame, ProtocolIdCodec, SaslFrame};
+use crate::codec::{
+    AmqpCodec, AmqpFrame, PerformativeCodec, ProtocolHeaderCodec, SaslFrame, PRE_OPEN_MAX_SIZE,
+};
+use crate::protocol::{
+    Fields, Frame, Milliseconds, ProtocolHeader, ProtocolId, ProtocolVersion, SaslCode,
+    SaslFrameBody, SaslInit, SaslResponse,
+};
 use crate::{error::ProtocolIdError, Configuration, Connection};
 
-use super::{connection::Client, error::ConnectError, SaslAuth};
+use super::sasl::{SaslAnonymous, SaslAuth, SaslExternal, SaslMechanism, SaslScramSha256};
+use super::{connection::Client, error::ConnectError, tls::TlsConnector};
 
 /// Amqp client connector
 pub struct Connector<A, T> {
@@ -29,6 +35,7 @@ pub struct Connector<A, T> {
     read_hw: u16,
     write_hw: u16,
     timer: Timer,
+    sasl: Option<Rc<dyn SaslMechanism>>,
     _t: PhantomData<A>,
 }
 
@@ -45,6 +52,7 @@ impl<A> Connector<A, ()> {
             write_hw: 8 * 1024,
             config: Configuration::default(),
             timer: Timer::with(Duration::from_secs(1)),
+            sasl: None,
             _t: PhantomData,
         }
     }
@@ -87,6 +95,14 @@ where
         self
     }
 
+    /// Set grace period in seconds added to local idle time-out.
+    ///
+    /// See `Configuration::idle_timeout_grace()`
+    pub fn idle_timeout_grace(&mut self, grace: u16) -> &mut Self {
+        self.config.idle_time_out_grace = grace;
+        self
+    }
+
     /// Set connection hostname
     ///
     /// Hostname is not set by default
@@ -95,6 +111,75 @@ where
         self
     }
 
+    /// Set prefix of connection ids.
+    ///
+    /// See `Configuration::connection_id_prefix()`
+    pub fn connection_id_prefix(&mut self, prefix: &str) -> &mut Self {
+        self.config.connection_id_prefix = Some(ByteString::from(prefix));
+        self
+    }
+
+    /// Set work budget of connection dispatcher.
+    ///
+    /// See `Configuration::poll_budget()`
+    pub fn poll_budget(&mut self, frames: usize, bytes: usize) -> &mut Self {
+        self.config.poll_budget(frames, bytes);
+        self
+    }
+
+    /// Set acceptable protocol versions, first one is proposed to server.
+    ///
+    /// See `Configuration::protocol_versions()`
+    pub fn protocol_versions(&mut self, versions: &[ProtocolVersion]) -> &mut Self {
+        self.config.protocol_versions(versions);
+        self
+    }
+
+    /// Register alternate codec of performative for protocol version.
+    ///
+    /// See `Configuration::performative_codec()`
+    pub fn performative_codec<C>(
+        &mut self,
+        version: ProtocolVersion,
+        descriptor: u64,
+        codec: C,
+    ) -> &mut Self
+    where
+        C: PerformativeCodec + 'static,
+    {
+        self.config.performative_codec(version, descriptor, codec);
+        self
+    }
+
+    /// Set locales advertised in `Open` frame.
+    ///
+    /// See `Configuration::outgoing_locales()` and `Configuration::incoming_locales()`
+    pub fn locales(&mut self, outgoing: &[&str], incoming: &[&str]) -> &mut Self {
+        self.config.outgoing_locales(outgoing);
+        self.config.incoming_locales(incoming);
+        self
+    }
+
+    /// Set connection capabilities advertised in `Open` frame.
+    ///
+    /// See `Configuration::offered_capabilities()` and `Configuration::desired_capabilities()`
+    pub fn capabilities(&mut self, offered: &[&str], desired: &[&str]) -> &mut Self {
+        self.config.offered_capabilities(offered);
+        self.config.desired_capabilities(desired);
+        self
+    }
+
+    /// Modify connection properties sent in `Open` frame.
+    ///
+    /// See `Configuration::properties()`
+    pub fn properties<F>(&mut self, f: F) -> &mut Self
+    where
+        F: FnOnce(&mut Fields),
+    {
+        self.config.properties(f);
+        self
+    }
+
     /// Set handshake timeout in milliseconds.
     ///
     /// Handshake includes `connect` packet and response `connect-ack`.
@@ -157,6 +242,62 @@ where
         self
     }
 
+    /// Authenticate with custom sasl mechanism.
+    ///
+    /// Sasl is not used by default
+    pub fn sasl<M: SaslMechanism + 'static>(mut self, mechanism: M) -> Self {
+        self.sasl = Some(Rc::new(mechanism));
+        self
+    }
+
+    /// Authenticate with sasl `PLAIN` mechanism.
+    pub fn sasl_plain<U, P>(self, username: U, password: P) -> Self
+    where
+        ByteString: From<U> + From<P>,
+    {
+        self.sasl(SaslAuth {
+            authz_id: ByteString::from_static(""),
+            authn_id: ByteString::from(username),
+            password: ByteString::from(password),
+        })
+    }
+
+    /// Authenticate with sasl `PLAIN` mechanism on behalf of `authz_id`.
+    ///
+    /// Server acts as `authz_id` if authenticated user is allowed to.
+    pub fn sasl_plain_authz<A, U, P>(self, authz_id: A, username: U, password: P) -> Self
+    where
+        ByteString: From<A> + From<U> + From<P>,
+    {
+        self.sasl(SaslAuth {
+            authz_id: ByteString::from(authz_id),
+            authn_id: ByteString::from(username),
+            password: ByteString::from(password),
+        })
+    }
+
+    /// Authenticate with sasl `ANONYMOUS` mechanism.
+    pub fn sasl_anonymous(self) -> Self {
+        self.sasl(SaslAnonymous)
+    }
+
+    /// Authenticate with sasl `EXTERNAL` mechanism.
+    ///
+    /// Identity is derived from transport, i.e. tls client certificate.
+    pub fn sasl_external(self) -> Self {
+        self.sasl(SaslExternal::default())
+    }
+
+    /// Authenticate with sasl `SCRAM-SHA-256` mechanism.
+    ///
+    /// Connect fails if server does not prove knowledge of the password.
+    pub fn sasl_scram_sha256<U, P>(self, username: U, password: P) -> Self
+    where
+        ByteString: From<U> + From<P>,
+    {
+        self.sasl(SaslScramSha256::new(username, password))
+    }
+
     /// Use custom connector
     pub fn connector<U>(self, connector: U) -> Connector<A, U>
     where
@@ -172,6 +313,7 @@ where
             read_hw: self.read_hw,
             write_hw: self.write_hw,
             timer: self.timer,
+            sasl: self.sasl,
             _t: PhantomData,
         }
     }
@@ -188,6 +330,7 @@ where
             read_hw: self.read_hw,
             write_hw: self.write_hw,
             timer: self.timer,
+            sasl: self.sasl,
             _t: PhantomData,
         }
     }
@@ -206,6 +349,7 @@ where
             read_hw: self.read_hw,
             write_hw: self.write_hw,
             timer: self.timer,
+            sasl: self.sasl,
             _t: PhantomData,
         }
     }
@@ -231,13 +375,44 @@ where
         }
     }
 
+    /// Connect to amqp server over tls.
+    ///
+    /// Tls protocol header is exchanged first, then tls handshake for
+    /// `domain` is performed and amqp negotiation continues over
+    /// encrypted stream, #5.2.1
+    pub fn connect_tls<C>(
+        &self,
+        address: A,
+        domain: &str,
+        connector: C,
+    ) -> impl Future<Output = Result<Client<C::Stream>, ConnectError>>
+    where
+        C: TlsConnector<T::Response>,
+    {
+        if self.handshake_timeout > 0 {
+            let fut = select(
+                delay_for(Duration::from_millis(self.handshake_timeout as u64)),
+                self._connect_tls(address, domain, connector),
+            );
+            Either::Left(async move {
+                match fut.await {
+                    Either::Left(_) => Err(ConnectError::HandshakeTimeout),
+                    Either::Right(res) => res.map_err(From::from),
+                }
+            })
+        } else {
+            Either::Right(self._connect_tls(address, domain, connector))
+        }
+    }
+
     /// Negotiate amqp protocol over opened socket
     pub fn negotiate<Io>(&self, io: Io) -> impl Future<Output = Result<Client<Io>, ConnectError>>
     where
         Io: AsyncRead + AsyncWrite + Unpin + 'static,
     {
-        trace!("Negotiation client protocol id: Amqp");
-
+        let config = self.config.clone();
+        let timer = self.timer.clone();
+        let sasl = self.sasl.clone();
         let state = State::with_params(
             self.read_hw,
             self.write_hw,
@@ -245,7 +420,13 @@ where
             self.disconnect_timeout,
         );
 
-        _connect_plain(io, state, self.config.clone(), self.timer.clone())
+        async move {
+            if let Some(sasl) = sasl {
+                _connect_sasl(io, state, sasl, config, timer).await
+            } else {
+                _connect_plain(io, state, config, timer).await
+            }
+        }
     }
 
     fn _connect(
@@ -255,6 +436,7 @@ where
         let fut = self.connector.call(Connect::new(address));
         let config = self.config.clone();
         let timer = self.timer.clone();
+        let sasl = self.sasl.clone();
         let state = State::with_params(
             self.read_hw,
             self.write_hw,
@@ -263,10 +445,56 @@ where
         );
 
         async move {
-            trace!("Negotiation client protocol id: Amqp");
-
             let io = fut.await?;
-            _connect_plain(io, state, config, timer).await
+            if let Some(sasl) = sasl {
+                _connect_sasl(io, state, sasl, config, timer).await
+            } else {
+                _connect_plain(io, state, config, timer).await
+            }
+        }
+    }
+
+    fn _connect_tls<C>(
+        &self,
+        address: A,
+        domain: &str,
+        connector: C,
+    ) -> impl Future<Output = Result<Client<C::Stream>, ConnectError>>
+    where
+        C: TlsConnector<T::Response>,
+    {
+        let fut = self.connector.call(Connect::new(address));
+        let domain = domain.to_string();
+        let config = self.config.clone();
+        let timer = self.timer.clone();
+        let sasl = self.sasl.clone();
+        let tls_state = State::with_params(
+            self.read_hw,
+            self.write_hw,
+            self.lw,
+            self.disconnect_timeout,
+        );
+        let state = State::with_params(
+            self.read_hw,
+            self.write_hw,
+            self.lw,
+            self.disconnect_timeout,
+        );
+
+        async move {
+            let mut io = fut.await?;
+            trace!("Negotiation client protocol id: AmqpTls");
+            negotiate_protocol(&mut io, &tls_state, &config, ProtocolId::AmqpTls).await?;
+
+            let io = connector
+                .connect(&domain, io)
+                .await
+                .map_err(ConnectError::Tls)?;
+            if let Some(sasl) = sasl {
+                _connect_sasl(io, state, sasl, config, timer).await
+            } else {
+                _connect_plain(io, state, config, timer).await
+            }
         }
     }
 
@@ -312,7 +540,7 @@ where
             self.disconnect_timeout,
         );
 
-        _connect_sasl(io, state, auth, config, timer)
+        _connect_sasl(io, state, Rc::new(auth), config, timer)
     }
 
     fn _connect_sasl(
@@ -330,14 +558,14 @@ where
             self.disconnect_timeout,
         );
 
-        async move { _connect_sasl(fut.await?, state, auth, config, timer).await }
+        async move { _connect_sasl(fut.await?, state, Rc::new(auth), config, timer).await }
     }
 }
 
 async fn _connect_sasl<T>(
     mut io: T,
     state: State,
-    auth: SaslAuth,
+    mechanism: Rc<dyn SaslMechanism>,
     config: Configuration,
     timer: Timer,
 ) -> Result<Client<T>, ConnectError>
@@ -345,67 +573,68 @@ where
     T: AsyncRead + AsyncWrite + Unpin + 'static,
 {
     trace!("Negotiation client protocol id: AmqpSasl");
+    negotiate_protocol(&mut io, &state, &config, ProtocolId::AmqpSasl).await?;
 
-    state
-        .send(&mut io, &ProtocolIdCodec, ProtocolId::AmqpSasl)
-        .await?;
-
-    let proto = state
-        .next(&mut io, &ProtocolIdCodec)
-        .await
-        .map_err(ConnectError::from)
-        .and_then(|res| {
-            res.ok_or_else(|| {
-                log::trace!("Amqp server is disconnected during handshake");
-                ConnectError::Disconnected
-            })
-        })?;
-    if proto != ProtocolId::AmqpSasl {
-        return Err(ConnectError::from(ProtocolIdError::Unexpected {
-            exp: ProtocolId::AmqpSasl,
-            got: proto,
-        }));
-    }
-
-    let codec = AmqpCodec::<SaslFrame>::new();
+    let codec = AmqpCodec::<SaslFrame>::new().max_size(PRE_OPEN_MAX_SIZE);
 
     // processing sasl-mechanisms
-    let _ = state
+    let sasl_frame = state
         .next(&mut io, &codec)
         .await
         .map_err(ConnectError::from)
         .and_then(|res| res.ok_or(ConnectError::Disconnected))?;
 
-    let initial_response =
-        SaslInit::prepare_response(&auth.authz_id, &auth.authn_id, &auth.password);
+    let name = mechanism.name();
+    if let SaslFrameBody::SaslMechanisms(mechanisms) = sasl_frame.body {
+        trace!(
+            "Server sasl mechanisms: {:?}",
+            mechanisms.sasl_server_mechanisms()
+        );
+        if !mechanisms.sasl_server_mechanisms().contains(&name) {
+            return Err(ConnectError::SaslMechanismNotSupported(name));
+        }
+    } else {
+        return Err(ConnectError::UnexpectedSaslFrame(Box::new(sasl_frame.body)));
+    }
 
     let sasl_init = SaslInit {
         hostname: config.hostname.clone(),
-        mechanism: Symbol::from("PLAIN"),
-        initial_response: Some(initial_response),
+        mechanism: name,
+        initial_response: mechanism.initial_response(),
     };
 
     state.send(&mut io, &codec, sasl_init.into()).await?;
 
-    // processing sasl-outcome
-    let sasl_frame = state
-        .next(&mut io, &codec)
-        .await
-        .map_err(ConnectError::from)
-        .and_then(|res| res.ok_or(ConnectError::Disconnected))?;
-
-    if let SaslFrame {
-        body: SaslFrameBody::SaslOutcome(outcome),
-    } = sasl_frame
-    {
-        if outcome.code() != SaslCode::Ok {
-            return Err(ConnectError::Sasl(outcome.code()));
+    // processing sasl-challenge and sasl-outcome
+    let additional_data = loop {
+        let sasl_frame = state
+            .next(&mut io, &codec)
+            .await
+            .map_err(ConnectError::from)
+            .and_then(|res| res.ok_or(ConnectError::Disconnected))?;
+
+        match sasl_frame.body {
+            SaslFrameBody::SaslChallenge(challenge) => {
+                trace!("Sasl challenge: {:?}", challenge);
+                let response = mechanism.challenge(challenge.challenge)?;
+                state
+                    .send(&mut io, &codec, SaslResponse { response }.into())
+                    .await?;
+            }
+            SaslFrameBody::SaslOutcome(outcome) => {
+                if outcome.code() != SaslCode::Ok {
+                    return Err(ConnectError::Sasl(outcome.code()));
+                }
+                mechanism.outcome(outcome.additional_data.as_ref())?;
+                break outcome.additional_data;
+            }
+            body => return Err(ConnectError::UnexpectedSaslFrame(Box::new(body))),
         }
-    } else {
-        return Err(ConnectError::Disconnected);
-    }
+    };
 
-    _connect_plain(io, state, config, timer).await
+    let mut client = _connect_plain(io, state, config, timer).await?;
+    client.set_sasl_additional_data(additional_data);
+    Ok(client)
 }
 
 async fn _connect_plain<T>(
@@ -418,31 +647,12 @@ where
     T: AsyncRead + AsyncWrite + Unpin + 'static,
 {
     trace!("Negotiation client protocol id: Amqp");
-
-    state
-        .send(&mut io, &ProtocolIdCodec, ProtocolId::Amqp)
-        .await?;
-
-    let proto = state
-        .next(&mut io, &ProtocolIdCodec)
-        .await
-        .map_err(ConnectError::from)
-        .and_then(|res| {
-            res.ok_or_else(|| {
-                log::trace!("Amqp server is disconnected during handshake");
-                ConnectError::Disconnected
-            })
-        })?;
-
-    if proto != ProtocolId::Amqp {
-        return Err(ConnectError::from(ProtocolIdError::Unexpected {
-            exp: ProtocolId::Amqp,
-            got: proto,
-        }));
-    }
+    let version = negotiate_protocol(&mut io, &state, &config, ProtocolId::Amqp).await?;
 
     let open = config.to_open();
-    let codec = AmqpCodec::<AmqpFrame>::new().max_size(config.max_frame_size as usize);
+    let codec = AmqpCodec::<AmqpFrame>::new()
+        .max_size(config.max_frame_size as usize)
+        .frame_table(config.frame_table(version));
 
     trace!("Open client amqp connection: {:?}", open);
     state
@@ -463,14 +673,14 @@ where
     if let Frame::Open(open) = frame.performative() {
         trace!("Open confirmed: {:?}", open);
         let remote_config = open.into();
-        let connection = Connection::new(state.clone(), &config, &remote_config);
+        let connection = Connection::new(state.clone(), &config, &remote_config, version);
         let client = Client::new(
             io,
             state,
             codec,
             connection,
-            config.timeout_secs() as u16,
             remote_config,
+            open.clone(),
             timer,
         );
         Ok(client)
@@ -478,3 +688,44 @@ where
         Err(ConnectError::ExpectOpenFrame(Box::new(frame)))
     }
 }
+
+/// Exchange protocol headers, client proposes preferred protocol version.
+///
+/// Server that does not support proposed version answers with its own one.
+async fn negotiate_protocol<T>(
+    io: &mut T,
+    state: &State,
+    config: &Configuration,
+    id: ProtocolId,
+) -> Result<ProtocolVersion, ConnectError>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    let version = config.protocol_version();
+    state
+        .send(io, &ProtocolHeaderCodec, ProtocolHeader { id, version })
+        .await?;
+
+    let header = state
+        .next(io, &ProtocolHeaderCodec)
+        .await
+        .map_err(ConnectError::from)
+        .and_then(|res| {
+            res.ok_or_else(|| {
+                log::trace!("Amqp server is disconnected during handshake");
+                ConnectError::Disconnected
+            })
+        })?;
+
+    if header.id != id {
+        return Err(ConnectError::from(ProtocolIdError::Unexpected {
+            exp: id,
+            got: header.id,
+        }));
+    }
+    if header.version != version {
+        log::trace!("Server protocol version: {}", header.version);
+        return Err(ConnectError::from(ProtocolIdError::Incompatible));
+    }
+    Ok(version)
+}
@@ -1,6 +1,8 @@
 use ntex::util::Either;
 
-use crate::codec::{protocol, AmqpCodecError, AmqpFrame, ProtocolIdError};
+use crate::codec::{types::Symbol, AmqpCodecError, AmqpFrame, ProtocolIdError};
+use crate::error::ErrorKind;
+use crate::protocol;
 
 /// Errors which can occur when attempting to handle amqp client connection.
 #[derive(Debug, Display, From)]
@@ -18,20 +20,86 @@ pub enum ConnectError {
     /// Expected open frame
     #[display(fmt = "Expect open frame, got: {:?}", _0)]
     ExpectOpenFrame(Box<AmqpFrame>),
-    /// Peer disconnected
+    /// Sasl outcome is not ok
     #[display(fmt = "Sasl error code: {:?}", _0)]
     Sasl(protocol::SaslCode),
+    #[from(ignore)]
+    /// Sasl mechanism is not offered by server
+    #[display(fmt = "Sasl mechanism is not supported by server: {}", _0)]
+    SaslMechanismNotSupported(Symbol),
+    /// Sasl mechanism failed to process server challenge
+    #[display(fmt = "Sasl challenge error: {}", _0)]
+    SaslChallenge(SaslError),
+    #[from(ignore)]
+    /// Unexpected sasl frame
+    #[display(fmt = "Unexpected sasl frame: {:?}", _0)]
+    UnexpectedSaslFrame(Box<protocol::SaslFrameBody>),
+    /// Peer disconnected
     #[display(fmt = "Peer disconnected")]
     Disconnected,
     /// Connect error
     #[display(fmt = "Connect error: {}", _0)]
     Connect(ntex::connect::ConnectError),
+    /// Tls handshake error
+    #[from(ignore)]
+    #[display(fmt = "Tls handshake error: {}", _0)]
+    Tls(std::io::Error),
     /// Unexpected io error
     Io(std::io::Error),
 }
 
 impl std::error::Error for ConnectError {}
 
+impl ConnectError {
+    /// Error classification
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            ConnectError::Codec(_) => ErrorKind::Malformed,
+            ConnectError::HandshakeTimeout => ErrorKind::Timeout,
+            ConnectError::ProtocolNegotiation(_) | ConnectError::ExpectOpenFrame(_) => {
+                ErrorKind::Protocol
+            }
+            ConnectError::Sasl(code) => match code {
+                protocol::SaslCode::Auth | protocol::SaslCode::Ok => ErrorKind::Unauthorized,
+                protocol::SaslCode::SysTemp => ErrorKind::Transport,
+                protocol::SaslCode::Sys => ErrorKind::Other,
+                protocol::SaslCode::SysPerm => ErrorKind::Rejected,
+            },
+            ConnectError::SaslMechanismNotSupported(_) => ErrorKind::Rejected,
+            ConnectError::SaslChallenge(_) => ErrorKind::Unauthorized,
+            ConnectError::UnexpectedSaslFrame(_) => ErrorKind::Protocol,
+            ConnectError::Disconnected
+            | ConnectError::Connect(_)
+            | ConnectError::Tls(_)
+            | ConnectError::Io(_) => ErrorKind::Transport,
+        }
+    }
+
+    /// Check if connect could succeed if retried
+    pub fn is_retryable(&self) -> bool {
+        self.kind().is_retryable()
+    }
+}
+
+/// Errors which can occur during sasl challenge processing
+#[derive(Debug, Display)]
+pub enum SaslError {
+    /// Mechanism does not expect challenges
+    #[display(fmt = "Unexpected sasl challenge")]
+    UnexpectedChallenge,
+    /// Challenge is not valid for mechanism
+    #[display(fmt = "Invalid sasl challenge")]
+    InvalidChallenge,
+    /// Server failed to prove its identity
+    #[display(fmt = "Invalid sasl server signature")]
+    ServerSignature,
+    /// Mechanism specific error
+    #[display(fmt = "{}", _0)]
+    Other(Box<dyn std::error::Error>),
+}
+
+impl std::error::Error for SaslError {}
+
 impl From<Either<AmqpCodecError, std::io::Error>> for ConnectError {
     fn from(err: Either<AmqpCodecError, std::io::Error>) -> Self {
         match err {
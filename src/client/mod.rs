@@ -1,17 +1,15 @@
-use ntex::util::ByteString;
-
 mod connection;
 mod connector;
 mod error;
+mod preflight;
+mod sasl;
+mod tls;
 
 pub use self::connection::Client;
 pub use self::connector::Connector;
-pub use self::error::ConnectError;
-
-#[derive(Debug)]
-/// Sasl authentication parameters
-pub struct SaslAuth {
-    pub authz_id: ByteString,
-    pub authn_id: ByteString,
-    pub password: ByteString,
-}
+pub use self::error::{ConnectError, SaslError};
+pub use self::preflight::{
+    Expectation, Preflight, PreflightCheck, PreflightError, PreflightReport,
+};
+pub use self::sasl::{SaslAnonymous, SaslAuth, SaslExternal, SaslMechanism, SaslScramSha256};
+pub use self::tls::TlsConnector;
@@ -0,0 +1,319 @@
+use std::{future::Future, pin::Pin, task::Context, task::Poll, time::Duration};
+
+use ntex::codec::{AsyncRead, AsyncWrite};
+use ntex::connect::{self, Address, Connect};
+use ntex::rt::time::sleep;
+use ntex::service::Service;
+use ntex::util::{select, ByteString, Bytes, Either};
+use ntex::Stream;
+use uuid::Uuid;
+
+use crate::codec::types::Symbol;
+use crate::error::AmqpProtocolError;
+use crate::protocol::{
+    Accepted, DeliveryState, Disposition, Error, ErrorCondition, Role, Transfer,
+};
+use crate::{rcvlink::ReceiverLink, Connection, Session};
+
+use super::{connector::Connector, error::ConnectError};
+
+/// Startup validation of broker expectations.
+///
+/// Connects to the broker, attaches and immediately detaches a link
+/// for each expectation and collects results into a report.
+/// Failed checks do not abort remaining checks.
+pub struct Preflight<A, T> {
+    connector: Connector<A, T>,
+    expectations: Vec<Expectation>,
+    timeout: Duration,
+}
+
+/// Broker expectation
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expectation {
+    /// Sender link could be attached to the address
+    Send(ByteString),
+    /// Receiver link could be attached to the address
+    Receive(ByteString),
+    /// Broker offers connection capability
+    Capability(Symbol),
+    /// Message could be sent to and received back from loopback address
+    Probe(ByteString),
+}
+
+/// Preflight check failure
+#[derive(Debug, Display)]
+pub enum PreflightError {
+    /// Broker refused or failed operation
+    #[display(fmt = "{}", _0)]
+    Protocol(AmqpProtocolError),
+    /// Capability is not offered by broker
+    #[display(fmt = "Capability is not offered")]
+    CapabilityNotOffered,
+    /// Probe message is not accepted
+    #[display(fmt = "Probe message is not accepted: {:?}", _0)]
+    NotAccepted(Option<DeliveryState>),
+    /// Check did not complete in time
+    #[display(fmt = "Check timed out")]
+    Timeout,
+}
+
+impl PreflightError {
+    /// Error reported by broker
+    pub fn error(&self) -> Option<&Error> {
+        match self {
+            PreflightError::Protocol(AmqpProtocolError::Closed(err))
+            | PreflightError::Protocol(AmqpProtocolError::SessionEnded(err))
+            | PreflightError::Protocol(AmqpProtocolError::LinkDetached(err)) => err.as_ref(),
+            PreflightError::NotAccepted(Some(DeliveryState::Rejected(rejected))) => {
+                rejected.error.as_ref()
+            }
+            _ => None,
+        }
+    }
+
+    /// Error condition reported by broker
+    pub fn condition(&self) -> Option<&ErrorCondition> {
+        self.error().map(|err| &err.condition)
+    }
+
+    /// Error description reported by broker
+    pub fn description(&self) -> Option<&ByteString> {
+        self.error().and_then(|err| err.description.as_ref())
+    }
+}
+
+/// Result of single expectation check
+#[derive(Debug)]
+pub struct PreflightCheck {
+    expectation: Expectation,
+    result: Result<(), PreflightError>,
+}
+
+impl PreflightCheck {
+    /// Checked expectation
+    pub fn expectation(&self) -> &Expectation {
+        &self.expectation
+    }
+
+    /// Check result
+    pub fn result(&self) -> Result<(), &PreflightError> {
+        self.result.as_ref().map(|_| ())
+    }
+
+    /// Check if expectation is met
+    pub fn is_ok(&self) -> bool {
+        self.result.is_ok()
+    }
+}
+
+/// Preflight report, checks are in the order of expectations
+#[derive(Debug)]
+pub struct PreflightReport {
+    checks: Vec<PreflightCheck>,
+}
+
+impl PreflightReport {
+    /// All checks
+    pub fn checks(&self) -> &[PreflightCheck] {
+        &self.checks
+    }
+
+    /// Failed checks
+    pub fn failures(&self) -> impl Iterator<Item = &PreflightCheck> {
+        self.checks.iter().filter(|check| !check.is_ok())
+    }
+
+    /// Check if all expectations are met
+    pub fn is_ok(&self) -> bool {
+        self.checks.iter().all(|check| check.is_ok())
+    }
+}
+
+impl<A, T> Preflight<A, T>
+where
+    A: Address,
+    T: Service<Request = Connect<A>, Error = connect::ConnectError>,
+    T::Response: AsyncRead + AsyncWrite + Unpin + 'static,
+{
+    /// Create preflight for connector
+    pub fn new(connector: Connector<A, T>) -> Self {
+        Preflight {
+            connector,
+            expectations: Vec::new(),
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    /// Expect sender link to the address
+    pub fn expect_send<U: Into<ByteString>>(mut self, address: U) -> Self {
+        self.expectations.push(Expectation::Send(address.into()));
+        self
+    }
+
+    /// Expect receiver link from the address
+    pub fn expect_receive<U: Into<ByteString>>(mut self, address: U) -> Self {
+        self.expectations.push(Expectation::Receive(address.into()));
+        self
+    }
+
+    /// Expect capability offered by broker
+    pub fn expect_capability<U: Into<Symbol>>(mut self, capability: U) -> Self {
+        self.expectations
+            .push(Expectation::Capability(capability.into()));
+        self
+    }
+
+    /// Send probe message to loopback address and receive it back
+    pub fn probe<U: Into<ByteString>>(mut self, address: U) -> Self {
+        self.expectations.push(Expectation::Probe(address.into()));
+        self
+    }
+
+    /// Set timeout for single check.
+    ///
+    /// By default timeout is set to 5 seconds
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Connect to broker and run checks
+    pub async fn run(&self, address: A) -> Result<PreflightReport, ConnectError> {
+        let client = self.connector.connect(address).await?;
+        let offered = client.remote_open().offered_capabilities.clone();
+        let sink = client.sink();
+        ntex::rt::spawn(async move {
+            let _ = client.start_default().await;
+        });
+
+        let mut checks = Vec::with_capacity(self.expectations.len());
+        for expectation in &self.expectations {
+            let result = if let Expectation::Capability(ref cap) = expectation {
+                if offered.as_ref().map(|c| c.contains(cap)).unwrap_or(false) {
+                    Ok(())
+                } else {
+                    Err(PreflightError::CapabilityNotOffered)
+                }
+            } else {
+                match select(sleep(self.timeout), check(&sink, expectation)).await {
+                    Either::Left(_) => Err(PreflightError::Timeout),
+                    Either::Right(res) => res,
+                }
+            };
+            log::trace!("Preflight check {:?}: {:?}", expectation, result);
+
+            checks.push(PreflightCheck {
+                expectation: expectation.clone(),
+                result,
+            });
+        }
+        let _ = sink.close().await;
+
+        Ok(PreflightReport { checks })
+    }
+}
+
+/// Every check uses its own session, links are dropped with session end
+async fn check(sink: &Connection, expectation: &Expectation) -> Result<(), PreflightError> {
+    let mut session = sink.open_session().await?;
+    let name = format!("preflight-{}", Uuid::new_v4().to_simple());
+
+    let result = match expectation {
+        Expectation::Send(address) => {
+            match session
+                .build_sender_link(name, address.clone())
+                .open()
+                .await
+            {
+                Ok(link) => link.close().await.map_err(PreflightError::from),
+                Err(err) => Err(err.into()),
+            }
+        }
+        Expectation::Receive(address) => {
+            match session
+                .build_receiver_link(name, address.clone())
+                .open()
+                .await
+            {
+                Ok(link) => link.close().await.map_err(PreflightError::from),
+                Err(err) => Err(err.into()),
+            }
+        }
+        Expectation::Probe(address) => {
+            match session
+                .build_receiver_link(format!("{}-rcv", name), address.clone())
+                .open()
+                .await
+            {
+                Ok(link) => {
+                    let result = probe(&mut session, link.clone(), name, address.clone()).await;
+                    let _ = link.close().await;
+                    result
+                }
+                Err(err) => Err(err.into()),
+            }
+        }
+        Expectation::Capability(_) => Ok(()),
+    };
+    let _ = session.end().await;
+    result
+}
+
+async fn probe(
+    session: &mut Session,
+    mut receiver: ReceiverLink,
+    name: String,
+    address: ByteString,
+) -> Result<(), PreflightError> {
+    receiver.set_link_credit(1);
+
+    let sender = session.build_sender_link(name, address).open().await?;
+    let result = sender.send(Bytes::from_static(b"preflight")).await;
+    let _ = sender.close().await;
+
+    let disp = result?;
+    match disp.state {
+        Some(DeliveryState::Accepted(_)) => (),
+        state => return Err(PreflightError::NotAccepted(state)),
+    }
+
+    // consume probe message
+    match Next(&mut receiver).await {
+        Some(Ok(transfer)) => {
+            settle(&receiver, &transfer);
+            Ok(())
+        }
+        Some(Err(err)) => Err(err.into()),
+        None => Err(AmqpProtocolError::LinkDetached(None).into()),
+    }
+}
+
+fn settle(receiver: &ReceiverLink, transfer: &Transfer) {
+    if let Some(id) = transfer.delivery_id {
+        receiver.send_disposition(Disposition {
+            role: Role::Receiver,
+            first: id,
+            last: None,
+            settled: true,
+            state: Some(DeliveryState::Accepted(Accepted {})),
+            batchable: false,
+        });
+    }
+}
+
+impl From<AmqpProtocolError> for PreflightError {
+    fn from(err: AmqpProtocolError) -> Self {
+        PreflightError::Protocol(err)
+    }
+}
+
+struct Next<'a>(&'a mut ReceiverLink);
+
+impl<'a> Future for Next<'a> {
+    type Output = Option<Result<Transfer, AmqpProtocolError>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut *self.0).poll_next(cx)
+    }
+}
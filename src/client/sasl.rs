@@ -0,0 +1,181 @@
+use std::cell::RefCell;
+
+use ntex::util::{ByteString, Bytes};
+
+use crate::codec::types::Symbol;
+use crate::protocol::SaslInit;
+use crate::scram::{self, ScramClient, ScramError};
+
+use super::error::SaslError;
+
+/// Client side sasl mechanism.
+///
+/// Connector sends `sasl-init` frame with mechanism name and initial response,
+/// then answers every `sasl-challenge` frame with the result of `challenge()`
+/// until server sends `sasl-outcome`.
+pub trait SaslMechanism {
+    /// Mechanism name, must be one of the mechanisms offered by server
+    fn name(&self) -> Symbol;
+
+    /// Initial response sent with `sasl-init` frame
+    fn initial_response(&self) -> Option<Bytes>;
+
+    /// Process server challenge, returned data is sent as `sasl-response`
+    fn challenge(&self, challenge: Bytes) -> Result<Bytes, SaslError>;
+
+    /// Process additional data of successful `sasl-outcome`
+    fn outcome(&self, _additional_data: Option<&Bytes>) -> Result<(), SaslError> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Sasl authentication parameters, `PLAIN` mechanism
+pub struct SaslAuth {
+    pub authz_id: ByteString,
+    pub authn_id: ByteString,
+    pub password: ByteString,
+}
+
+impl SaslMechanism for SaslAuth {
+    fn name(&self) -> Symbol {
+        Symbol::from_static("PLAIN")
+    }
+
+    fn initial_response(&self) -> Option<Bytes> {
+        Some(SaslInit::prepare_response(
+            &self.authz_id,
+            &self.authn_id,
+            &self.password,
+        ))
+    }
+
+    fn challenge(&self, _: Bytes) -> Result<Bytes, SaslError> {
+        Err(SaslError::UnexpectedChallenge)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// `ANONYMOUS` sasl mechanism
+pub struct SaslAnonymous;
+
+impl SaslMechanism for SaslAnonymous {
+    fn name(&self) -> Symbol {
+        Symbol::from_static("ANONYMOUS")
+    }
+
+    fn initial_response(&self) -> Option<Bytes> {
+        None
+    }
+
+    fn challenge(&self, _: Bytes) -> Result<Bytes, SaslError> {
+        Err(SaslError::UnexpectedChallenge)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// `EXTERNAL` sasl mechanism.
+///
+/// Identity is established by transport layer, for example
+/// with tls client certificate.
+pub struct SaslExternal {
+    /// Authorization identity, empty value means
+    /// identity derived from transport credentials
+    pub authz_id: ByteString,
+}
+
+impl SaslMechanism for SaslExternal {
+    fn name(&self) -> Symbol {
+        Symbol::from_static("EXTERNAL")
+    }
+
+    fn initial_response(&self) -> Option<Bytes> {
+        Some(Bytes::copy_from_slice(self.authz_id.as_bytes()))
+    }
+
+    fn challenge(&self, _: Bytes) -> Result<Bytes, SaslError> {
+        Err(SaslError::UnexpectedChallenge)
+    }
+}
+
+/// `SCRAM-SHA-256` sasl mechanism.
+///
+/// Channel binding is not used, server signature is verified
+/// with additional data of `sasl-outcome`.
+pub struct SaslScramSha256 {
+    username: ByteString,
+    password: ByteString,
+    max_iterations: u32,
+    state: RefCell<Option<(ScramClient, Option<[u8; 32]>)>>,
+}
+
+impl SaslScramSha256 {
+    pub fn new<U, P>(username: U, password: P) -> Self
+    where
+        ByteString: From<U> + From<P>,
+    {
+        SaslScramSha256 {
+            username: ByteString::from(username),
+            password: ByteString::from(password),
+            max_iterations: scram::DEFAULT_MAX_ITERATIONS,
+            state: RefCell::new(None),
+        }
+    }
+
+    /// Set max number of pbkdf2 iterations requested by server.
+    ///
+    /// Exchange fails if server asks for more iterations.
+    /// By default limit is 1 000 000.
+    pub fn max_iterations(mut self, max_iterations: u32) -> Self {
+        self.max_iterations = max_iterations;
+        self
+    }
+}
+
+impl SaslMechanism for SaslScramSha256 {
+    fn name(&self) -> Symbol {
+        Symbol::from_static(scram::MECHANISM)
+    }
+
+    fn initial_response(&self) -> Option<Bytes> {
+        let client =
+            ScramClient::new(&self.username, &self.password).max_iterations(self.max_iterations);
+        let first = client.client_first();
+        *self.state.borrow_mut() = Some((client, None));
+        Some(Bytes::from(first))
+    }
+
+    fn challenge(&self, challenge: Bytes) -> Result<Bytes, SaslError> {
+        let mut state = self.state.borrow_mut();
+        let (client, signature) = state.as_mut().ok_or(SaslError::UnexpectedChallenge)?;
+        if signature.is_some() {
+            return Err(SaslError::UnexpectedChallenge);
+        }
+
+        let server_first =
+            std::str::from_utf8(&challenge).map_err(|_| SaslError::InvalidChallenge)?;
+        let (client_final, server_signature) =
+            client.client_final(server_first).map_err(scram_error)?;
+        *signature = Some(server_signature);
+        Ok(Bytes::from(client_final))
+    }
+
+    fn outcome(&self, additional_data: Option<&Bytes>) -> Result<(), SaslError> {
+        let signature = self.state.borrow_mut().take().and_then(|(_, sig)| sig);
+        match (signature, additional_data) {
+            (Some(signature), Some(data)) => {
+                let server_final =
+                    std::str::from_utf8(data).map_err(|_| SaslError::ServerSignature)?;
+                scram::verify_server_final(server_final, &signature).map_err(scram_error)
+            }
+            _ => Err(SaslError::ServerSignature),
+        }
+    }
+}
+
+fn scram_error(err: ScramError) -> SaslError {
+    match err {
+        ScramError::InvalidServerSignature => SaslError::ServerSignature,
+        err => SaslError::Other(Box::new(err)),
+    }
+}
@@ -0,0 +1,47 @@
+use std::{future::Future, io, pin::Pin};
+
+use ntex::codec::{AsyncRead, AsyncWrite};
+
+/// Tls handshake of `amqp tls` connections, #5.2.1
+///
+/// Connector is called after server echoed tls protocol header,
+/// amqp protocol negotiation continues over returned stream.
+pub trait TlsConnector<Io>: 'static {
+    type Stream: AsyncRead + AsyncWrite + Unpin + 'static;
+
+    fn connect(
+        &self,
+        domain: &str,
+        io: Io,
+    ) -> Pin<Box<dyn Future<Output = io::Result<Self::Stream>>>>;
+}
+
+#[cfg(feature = "rustls")]
+mod rustls {
+    use tokio_rustls::{client::TlsStream, webpki::DNSNameRef, TlsConnector as RustlsConnector};
+
+    use super::*;
+
+    impl<Io> TlsConnector<Io> for RustlsConnector
+    where
+        Io: AsyncRead + AsyncWrite + Unpin + 'static,
+    {
+        type Stream = TlsStream<Io>;
+
+        fn connect(
+            &self,
+            domain: &str,
+            io: Io,
+        ) -> Pin<Box<dyn Future<Output = io::Result<TlsStream<Io>>>>> {
+            match DNSNameRef::try_from_ascii_str(domain) {
+                Ok(domain) => Box::pin(RustlsConnector::connect(self, domain, io)),
+                Err(_) => Box::pin(async {
+                    Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "Invalid dns name",
+                    ))
+                }),
+            }
+        }
+    }
+}
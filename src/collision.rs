@@ -0,0 +1,218 @@
+use std::collections::{hash_map::DefaultHasher, VecDeque};
+use std::{fmt, hash::Hasher};
+
+use ntex::util::{BytesMut, HashMap};
+use ntex_amqp_codec::protocol::{MessageId, TransferBody};
+use ntex_amqp_codec::{Decode, Encode, Message, MessageBody};
+
+/// Detector of `message-id` reuse with different payload.
+///
+/// Detector keeps ids and body hashes of the last `window` messages,
+/// older ids are forgotten. Ids are compared exactly, message that repeats
+/// both id and body (i.e. redelivery) is not reported.
+pub struct CollisionDetector {
+    window: usize,
+    hashes: HashMap<MessageId, u64>,
+    order: VecDeque<MessageId>,
+    reject: bool,
+    on_collision: Option<Box<dyn Fn(&MessageId)>>,
+}
+
+impl fmt::Debug for CollisionDetector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CollisionDetector")
+            .field("window", &self.window)
+            .field("len", &self.order.len())
+            .field("reject", &self.reject)
+            .finish()
+    }
+}
+
+impl CollisionDetector {
+    /// Create detector that remembers last `window` message ids
+    pub fn new(window: usize) -> Self {
+        assert!(window > 0, "Window must be greater than zero");
+        CollisionDetector {
+            window,
+            hashes: HashMap::default(),
+            order: VecDeque::with_capacity(window),
+            reject: false,
+            on_collision: None,
+        }
+    }
+
+    /// Fail send with `AmqpProtocolError::MessageIdCollision`.
+    ///
+    /// Applies to sender links only, by default collision is reported
+    /// to callback and message is sent.
+    pub fn reject(mut self) -> Self {
+        self.reject = true;
+        self
+    }
+
+    /// Call `f` with id of every detected collision
+    pub fn on_collision<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&MessageId) + 'static,
+    {
+        self.on_collision = Some(Box::new(f));
+        self
+    }
+
+    /// Number of remembered message ids
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    /// Check if no message id is remembered
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+
+    pub(crate) fn is_reject(&self) -> bool {
+        self.reject
+    }
+
+    /// Check and remember message, returns collided id
+    pub(crate) fn check_message(&mut self, msg: &Message) -> Option<MessageId> {
+        let id = msg.message_id()?;
+        if self.check(id, msg.body()) {
+            Some(id.clone())
+        } else {
+            None
+        }
+    }
+
+    /// Check and remember transfer body, opaque bodies are skipped
+    pub(crate) fn check_body(&mut self, body: &TransferBody) -> Option<MessageId> {
+        match body {
+            TransferBody::Message(msg) => self.check_message(msg),
+            TransferBody::Data(data) => match Message::decode(data) {
+                Ok(([], msg)) => self.check_message(&msg),
+                _ => None,
+            },
+        }
+    }
+
+    fn check(&mut self, id: &MessageId, body: &MessageBody) -> bool {
+        let hash = body_hash(body);
+        if let Some(prev) = self.hashes.get(id) {
+            if *prev == hash {
+                return false;
+            }
+            log::trace!("Message id {} is reused with different payload", id);
+            if let Some(ref f) = self.on_collision {
+                f(id);
+            }
+            return true;
+        }
+
+        if self.order.len() == self.window {
+            if let Some(oldest) = self.order.pop_front() {
+                self.hashes.remove(&oldest);
+            }
+        }
+        self.hashes.insert(id.clone(), hash);
+        self.order.push_back(id.clone());
+        false
+    }
+}
+
+fn body_hash(body: &MessageBody) -> u64 {
+    let mut buf = BytesMut::with_capacity(body.encoded_size());
+    body.encode(&mut buf);
+    let mut hasher = DefaultHasher::new();
+    hasher.write(&buf);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use ntex::util::Bytes;
+
+    use super::*;
+
+    fn body(data: &'static [u8]) -> MessageBody {
+        MessageBody {
+            data: vec![Bytes::from_static(data)],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_collision() {
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let seen2 = seen.clone();
+        let mut detector =
+            CollisionDetector::new(16).on_collision(move |id| seen2.borrow_mut().push(id.clone()));
+
+        assert!(!detector.check(&MessageId::Ulong(1), &body(b"one")));
+        assert!(!detector.check(&MessageId::Ulong(2), &body(b"two")));
+        // redelivery is not a collision
+        assert!(!detector.check(&MessageId::Ulong(1), &body(b"one")));
+        assert!(detector.check(&MessageId::Ulong(1), &body(b"other")));
+        assert_eq!(*seen.borrow(), vec![MessageId::Ulong(1)]);
+    }
+
+    #[test]
+    fn test_window() {
+        let mut detector = CollisionDetector::new(2);
+        assert!(!detector.check(&MessageId::Ulong(1), &body(b"one")));
+        assert!(!detector.check(&MessageId::Ulong(2), &body(b"two")));
+        assert!(!detector.check(&MessageId::Ulong(3), &body(b"three")));
+        assert_eq!(detector.len(), 2);
+
+        // id 1 is out of window
+        assert!(!detector.check(&MessageId::Ulong(1), &body(b"other")));
+        assert!(detector.check(&MessageId::Ulong(3), &body(b"other")));
+    }
+
+    #[test]
+    fn test_distinct_ids() {
+        let mut detector = CollisionDetector::new(1000);
+        let mut alarms = 0;
+        for id in 0..100_000u64 {
+            let data = Bytes::from(id.to_be_bytes().to_vec());
+            let body = MessageBody {
+                data: vec![data],
+                ..Default::default()
+            };
+            if detector.check(&MessageId::Ulong(id), &body) {
+                alarms += 1;
+            }
+            assert!(detector.len() <= 1000);
+            assert!(detector.hashes.len() <= 1000);
+        }
+        assert_eq!(alarms, 0);
+    }
+
+    #[test]
+    fn test_check_body() {
+        let mut detector = CollisionDetector::new(16);
+        let msg = Message::build()
+            .message_id(MessageId::Ulong(1))
+            .body(Bytes::from_static(b"one"))
+            .done();
+
+        let mut buf = BytesMut::with_capacity(msg.encoded_size());
+        msg.encode(&mut buf);
+        assert_eq!(detector.check_body(&TransferBody::Data(buf.freeze())), None);
+
+        let msg = Message::build()
+            .message_id(MessageId::Ulong(1))
+            .body(Bytes::from_static(b"two"))
+            .done();
+        assert_eq!(
+            detector.check_body(&TransferBody::Message(Box::new(msg))),
+            Some(MessageId::Ulong(1))
+        );
+
+        // opaque payload is skipped
+        assert_eq!(
+            detector.check_body(&TransferBody::Data(Bytes::from_static(b"raw"))),
+            None
+        );
+    }
+}
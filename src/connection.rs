@@ -1,68 +1,228 @@
-use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{cell, collections::VecDeque, future::Future, pin::Pin, task::Context, time::Duration};
 
 use ntex::channel::{condition::Condition, condition::Waiter, oneshot};
 use ntex::framed::State;
-use ntex::util::{HashMap, Ready};
+use ntex::rt::time::{sleep, sleep_until, Instant, Sleep};
+use ntex::task::LocalWaker;
+use ntex::util::{select, ByteString, Either, HashMap, Ready};
+use uuid::Uuid;
 
-use crate::cell::Cell;
-use crate::codec::protocol::{Begin, Close, End, Error, Frame};
+use crate::cell::{Cell, WeakCell};
 use crate::codec::{AmqpCodec, AmqpCodecError, AmqpFrame};
-use crate::error::AmqpProtocolError;
-use crate::session::{Session, SessionInner};
+use crate::error::{AmqpProtocolError, SessionOpenError};
+use crate::lifecycle::{is_clean_close, ConnectionState, SessionState, StateCell, StateChanges};
+use crate::protocol::{Begin, Close, ConnectionError, End, Error, Fields, Frame, ProtocolVersion};
+use crate::session::{Session, SessionBuilder, SessionInner};
+use crate::sndlink::SenderLinkInner;
 use crate::Configuration;
 
+/// Time to wait for remote Close frame
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Idle time-outs that differ more than this factor are reported
+const IDLE_TIMEOUT_RATIO: u32 = 10;
+
+/// Number of created connections, part of connection id
+static CONNECTION_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// New connection id, `prefix-num` where prefix is random token by default
+fn connection_id(prefix: Option<&ByteString>) -> ByteString {
+    let num = CONNECTION_COUNT.fetch_add(1, Ordering::Relaxed);
+    match prefix {
+        Some(prefix) => ByteString::from(format!("{}-{}", prefix, num)),
+        None => {
+            let token = Uuid::new_v4().to_simple().to_string();
+            ByteString::from(format!("{}-{}", &token[..8], num))
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Connection(pub(crate) Cell<ConnectionInner>);
 
+/// Token refills of rate limited sender links.
+///
+/// Connection keeps one timer for all links, dispatcher polls it and
+/// releases pending transfers of links with due refills.
+struct RefillTimer {
+    refills: Vec<(Instant, u64, WeakCell<SenderLinkInner>)>,
+    delay: Pin<Box<Sleep>>,
+    task: LocalWaker,
+}
+
+impl RefillTimer {
+    fn new() -> Self {
+        RefillTimer {
+            refills: Vec::new(),
+            delay: Box::pin(sleep_until(Instant::now())),
+            task: LocalWaker::new(),
+        }
+    }
+
+    fn schedule(&mut self, at: Instant, generation: u64, link: WeakCell<SenderLinkInner>) {
+        self.refills.push((at, generation, link));
+        self.task.wake();
+    }
+
+    /// Take due refills and re-arm timer for the earliest remaining one
+    fn poll(&mut self, cx: &mut Context<'_>) -> Vec<(u64, WeakCell<SenderLinkInner>)> {
+        self.task.register(cx.waker());
+
+        let now = Instant::now();
+        let mut due = Vec::new();
+        let mut next: Option<Instant> = None;
+        self.refills.retain(|(at, generation, link)| {
+            if *at <= now {
+                due.push((*generation, link.clone()));
+                false
+            } else {
+                next = Some(next.map_or(*at, |next| std::cmp::min(next, *at)));
+                true
+            }
+        });
+
+        if let Some(next) = next {
+            self.delay.as_mut().reset(next);
+            if self.delay.as_mut().poll(cx).is_ready() {
+                cx.waker().wake_by_ref();
+            }
+        }
+        due
+    }
+}
+
+/// Connection parameters in effect after `Open` exchange
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Negotiated {
+    /// Largest frame accepted by both sides
+    pub max_frame_size: u32,
+    /// Highest channel number accepted by both sides
+    pub channel_max: u16,
+    /// Local idle time-out including grace period
+    pub idle_timeout: Option<Duration>,
+    /// Idle time-out advertised by peer
+    pub remote_idle_timeout: Option<Duration>,
+}
+
 pub(crate) struct ConnectionInner {
-    st: ConnectionState,
+    id: ByteString,
+    st: StateCell<ConnectionState>,
     state: State,
     codec: AmqpCodec<AmqpFrame>,
     pub(crate) sessions: slab::Slab<ChannelState>,
     pub(crate) sessions_map: HashMap<u16, usize>,
+    // local sessions waiting for remote Begin, in order of sent Begin frames
+    opening: VecDeque<usize>,
     pub(crate) on_close: Condition,
     pub(crate) error: Option<AmqpProtocolError>,
+    write_error: cell::Cell<Option<AmqpProtocolError>>,
+    close_waiter: Option<oneshot::Sender<()>>,
     channel_max: usize,
     pub(crate) max_frame_size: usize,
+    negotiated: Negotiated,
+    remote_idle_timeout: Option<Duration>,
+    protocol_version: ProtocolVersion,
+    // dispatcher work budget, frames and bytes per poll
+    pub(crate) poll_budget: (usize, usize),
+    // default limit of buffered multi-frame delivery
+    pub(crate) max_reassembly_bytes: usize,
+    // credit of links routed by `Router`
+    pub(crate) link_credit: u32,
+    // values advertised by peer in `Open` frame
+    remote_container_id: ByteString,
+    remote_hostname: Option<ByteString>,
+    remote_channel_max: u16,
+    remote_properties: Option<Fields>,
+    refills: RefillTimer,
 }
 
 pub(crate) enum ChannelState {
-    Opening(Option<oneshot::Sender<Session>>, Cell<ConnectionInner>),
+    /// Local session is waiting for remote Begin, keeps local Begin
+    Opening(
+        Option<oneshot::Sender<Result<Session, AmqpProtocolError>>>,
+        Cell<ConnectionInner>,
+        Begin,
+    ),
     Established(Cell<SessionInner>),
-    #[allow(dead_code)]
-    Closing(Option<oneshot::Sender<Result<(), AmqpProtocolError>>>),
+    Closing(
+        Option<oneshot::Sender<Result<(), AmqpProtocolError>>>,
+        Cell<SessionInner>,
+    ),
 }
 
 impl ChannelState {
     fn is_opening(&self) -> bool {
-        matches!(self, ChannelState::Opening(_, _))
+        matches!(self, ChannelState::Opening(_, _, _))
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
-pub(crate) enum ConnectionState {
-    Normal,
-    Closing,
-    RemoteClose,
-    Drop,
-}
-
 impl Connection {
     pub(crate) fn new(
         state: State,
         local_config: &Configuration,
         remote_config: &Configuration,
+        protocol_version: ProtocolVersion,
     ) -> Connection {
+        let id = connection_id(local_config.connection_id_prefix.as_ref());
+        log::trace!("{}: Connection opened", id);
+
+        let remote_idle_timeout = if remote_config.idle_time_out > 0 {
+            Some(Duration::from_millis(remote_config.idle_time_out as u64))
+        } else {
+            None
+        };
+        if let (Some(local), Some(remote)) =
+            (local_config.local_idle_timeout(), remote_idle_timeout)
+        {
+            if remote * IDLE_TIMEOUT_RATIO < local || local * IDLE_TIMEOUT_RATIO < remote {
+                log::warn!(
+                    "{}: Idle time-outs are asymmetric, local: {:?} remote: {:?}",
+                    id,
+                    local,
+                    remote
+                );
+            }
+        }
+
+        let negotiated = Negotiated {
+            max_frame_size: std::cmp::min(
+                local_config.max_frame_size,
+                remote_config.max_frame_size,
+            ),
+            channel_max: std::cmp::min(local_config.channel_max, remote_config.channel_max) as u16,
+            idle_timeout: local_config.local_idle_timeout(),
+            remote_idle_timeout,
+        };
+
         Connection(Cell::new(ConnectionInner {
+            id,
             state,
-            codec: AmqpCodec::new(),
-            st: ConnectionState::Normal,
+            // frames larger than peer's max frame size are not sent
+            codec: AmqpCodec::new()
+                .max_outbound_size(remote_config.max_frame_size as usize)
+                .frame_table(local_config.frame_table(protocol_version)),
+            st: StateCell::new(ConnectionState::Opened, ConnectionState::is_terminal),
             sessions: slab::Slab::with_capacity(8),
             sessions_map: HashMap::default(),
+            opening: VecDeque::new(),
             error: None,
+            write_error: cell::Cell::new(None),
+            close_waiter: None,
             on_close: Condition::new(),
-            channel_max: local_config.channel_max,
+            channel_max: negotiated.channel_max as usize,
             max_frame_size: remote_config.max_frame_size as usize,
+            negotiated,
+            remote_idle_timeout,
+            protocol_version,
+            poll_budget: (local_config.max_poll_frames, local_config.max_poll_bytes),
+            max_reassembly_bytes: local_config.max_reassembly_bytes,
+            link_credit: local_config.link_credit,
+            remote_container_id: remote_config.container_id.clone().unwrap_or_default(),
+            remote_hostname: remote_config.hostname.clone(),
+            remote_channel_max: remote_config.channel_max as u16,
+            remote_properties: remote_config.properties.clone(),
+            refills: RefillTimer::new(),
         }))
     }
 
@@ -70,20 +230,80 @@ impl Connection {
     /// Force close connection
     pub fn force_close(&self) {
         let inner = self.0.get_mut();
-        inner.st = ConnectionState::Drop;
+        inner.set_state(ConnectionState::Closed);
         inner.state.force_close();
     }
 
+    /// Connection id, it is unique within the process.
+    ///
+    /// Sessions and links use it as prefix of their log ids.
+    pub fn id(&self) -> &str {
+        &self.0.get_ref().id
+    }
+
+    /// Idle time-out advertised by peer, `None` if peer does not require heartbeats.
+    ///
+    /// Empty frames are sent to peer often enough to keep it satisfied.
+    pub fn remote_idle_timeout(&self) -> Option<Duration> {
+        self.0.get_ref().remote_idle_timeout
+    }
+
+    /// Protocol version negotiated with protocol header exchange
+    pub fn protocol_version(&self) -> ProtocolVersion {
+        self.0.get_ref().protocol_version
+    }
+
+    /// Frame size, channel max and idle time-outs agreed with peer
+    pub fn negotiated(&self) -> Negotiated {
+        self.0.get_ref().negotiated
+    }
+
+    #[inline]
+    /// Container id advertised by peer
+    pub fn remote_container_id(&self) -> &ByteString {
+        &self.0.get_ref().remote_container_id
+    }
+
+    #[inline]
+    /// Hostname advertised by peer
+    pub fn remote_hostname(&self) -> Option<&ByteString> {
+        self.0.get_ref().remote_hostname.as_ref()
+    }
+
+    #[inline]
+    /// Channel max advertised by peer.
+    ///
+    /// Channels of local sessions are limited by `negotiated().channel_max`
+    pub fn remote_channel_max(&self) -> u16 {
+        self.0.get_ref().remote_channel_max
+    }
+
+    #[inline]
+    /// Connection properties advertised by peer
+    pub fn remote_properties(&self) -> Option<&Fields> {
+        self.0.get_ref().remote_properties.as_ref()
+    }
+
     #[inline]
     /// Check connection state
-    pub fn is_opened(&mut self) -> bool {
-        let inner = self.0.get_mut();
-        if inner.st != ConnectionState::Normal {
+    pub fn is_opened(&self) -> bool {
+        let inner = self.0.get_ref();
+        if !matches!(inner.st.get(), ConnectionState::Opened) {
             return false;
         }
         inner.error.is_none()
     }
 
+    /// Current connection state
+    pub fn state(&self) -> ConnectionState {
+        self.0.get_ref().st.get()
+    }
+
+    /// Subscribe to connection state changes
+    pub fn state_changes(&self) -> StateChanges<ConnectionState> {
+        self.0.get_ref().st.subscribe()
+    }
+
     /// Get waiter for on_close event
     pub fn on_close(&self) -> Waiter {
         self.0.get_ref().on_close.wait()
@@ -95,26 +315,84 @@ impl Connection {
     }
 
     /// Gracefully close connection
+    ///
+    /// Open sessions are ended, then Close frame is sent and returned
+    /// future resolves when remote Close is received. Pending
+    /// operations fail with `AmqpProtocolError::Closed`.
     pub fn close(&self) -> impl Future<Output = Result<(), AmqpProtocolError>> {
-        self.0.get_ref().state.close();
-        Ready::Ok(())
+        self.close_inner(None)
     }
 
-    // TODO: implement
-    /// Close connection with error
-    pub fn close_with_error<E>(
-        &self,
-        _err: E,
-    ) -> impl Future<Output = Result<(), AmqpProtocolError>>
+    /// Close connection with error, see `close()`
+    pub fn close_with_error<E>(&self, err: E) -> impl Future<Output = Result<(), AmqpProtocolError>>
     where
         Error: From<E>,
     {
-        self.0.get_ref().state.close();
-        Ready::Ok(())
+        self.close_inner(Some(err.into()))
+    }
+
+    fn close_inner(
+        &self,
+        error: Option<Error>,
+    ) -> impl Future<Output = Result<(), AmqpProtocolError>> {
+        let inner = self.0.get_mut();
+
+        if !matches!(inner.st.get(), ConnectionState::Opened) || inner.error.is_some() {
+            inner.state.close();
+            return Either::Left(Ready::Ok(()));
+        }
+
+        let (tx, rx) = oneshot::channel();
+        inner.set_state(ConnectionState::Closing);
+        inner.close_waiter = Some(tx);
+        inner.end_sessions(AmqpProtocolError::Closed(error.clone()));
+        inner.post_frame(AmqpFrame::new(
+            0,
+            Close {
+                error: error.clone(),
+            }
+            .into(),
+        ));
+        inner.set_error(AmqpProtocolError::Closed(error));
+
+        let con = self.clone();
+        Either::Right(async move {
+            if let Either::Right(_) = select(rx, sleep(CLOSE_TIMEOUT)).await {
+                log::trace!(
+                    "{}: Remote Close frame is not received, closing connection",
+                    con.id()
+                );
+            }
+            let inner = con.0.get_mut();
+            inner.set_state(ConnectionState::Closed);
+            inner.state.close();
+            Ok(())
+        })
     }
 
     /// Opens the session
     pub fn open_session(&self) -> impl Future<Output = Result<Session, AmqpProtocolError>> {
+        self.build_session().open()
+    }
+
+    /// Begin new session, same as `open_session()`.
+    ///
+    /// Future resolves after remote Begin is received, use `build_session()`
+    /// to configure session windows.
+    pub fn begin_session(&self) -> impl Future<Output = Result<Session, AmqpProtocolError>> {
+        self.open_session()
+    }
+
+    /// Create session builder
+    pub fn build_session(&self) -> SessionBuilder {
+        SessionBuilder::new(self.clone())
+    }
+
+    /// Send Begin frame and wait for remote Begin
+    pub(crate) fn begin_session_with(
+        &self,
+        begin: Begin,
+    ) -> impl Future<Output = Result<Session, AmqpProtocolError>> {
         let cell = self.0.clone();
         let inner = self.0.clone();
 
@@ -130,31 +408,37 @@ impl Connection {
                 let entry = inner.sessions.vacant_entry();
                 let token = entry.key();
 
-                if token >= inner.channel_max {
+                // channel of ended session is reused only after remote End
+                if token > inner.channel_max {
                     log::trace!("Too many channels: {:?}", token);
-                    Err(AmqpProtocolError::TooManyChannels)
+                    Err(AmqpProtocolError::ChannelLimitReached(
+                        inner.channel_max as u16,
+                    ))
                 } else {
-                    entry.insert(ChannelState::Opening(Some(tx), cell));
-
-                    let begin = Begin {
-                        remote_channel: None,
-                        next_outgoing_id: 1,
-                        incoming_window: std::u32::MAX,
-                        outgoing_window: std::u32::MAX,
-                        handle_max: std::u32::MAX,
-                        offered_capabilities: None,
-                        desired_capabilities: None,
-                        properties: None,
-                    };
+                    entry.insert(ChannelState::Opening(Some(tx), cell, begin.clone()));
+                    inner.opening.push_back(token);
                     inner.post_frame(AmqpFrame::new(token as u16, begin.into()));
 
-                    rx.await.map_err(|_| AmqpProtocolError::Disconnected)
+                    match rx.await {
+                        Ok(res) => res,
+                        Err(_) => Err(AmqpProtocolError::Disconnected),
+                    }
                 }
             }
         }
     }
 
-    /// Get session by remote id. This method panics if session does not exists or in opening/closing state.
+    /// Established session by local channel id
+    pub(crate) fn get_session(&self, id: usize) -> Option<Cell<SessionInner>> {
+        if let Some(ChannelState::Established(session)) = self.0.get_ref().sessions.get(id) {
+            Some(session.clone())
+        } else {
+            None
+        }
+    }
+
+    /// Established session by remote channel id, `None` if session
+    /// does not exist or is in opening/closing state
     pub(crate) fn get_remote_session(&self, id: usize) -> Option<Cell<SessionInner>> {
         let inner = self.0.get_ref();
         inner.sessions_map.get(&(id as u16)).and_then(|token| {
@@ -180,19 +464,7 @@ impl Connection {
         let entry = inner.sessions.vacant_entry();
         let token = entry.key();
 
-        let session = Cell::new(SessionInner::new(
-            token,
-            false,
-            Connection(cell),
-            token as u16,
-            begin.next_outgoing_id(),
-            begin.incoming_window(),
-            begin.outgoing_window(),
-        ));
-        entry.insert(ChannelState::Established(session));
-        inner.sessions_map.insert(channel_id, token);
-
-        let begin = Begin {
+        let local = Begin {
             remote_channel: Some(channel_id),
             next_outgoing_id: 1,
             incoming_window: std::u32::MAX,
@@ -203,30 +475,94 @@ impl Connection {
             properties: None,
         };
 
+        let session = Cell::new(SessionInner::new(
+            token,
+            false,
+            Connection(cell),
+            token as u16,
+            &local,
+            begin,
+        ));
+        entry.insert(ChannelState::Established(session));
+        inner.sessions_map.insert(channel_id, token);
+
         inner
             .state
             .write()
-            .encode(AmqpFrame::new(token as u16, begin.into()), &inner.codec)
+            .encode(AmqpFrame::new(token as u16, local.into()), &inner.codec)
             .map(|_| ())
     }
 
+    /// Post frame to write buffer.
+    ///
+    /// Frames are posted by sessions and links of any connection handle,
+    /// including while connection processes incoming frame, so connection
+    /// is accessed by shared reference only.
     pub(crate) fn post_frame(&self, frame: AmqpFrame) {
         #[cfg(feature = "frame-trace")]
         log::trace!("outcoming: {:#?}", frame);
 
-        let inner = self.0.get_mut();
-        if let Err(e) = inner.state.write().encode(frame, &inner.codec) {
-            inner.set_error(e.into())
+        self.0.get_ref().post_frame(frame)
+    }
+
+    /// Schedule token refill of rate limited sender link
+    pub(crate) fn schedule_refill(
+        &self,
+        at: Instant,
+        generation: u64,
+        link: WeakCell<SenderLinkInner>,
+    ) {
+        self.0.get_mut().refills.schedule(at, generation, link);
+    }
+
+    /// Release pending transfers of links with due token refills
+    pub(crate) fn poll_refills(&self, cx: &mut Context<'_>) {
+        let due = self.0.get_mut().refills.poll(cx);
+        for (generation, link) in due {
+            if let Some(link) = link.upgrade() {
+                link.get_mut().refill(generation);
+            }
+        }
+    }
+
+    /// Apply error of failed frame write
+    pub(crate) fn apply_write_error(&self) {
+        let inner = self.0.get_ref();
+        if let Some(err) = inner.write_error.take() {
+            self.0.get_mut().set_error(err);
         }
     }
 }
 
 impl ConnectionInner {
+    /// Change connection state, all transitions go through this method
+    pub(crate) fn set_state(&mut self, st: ConnectionState) {
+        log::trace!("{}: Connection state: {:?}", self.id, st);
+        self.st.set(st);
+    }
+
     pub(crate) fn set_error(&mut self, err: AmqpProtocolError) {
-        log::trace!("Set connection error: {:?}", err);
+        log::trace!("{}: Set connection error: {:?}", self.id, err);
+
+        // local close or remote close sets final state
+        if matches!(self.st.get(), ConnectionState::Opened) {
+            if is_clean_close(&err) {
+                self.set_state(ConnectionState::Closed);
+            } else {
+                self.set_state(ConnectionState::Failed(err.clone()));
+            }
+        }
+
         for (_, channel) in self.sessions.iter_mut() {
             match channel {
-                ChannelState::Opening(_, _) | ChannelState::Closing(_) => (),
+                ChannelState::Opening(ref mut tx, _, _) => {
+                    if let Some(tx) = tx.take() {
+                        let _ = tx.send(Err(err.clone()));
+                    }
+                }
+                ChannelState::Closing(_, ref mut ses) => {
+                    ses.get_mut().set_state(SessionState::closed(err.clone()));
+                }
                 ChannelState::Established(ref mut ses) => {
                     ses.get_mut().set_error(err.clone());
                 }
@@ -234,15 +570,74 @@ impl ConnectionInner {
         }
         self.sessions.clear();
         self.sessions_map.clear();
+        self.opening.clear();
 
         if self.error.is_none() {
             self.error = Some(err);
         }
     }
 
-    pub(crate) fn post_frame(&mut self, frame: AmqpFrame) {
+    pub(crate) fn post_frame(&self, frame: AmqpFrame) {
         if let Err(e) = self.state.write().encode(frame, &self.codec) {
-            self.set_error(e.into())
+            // sessions must not be touched from here, error is applied by dispatcher
+            log::error!("Cannot encode frame: {:?}", e);
+            let err = self.write_error.take().unwrap_or_else(|| e.into());
+            self.write_error.set(Some(err));
+            self.state.close();
+        }
+    }
+
+    /// Send End frame, session state is dropped.
+    ///
+    /// Returned receiver is resolved when remote End is received.
+    pub(crate) fn end_session(
+        &mut self,
+        id: usize,
+        error: Option<Error>,
+    ) -> oneshot::Receiver<Result<(), AmqpProtocolError>> {
+        let (tx, rx) = oneshot::channel();
+        let mut tx = Some(tx);
+
+        if let Some(channel) = self.sessions.get_mut(id) {
+            if let ChannelState::Established(ref session) = channel {
+                let session = session.clone();
+                session.get_mut().ending();
+                *channel = ChannelState::Closing(tx.take(), session);
+            }
+        }
+
+        if let Some(tx) = tx {
+            if let Some(ref err) = self.error {
+                let _ = tx.send(Err(err.clone()));
+            } else {
+                let _ = tx.send(Ok(()));
+            }
+        } else {
+            trace!("Ending session: {}", id);
+            let end = End { error };
+            self.post_frame(AmqpFrame::new(id as u16, end.into()));
+        }
+        rx
+    }
+
+    /// Send End frame for every established session.
+    ///
+    /// Pending session operations fail with `err`, remote End
+    /// frames are expected before remote Close.
+    fn end_sessions(&mut self, err: AmqpProtocolError) {
+        let mut ended = Vec::new();
+        for (id, channel) in self.sessions.iter_mut() {
+            if let ChannelState::Established(ref session) = channel {
+                let session = session.clone();
+                session.get_mut().set_error(err.clone());
+                *channel = ChannelState::Closing(None, session);
+                ended.push(id);
+            }
+        }
+        for id in ended {
+            trace!("Ending session on connection close: {}", id);
+            let end = End { error: None };
+            self.post_frame(AmqpFrame::new(id as u16, end.into()));
         }
     }
 
@@ -260,30 +655,46 @@ impl ConnectionInner {
 
         let id = remote_channel_id as usize;
 
-        if let Some(channel) = self.sessions.get_mut(id) {
-            if channel.is_opening() {
-                if let ChannelState::Opening(tx, cell) = channel {
-                    let session = Cell::new(SessionInner::new(
-                        id,
-                        true,
-                        Connection(cell.clone()),
-                        channel_id,
-                        begin.next_outgoing_id(),
-                        begin.incoming_window(),
-                        begin.outgoing_window(),
-                    ));
-                    self.sessions_map.insert(channel_id, id);
-
-                    // TODO: send end session if `tx` is None
-                    tx.take()
-                        .and_then(|tx| tx.send(Session::new(session.clone())).err());
-                    *channel = ChannelState::Established(session)
-                }
-            } else {
-                // TODO: send error response
+        if let Some(ChannelState::Opening(tx, cell, local)) = self.sessions.get_mut(id) {
+            self.opening.retain(|token| *token != id);
+            let session = Cell::new(SessionInner::new(
+                id,
+                true,
+                Connection(cell.clone()),
+                channel_id,
+                local,
+                begin,
+            ));
+            self.sessions_map.insert(channel_id, id);
+
+            let opened = tx.take().map_or(false, |tx| {
+                tx.send(Ok(Session::new(session.clone()))).is_ok()
+            });
+            self.sessions[id] = ChannelState::Established(session);
+
+            // nobody waits for the session anymore
+            if !opened {
+                trace!("Session opener is gone, ending session: {}", id);
+                let _ = self.end_session(id, None);
             }
         } else {
-            // TODO: rogue begin right now - do nothing. in future might indicate incoming attach
+            // peer answers Begin frames in order, invalid answer
+            // fails the oldest opening session only
+            error!(
+                "Remote Begin refers to unknown channel: {}",
+                remote_channel_id
+            );
+            let err = AmqpProtocolError::SessionOpen(SessionOpenError::InvalidRemoteChannel(
+                remote_channel_id,
+            ));
+            while let Some(token) = self.opening.pop_front() {
+                if self.sessions.get(token).map_or(false, |ch| ch.is_opening()) {
+                    if let ChannelState::Opening(Some(tx), _, _) = self.sessions.remove(token) {
+                        let _ = tx.send(Err(err));
+                    }
+                    break;
+                }
+            }
         }
     }
 
@@ -296,21 +707,30 @@ impl ConnectionInner {
         }
 
         if let Frame::Close(ref close) = frame.performative() {
-            self.set_error(AmqpProtocolError::Closed(close.error.clone()));
-
-            if self.st == ConnectionState::Closing {
-                log::trace!("Connection closed: {:?}", close);
+            if matches!(self.st.get(), ConnectionState::Closing) {
+                log::trace!("{}: Connection closed: {:?}", self.id, close);
+                self.set_state(ConnectionState::Closed);
+                self.set_error(AmqpProtocolError::Closed(close.error.clone()));
                 self.set_error(AmqpProtocolError::Disconnected);
+                if let Some(tx) = self.close_waiter.take() {
+                    let _ = tx.send(());
+                }
             } else {
-                log::trace!("Connection closed remotely: {:?}", close);
+                log::trace!("{}: Connection closed remotely: {:?}", self.id, close);
+                self.set_error(AmqpProtocolError::Closed(close.error.clone()));
                 let close = Close { error: None };
                 self.post_frame(AmqpFrame::new(0, close.into()));
-                self.st = ConnectionState::RemoteClose;
             }
             return Ok(None);
         }
 
         if self.error.is_some() {
+            if let (Frame::End(end), ConnectionState::Closing) =
+                (frame.performative(), self.st.get())
+            {
+                trace!("Session end is confirmed on connection close: {:?}", end);
+                return Ok(None);
+            }
             error!("Connection closed but new framed is received: {:?}", frame);
             return Ok(None);
         }
@@ -330,6 +750,21 @@ impl ConnectionInner {
         } else {
             // we dont have channel info, only Begin frame is allowed on new channel
             if let Frame::Begin(ref begin) = frame.performative() {
+                if frame.channel_id() as usize > self.channel_max {
+                    // #2.7.1 channel outside of supported range is a framing error
+                    log::trace!("{}: Begin on channel {}", self.id, frame.channel_id());
+                    let err = AmqpProtocolError::ChannelLimitReached(self.channel_max as u16);
+                    let close = Close {
+                        error: Some(Error {
+                            condition: ConnectionError::FramingError.into(),
+                            description: Some(ByteString::from(err.to_string())),
+                            info: None,
+                        }),
+                    };
+                    self.post_frame(AmqpFrame::new(0, close.into()));
+                    self.set_error(err.clone());
+                    return Err(err);
+                }
                 // response Begin for open session
                 if let Some(id) = begin.remote_channel() {
                     self.complete_session_creation(frame.channel_id(), id, begin);
@@ -348,7 +783,7 @@ impl ConnectionInner {
 
         // handle session frames
         match state {
-            ChannelState::Opening(_, _) => {
+            ChannelState::Opening(_, _, _) => {
                 error!("Unexpected opening state: {}", frame.channel_id());
                 Err(AmqpProtocolError::UnexpectedOpeningState(Box::new(
                     frame.into_parts().1,
@@ -382,9 +817,12 @@ impl ConnectionInner {
                     Ok(None)
                 }
             },
-            ChannelState::Closing(ref mut tx) => match frame.performative() {
+            ChannelState::Closing(ref mut tx, ref session) => match frame.performative() {
                 Frame::End(frm) => {
                     trace!("Session end is confirmed: {:?}", frm);
+                    session.get_mut().set_state(SessionState::closed(
+                        AmqpProtocolError::SessionEnded(frm.error.clone()),
+                    ));
                     if let Some(tx) = tx.take() {
                         let _ = tx.send(Ok(()));
                     }
@@ -401,3 +839,79 @@ impl ConnectionInner {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, sync::Once};
+
+    use super::*;
+
+    /// Logger collecting warnings of current thread
+    struct Warnings;
+
+    thread_local! {
+        static WARNINGS: RefCell<Vec<String>> = RefCell::new(Vec::new());
+    }
+
+    impl log::Log for Warnings {
+        fn enabled(&self, metadata: &log::Metadata<'_>) -> bool {
+            metadata.level() <= log::Level::Warn
+        }
+
+        fn log(&self, record: &log::Record<'_>) {
+            if self.enabled(record.metadata()) {
+                WARNINGS.with(|w| w.borrow_mut().push(record.args().to_string()));
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    /// Warnings logged while connection with given idle time-outs is created
+    fn idle_warnings(local: u16, remote: u32) -> Vec<String> {
+        static INIT: Once = Once::new();
+        INIT.call_once(|| {
+            log::set_logger(&Warnings).unwrap();
+            log::set_max_level(log::LevelFilter::Warn);
+        });
+        WARNINGS.with(|w| w.borrow_mut().clear());
+
+        let mut local_config = Configuration::default();
+        local_config.idle_timeout(local);
+        let mut remote_config = Configuration::default();
+        remote_config.idle_time_out = remote;
+        let con = Connection::new(
+            State::with_params(8 * 1024, 8 * 1024, 1024, 3),
+            &local_config,
+            &remote_config,
+            ProtocolVersion::V1_0_0,
+        );
+        assert_eq!(
+            con.remote_idle_timeout(),
+            if remote > 0 {
+                Some(Duration::from_millis(remote as u64))
+            } else {
+                None
+            }
+        );
+        WARNINGS.with(|w| w.borrow_mut().split_off(0))
+    }
+
+    #[test]
+    fn test_asymmetric_idle_timeouts() {
+        // remote is far smaller than local
+        let warnings = idle_warnings(60, 1500);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("Idle time-outs are asymmetric"));
+        assert!(warnings[0].contains("local: 60s remote: 1.5s"));
+
+        // local is far smaller than remote
+        assert_eq!(idle_warnings(1, 60_000).len(), 1);
+
+        // comparable time-outs, or no time-out on either side
+        assert!(idle_warnings(60, 10_000).is_empty());
+        assert!(idle_warnings(10, 60_000).is_empty());
+        assert!(idle_warnings(0, 1500).is_empty());
+        assert!(idle_warnings(60, 0).is_empty());
+    }
+}
@@ -1,8 +1,10 @@
 use std::fmt;
 
+use ntex::util::ByteString;
 use ntex_amqp_codec::protocol;
 
 use crate::cell::Cell;
+use crate::connection::Connection;
 use crate::error::AmqpProtocolError;
 use crate::rcvlink::ReceiverLink;
 use crate::session::{Session, SessionInner};
@@ -13,6 +15,7 @@ pub struct ControlFrame(pub(super) Cell<FrameInner>);
 pub(super) struct FrameInner {
     pub(super) kind: ControlFrameKind,
     pub(super) session: Option<Cell<SessionInner>>,
+    connection_id: ByteString,
 }
 
 impl fmt::Debug for ControlFrame {
@@ -28,6 +31,7 @@ pub enum ControlFrameKind {
     AttachReceiver(ReceiverLink),
     AttachSender(Box<protocol::Attach>, SenderLink),
     Flow(protocol::Flow, SenderLink),
+    ReceiverFlow(protocol::Flow, ReceiverLink),
     DetachSender(protocol::Detach, SenderLink),
     DetachReceiver(protocol::Detach, ReceiverLink),
     ProtocolError(AmqpProtocolError),
@@ -36,16 +40,19 @@ pub enum ControlFrameKind {
 
 impl ControlFrame {
     pub(crate) fn new(session: Cell<SessionInner>, kind: ControlFrameKind) -> Self {
+        let connection_id = ByteString::from(session.get_ref().connection().id());
         ControlFrame(Cell::new(FrameInner {
             session: Some(session),
             kind,
+            connection_id,
         }))
     }
 
-    pub(crate) fn new_kind(kind: ControlFrameKind) -> Self {
+    pub(crate) fn new_kind(connection: &Connection, kind: ControlFrameKind) -> Self {
         ControlFrame(Cell::new(FrameInner {
             session: None,
             kind,
+            connection_id: ByteString::from(connection.id()),
         }))
     }
 
@@ -65,4 +72,26 @@ impl ControlFrame {
     pub fn session(&self) -> Option<Session> {
         self.0.get_ref().session.clone().map(Session::new)
     }
+
+    /// Id of connection the frame belongs to
+    pub fn connection_id(&self) -> &str {
+        &self.0.get_ref().connection_id
+    }
+
+    /// Log id of the frame's link, session or connection
+    pub fn log_id(&self) -> String {
+        let inner = self.0.get_ref();
+        match inner.kind {
+            ControlFrameKind::AttachReceiver(ref link)
+            | ControlFrameKind::ReceiverFlow(_, ref link)
+            | ControlFrameKind::DetachReceiver(_, ref link) => link.log_id(),
+            ControlFrameKind::AttachSender(_, ref link)
+            | ControlFrameKind::Flow(_, ref link)
+            | ControlFrameKind::DetachSender(_, ref link) => link.log_id(),
+            _ => match inner.session {
+                Some(ref session) => session.get_ref().log_id(),
+                None => inner.connection_id.to_string(),
+            },
+        }
+    }
 }
@@ -0,0 +1,108 @@
+use crate::protocol::SequenceNo;
+
+/// Sender side of link flow control, #2.6.7
+///
+/// `delivery_count` uses serial number arithmetic and wraps around,
+/// it is advanced only by sent deliveries and by drain.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct SenderCredit {
+    initial_delivery_count: SequenceNo,
+    delivery_count: SequenceNo,
+    link_credit: u32,
+}
+
+impl SenderCredit {
+    pub(crate) fn new(initial_delivery_count: SequenceNo) -> Self {
+        SenderCredit {
+            initial_delivery_count,
+            delivery_count: initial_delivery_count,
+            link_credit: 0,
+        }
+    }
+
+    pub(crate) fn delivery_count(&self) -> SequenceNo {
+        self.delivery_count
+    }
+
+    pub(crate) fn link_credit(&self) -> u32 {
+        self.link_credit
+    }
+
+    /// Apply receiver's flow state.
+    ///
+    /// Credit is relative to receiver's delivery count, deliveries that are
+    /// in flight are subtracted from it. Absent delivery count means
+    /// receiver has not seen our attach yet. Delivery count ahead of ours
+    /// is a peer error, credit is not extended beyond `credit` in that case.
+    pub(crate) fn apply(&mut self, delivery_count: Option<SequenceNo>, credit: u32) {
+        let delivery_count = delivery_count.unwrap_or(self.initial_delivery_count);
+        let in_flight = self.delivery_count.wrapping_sub(delivery_count);
+        let in_flight = if (in_flight as i32) < 0 { 0 } else { in_flight };
+        self.link_credit = credit.saturating_sub(in_flight);
+
+        debug_assert!(self.link_credit <= credit);
+    }
+
+    /// Use one credit for a new delivery, `false` if there is no credit
+    pub(crate) fn consume(&mut self) -> bool {
+        if self.link_credit == 0 {
+            false
+        } else {
+            self.link_credit -= 1;
+            self.delivery_count = self.delivery_count.wrapping_add(1);
+            true
+        }
+    }
+
+    /// Use up all credit without sending, #2.6.7 drain
+    pub(crate) fn drain(&mut self) {
+        self.delivery_count = self.delivery_count.wrapping_add(self.link_credit);
+        self.link_credit = 0;
+    }
+
+    /// Credit is not valid anymore, i.e. link is detached
+    pub(crate) fn reset(&mut self) {
+        self.link_credit = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wrap_around() {
+        let mut credit = SenderCredit::new(u32::MAX);
+        credit.apply(None, 2);
+        assert!(credit.consume());
+        assert_eq!(credit.delivery_count(), 0);
+        assert!(credit.consume());
+        assert!(!credit.consume());
+
+        // receiver has seen one delivery
+        credit.apply(Some(0), 10);
+        assert_eq!(credit.link_credit(), 9);
+
+        // stale flow, all credit is in flight
+        credit.apply(Some(u32::MAX), 2);
+        assert_eq!(credit.link_credit(), 0);
+
+        // unlimited credit
+        credit.apply(Some(1), u32::MAX);
+        assert_eq!(credit.link_credit(), u32::MAX);
+
+        // peer's delivery count is ahead of ours
+        credit.apply(Some(5), 3);
+        assert_eq!(credit.link_credit(), 3);
+    }
+
+    #[test]
+    fn drain() {
+        let mut credit = SenderCredit::new(10);
+        credit.apply(Some(10), 5);
+        assert!(credit.consume());
+        credit.drain();
+        assert_eq!(credit.delivery_count(), 15);
+        assert_eq!(credit.link_credit(), 0);
+    }
+}
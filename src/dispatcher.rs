@@ -1,27 +1,216 @@
-use std::{cell::RefCell, fmt, future::Future, pin::Pin, task::Context, task::Poll, time};
+use std::{cell::RefCell, fmt, future::Future, pin::Pin, task::Context, task::Poll};
 
 use ntex::framed::DispatchItem;
-use ntex::rt::time::{sleep, Sleep};
 use ntex::service::Service;
-use ntex::util::Ready;
+use ntex::util::{ByteString, Ready};
 
 use crate::cell::Cell;
-use crate::codec::protocol::{Frame, Role};
 use crate::codec::{AmqpCodec, AmqpFrame};
-use crate::error::{AmqpProtocolError, DispatcherError, Error};
+use crate::error::{framing_error, AmqpProtocolError, DispatcherError, Error};
+use crate::hb::{Heartbeat, HeartbeatAction};
+use crate::protocol::{self, Close, Frame, Role};
 use crate::sndlink::{SenderLink, SenderLinkInner};
-use crate::{connection::Connection, types, ControlFrame, ControlFrameKind, State};
+use crate::{connection::Connection, types, ControlFrame, ControlFrameKind, LinkState, State};
 
-/// Amqp server dispatcher service.
+/// Result of incoming frame routing
+pub(crate) enum Route {
+    /// Frame is handled by connection, session or link
+    Handled,
+    /// Frame requires decision of control service
+    Control(ControlFrame),
+}
+
+/// Frame router, shared by client and server connections.
+///
+/// Router resolves channel to session and handle to link, enforces
+/// protocol rules and keeps idle time-out bookkeeping. Local idle time-out
+/// and heartbeat interval are derived from negotiated connection parameters,
+/// role specific policy is applied by dispatcher's link and control services.
+pub(crate) struct FrameRouter {
+    sink: Connection,
+    hb: RefCell<Option<Heartbeat>>,
+}
+
+impl FrameRouter {
+    pub(crate) fn new(sink: Connection) -> Self {
+        // close connection if nothing is received within local idle time-out,
+        // send empty frames at half of the remote idle time-out
+        let negotiated = sink.negotiated();
+        let local = negotiated.idle_timeout;
+        let remote = negotiated.remote_idle_timeout.map(|timeout| timeout / 2);
+
+        let hb = if local.is_some() || remote.is_some() {
+            Some(Heartbeat::new(local, remote))
+        } else {
+            None
+        };
+        FrameRouter {
+            sink,
+            hb: RefCell::new(hb),
+        }
+    }
+
+    /// Check idle time-outs, send heartbeat or close connection
+    pub(crate) fn poll_idle(&self, cx: &mut Context<'_>) -> Result<(), DispatcherError> {
+        if let Some(ref mut hb) = *self.hb.borrow_mut() {
+            match hb.poll(cx) {
+                HeartbeatAction::None => (),
+                HeartbeatAction::Heartbeat => {
+                    log::trace!("{}: Send keep-alive ping", self.sink.id());
+                    self.sink.post_frame(AmqpFrame::new(0, Frame::Empty));
+                }
+                HeartbeatAction::Close => {
+                    log::warn!(
+                        "{}: Nothing is received from peer within idle time-out",
+                        self.sink.id()
+                    );
+
+                    // best-effort Close, write buffer is flushed during
+                    // dispatcher shutdown within disconnect timeout
+                    let close = Close {
+                        error: Some(protocol::Error {
+                            condition: protocol::AmqpError::ResourceLimitExceeded.into(),
+                            description: Some(ByteString::from_static("idle timeout expired")),
+                            info: None,
+                        }),
+                    };
+                    self.sink.post_frame(AmqpFrame::new(0, close.into()));
+                    self.sink
+                        .0
+                        .get_mut()
+                        .set_error(AmqpProtocolError::IdleTimeout);
+                    return Err(DispatcherError::Protocol(AmqpProtocolError::IdleTimeout));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Route incoming frame to connection, session or link
+    pub(crate) fn route(&self, frame: AmqpFrame) -> Result<Route, DispatcherError> {
+        // any frame, including heartbeat, resets idle time-out
+        if let Some(ref mut hb) = *self.hb.borrow_mut() {
+            hb.update_local(true);
+        }
+
+        let frame = if let Some(frame) = self
+            .sink
+            .0
+            .get_mut()
+            .handle_frame(frame)
+            .map_err(DispatcherError::Protocol)?
+        {
+            frame
+        } else {
+            return Ok(Route::Handled);
+        };
+
+        let (channel_id, frame) = frame.into_parts();
+
+        // remote session
+        if let Frame::Begin(frm) = frame {
+            return self
+                .sink
+                .register_remote_session(channel_id, &frm)
+                .map(|_| Route::Handled)
+                .map_err(DispatcherError::Codec);
+        }
+
+        let id = channel_id as usize;
+        let session = match self.sink.get_remote_session(id) {
+            Some(session) => session,
+            None => return Err(AmqpProtocolError::UnknownSession(id, Box::new(frame)).into()),
+        };
+
+        match frame {
+            Frame::Flow(frm) => {
+                // apply flow to specific link
+                if let Some(link_id) = frm.handle {
+                    // TODO: close session if link is not found
+                    if let Some(link) = session.get_sender_link_by_handle(link_id) {
+                        let link = link.clone();
+                        return Ok(Route::Control(ControlFrame::new(
+                            session,
+                            ControlFrameKind::Flow(frm, link),
+                        )));
+                    }
+                    // receiver links report changes of flow properties only
+                    if let Some(link) = session.get_receiver_link_by_handle(link_id) {
+                        if frm.properties.is_some()
+                            && frm.properties.as_ref() != link.remote_flow_properties()
+                        {
+                            let link = link.clone();
+                            return Ok(Route::Control(ControlFrame::new(
+                                session,
+                                ControlFrameKind::ReceiverFlow(frm, link),
+                            )));
+                        }
+                    }
+                }
+                session.get_mut().apply_flow(&frm);
+                Ok(Route::Handled)
+            }
+            Frame::Attach(attach) => match attach.role {
+                Role::Receiver => {
+                    // remotly opened sender link
+                    let link =
+                        SenderLink::new(Cell::new(SenderLinkInner::with(&attach, session.clone())));
+                    Ok(Route::Control(ControlFrame::new(
+                        session,
+                        ControlFrameKind::AttachSender(Box::new(attach), link),
+                    )))
+                }
+                Role::Sender => {
+                    // receiver link
+                    let link = session
+                        .get_mut()
+                        .open_receiver_link(session.clone(), attach);
+                    Ok(Route::Control(ControlFrame::new(
+                        session,
+                        ControlFrameKind::AttachReceiver(link),
+                    )))
+                }
+            },
+            Frame::Detach(frm) => {
+                if let Some(link) = session.get_sender_link_by_handle(frm.handle) {
+                    let link = link.clone();
+                    Ok(Route::Control(ControlFrame::new(
+                        session,
+                        ControlFrameKind::DetachSender(frm, link),
+                    )))
+                } else if let Some(link) = session.get_receiver_link_by_handle(frm.handle) {
+                    let link = link.clone();
+                    Ok(Route::Control(ControlFrame::new(
+                        session,
+                        ControlFrameKind::DetachReceiver(frm, link),
+                    )))
+                } else {
+                    session.get_mut().handle_frame(Frame::Detach(frm));
+                    Ok(Route::Handled)
+                }
+            }
+            _ => Err(AmqpProtocolError::Unexpected(Box::new(frame)).into()),
+        }
+    }
+}
+
+/// Amqp dispatcher service.
+///
+/// Frame routing and protocol rules are implemented by `FrameRouter`,
+/// dispatcher passes routed frames to link and control services.
 pub(crate) struct Dispatcher<St, Sr, Ctl: Service> {
     state: State<St>,
     sink: Connection,
+    router: FrameRouter,
     service: Sr,
     ctl_service: Ctl,
     ctl_fut: RefCell<Option<(ControlFrame, Pin<Box<Ctl::Future>>)>>,
     shutdown: std::cell::Cell<bool>,
-    expire: RefCell<Pin<Box<Sleep>>>,
-    idle_timeout: usize,
+    budget: (usize, usize),
+    // frames and bytes processed within current poll
+    spent: std::cell::Cell<(usize, usize)>,
+    // readiness is reported, no frame is dispatched since
+    ready: std::cell::Cell<bool>,
 }
 
 impl<St, Sr, Ctl> Dispatcher<St, Sr, Ctl>
@@ -33,37 +222,48 @@ where
     Ctl::Error: 'static,
     Error: From<Sr::Error> + From<Ctl::Error>,
 {
-    pub(crate) fn new(
-        state: State<St>,
-        sink: Connection,
-        service: Sr,
-        ctl_service: Ctl,
-        idle_timeout: usize,
-    ) -> Self {
+    pub(crate) fn new(state: State<St>, sink: Connection, service: Sr, ctl_service: Ctl) -> Self {
+        let budget = sink.0.get_ref().poll_budget;
+
         Dispatcher {
+            budget,
+            spent: std::cell::Cell::new((0, 0)),
+            ready: std::cell::Cell::new(false),
+            router: FrameRouter::new(sink.clone()),
             sink,
             state,
             service,
             ctl_service,
-            idle_timeout,
             ctl_fut: RefCell::new(None),
             shutdown: std::cell::Cell::new(false),
-            expire: RefCell::new(Box::pin(sleep(time::Duration::from_secs(
-                idle_timeout as u64,
-            )))),
         }
     }
 
-    fn handle_idle_timeout(&self, cx: &mut Context<'_>) {
-        let idle_timeout = self.idle_timeout;
-        if idle_timeout > 0 {
-            let mut expire = self.expire.borrow_mut();
-            if Pin::new(&mut *expire).poll(cx).is_ready() {
-                log::trace!("Send keep-alive ping, timeout: {:?} secs", idle_timeout);
-                self.sink.post_frame(AmqpFrame::new(0, Frame::Empty));
-                *expire = Box::pin(sleep(time::Duration::from_secs(idle_timeout as u64)));
-                let _ = Pin::new(&mut *expire).poll(cx);
-            }
+    /// Account processed frame and size of its payload
+    fn spend(&self, bytes: usize) {
+        self.ready.set(false);
+        let (frames, spent) = self.spent.get();
+        self.spent.set((frames + 1, spent + bytes));
+    }
+
+    /// Yield to other tasks if work budget is exhausted.
+    ///
+    /// Task is woken up immediately, pending frames are processed on next poll.
+    fn poll_budget(&self, cx: &mut Context<'_>) -> Poll<()> {
+        let (frames, bytes) = self.spent.get();
+        let (max_frames, max_bytes) = self.budget;
+        if (max_frames != 0 && frames >= max_frames) || (max_bytes != 0 && bytes >= max_bytes) {
+            log::trace!(
+                "{}: Work budget is exhausted, frames: {} bytes: {}",
+                self.sink.id(),
+                frames,
+                bytes
+            );
+            self.spent.set((0, 0));
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        } else {
+            Poll::Ready(())
         }
     }
 
@@ -106,6 +306,9 @@ where
                 ControlFrameKind::Flow(_, ref link) => {
                     let _ = link.close_with_error(err);
                 }
+                ControlFrameKind::ReceiverFlow(_, ref link) => {
+                    let _ = link.close_with_error(err);
+                }
                 ControlFrameKind::DetachSender(_, ref link) => {
                     let _ = link.close_with_error(err);
                 }
@@ -124,6 +327,13 @@ where
                         .call(types::Link::new(link.clone(), self.state.clone()));
                     ntex::rt::spawn(async move {
                         let res = fut.await;
+                        // link is rejected or closed by service
+                        let state = link.state();
+                        if state.is_terminal()
+                            || matches!(state, LinkState::Detaching | LinkState::Resumable)
+                        {
+                            return;
+                        }
                         match res {
                             Ok(_) => link.close().await,
                             Err(err) => link.close_with_error(Error::from(err)).await,
@@ -136,7 +346,7 @@ where
                         .get_mut()
                         .confirm_sender_link_inner(&frm, link.inner.clone());
                 }
-                ControlFrameKind::Flow(ref frm, _) => {
+                ControlFrameKind::Flow(ref frm, _) | ControlFrameKind::ReceiverFlow(ref frm, _) => {
                     frame.session_cell().get_mut().apply_flow(frm);
                 }
                 ControlFrameKind::DetachSender(ref mut frm, _) => {
@@ -169,6 +379,15 @@ where
     type Future = Ready<Self::Response, Self::Error>;
 
     fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // readiness is checked before every frame, if previous readiness was
+        // not followed by a frame, previous poll is over and new one starts
+        if self.ready.replace(true) {
+            self.spent.set((0, 0));
+        }
+        self.sink.apply_write_error();
+        self.router.poll_idle(cx)?;
+        self.sink.poll_refills(cx);
+
         // process control frame
         let res0 = !self.handle_control_fut(cx)?;
 
@@ -184,7 +403,7 @@ where
             DispatcherError::Service
         })?;
 
-        if res0 || res1.is_pending() || res2.is_pending() {
+        if res0 || res1.is_pending() || res2.is_pending() || self.poll_budget(cx).is_pending() {
             Poll::Pending
         } else {
             Poll::Ready(Ok(()))
@@ -194,15 +413,17 @@ where
     fn poll_shutdown(&self, cx: &mut Context<'_>, is_error: bool) -> Poll<()> {
         if !self.shutdown.get() {
             self.shutdown.set(true);
+            self.sink.apply_write_error();
             let sink = self.sink.0.get_mut();
             if is_error {
                 sink.set_error(AmqpProtocolError::Disconnected);
             }
             sink.on_close.notify();
             sink.set_error(AmqpProtocolError::Disconnected);
-            let fut = self
-                .ctl_service
-                .call(ControlFrame::new_kind(ControlFrameKind::Closed(is_error)));
+            let fut = self.ctl_service.call(ControlFrame::new_kind(
+                &self.sink,
+                ControlFrameKind::Closed(is_error),
+            ));
             ntex::rt::spawn(async move {
                 let _ = fut.await;
             });
@@ -223,119 +444,32 @@ where
                 #[cfg(feature = "frame-trace")]
                 log::trace!("incoming: {:#?}", frame);
 
-                let item = try_ready_err!(self
-                    .sink
-                    .0
-                    .get_mut()
-                    .handle_frame(frame)
-                    .map_err(DispatcherError::Protocol));
-                let frame = if let Some(item) = item {
-                    item
-                } else {
-                    return Ready::Ok(());
-                };
-
-                let (channel_id, frame) = frame.into_parts();
-
-                // remote session
-                if let Frame::Begin(frm) = frame {
-                    return Ready::from(
-                        self.sink
-                            .register_remote_session(channel_id, &frm)
-                            .map_err(DispatcherError::Codec),
-                    );
-                }
-
-                let id = channel_id as usize;
-                let session = match self.sink.get_remote_session(id) {
-                    Some(session) => session,
-                    None => {
-                        return Ready::from(Err(AmqpProtocolError::UnknownSession(
-                            id,
-                            Box::new(frame),
-                        )
-                        .into()))
+                let size = match frame.performative() {
+                    Frame::Transfer(ref transfer) => {
+                        transfer.body.as_ref().map(|body| body.len()).unwrap_or(0)
                     }
+                    _ => 0,
                 };
+                self.spend(size);
 
-                let result = match frame {
-                    Frame::Flow(frm) => {
-                        // apply flow to specific link
-                        if let Some(link_id) = frm.handle {
-                            // TODO: close session if link is not found
-                            if let Some(link) = session.get_sender_link_by_handle(link_id) {
-                                let frame = ControlFrame::new(
-                                    session.clone(),
-                                    ControlFrameKind::Flow(frm, link.clone()),
-                                );
-                                *self.ctl_fut.borrow_mut() =
-                                    Some((frame.clone(), Box::pin(self.ctl_service.call(frame))));
-                                return Ready::from(Ok(()));
-                            }
-                        }
-                        session.get_mut().apply_flow(&frm);
-                        Ok(())
+                match self.router.route(frame) {
+                    Ok(Route::Handled) => Ready::Ok(()),
+                    Ok(Route::Control(frame)) => {
+                        *self.ctl_fut.borrow_mut() =
+                            Some((frame.clone(), Box::pin(self.ctl_service.call(frame))));
+                        Ready::Ok(())
                     }
-                    Frame::Attach(attach) => {
-                        match attach.role {
-                            Role::Receiver => {
-                                // remotly opened sender link
-                                let link = SenderLink::new(Cell::new(SenderLinkInner::with(
-                                    &attach,
-                                    session.clone(),
-                                )));
-                                let frame = ControlFrame::new(
-                                    session,
-                                    ControlFrameKind::AttachSender(Box::new(attach), link),
-                                );
-
-                                *self.ctl_fut.borrow_mut() =
-                                    Some((frame.clone(), Box::pin(self.ctl_service.call(frame))));
-                                Ok(())
-                            }
-                            Role::Sender => {
-                                // receiver link
-                                let link = session
-                                    .get_mut()
-                                    .open_receiver_link(session.clone(), attach);
-
-                                let frame = ControlFrame::new(
-                                    session,
-                                    ControlFrameKind::AttachReceiver(link),
-                                );
-                                *self.ctl_fut.borrow_mut() =
-                                    Some((frame.clone(), Box::pin(self.ctl_service.call(frame))));
-                                Ok(())
-                            }
-                        }
-                    }
-                    Frame::Detach(frm) => {
-                        if let Some(link) = session.get_sender_link_by_handle(frm.handle) {
-                            let frame = ControlFrame::new(
-                                session.clone(),
-                                ControlFrameKind::DetachSender(frm, link.clone()),
-                            );
-                            *self.ctl_fut.borrow_mut() =
-                                Some((frame.clone(), Box::pin(self.ctl_service.call(frame))));
-                        } else if let Some(link) = session.get_receiver_link_by_handle(frm.handle) {
-                            let frame = ControlFrame::new(
-                                session.clone(),
-                                ControlFrameKind::DetachReceiver(frm, link.clone()),
-                            );
-                            *self.ctl_fut.borrow_mut() =
-                                Some((frame.clone(), Box::pin(self.ctl_service.call(frame))));
-                        } else {
-                            session.get_mut().handle_frame(Frame::Detach(frm));
-                        }
-                        Ok(())
-                    }
-                    _ => Err(AmqpProtocolError::Unexpected(Box::new(frame)).into()),
-                };
-
-                Ready::from(result)
+                    Err(err) => Ready::from(Err(err)),
+                }
             }
             DispatchItem::EncoderError(err) | DispatchItem::DecoderError(err) => {
-                let frame = ControlFrame::new_kind(ControlFrameKind::ProtocolError(err.into()));
+                if let Some(error) = framing_error(&err) {
+                    // best-effort Close, connection is dropped after control frame
+                    let close = Close { error: Some(error) };
+                    self.sink.post_frame(AmqpFrame::new(0, close.into()));
+                }
+                let frame =
+                    ControlFrame::new_kind(&self.sink, ControlFrameKind::ProtocolError(err.into()));
                 *self.ctl_fut.borrow_mut() =
                     Some((frame.clone(), Box::pin(self.ctl_service.call(frame))));
                 Ready::from(Ok(()))
@@ -360,3 +494,279 @@ where
         }
     }
 }
+
+impl<St, Sr, Ctl: Service> Drop for Dispatcher<St, Sr, Ctl> {
+    fn drop(&mut self) {
+        // dispatcher is dropped without shutdown, release pending operations.
+        // session and link inners are kept alive by application handles.
+        if !self.shutdown.get() {
+            self.shutdown.set(true);
+            self.sink.apply_write_error();
+            let sink = self.sink.0.get_mut();
+            sink.set_error(AmqpProtocolError::Disconnected);
+            sink.on_close.notify();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::VecDeque, time::Duration};
+
+    use ntex::framed::State;
+    use ntex::service::fn_service;
+
+    use super::*;
+    use crate::protocol::{
+        Attach, Begin, Detach, Flow, ProtocolVersion, ReceiverSettleMode, SenderSettleMode,
+    };
+    use crate::Configuration;
+
+    struct PollIdle<'a>(&'a FrameRouter);
+
+    impl<'a> Future for PollIdle<'a> {
+        type Output = Result<(), DispatcherError>;
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+            Poll::Ready(self.0.poll_idle(cx))
+        }
+    }
+
+    /// Single poll of framed dispatcher, frames are dispatched while
+    /// service is ready. Resolves to number of dispatched frames.
+    struct DispatchPoll<'a, S>(&'a S, &'a mut VecDeque<AmqpFrame>);
+
+    impl<'a, S> Future for DispatchPoll<'a, S>
+    where
+        S: Service<Request = DispatchItem<AmqpCodec<AmqpFrame>>>,
+        S::Error: fmt::Debug,
+    {
+        type Output = usize;
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+            let this = self.get_mut();
+            let mut count = 0;
+            loop {
+                if let Poll::Ready(res) = this.0.poll_ready(cx) {
+                    res.unwrap();
+                } else {
+                    return Poll::Ready(count);
+                }
+                if let Some(frame) = this.1.pop_front() {
+                    let _ = this.0.call(DispatchItem::Item(frame));
+                    count += 1;
+                } else {
+                    return Poll::Ready(count);
+                }
+            }
+        }
+    }
+
+    fn dispatcher(
+        max_frames: usize,
+        max_bytes: usize,
+    ) -> impl Service<Request = DispatchItem<AmqpCodec<AmqpFrame>>, Error = DispatcherError> {
+        let mut cfg = Configuration::default();
+        cfg.poll_budget(max_frames, max_bytes);
+        let sink = Connection::new(
+            State::with_params(8 * 1024, 8 * 1024, 1024, 3),
+            &cfg,
+            &cfg,
+            ProtocolVersion::V1_0_0,
+        );
+        Dispatcher::new(
+            crate::State::new(()),
+            sink,
+            fn_service(|_: types::Link<()>| Ready::<(), Error>::Ok(())),
+            fn_service(|_: ControlFrame| Ready::<(), Error>::Ok(())),
+        )
+    }
+
+    /// Dispatch `count` empty frames, frames dispatched by each poll
+    async fn dispatch<S>(disp: &S, count: usize) -> Vec<usize>
+    where
+        S: Service<Request = DispatchItem<AmqpCodec<AmqpFrame>>>,
+        S::Error: fmt::Debug,
+    {
+        let mut frames: VecDeque<_> = (0..count)
+            .map(|_| AmqpFrame::new(0, Frame::Empty))
+            .collect();
+        let mut polls = Vec::new();
+        while !frames.is_empty() {
+            polls.push(DispatchPoll(disp, &mut frames).await);
+        }
+        polls
+    }
+
+    fn router(local_idle: u32, remote_idle: u32) -> FrameRouter {
+        let mut local = Configuration::default();
+        local.idle_time_out = local_idle;
+        let mut remote = Configuration::default();
+        remote.idle_time_out = remote_idle;
+        FrameRouter::new(Connection::new(
+            State::with_params(8 * 1024, 8 * 1024, 1024, 3),
+            &local,
+            &remote,
+            ProtocolVersion::V1_0_0,
+        ))
+    }
+
+    fn begin() -> AmqpFrame {
+        let begin = Begin {
+            remote_channel: None,
+            next_outgoing_id: 1,
+            incoming_window: std::u32::MAX,
+            outgoing_window: std::u32::MAX,
+            handle_max: std::u32::MAX,
+            offered_capabilities: None,
+            desired_capabilities: None,
+            properties: None,
+        };
+        AmqpFrame::new(0, begin.into())
+    }
+
+    fn attach(role: Role) -> AmqpFrame {
+        let attach = Attach {
+            name: ByteString::from_static("test"),
+            handle: 0,
+            role,
+            snd_settle_mode: SenderSettleMode::Mixed,
+            rcv_settle_mode: ReceiverSettleMode::First,
+            source: None,
+            target: None,
+            unsettled: None,
+            incomplete_unsettled: false,
+            initial_delivery_count: Some(0),
+            max_message_size: None,
+            offered_capabilities: None,
+            desired_capabilities: None,
+            properties: None,
+        };
+        AmqpFrame::new(0, attach.into())
+    }
+
+    fn flow(channel: u16) -> AmqpFrame {
+        let flow = Flow {
+            next_incoming_id: Some(1),
+            incoming_window: std::u32::MAX,
+            next_outgoing_id: 1,
+            outgoing_window: std::u32::MAX,
+            handle: None,
+            delivery_count: None,
+            link_credit: None,
+            available: None,
+            drain: false,
+            echo: false,
+            properties: None,
+        };
+        AmqpFrame::new(channel, flow.into())
+    }
+
+    #[ntex::test]
+    async fn test_route_session_frames() {
+        let router = router(0, 0);
+
+        // only Begin is allowed on new channel
+        assert!(matches!(
+            router.route(flow(0)),
+            Err(DispatcherError::Protocol(
+                AmqpProtocolError::UnknownSession(0, _)
+            ))
+        ));
+        assert!(matches!(router.route(begin()), Ok(Route::Handled)));
+        assert!(router.sink.get_remote_session(0).is_some());
+
+        // session level flow and heartbeat are handled by router
+        assert!(matches!(router.route(flow(0)), Ok(Route::Handled)));
+        assert!(matches!(
+            router.route(AmqpFrame::new(0, Frame::Empty)),
+            Ok(Route::Handled)
+        ));
+        assert!(matches!(
+            router.route(flow(1)),
+            Err(DispatcherError::Protocol(
+                AmqpProtocolError::UnknownSession(1, _)
+            ))
+        ));
+    }
+
+    #[ntex::test]
+    async fn test_route_link_frames() {
+        let router = router(0, 0);
+        assert!(matches!(router.route(begin()), Ok(Route::Handled)));
+
+        // remote attach is passed to control service
+        match router.route(attach(Role::Receiver)) {
+            Ok(Route::Control(frame)) => assert!(matches!(
+                frame.frame(),
+                ControlFrameKind::AttachSender(_, _)
+            )),
+            _ => panic!("expected control frame"),
+        }
+        match router.route(attach(Role::Sender)) {
+            Ok(Route::Control(frame)) => {
+                assert!(matches!(frame.frame(), ControlFrameKind::AttachReceiver(_)))
+            }
+            _ => panic!("expected control frame"),
+        }
+
+        // detach of unknown handle is handled by session
+        let detach = Detach {
+            handle: 10,
+            closed: true,
+            error: None,
+        };
+        assert!(matches!(
+            router.route(AmqpFrame::new(0, detach.into())),
+            Ok(Route::Handled)
+        ));
+    }
+
+    #[ntex::test]
+    async fn test_route_idle_timeout() {
+        tokio::time::pause();
+
+        // time-outs are disabled
+        let router = router(0, 0);
+        assert!(router.hb.borrow().is_none());
+
+        // any frame resets local idle time-out
+        let router = router(1000, 0);
+        tokio::time::advance(Duration::from_millis(600)).await;
+        assert!(matches!(
+            router.route(AmqpFrame::new(0, Frame::Empty)),
+            Ok(Route::Handled)
+        ));
+        tokio::time::advance(Duration::from_millis(600)).await;
+        assert!(PollIdle(&router).await.is_ok());
+        tokio::time::advance(Duration::from_millis(500)).await;
+        assert!(matches!(
+            PollIdle(&router).await,
+            Err(DispatcherError::Protocol(AmqpProtocolError::IdleTimeout))
+        ));
+        assert!(matches!(
+            router.sink.get_error(),
+            Some(AmqpProtocolError::IdleTimeout)
+        ));
+    }
+
+    #[ntex::test]
+    async fn test_poll_budget() {
+        // without budget all available frames are dispatched in one poll
+        let disp = dispatcher(0, 0);
+        assert_eq!(dispatch(&disp, 1000).await, vec![1000]);
+
+        // dispatcher yields once budget is spent
+        let disp = dispatcher(128, 0);
+        assert_eq!(
+            dispatch(&disp, 1000).await,
+            vec![128, 128, 128, 128, 128, 128, 128, 104]
+        );
+
+        // budget is not carried over to next poll
+        let disp = dispatcher(4, 0);
+        assert_eq!(dispatch(&disp, 3).await, vec![3]);
+        assert_eq!(dispatch(&disp, 3).await, vec![3]);
+        assert_eq!(dispatch(&disp, 6).await, vec![4, 2]);
+    }
+}
@@ -2,9 +2,9 @@ use std::{convert::TryFrom, io};
 
 use ntex::util::{ByteString, Either};
 
-pub use crate::codec::protocol::Error;
 pub use crate::codec::{AmqpCodecError, AmqpParseError, ProtocolIdError};
-use crate::{codec::protocol, types::Outcome};
+pub use crate::protocol::Error;
+use crate::{protocol, types::Outcome};
 
 /// Errors which can occur when attempting to handle amqp connection.
 #[derive(Debug, Display, From)]
@@ -36,8 +36,11 @@ impl From<Either<AmqpCodecError, io::Error>> for DispatcherError {
 #[derive(Clone, Debug, Display)]
 pub enum AmqpProtocolError {
     Codec(AmqpCodecError),
+    #[deprecated(since = "0.5.0", note = "Use `AmqpProtocolError::ChannelLimitReached`")]
     TooManyChannels,
     KeepAliveTimeout,
+    #[display(fmt = "Idle time-out expired")]
+    IdleTimeout,
     Disconnected,
     #[display(fmt = "Unknown session: {} {:?}", _0, _1)]
     UnknownSession(usize, Box<protocol::Frame>),
@@ -51,6 +54,191 @@ pub enum AmqpProtocolError {
     UnexpectedOpeningState(Box<protocol::Frame>),
     #[display(fmt = "Unexpected frame, got: {:?}", _0)]
     Unexpected(Box<protocol::Frame>),
+    /// Send queue reached `SenderLink::set_max_buffered()` limit, reported
+    /// as protocol error like other `SenderLink::send()` errors
+    #[display(fmt = "Link send queue is full")]
+    SendQueueFull,
+    #[display(fmt = "Operation timed out")]
+    Timeout,
+    #[display(fmt = "Session open error: {}", _0)]
+    SessionOpen(SessionOpenError),
+    #[display(fmt = "Link handle would exceed peer handle-max: {}", _0)]
+    HandleMaxExceeded(u32),
+    #[display(fmt = "Session channel would exceed negotiated channel-max: {}", _0)]
+    ChannelLimitReached(u16),
+    #[display(fmt = "Message exceeds peer max-message-size: {}", _0)]
+    MessageSizeExceeded(u64),
+    /// Delivery tag length, max length is 32 bytes
+    #[display(fmt = "Delivery tag exceeds 32 bytes: {}", _0)]
+    DeliveryTagTooLong(usize),
+    #[display(fmt = "Delivery could not be resumed on link reattach")]
+    DeliveryAborted,
+    /// Link requires `message-id` property and message does not have one
+    #[display(fmt = "Message does not have message-id")]
+    MessageIdRequired,
+    /// `message-id` is reused with different payload, see `CollisionDetector`
+    #[display(fmt = "Message id {} is reused with different payload", _0)]
+    MessageIdCollision(protocol::MessageId),
+    /// Link properties are exchanged in `Attach` frames only
+    #[display(fmt = "Link properties could not be changed after attach")]
+    LinkPropertiesImmutable,
+    /// Links could be migrated between sessions of the same connection only,
+    /// unless session of the link has failed
+    #[display(fmt = "Link could not be migrated to session of another connection")]
+    MigrationForeignConnection,
+    /// Peer does not keep terminus of migrated link
+    #[display(fmt = "Peer refused to attach migrated link")]
+    MigrationRefused,
+}
+
+/// Errors caused by invalid remote `Begin` frame
+#[derive(Clone, Debug, Display, PartialEq)]
+pub enum SessionOpenError {
+    /// Remote Begin refers to channel without opening session
+    #[display(fmt = "Invalid remote-channel: {}", _0)]
+    InvalidRemoteChannel(u16),
+}
+
+/// Errors of address validation
+#[derive(Clone, Debug, Display, PartialEq)]
+pub enum AddressError {
+    #[display(fmt = "Address is empty")]
+    Empty,
+    /// Address length and max allowed length
+    #[display(fmt = "Address is too long: {} > {}", _0, _1)]
+    TooLong(usize, usize),
+    /// Character is not allowed, position is byte offset
+    #[display(fmt = "Invalid address character {:?} at {}", _0, _1)]
+    InvalidChar(char, usize),
+    /// `%` is not followed by two hex digits
+    #[display(fmt = "Invalid percent-encoding at {}", _0)]
+    InvalidEncoding(usize),
+}
+
+impl std::error::Error for AddressError {}
+
+impl AmqpProtocolError {
+    /// Error classification
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            AmqpProtocolError::Codec(_)
+            | AmqpProtocolError::DeliveryTagTooLong(_)
+            | AmqpProtocolError::MessageIdRequired
+            | AmqpProtocolError::MessageIdCollision(_) => ErrorKind::Malformed,
+            AmqpProtocolError::TooManyChannels
+            | AmqpProtocolError::SendQueueFull
+            | AmqpProtocolError::HandleMaxExceeded(_)
+            | AmqpProtocolError::ChannelLimitReached(_) => ErrorKind::ResourceLimit,
+            AmqpProtocolError::KeepAliveTimeout
+            | AmqpProtocolError::IdleTimeout
+            | AmqpProtocolError::Timeout => ErrorKind::Timeout,
+            AmqpProtocolError::Disconnected => ErrorKind::Transport,
+            AmqpProtocolError::DeliveryAborted => ErrorKind::Closed,
+            AmqpProtocolError::UnknownSession(_, _)
+            | AmqpProtocolError::UnexpectedOpeningState(_)
+            | AmqpProtocolError::Unexpected(_)
+            | AmqpProtocolError::SessionOpen(_)
+            | AmqpProtocolError::LinkPropertiesImmutable
+            | AmqpProtocolError::MigrationForeignConnection => ErrorKind::Protocol,
+            AmqpProtocolError::MessageSizeExceeded(_) | AmqpProtocolError::MigrationRefused => {
+                ErrorKind::Rejected
+            }
+            AmqpProtocolError::Closed(err)
+            | AmqpProtocolError::SessionEnded(err)
+            | AmqpProtocolError::LinkDetached(err) => err
+                .as_ref()
+                .map(ErrorKind::from_error)
+                .unwrap_or(ErrorKind::Closed),
+        }
+    }
+
+    /// Check if operation could succeed if retried, possibly over new connection
+    pub fn is_retryable(&self) -> bool {
+        self.kind().is_retryable()
+    }
+}
+
+/// Classification of errors for retry decisions
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// Transport failure
+    Transport,
+    /// Operation or keep-alive timeout
+    Timeout,
+    /// Connection, session or link is closed without permanent error
+    Closed,
+    /// Local or remote resource limit is reached
+    ResourceLimit,
+    /// Authentication or authorization failure
+    Unauthorized,
+    /// Node does not exist
+    NotFound,
+    /// Peer refused operation
+    Rejected,
+    /// Malformed data
+    Malformed,
+    /// Protocol violation
+    Protocol,
+    /// Unknown error condition
+    Other,
+}
+
+impl ErrorKind {
+    /// Classify error condition received from peer
+    pub fn from_error(err: &protocol::Error) -> Self {
+        ErrorKind::from_condition(&err.condition)
+    }
+
+    /// Classify error condition
+    pub fn from_condition(cond: &protocol::ErrorCondition) -> Self {
+        use protocol::{AmqpError as A, ConnectionError as C, LinkError as L};
+
+        match cond {
+            protocol::ErrorCondition::AmqpError(err) => match err {
+                A::InternalError => ErrorKind::Closed,
+                A::NotFound | A::ResourceDeleted => ErrorKind::NotFound,
+                A::UnauthorizedAccess => ErrorKind::Unauthorized,
+                A::DecodeError | A::InvalidField | A::FrameSizeTooSmall => ErrorKind::Malformed,
+                A::ResourceLimitExceeded | A::ResourceLocked => ErrorKind::ResourceLimit,
+                A::NotAllowed | A::NotImplemented | A::PreconditionFailed => ErrorKind::Rejected,
+                A::IllegalState => ErrorKind::Protocol,
+            },
+            protocol::ErrorCondition::ConnectionError(err) => match err {
+                C::ConnectionForced | C::Redirect => ErrorKind::Closed,
+                C::FramingError => ErrorKind::Malformed,
+            },
+            protocol::ErrorCondition::SessionError(_) => ErrorKind::Protocol,
+            protocol::ErrorCondition::LinkError(err) => match err {
+                L::DetachForced | L::Redirect | L::Stolen => ErrorKind::Closed,
+                L::TransferLimitExceeded => ErrorKind::ResourceLimit,
+                L::MessageSizeExceeded => ErrorKind::Rejected,
+            },
+            protocol::ErrorCondition::Custom(_) => ErrorKind::Other,
+        }
+    }
+
+    /// Transient errors, operation could succeed if retried
+    pub fn is_retryable(self) -> bool {
+        matches!(
+            self,
+            ErrorKind::Transport
+                | ErrorKind::Timeout
+                | ErrorKind::Closed
+                | ErrorKind::ResourceLimit
+        )
+    }
+}
+
+/// `Close` error for frame size violations, #2.3.1
+pub(crate) fn framing_error(err: &AmqpCodecError) -> Option<Error> {
+    match err {
+        AmqpCodecError::MaxSizeExceeded(..) | AmqpCodecError::InvalidFrameSize(_) => Some(Error {
+            condition: protocol::ConnectionError::FramingError.into(),
+            description: Some(ByteString::from(err.to_string())),
+            info: None,
+        }),
+        _ => None,
+    }
 }
 
 impl From<AmqpCodecError> for AmqpProtocolError {
@@ -4,81 +4,86 @@ use std::task::{Context, Poll};
 use std::time::Duration;
 
 use ntex::rt::time::{sleep_until, Instant, Sleep};
-use ntex::util::time::LowResTimeService;
 
+#[derive(Debug, PartialEq)]
 pub(crate) enum HeartbeatAction {
     None,
     Heartbeat,
     Close,
 }
 
+/// Connection idle timeouts.
+///
+/// `local` is the idle time-out advertised by us, connection is closed
+/// if nothing is received from peer within this time. `remote` is
+/// the interval of empty frames we send to keep peer's time-out satisfied.
 pub(crate) struct Heartbeat {
     expire_local: Instant,
     expire_remote: Instant,
-    local: Duration,
+    local: Option<Duration>,
     remote: Option<Duration>,
-    time: LowResTimeService,
     delay: Pin<Box<Sleep>>,
 }
 
 impl Heartbeat {
-    pub(crate) fn new(local: Duration, remote: Option<Duration>, time: LowResTimeService) -> Self {
-        let now = Instant::from_std(time.now());
-        let delay = if let Some(remote) = remote {
-            Box::pin(sleep_until(now + std::cmp::min(local, remote)))
-        } else {
-            Box::pin(sleep_until(now + local))
-        };
-
-        Heartbeat {
+    pub(crate) fn new(local: Option<Duration>, remote: Option<Duration>) -> Self {
+        let now = Instant::now();
+        let mut hb = Heartbeat {
             expire_local: now,
             expire_remote: now,
             local,
             remote,
-            time,
-            delay,
-        }
+            delay: Box::pin(sleep_until(now)),
+        };
+        let expire = hb.next_expire();
+        hb.delay.as_mut().reset(expire);
+        hb
     }
 
+    /// Frame is received from peer
     pub(crate) fn update_local(&mut self, update: bool) {
-        if update {
-            self.expire_local = Instant::from_std(self.time.now());
+        if update && self.local.is_some() {
+            self.expire_local = Instant::now();
         }
     }
 
+    /// Frame is sent to peer
     pub(crate) fn update_remote(&mut self, update: bool) {
         if update && self.remote.is_some() {
-            self.expire_remote = Instant::from_std(self.time.now());
+            self.expire_remote = Instant::now();
         }
     }
 
     fn next_expire(&self) -> Instant {
-        if let Some(remote) = self.remote {
-            let t1 = self.expire_local + self.local;
-            let t2 = self.expire_remote + remote;
-            if t1 < t2 {
-                t1
-            } else {
-                t2
+        match (self.local, self.remote) {
+            (Some(local), Some(remote)) => {
+                std::cmp::min(self.expire_local + local, self.expire_remote + remote)
             }
-        } else {
-            self.expire_local + self.local
+            (Some(local), None) => self.expire_local + local,
+            (None, Some(remote)) => self.expire_remote + remote,
+            // nothing to track, check once a day
+            (None, None) => Instant::now() + Duration::from_secs(86_400),
         }
     }
 
     pub(crate) fn poll(&mut self, cx: &mut Context<'_>) -> HeartbeatAction {
         match Pin::new(&mut self.delay).poll(cx) {
             Poll::Ready(_) => {
+                // timer could fire late, or after expiries moved forward
+                // because of traffic, so check against current time
                 let mut act = HeartbeatAction::None;
-                let dl = self.delay.deadline();
-                if dl >= self.expire_local + self.local {
-                    // close connection
-                    return HeartbeatAction::Close;
+                let now = Instant::now();
+                if let Some(local) = self.local {
+                    if now >= self.expire_local + local {
+                        // close connection
+                        return HeartbeatAction::Close;
+                    }
                 }
                 if let Some(remote) = self.remote {
-                    if dl >= self.expire_remote + remote {
+                    if now >= self.expire_remote + remote {
                         // send heartbeat
                         act = HeartbeatAction::Heartbeat;
+                        self.expire_remote = now;
                     }
                 }
                 let expire = self.next_expire();
@@ -90,3 +95,91 @@ impl Heartbeat {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct PollOnce<'a>(&'a mut Heartbeat);
+
+    impl<'a> Future for PollOnce<'a> {
+        type Output = HeartbeatAction;
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+            Poll::Ready(self.0.poll(cx))
+        }
+    }
+
+    /// Advance time by one second `secs` times, collect non-empty actions
+    /// with the second they happened at.
+    async fn run(
+        hb: &mut Heartbeat,
+        secs: u64,
+        inbound: bool,
+        outbound: bool,
+    ) -> Vec<(u64, HeartbeatAction)> {
+        let mut actions = Vec::new();
+        for sec in 1..=secs {
+            tokio::time::advance(Duration::from_secs(1)).await;
+            hb.update_local(inbound);
+            hb.update_remote(outbound);
+            match PollOnce(hb).await {
+                HeartbeatAction::None => (),
+                act => {
+                    let close = act == HeartbeatAction::Close;
+                    actions.push((sec, act));
+                    if close {
+                        break;
+                    }
+                }
+            }
+        }
+        actions
+    }
+
+    fn heartbeat() -> Heartbeat {
+        Heartbeat::new(Some(Duration::from_secs(10)), Some(Duration::from_secs(4)))
+    }
+
+    #[ntex::test]
+    async fn test_inbound_only() {
+        tokio::time::pause();
+        let mut hb = heartbeat();
+        assert_eq!(
+            run(&mut hb, 30, true, false).await,
+            vec![
+                (4, HeartbeatAction::Heartbeat),
+                (8, HeartbeatAction::Heartbeat),
+                (12, HeartbeatAction::Heartbeat),
+                (16, HeartbeatAction::Heartbeat),
+                (20, HeartbeatAction::Heartbeat),
+                (24, HeartbeatAction::Heartbeat),
+                (28, HeartbeatAction::Heartbeat),
+            ]
+        );
+    }
+
+    #[ntex::test]
+    async fn test_outbound_only() {
+        tokio::time::pause();
+        let mut hb = heartbeat();
+        assert_eq!(
+            run(&mut hb, 30, false, true).await,
+            vec![(10, HeartbeatAction::Close)]
+        );
+    }
+
+    #[ntex::test]
+    async fn test_idle() {
+        tokio::time::pause();
+        let mut hb = heartbeat();
+        assert_eq!(
+            run(&mut hb, 30, false, false).await,
+            vec![
+                (4, HeartbeatAction::Heartbeat),
+                (8, HeartbeatAction::Heartbeat),
+                (10, HeartbeatAction::Close),
+            ]
+        );
+    }
+}
@@ -6,57 +6,172 @@ extern crate derive_more;
 #[macro_use]
 extern crate log;
 
-use std::{future::Future, pin::Pin, task::Context, task::Poll};
+use std::{future::Future, pin::Pin, sync::Arc, task::Context, task::Poll, time::Duration};
 
 use ntex::channel::oneshot;
 use ntex::util::ByteString;
-use ntex_amqp_codec::protocol::{Disposition, Handle, Milliseconds, Open};
+use ntex_amqp_codec::protocol::{Disposition, Fields, Handle, Milliseconds, Open, ProtocolVersion};
+use ntex_amqp_codec::types::Multiple;
+use ntex_amqp_codec::{FrameTable, FrameTables, PerformativeCodec};
 use uuid::Uuid;
 
+use self::error::AddressError;
+
 #[macro_use]
 mod utils;
 
+mod address;
 mod cell;
+mod checkpoint;
 pub mod client;
+mod collision;
 mod connection;
 mod control;
+mod credit;
 mod default;
 mod dispatcher;
+// derived impls of `AmqpProtocolError` use deprecated variant
+#[allow(deprecated)]
 pub mod error;
 pub mod error_code;
 mod hb;
+mod lifecycle;
+mod node;
 mod rcvlink;
 mod router;
+mod scram;
 pub mod server;
 mod session;
 mod sndlink;
 mod state;
+mod stats;
 pub mod types;
 
-pub use self::connection::Connection;
+pub use self::address::{Address, AddressKind, AddressOptions, PrefixStyle, Preset, Strictness};
+pub use self::checkpoint::{Checkpoint, PossibleDuplicates};
+pub use self::collision::CollisionDetector;
+pub use self::connection::{Connection, Negotiated};
 pub use self::control::{ControlFrame, ControlFrameKind};
+pub use self::lifecycle::{ConnectionState, Events, LinkState, SessionState, StateChanges};
+pub use self::node::NodePropertyMismatch;
 pub use self::rcvlink::{ReceiverLink, ReceiverLinkBuilder};
-pub use self::session::Session;
-pub use self::sndlink::{SenderLink, SenderLinkBuilder};
+pub use self::session::{LinkEvent, Session, SessionBuilder, TagGenerator};
+pub use self::sndlink::{ReattachSummary, SendOptions, SenderLink, SenderLinkBuilder};
 pub use self::state::State;
+pub use self::stats::{ReceiverLinkStats, SenderLinkStats, SessionStats};
+pub use ntex_amqp_codec::types::{Symbol, Variant};
+pub use ntex_amqp_codec::{Body, Message, MessageBody, MessageBuilder};
 
 pub mod codec {
     pub use ntex_amqp_codec::*;
+
+    // items below shadow glob re-export with deprecated paths
+
+    #[deprecated(since = "0.5.0", note = "Use `ntex_amqp::protocol`")]
+    pub mod protocol {
+        pub use ntex_amqp_codec::protocol::*;
+    }
+
+    #[deprecated(since = "0.5.0", note = "Use `ntex_amqp::Message`")]
+    pub type Message = ntex_amqp_codec::Message;
+
+    #[deprecated(since = "0.5.0", note = "Use `ntex_amqp::MessageBody`")]
+    pub type MessageBody = ntex_amqp_codec::MessageBody;
+
+    #[deprecated(since = "0.5.0", note = "Use `ntex_amqp::MessageBuilder`")]
+    pub type MessageBuilder = ntex_amqp_codec::MessageBuilder;
 }
 
+/// Amqp protocol types
+pub mod protocol {
+    pub use ntex_amqp_codec::protocol::*;
+}
+
+/// Commonly used types
+pub mod prelude {
+    pub use crate::client::{Client, Connector, SaslAuth};
+    pub use crate::error::{AmqpError, AmqpProtocolError, LinkError};
+    pub use crate::types::{Link, Outcome, Transfer};
+    pub use crate::{Configuration, Connection, Message, Session, SessionBuilder, Variant};
+    pub use crate::{ReceiverLink, ReceiverLinkBuilder, SenderLink, SenderLinkBuilder};
+}
+
+/// Outcome of sent delivery.
+///
+/// Future resolves once delivery outcome is known. With receiver settle
+/// mode `second` delivery is settled later, see `Delivery::settled()`.
 pub enum Delivery {
     Resolved(Result<Disposition, error::AmqpProtocolError>),
-    Pending(oneshot::Receiver<Result<Disposition, error::AmqpProtocolError>>),
+    Pending(
+        oneshot::Receiver<Result<Disposition, error::AmqpProtocolError>>,
+        oneshot::Receiver<Result<Disposition, error::AmqpProtocolError>>,
+    ),
     Gone,
 }
 
-type DeliveryPromise = oneshot::Sender<Result<Disposition, error::AmqpProtocolError>>;
+impl Delivery {
+    /// Wait until delivery is settled by both sender and receiver
+    pub async fn settled(self) -> Result<Disposition, error::AmqpProtocolError> {
+        match self {
+            Delivery::Resolved(res) => res,
+            Delivery::Pending(_, settled) => match settled.await {
+                Ok(res) => res,
+                Err(_) => Err(error::AmqpProtocolError::Disconnected),
+            },
+            Delivery::Gone => Err(error::AmqpProtocolError::Disconnected),
+        }
+    }
+}
+
+/// Sender side of `Delivery`
+pub(crate) struct DeliveryPromise {
+    outcome: Option<oneshot::Sender<Result<Disposition, error::AmqpProtocolError>>>,
+    settled: oneshot::Sender<Result<Disposition, error::AmqpProtocolError>>,
+}
+
+impl DeliveryPromise {
+    pub(crate) fn new() -> (DeliveryPromise, Delivery) {
+        let (outcome, outcome_rx) = oneshot::channel();
+        let (settled, settled_rx) = oneshot::channel();
+        (
+            DeliveryPromise {
+                settled,
+                outcome: Some(outcome),
+            },
+            Delivery::Pending(outcome_rx, settled_rx),
+        )
+    }
+
+    /// Outcome is known, delivery is not settled yet
+    pub(crate) fn outcome(&mut self, disposition: Disposition) {
+        if let Some(tx) = self.outcome.take() {
+            let _ = tx.send(Ok(disposition));
+        }
+    }
+
+    /// Delivery is settled or failed
+    pub(crate) fn settle(self, result: Result<Disposition, error::AmqpProtocolError>) {
+        if let Some(tx) = self.outcome {
+            let _ = tx.send(result.clone());
+        }
+        let _ = self.settled.send(result);
+    }
+
+    /// Nobody waits for delivery
+    pub(crate) fn is_canceled(&self) -> bool {
+        self.outcome
+            .as_ref()
+            .map(|tx| tx.is_canceled())
+            .unwrap_or(true)
+            && self.settled.is_canceled()
+    }
+}
 
 impl Future for Delivery {
     type Output = Result<Disposition, error::AmqpProtocolError>;
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        if let Delivery::Pending(ref mut receiver) = *self {
+        if let Delivery::Pending(ref mut receiver, _) = *self {
             return match Pin::new(receiver).poll(cx) {
                 Poll::Ready(Ok(r)) => Poll::Ready(r),
                 Poll::Pending => Poll::Pending,
@@ -78,13 +193,35 @@ impl Future for Delivery {
     }
 }
 
+const DEFAULT_MAX_POLL_FRAMES: usize = 128;
+const DEFAULT_MAX_POLL_BYTES: usize = 256 * 1024;
+const DEFAULT_MAX_REASSEMBLY_BYTES: usize = 256 * 1024;
+const DEFAULT_LINK_CREDIT: u32 = 50;
+
 /// Amqp1 transport configuration.
 #[derive(Debug, Clone)]
 pub struct Configuration {
     pub max_frame_size: u32,
     pub channel_max: usize,
     pub idle_time_out: Milliseconds,
+    pub idle_time_out_grace: u16,
     pub hostname: Option<ByteString>,
+    pub sasl_mechanisms: Vec<Symbol>,
+    pub connection_id_prefix: Option<ByteString>,
+    pub max_poll_frames: usize,
+    pub max_poll_bytes: usize,
+    pub max_reassembly_bytes: usize,
+    pub link_credit: u32,
+    pub protocol_versions: Vec<ProtocolVersion>,
+    pub frame_tables: FrameTables,
+    pub outgoing_locales: Vec<Symbol>,
+    pub incoming_locales: Vec<Symbol>,
+    pub offered_capabilities: Vec<Symbol>,
+    pub desired_capabilities: Vec<Symbol>,
+    pub properties: Option<Fields>,
+    pub container_id: Option<ByteString>,
+    pub address_style: PrefixStyle,
+    pub address_options: AddressOptions,
 }
 
 impl Default for Configuration {
@@ -100,10 +237,37 @@ impl Configuration {
             max_frame_size: std::u16::MAX as u32,
             channel_max: 1024,
             idle_time_out: 120_000,
+            idle_time_out_grace: 0,
             hostname: None,
+            sasl_mechanisms: Vec::new(),
+            connection_id_prefix: None,
+            max_poll_frames: DEFAULT_MAX_POLL_FRAMES,
+            max_poll_bytes: DEFAULT_MAX_POLL_BYTES,
+            max_reassembly_bytes: DEFAULT_MAX_REASSEMBLY_BYTES,
+            link_credit: DEFAULT_LINK_CREDIT,
+            protocol_versions: vec![ProtocolVersion::V1_0_0],
+            frame_tables: FrameTables::default(),
+            outgoing_locales: Vec::new(),
+            incoming_locales: Vec::new(),
+            offered_capabilities: Vec::new(),
+            desired_capabilities: Vec::new(),
+            properties: None,
+            container_id: None,
+            address_style: PrefixStyle::Plain,
+            address_options: AddressOptions::default(),
         }
     }
 
+    /// Create configuration with broker family settings.
+    ///
+    /// Preset sets address prefix convention used by `queue_address()`
+    /// and `topic_address()`, other settings are defaults.
+    pub fn preset(preset: Preset) -> Self {
+        let mut config = Configuration::new();
+        config.address_style = preset.prefix_style();
+        config
+    }
+
     /// The channel-max value is the highest channel number that
     /// may be used on the Connection. This value plus one is the maximum
     /// number of Sessions that can be simultaneously active on the Connection.
@@ -135,6 +299,16 @@ impl Configuration {
         self
     }
 
+    /// Set grace period in seconds added to local idle time-out.
+    ///
+    /// Peer sees advertised idle time-out, connection is closed only after
+    /// idle time-out plus grace period passes without incoming frames.
+    /// By default grace period is not set
+    pub fn idle_timeout_grace(&mut self, grace: u16) -> &mut Self {
+        self.idle_time_out_grace = grace;
+        self
+    }
+
     /// Set connection hostname
     ///
     /// Hostname is not set by default
@@ -143,10 +317,188 @@ impl Configuration {
         self
     }
 
+    /// Set sasl mechanisms offered by server.
+    ///
+    /// Server rejects clients that pick mechanism which is not offered.
+    /// Additional mechanisms could be added per connection with `Sasl::mechanism()`
+    pub fn sasl_mechanisms(&mut self, mechanisms: &[&str]) -> &mut Self {
+        self.sasl_mechanisms = symbols(mechanisms);
+        self
+    }
+
+    /// Set prefix of connection ids.
+    ///
+    /// Connection id is prefix followed by process wide connection counter,
+    /// by default prefix is short random token.
+    pub fn connection_id_prefix(&mut self, prefix: &str) -> &mut Self {
+        self.connection_id_prefix = Some(ByteString::from(prefix));
+        self
+    }
+
+    /// Set work budget of connection dispatcher.
+    ///
+    /// Dispatcher yields to other tasks of the thread after `frames` incoming
+    /// frames or `bytes` of transfer payload, processing continues on next wakeup.
+    /// Zero value disables the limit. By default budget is 128 frames and 256kb
+    pub fn poll_budget(&mut self, frames: usize, bytes: usize) -> &mut Self {
+        self.max_poll_frames = frames;
+        self.max_poll_bytes = bytes;
+        self
+    }
+
+    /// Set max size of multi-frame delivery being received.
+    ///
+    /// Transfer payloads of unfinished delivery are buffered until
+    /// last transfer arrives, link is detached with `message-size-exceeded`
+    /// error if buffered payload grows over the limit. Limit could be changed
+    /// per link with `ReceiverLink::set_max_partial_transfer_size()`.
+    /// By default limit is 256kb
+    pub fn max_reassembly_bytes(&mut self, size: usize) -> &mut Self {
+        self.max_reassembly_bytes = size;
+        self
+    }
+
+    /// Set link credit of links attached by peer in sender role.
+    ///
+    /// Credit is granted when router opens the link and is restored
+    /// once peer used it up. By default credit is 50
+    pub fn link_credit(&mut self, credit: u32) -> &mut Self {
+        self.link_credit = credit;
+        self
+    }
+
+    /// Set acceptable protocol versions in order of preference.
+    ///
+    /// Client proposes first version, server accepts any listed version.
+    /// Empty list is rejected, amqp 1.0.0 is used instead.
+    /// By default only amqp 1.0.0 is accepted
+    pub fn protocol_versions(&mut self, versions: &[ProtocolVersion]) -> &mut Self {
+        if versions.is_empty() {
+            log::warn!("Empty list of protocol versions, amqp 1.0.0 is used");
+            self.protocol_versions = vec![ProtocolVersion::V1_0_0];
+        } else {
+            self.protocol_versions = versions.to_vec();
+        }
+        self
+    }
+
+    /// Register alternate codec of performative for protocol version.
+    ///
+    /// Codec applies to connections that negotiated `version` only,
+    /// `descriptor` is numeric descriptor code of the performative.
+    pub fn performative_codec<C>(
+        &mut self,
+        version: ProtocolVersion,
+        descriptor: u64,
+        codec: C,
+    ) -> &mut Self
+    where
+        C: PerformativeCodec + 'static,
+    {
+        self.frame_tables.register(version, descriptor, codec);
+        self
+    }
+
+    /// Set locales the connection writes in, as IETF language tags.
+    ///
+    /// Locales are not advertised by default
+    pub fn outgoing_locales(&mut self, locales: &[&str]) -> &mut Self {
+        self.outgoing_locales = symbols(locales);
+        self
+    }
+
+    /// Set locales the connection accepts from peer, most preferred first.
+    ///
+    /// Locales are not advertised by default
+    pub fn incoming_locales(&mut self, locales: &[&str]) -> &mut Self {
+        self.incoming_locales = symbols(locales);
+        self
+    }
+
+    /// Set connection capabilities supported by this side
+    pub fn offered_capabilities(&mut self, capabilities: &[&str]) -> &mut Self {
+        self.offered_capabilities = symbols(capabilities);
+        self
+    }
+
+    /// Set connection capabilities this side could use if peer supports them
+    pub fn desired_capabilities(&mut self, capabilities: &[&str]) -> &mut Self {
+        self.desired_capabilities = symbols(capabilities);
+        self
+    }
+
+    /// Modify connection properties sent in `Open` frame
+    pub fn properties<F>(&mut self, f: F) -> &mut Self
+    where
+        F: FnOnce(&mut Fields),
+    {
+        f(self.properties.get_or_insert_with(Fields::default));
+        self
+    }
+
+    /// Set address prefix convention of the broker.
+    ///
+    /// By default addresses are plain names
+    pub fn address_style(&mut self, style: PrefixStyle) -> &mut Self {
+        self.address_style = style;
+        self
+    }
+
+    /// Set validation and normalization options of addresses
+    /// created by `queue_address()` and `topic_address()`
+    pub fn address_options(&mut self, opts: AddressOptions) -> &mut Self {
+        self.address_options = opts;
+        self
+    }
+
+    /// Queue address in configured prefix convention
+    pub fn queue_address(&self, name: &str) -> Result<Address, AddressError> {
+        Address::prefixed_with(
+            AddressKind::Queue,
+            name,
+            self.address_style,
+            &self.address_options,
+        )
+    }
+
+    /// Topic address in configured prefix convention
+    pub fn topic_address(&self, name: &str) -> Result<Address, AddressError> {
+        Address::prefixed_with(
+            AddressKind::Topic,
+            name,
+            self.address_style,
+            &self.address_options,
+        )
+    }
+
+    /// Preferred protocol version, amqp 1.0.0 if no version is set
+    pub(crate) fn protocol_version(&self) -> ProtocolVersion {
+        self.protocol_versions
+            .first()
+            .copied()
+            .unwrap_or(ProtocolVersion::V1_0_0)
+    }
+
+    /// Check if protocol version is acceptable
+    pub(crate) fn accepts_version(&self, version: ProtocolVersion) -> bool {
+        if self.protocol_versions.is_empty() {
+            return version == ProtocolVersion::V1_0_0;
+        }
+        self.protocol_versions.contains(&version)
+    }
+
+    /// Frame table of protocol version, `None` for standard encoding
+    pub(crate) fn frame_table(&self, version: ProtocolVersion) -> Option<Arc<FrameTable>> {
+        self.frame_tables.get(version)
+    }
+
     /// Create `Open` performative for this configuration.
     pub fn to_open(&self) -> Open {
         Open {
-            container_id: ByteString::from(Uuid::new_v4().to_simple().to_string()),
+            container_id: self
+                .container_id
+                .clone()
+                .unwrap_or_else(|| ByteString::from(Uuid::new_v4().to_simple().to_string())),
             hostname: self.hostname.clone(),
             max_frame_size: self.max_frame_size,
             channel_max: self.channel_max as u16,
@@ -155,23 +507,40 @@ impl Configuration {
             } else {
                 None
             },
-            outgoing_locales: None,
-            incoming_locales: None,
-            offered_capabilities: None,
-            desired_capabilities: None,
-            properties: None,
+            outgoing_locales: multiple(&self.outgoing_locales),
+            incoming_locales: multiple(&self.incoming_locales),
+            offered_capabilities: multiple(&self.offered_capabilities),
+            desired_capabilities: multiple(&self.desired_capabilities),
+            properties: self.properties.clone(),
         }
     }
 
-    pub(crate) fn timeout_secs(&self) -> usize {
+    /// Local idle time-out including grace period.
+    ///
+    /// Connection is closed if nothing is received within this time,
+    /// `None` if idle time-out is disabled.
+    pub fn local_idle_timeout(&self) -> Option<Duration> {
         if self.idle_time_out > 0 {
-            (self.idle_time_out / 1000) as usize
+            Some(
+                Duration::from_millis(self.idle_time_out as u64)
+                    + Duration::from_secs(self.idle_time_out_grace as u64),
+            )
         } else {
-            0
+            None
         }
     }
 
-    pub(crate) fn timeout_remote_secs(&self) -> usize {
+    /// Local idle time-out including grace period in whole seconds, `0` if disabled
+    pub fn keepalive_secs(&self) -> u16 {
+        self.local_idle_timeout()
+            .map(|timeout| std::cmp::min(timeout.as_secs(), std::u16::MAX as u64) as u16)
+            .unwrap_or(0)
+    }
+
+    /// Interval of empty frames sent to peer, configuration is peer's one.
+    ///
+    /// Three quarters of peer's idle time-out in seconds, `0` if disabled.
+    pub fn timeout_remote_secs(&self) -> usize {
         if self.idle_time_out > 0 {
             ((self.idle_time_out as f32) * 0.75 / 1000.0) as usize
         } else {
@@ -186,7 +555,44 @@ impl<'a> From<&'a Open> for Configuration {
             max_frame_size: open.max_frame_size,
             channel_max: open.channel_max as usize,
             idle_time_out: open.idle_time_out.unwrap_or(0),
+            idle_time_out_grace: 0,
             hostname: open.hostname.clone(),
+            sasl_mechanisms: Vec::new(),
+            connection_id_prefix: None,
+            max_poll_frames: DEFAULT_MAX_POLL_FRAMES,
+            max_poll_bytes: DEFAULT_MAX_POLL_BYTES,
+            max_reassembly_bytes: DEFAULT_MAX_REASSEMBLY_BYTES,
+            link_credit: DEFAULT_LINK_CREDIT,
+            protocol_versions: vec![ProtocolVersion::V1_0_0],
+            frame_tables: FrameTables::default(),
+            outgoing_locales: from_multiple(open.outgoing_locales()),
+            incoming_locales: from_multiple(open.incoming_locales()),
+            offered_capabilities: from_multiple(open.offered_capabilities()),
+            desired_capabilities: from_multiple(open.desired_capabilities()),
+            properties: open.properties.clone(),
+            container_id: Some(open.container_id.clone()),
+            address_style: PrefixStyle::Plain,
+            address_options: AddressOptions::default(),
         }
     }
 }
+
+fn symbols(items: &[&str]) -> Vec<Symbol> {
+    items
+        .iter()
+        .map(|item| Symbol::from(ByteString::from(*item)))
+        .collect()
+}
+
+/// Optional field of `Open`, empty list is omitted
+fn multiple(items: &[Symbol]) -> Option<Multiple<Symbol>> {
+    if items.is_empty() {
+        None
+    } else {
+        Some(Multiple(items.to_vec()))
+    }
+}
+
+fn from_multiple(items: Option<&Multiple<Symbol>>) -> Vec<Symbol> {
+    items.map(|items| items.0.clone()).unwrap_or_default()
+}
@@ -0,0 +1,400 @@
+//! Lifecycle states of connection, session and links
+use std::collections::VecDeque;
+use std::{cell::RefCell, future::Future, mem, pin::Pin, rc::Rc, task::Context, task::Poll};
+
+use ntex::Stream;
+
+use crate::error::AmqpProtocolError;
+
+/// Connection lifecycle state
+///
+/// ```text
+///  Opened ---> Closing ---> Closed
+///    |
+///    +-------------------> Closed | Failed
+/// ```
+///
+/// Connection handle exists after `Open` frames are exchanged. Remote `Close`
+/// without error, local `close()` and `force_close()` lead to `Closed` state.
+#[derive(Clone, Debug)]
+pub enum ConnectionState {
+    /// Connection is opened
+    Opened,
+    /// Local `Close` frame is sent, waiting for remote `Close`
+    Closing,
+    /// Connection is closed
+    Closed,
+    /// Connection is closed by peer with error or transport failed
+    Failed(AmqpProtocolError),
+}
+
+impl ConnectionState {
+    /// Check if state is final
+    pub fn is_terminal(&self) -> bool {
+        matches!(self, ConnectionState::Closed | ConnectionState::Failed(_))
+    }
+
+    /// Error of failed connection
+    pub fn error(&self) -> Option<&AmqpProtocolError> {
+        match self {
+            ConnectionState::Failed(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Session lifecycle state
+///
+/// ```text
+///  Opened ---> Ending ---> Ended
+///    |
+///    +-----------------> Ended | Failed
+/// ```
+///
+/// Session handle exists after `Begin` frames are exchanged.
+#[derive(Clone, Debug)]
+pub enum SessionState {
+    /// Session is mapped to channel
+    Opened,
+    /// Local `End` frame is sent, waiting for remote `End`
+    Ending,
+    /// Session is ended
+    Ended,
+    /// Session is ended by peer with error or connection failed
+    Failed(AmqpProtocolError),
+}
+
+impl SessionState {
+    /// Check if state is final
+    pub fn is_terminal(&self) -> bool {
+        matches!(self, SessionState::Ended | SessionState::Failed(_))
+    }
+
+    /// Error of failed session
+    pub fn error(&self) -> Option<&AmqpProtocolError> {
+        match self {
+            SessionState::Failed(err) => Some(err),
+            _ => None,
+        }
+    }
+
+    /// Final state for session terminated with `err`
+    pub(crate) fn closed(err: AmqpProtocolError) -> SessionState {
+        if is_clean_close(&err) {
+            SessionState::Ended
+        } else {
+            SessionState::Failed(err)
+        }
+    }
+}
+
+/// Link lifecycle state
+///
+/// ```text
+///  Attaching ---> Attached <---> Suspended
+///                    |               |
+///                    +---------------+---> Detaching ---> Detached | Resumable
+///                    |               |
+///                    +---------------+---> Detached | Failed | Resumable
+///
+///  Resumable ---> Attached
+/// ```
+///
+/// Only links attached by remote peer are observed in `Attaching` state,
+/// until link is confirmed. Receiver link is suspended by
+/// `ReceiverLink::suspend()`, sender link is suspended while peer
+/// withdraws link credit. `Detach` with `closed=false`, sent by peer or by
+/// `suspend_link()`, leads to `Resumable` state, `resume_link()` attaches
+/// the link again.
+#[derive(Clone, Debug)]
+pub enum LinkState {
+    /// Remote `Attach` is received, link is not confirmed yet
+    Attaching,
+    /// Link is attached
+    Attached,
+    /// Link is attached, messages do not flow
+    Suspended,
+    /// Local `Detach` frame is sent, waiting for remote `Detach`
+    Detaching,
+    /// Link is detached
+    Detached,
+    /// Link is detached by peer with error, or session failed
+    Failed(AmqpProtocolError),
+    /// Link is detached without closing, terminus is kept
+    /// and link could be resumed by attaching with the same name
+    Resumable,
+}
+
+impl LinkState {
+    /// Check if state is final
+    pub fn is_terminal(&self) -> bool {
+        matches!(self, LinkState::Detached | LinkState::Failed(_))
+    }
+
+    /// Error of failed link
+    pub fn error(&self) -> Option<&AmqpProtocolError> {
+        match self {
+            LinkState::Failed(err) => Some(err),
+            _ => None,
+        }
+    }
+
+    /// Final state for link terminated with `err`
+    pub(crate) fn closed(err: AmqpProtocolError) -> LinkState {
+        if is_clean_close(&err) {
+            LinkState::Detached
+        } else {
+            LinkState::Failed(err)
+        }
+    }
+
+    /// State for link detached by peer, `closed` is the `Detach` flag
+    pub(crate) fn detached(err: AmqpProtocolError, closed: bool) -> LinkState {
+        if closed {
+            LinkState::closed(err)
+        } else {
+            LinkState::Resumable
+        }
+    }
+}
+
+/// Error is reported for normal close of connection, session or link
+pub(crate) fn is_clean_close(err: &AmqpProtocolError) -> bool {
+    matches!(
+        err,
+        AmqpProtocolError::Closed(None)
+            | AmqpProtocolError::SessionEnded(None)
+            | AmqpProtocolError::LinkDetached(None)
+    )
+}
+
+struct Shared<T> {
+    state: T,
+    version: usize,
+    dropped: bool,
+    terminal: fn(&T) -> bool,
+    wakers: Vec<std::task::Waker>,
+}
+
+/// Current state with subscribers
+pub(crate) struct StateCell<T>(Rc<RefCell<Shared<T>>>);
+
+impl<T: Clone> StateCell<T> {
+    pub(crate) fn new(state: T, terminal: fn(&T) -> bool) -> Self {
+        StateCell(Rc::new(RefCell::new(Shared {
+            state,
+            terminal,
+            version: 0,
+            dropped: false,
+            wakers: Vec::new(),
+        })))
+    }
+
+    pub(crate) fn get(&self) -> T {
+        self.0.borrow().state.clone()
+    }
+
+    /// Change state and notify subscribers.
+    ///
+    /// Final state could not be changed, returns `false`
+    /// if state is not changed.
+    pub(crate) fn set(&self, state: T) -> bool {
+        let wakers = {
+            let mut shared = self.0.borrow_mut();
+            if (shared.terminal)(&shared.state)
+                || mem::discriminant(&shared.state) == mem::discriminant(&state)
+            {
+                return false;
+            }
+            shared.state = state;
+            shared.version += 1;
+            mem::take(&mut shared.wakers)
+        };
+        for waker in wakers {
+            waker.wake();
+        }
+        true
+    }
+
+    pub(crate) fn subscribe(&self) -> StateChanges<T> {
+        StateChanges {
+            version: self.0.borrow().version,
+            shared: self.0.clone(),
+            done: false,
+        }
+    }
+}
+
+impl<T: std::fmt::Debug> std::fmt::Debug for StateCell<T> {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fmt.debug_tuple("StateCell")
+            .field(&self.0.borrow().state)
+            .finish()
+    }
+}
+
+impl<T> Drop for StateCell<T> {
+    fn drop(&mut self) {
+        let wakers = {
+            let mut shared = self.0.borrow_mut();
+            shared.dropped = true;
+            mem::take(&mut shared.wakers)
+        };
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
+
+/// Stream of state changes.
+///
+/// Rapid transitions are coalesced, stream yields the latest state
+/// since previous poll. Stream terminates after final state.
+pub struct StateChanges<T> {
+    shared: Rc<RefCell<Shared<T>>>,
+    version: usize,
+    done: bool,
+}
+
+impl<T: Clone> StateChanges<T> {
+    /// Wait for next state change
+    pub async fn recv(&mut self) -> Option<T> {
+        Recv(self).await
+    }
+}
+
+impl<T> std::fmt::Debug for StateChanges<T> {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fmt.debug_struct("StateChanges")
+            .field("version", &self.version)
+            .finish()
+    }
+}
+
+impl<T: Clone> Stream for StateChanges<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        if self.done {
+            return Poll::Ready(None);
+        }
+
+        let shared = self.shared.clone();
+        let mut shared = shared.borrow_mut();
+        if shared.version != self.version {
+            self.version = shared.version;
+            self.done = (shared.terminal)(&shared.state);
+            Poll::Ready(Some(shared.state.clone()))
+        } else if shared.dropped || (shared.terminal)(&shared.state) {
+            self.done = true;
+            Poll::Ready(None)
+        } else {
+            if !shared.wakers.iter().any(|w| w.will_wake(cx.waker())) {
+                shared.wakers.push(cx.waker().clone());
+            }
+            Poll::Pending
+        }
+    }
+}
+
+struct Recv<'a, S>(&'a mut S);
+
+impl<'a, S: Stream + Unpin> Future for Recv<'a, S> {
+    type Output = Option<S::Item>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut *self.0).poll_next(cx)
+    }
+}
+
+struct Queue<T> {
+    items: VecDeque<T>,
+    closed: bool,
+    waker: Option<std::task::Waker>,
+}
+
+/// Subscribers of events, every subscriber gets every event
+pub(crate) struct EventSubscribers<T>(Vec<Rc<RefCell<Queue<T>>>>);
+
+impl<T: Clone> EventSubscribers<T> {
+    pub(crate) fn new() -> Self {
+        EventSubscribers(Vec::new())
+    }
+
+    pub(crate) fn subscribe(&mut self) -> Events<T> {
+        let queue = Rc::new(RefCell::new(Queue {
+            items: VecDeque::new(),
+            closed: false,
+            waker: None,
+        }));
+        self.0.push(queue.clone());
+        Events(queue)
+    }
+
+    /// Queue event, dropped subscribers are removed
+    pub(crate) fn emit(&mut self, event: T) {
+        self.0.retain(|queue| Rc::strong_count(queue) > 1);
+        for queue in &self.0 {
+            let mut queue = queue.borrow_mut();
+            queue.items.push_back(event.clone());
+            if let Some(waker) = queue.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+impl<T> EventSubscribers<T> {
+    /// Terminate streams, queued events are still delivered
+    pub(crate) fn close(&mut self) {
+        for queue in self.0.drain(..) {
+            let mut queue = queue.borrow_mut();
+            queue.closed = true;
+            if let Some(waker) = queue.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+impl<T> Drop for EventSubscribers<T> {
+    fn drop(&mut self) {
+        self.close()
+    }
+}
+
+/// Stream of events.
+///
+/// Unlike `StateChanges`, events are not coalesced.
+pub struct Events<T>(Rc<RefCell<Queue<T>>>);
+
+impl<T> Events<T> {
+    /// Wait for next event
+    pub async fn recv(&mut self) -> Option<T> {
+        Recv(self).await
+    }
+}
+
+impl<T> std::fmt::Debug for Events<T> {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fmt.debug_struct("Events")
+            .field("queued", &self.0.borrow().items.len())
+            .finish()
+    }
+}
+
+impl<T> Stream for Events<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let mut queue = self.0.borrow_mut();
+        if let Some(item) = queue.items.pop_front() {
+            Poll::Ready(Some(item))
+        } else if queue.closed {
+            Poll::Ready(None)
+        } else {
+            queue.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
@@ -0,0 +1,118 @@
+//! Properties of dynamically created nodes
+use std::collections::HashMap;
+
+use ntex_amqp_codec::protocol::{DistributionMode, Fields, LifetimePolicy};
+use ntex_amqp_codec::types::{Symbol, Variant};
+
+const LIFETIME_POLICY: &str = "lifetime-policy";
+const SUPPORTED_DIST_MODES: &str = "supported-dist-modes";
+
+/// Requested dynamic node property that is not honored by peer
+#[derive(Clone, Debug, PartialEq)]
+pub enum NodePropertyMismatch {
+    /// Node is created with different lifetime policy,
+    /// `None` if peer did not report policy
+    LifetimePolicy {
+        requested: LifetimePolicy,
+        granted: Option<LifetimePolicy>,
+    },
+    /// Node does not support some of requested distribution modes
+    SupportedDistModes {
+        requested: Vec<Symbol>,
+        granted: Vec<Symbol>,
+    },
+    /// Value of other property differs
+    Property {
+        key: Symbol,
+        requested: Variant,
+        granted: Option<Variant>,
+    },
+}
+
+pub(crate) fn set_lifetime_policy(props: &mut Option<Fields>, policy: LifetimePolicy) {
+    props
+        .get_or_insert_with(HashMap::default)
+        .insert(Symbol::from_static(LIFETIME_POLICY), policy.into());
+}
+
+pub(crate) fn set_supported_dist_modes(props: &mut Option<Fields>, modes: &[DistributionMode]) {
+    let modes: Vec<_> = modes
+        .iter()
+        .map(|m| Variant::Symbol(m.to_symbol()))
+        .collect();
+    props.get_or_insert_with(HashMap::default).insert(
+        Symbol::from_static(SUPPORTED_DIST_MODES),
+        Variant::from(modes),
+    );
+}
+
+/// Symbols of single symbol or array of symbols
+fn symbols(value: Option<&Variant>) -> Vec<Symbol> {
+    let symbol = |v: &Variant| match v {
+        Variant::Symbol(s) => Some(s.clone()),
+        Variant::StaticSymbol(s) => Some(Symbol::from_static(s.0)),
+        _ => None,
+    };
+
+    match value {
+        Some(Variant::Array(items)) => items.iter().filter_map(symbol).collect(),
+        Some(v) => symbol(v).into_iter().collect(),
+        None => Vec::new(),
+    }
+}
+
+/// Compare requested node properties with properties granted by peer.
+///
+/// Mismatches are reported in no particular order.
+pub(crate) fn verify(
+    requested: Option<&Fields>,
+    granted: Option<&Fields>,
+) -> Result<(), Vec<NodePropertyMismatch>> {
+    let mut result = Vec::new();
+    let requested = if let Some(requested) = requested {
+        requested
+    } else {
+        return Ok(());
+    };
+
+    for (key, value) in requested {
+        let granted_value = granted.and_then(|g| g.get(key));
+        match key.as_str() {
+            LIFETIME_POLICY => {
+                if let Some(policy) = LifetimePolicy::from_variant(value) {
+                    let granted = granted_value.and_then(LifetimePolicy::from_variant);
+                    if granted != Some(policy) {
+                        result.push(NodePropertyMismatch::LifetimePolicy {
+                            requested: policy,
+                            granted,
+                        });
+                    }
+                    continue;
+                }
+            }
+            SUPPORTED_DIST_MODES => {
+                let requested = symbols(Some(value));
+                let granted = symbols(granted_value);
+                if requested.iter().any(|m| !granted.contains(m)) {
+                    result.push(NodePropertyMismatch::SupportedDistModes { requested, granted });
+                }
+                continue;
+            }
+            _ => (),
+        }
+
+        if granted_value != Some(value) {
+            result.push(NodePropertyMismatch::Property {
+                key: key.clone(),
+                requested: value.clone(),
+                granted: granted_value.cloned(),
+            });
+        }
+    }
+
+    if result.is_empty() {
+        Ok(())
+    } else {
+        Err(result)
+    }
+}
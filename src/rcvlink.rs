@@ -1,19 +1,26 @@
 use std::collections::HashMap;
-use std::{collections::VecDeque, future::Future, pin::Pin, task::Context, task::Poll};
+use std::{collections::VecDeque, future::Future, mem, pin::Pin, task::Context, task::Poll};
 
-use ntex::util::{ByteString, BytesMut};
+use ntex::util::{ByteString, Bytes, BytesMut};
 use ntex::Stream;
 use ntex::{channel::oneshot, task::LocalWaker};
 use ntex_amqp_codec::protocol::{
-    Attach, DeliveryNumber, Disposition, Error, Handle, LinkError, ReceiverSettleMode, Role,
-    SenderSettleMode, Source, TerminusDurability, TerminusExpiryPolicy, Transfer, TransferBody,
+    Attach, DeliveryNumber, DeliveryState, Disposition, DistributionMode, Error, ErrorCondition,
+    Fields, Flow, Handle, LifetimePolicy, LinkError, Map, Outcome, ReceiverSettleMode, Rejected,
+    Role, Seconds, SenderSettleMode, Source, Target, TerminusDurability, TerminusExpiryPolicy,
+    TransactionalState, Transfer, TransferBody,
 };
-use ntex_amqp_codec::types::{Symbol, Variant};
+use ntex_amqp_codec::types::{Multiple, Symbol, Variant};
 use ntex_amqp_codec::Encode;
 
 use crate::cell::Cell;
+use crate::checkpoint::Checkpoint;
+use crate::collision::CollisionDetector;
 use crate::error::AmqpProtocolError;
+use crate::lifecycle::{LinkState, StateCell, StateChanges};
+use crate::node::{self, NodePropertyMismatch};
 use crate::session::{Session, SessionInner};
+use crate::stats::ReceiverLinkStats;
 
 #[derive(Clone, Debug)]
 pub struct ReceiverLink {
@@ -29,10 +36,32 @@ impl ReceiverLink {
         self.inner.get_ref().handle as Handle
     }
 
+    /// Link id for logging, `connid.ch3.h5` where `5` is local handle
+    pub fn log_id(&self) -> String {
+        let inner = self.inner.get_ref();
+        format!("{}.h{}", inner.session.log_id(), inner.handle)
+    }
+
     pub fn credit(&self) -> u32 {
         self.inner.get_ref().credit
     }
 
+    /// Number of deliveries the sender reported as available
+    pub fn available(&self) -> u32 {
+        self.inner.get_ref().available
+    }
+
+    /// Snapshot of link counters
+    pub fn stats(&self) -> ReceiverLinkStats {
+        let inner = self.inner.get_ref();
+        ReceiverLinkStats {
+            link_credit: inner.credit,
+            delivery_count: inner.delivery_count,
+            queued_transfers: inner.queue.len(),
+            ..inner.stats
+        }
+    }
+
     pub fn session(&self) -> &Session {
         &self.inner.get_ref().session
     }
@@ -45,6 +74,67 @@ impl ReceiverLink {
         &self.inner.get_ref().attach
     }
 
+    /// Set or reset a property of `Attach` frame sent in response to peer.
+    ///
+    /// Link properties are exchanged on attach only, properties could be
+    /// set for link attached by peer until link is opened. Otherwise
+    /// `AmqpProtocolError::LinkPropertiesImmutable` is returned, properties
+    /// of locally attached links are set by `ReceiverLinkBuilder::property()`.
+    pub fn set_property(
+        &self,
+        key: Symbol,
+        value: Option<Variant>,
+    ) -> Result<(), AmqpProtocolError> {
+        let inner = self.inner.get_mut();
+        if !inner
+            .session
+            .inner
+            .get_ref()
+            .is_unconfirmed_receiver_link(inner.handle)
+        {
+            return Err(AmqpProtocolError::LinkPropertiesImmutable);
+        }
+
+        let props = inner
+            .response_properties
+            .get_or_insert_with(Fields::default);
+        match value {
+            Some(value) => props.insert(key, value),
+            None => props.remove(&key),
+        };
+        Ok(())
+    }
+
+    /// Peer's `Attach` frame of locally opened link
+    pub fn remote_frame(&self) -> Option<&Attach> {
+        self.inner.get_ref().remote_attach.as_ref()
+    }
+
+    /// Source terminus of the link.
+    ///
+    /// Source is defined by sending peer, terminus of peer's `Attach`
+    /// is returned. Requested source is returned until peer attaches.
+    pub fn source(&self) -> Option<&Source> {
+        self.remote_frame().unwrap_or_else(|| self.frame()).source()
+    }
+
+    /// Properties of dynamically created source node, as reported by peer
+    pub fn dynamic_node_properties(&self) -> Option<&Fields> {
+        self.remote_frame()
+            .and_then(|attach| attach.source())
+            .and_then(|source| source.dynamic_node_properties())
+    }
+
+    /// Check that peer created dynamic source node with requested properties
+    pub fn verify_dynamic_node(&self) -> Result<(), Vec<NodePropertyMismatch>> {
+        let requested = self
+            .frame()
+            .source()
+            .filter(|source| source.dynamic)
+            .and_then(|source| source.dynamic_node_properties());
+        node::verify(requested, self.dynamic_node_properties())
+    }
+
     pub fn open(&mut self) {
         let inner = self.inner.get_mut();
         inner
@@ -55,24 +145,270 @@ impl ReceiverLink {
     }
 
     pub fn set_link_credit(&self, credit: u32) {
-        self.inner.get_mut().set_link_credit(credit);
+        self.inner.get_mut().set_link_credit(credit, None);
+    }
+
+    /// Add link credit, `properties` are sent with the `Flow` frame.
+    ///
+    /// Entries override link's default flow properties for this frame only.
+    pub fn set_link_credit_with_properties(&self, credit: u32, properties: Fields) {
+        self.inner
+            .get_mut()
+            .set_link_credit(credit, Some(properties));
+    }
+
+    /// Set default properties for `Flow` frames sent by this link
+    pub fn set_flow_properties(&self, properties: Option<Fields>) {
+        self.inner.get_mut().flow_properties = properties;
+    }
+
+    /// Properties of the last link `Flow` received from peer
+    pub fn remote_flow_properties(&self) -> Option<&Fields> {
+        self.inner.get_ref().remote_flow_properties.as_ref()
+    }
+
+    /// Suspend link.
+    ///
+    /// Link credit is withdrawn, sender stops transfers until link is resumed.
+    pub fn suspend(&self) {
+        self.inner.get_mut().suspend();
+    }
+
+    /// Resume suspended link, link credit is restored
+    pub fn resume(&self) {
+        self.inner.get_mut().resume();
+    }
+
+    /// Suspend link, `Detach` frame with `closed=false` is sent to peer.
+    ///
+    /// Unlike `suspend()`, link is detached. Tags of unsettled deliveries
+    /// are kept and reported to peer when link is resumed with `resume_link()`.
+    pub fn suspend_link(&self) -> impl Future<Output = Result<(), AmqpProtocolError>> {
+        self.inner.get_mut().suspend_link()
+    }
+
+    /// Re-attach suspended link, link credit is restored.
+    ///
+    /// Only locally opened links could be resumed.
+    pub fn resume_link(&self) -> impl Future<Output = Result<(), AmqpProtocolError>> {
+        let inner = self.inner.get_mut();
+        let credit = inner.credit;
+        let rx = match inner.state.get() {
+            LinkState::Resumable if inner.remote_attach.is_some() => Some(
+                inner
+                    .session
+                    .inner
+                    .get_mut()
+                    .resume_receiver_link(self.inner.clone()),
+            ),
+            _ => None,
+        };
+
+        async move {
+            match rx {
+                Some(rx) => match rx.await {
+                    Ok(Ok(link)) => {
+                        if credit > 0 {
+                            link.set_link_credit(credit);
+                        }
+                        Ok(())
+                    }
+                    Ok(Err(err)) => Err(err),
+                    Err(_) => Err(AmqpProtocolError::Disconnected),
+                },
+                None => Err(AmqpProtocolError::LinkDetached(None)),
+            }
+        }
+    }
+
+    /// Move link to another session of the same connection.
+    ///
+    /// Link is suspended and attached with the same name and terminus to
+    /// `session`, link credit is restored on new session. Received transfers
+    /// that are not consumed yet stay in the link queue, except unsettled
+    /// ones: peer redelivers unsettled deliveries with new delivery ids.
+    /// Link epoch is incremented, dispositions of consumed deliveries sent
+    /// with `send_epoch_disposition()` are dropped after migration. Fails with `AmqpProtocolError::MigrationRefused`
+    /// if peer does not keep link terminus. Only locally opened links
+    /// could be migrated.
+    pub fn migrate_to(
+        &self,
+        session: &Session,
+    ) -> impl Future<Output = Result<(), AmqpProtocolError>> {
+        let link = self.clone();
+        let target = session.clone();
+
+        async move {
+            let source = link.inner.get_ref().session.clone();
+            if !source
+                .inner
+                .get_ref()
+                .check_migration(target.inner.get_ref())?
+            {
+                return Ok(());
+            }
+            if link.inner.get_ref().remote_attach.is_none() {
+                return Err(AmqpProtocolError::LinkDetached(None));
+            }
+
+            if let LinkState::Attached | LinkState::Suspended = link.state() {
+                link.suspend_link().await?;
+            }
+            link.inner.get_mut().migrated(target.clone());
+
+            let fut = link.resume_link();
+            // tags are reported in attach frame, ids of previous session are stale
+            link.inner.get_mut().unsettled.clear();
+            fut.await.map_err(|err| match err {
+                AmqpProtocolError::LinkDetached(_) => AmqpProtocolError::MigrationRefused,
+                err => err,
+            })?;
+
+            let inner = link.inner.get_mut();
+            trace!(
+                "Receiver link {:?} is migrated to {}",
+                inner.attach.name,
+                target.log_id()
+            );
+            inner.stats.migrations += 1;
+            target
+                .inner
+                .get_mut()
+                .link_migrated(inner.handle, &inner.attach.name, Role::Receiver);
+            Ok(())
+        }
+    }
+
+    /// Current link state
+    pub fn state(&self) -> LinkState {
+        self.inner.get_ref().state.get()
+    }
+
+    /// Subscribe to link state changes
+    pub fn state_changes(&self) -> StateChanges<LinkState> {
+        self.inner.get_ref().state.subscribe()
+    }
+
+    /// Send flow frame with provided values.
+    ///
+    /// Low level api, link delivery count is reset to `delivery_count`
+    /// and link credit is reset to `credit`.
+    pub fn send_flow(&self, delivery_count: u32, credit: u32, drain: bool, echo: bool) {
+        let inner = self.inner.get_mut();
+        inner.credit = credit;
+        inner.delivery_count = delivery_count;
+        inner.session.inner.get_mut().rcv_link_flow(
+            inner.handle as u32,
+            delivery_count,
+            credit,
+            drain,
+            echo,
+            inner.flow_properties.clone(),
+        );
     }
 
     /// Set max total size for partial transfers.
     ///
-    /// Default is 256Kb
+    /// Link is detached with `amqp:link:message-size-exceeded` error if
+    /// multi-frame delivery grows over the limit.
+    /// Default is set by `Configuration::max_reassembly_bytes()`
     pub fn set_max_partial_transfer_size(&self, size: usize) {
         self.inner.get_mut().set_max_partial_transfer(size);
     }
 
+    /// Wrap outcomes of transactional deliveries into `TransactionalState`.
+    ///
+    /// If enabled, disposition of a delivery received with transactional
+    /// state carries the same txn-id. Enabled by default.
+    pub fn set_transactional_outcomes(&self, enabled: bool) {
+        self.inner.get_mut().txn_outcomes = enabled;
+    }
+
+    /// Grant one link credit for every settled delivery.
+    ///
+    /// Number of unsettled deliveries in flight stays equal to
+    /// initially granted credit. Deliveries settled by peer are not
+    /// counted. Disabled by default.
+    pub fn credit_on_settle(&self, enabled: bool) {
+        self.inner.get_mut().credit_on_settle = enabled;
+    }
+
+    /// Report reuse of `message-id` with different payload.
+    ///
+    /// Detector callback is called when collided delivery is read from link.
+    pub fn set_collision_detector(&self, detector: CollisionDetector) {
+        self.inner.get_mut().collisions = Some(detector);
+    }
+
+    /// Process deliveries with at-least-once guarantee, see `Checkpoint`
+    pub fn checkpoint<F, Fut, E>(&self, handler: F) -> Checkpoint<F, E>
+    where
+        F: Fn(Transfer) -> Fut,
+        Fut: Future<Output = Result<(), E>>,
+    {
+        Checkpoint::new(self.clone(), handler)
+    }
+
     /// Send disposition frame
     pub fn send_disposition(&self, disp: Disposition) {
-        self.inner
-            .get_mut()
-            .session
-            .inner
-            .get_mut()
-            .post_frame(disp.into());
+        self.inner.get_mut().send_disposition(disp);
+    }
+
+    /// Migration epoch of the link.
+    ///
+    /// Epoch is incremented by every `migrate_to()`, delivery ids
+    /// are valid only within epoch they were received in.
+    pub fn epoch(&self) -> u32 {
+        self.inner.get_ref().epoch
+    }
+
+    /// Send disposition frame for deliveries received in `epoch`.
+    ///
+    /// Disposition is dropped if link is migrated since, peer redelivers
+    /// unsettled deliveries and ids could be reused by new session.
+    /// Returns `false` if disposition is dropped.
+    pub fn send_epoch_disposition(&self, epoch: u32, disp: Disposition) -> bool {
+        let inner = self.inner.get_mut();
+        if inner.epoch == epoch {
+            inner.send_disposition(disp);
+            true
+        } else {
+            trace!(
+                "Drop disposition of link {:?} from epoch {}, current is {}",
+                inner.attach.name,
+                epoch,
+                inner.epoch
+            );
+            false
+        }
+    }
+
+    /// Settle delivery with rejected outcome
+    ///
+    /// Rejection error carries `info` map with additional details.
+    pub fn reject_with_info<T>(
+        &self,
+        delivery_id: DeliveryNumber,
+        condition: T,
+        description: Option<ByteString>,
+        info: Fields,
+    ) where
+        ErrorCondition: From<T>,
+    {
+        self.send_disposition(Disposition {
+            role: Role::Receiver,
+            first: delivery_id,
+            last: None,
+            settled: true,
+            state: Some(DeliveryState::Rejected(Rejected {
+                error: Some(Error {
+                    description,
+                    condition: condition.into(),
+                    info: Some(info),
+                }),
+            })),
+            batchable: false,
+        })
     }
 
     /// Wait for disposition with specified number
@@ -97,9 +433,46 @@ impl ReceiverLink {
         self.inner.get_mut().close(Some(error.into()))
     }
 
-    pub(crate) fn remote_closed(&self, error: Option<Error>) {
-        trace!("Receiver link has been closed remotely");
+    /// Detach link after panic of its handler
+    pub(crate) fn handler_panicked(&self, message: ByteString, error: Error) {
         let inner = self.inner.get_mut();
+        inner.stats.panics += 1;
+        inner
+            .session
+            .inner
+            .get_mut()
+            .link_panicked(inner.handle, &inner.attach.name, message);
+        let _ = inner.close(Some(error));
+    }
+
+    /// Close link and check that peer removed source node.
+    ///
+    /// Resolves to `false` if node is still reachable after close,
+    /// see `Session::node_exists()`.
+    pub async fn close_and_verify(&self) -> Result<bool, AmqpProtocolError> {
+        let address = self
+            .remote_frame()
+            .and_then(|attach| attach.source())
+            .and_then(|source| source.address.clone());
+        self.close().await?;
+
+        if let Some(address) = address {
+            Ok(!self.session().node_exists(address).await?)
+        } else {
+            Ok(true)
+        }
+    }
+
+    pub(crate) fn remote_closed(&self, error: Option<Error>, closed: bool) {
+        trace!(
+            "Receiver link has been detached remotely, closed: {}",
+            closed
+        );
+        let inner = self.inner.get_mut();
+        inner.set_state(LinkState::detached(
+            AmqpProtocolError::LinkDetached(error.clone()),
+            closed,
+        ));
         inner.closed = true;
         inner.error = error;
         inner.reader_task.wake();
@@ -124,6 +497,9 @@ impl Stream for ReceiverLink {
                 Poll::Pending
             }
         } else if let Some(tr) = inner.queue.pop_front() {
+            if let (Some(detector), Some(body)) = (inner.collisions.as_mut(), tr.body.as_ref()) {
+                detector.check_body(body);
+            }
             Poll::Ready(Some(Ok(tr)))
         } else if inner.closed {
             if let Some(err) = inner.error.take() {
@@ -141,16 +517,33 @@ impl Stream for ReceiverLink {
 #[derive(Debug)]
 pub(crate) struct ReceiverLinkInner {
     handle: Handle,
-    attach: Attach,
+    pub(crate) attach: Attach,
     session: Session,
     closed: bool,
     reader_task: LocalWaker,
     queue: VecDeque<Transfer>,
     credit: u32,
     delivery_count: u32,
+    available: u32,
     error: Option<Error>,
     partial_body: Option<BytesMut>,
     partial_body_max: usize,
+    suspended_credit: u32,
+    state: StateCell<LinkState>,
+    txn_deliveries: HashMap<DeliveryNumber, Bytes>,
+    txn_outcomes: bool,
+    credit_on_settle: bool,
+    // tags of received deliveries that are not settled
+    unsettled: HashMap<DeliveryNumber, Bytes>,
+    // number of migrations, delivery ids are valid within epoch
+    epoch: u32,
+    flow_properties: Option<Fields>,
+    remote_flow_properties: Option<Fields>,
+    pub(crate) remote_attach: Option<Attach>,
+    stats: ReceiverLinkStats,
+    collisions: Option<CollisionDetector>,
+    // properties of attach response, for links attached by peer
+    pub(crate) response_properties: Option<Fields>,
 }
 
 impl ReceiverLinkInner {
@@ -159,6 +552,7 @@ impl ReceiverLinkInner {
         handle: Handle,
         attach: Attach,
     ) -> ReceiverLinkInner {
+        let partial_body_max = session.get_ref().max_reassembly_bytes();
         ReceiverLinkInner {
             handle,
             session: Session::new(session),
@@ -166,20 +560,160 @@ impl ReceiverLinkInner {
             reader_task: LocalWaker::new(),
             queue: VecDeque::with_capacity(4),
             credit: 0,
+            available: 0,
             error: None,
             partial_body: None,
-            partial_body_max: 262144,
+            partial_body_max,
+            suspended_credit: 0,
+            state: StateCell::new(LinkState::Attaching, LinkState::is_terminal),
+            txn_deliveries: HashMap::new(),
+            txn_outcomes: true,
+            credit_on_settle: false,
+            unsettled: HashMap::new(),
+            epoch: 0,
+            flow_properties: None,
+            remote_flow_properties: None,
+            remote_attach: None,
+            stats: ReceiverLinkStats::default(),
+            collisions: None,
+            response_properties: None,
             delivery_count: attach.initial_delivery_count().unwrap_or(0),
             attach,
         }
     }
 
+    /// Change link state, all transitions go through this method
+    pub(crate) fn set_state(&mut self, st: LinkState) {
+        trace!("Receiver link {:?} state: {:?}", self.attach.name, st);
+        self.state.set(st);
+    }
+
+    /// Link is moved to `session`, unsettled queued transfers are
+    /// dropped, peer redelivers them on new session
+    fn migrated(&mut self, session: Session) {
+        // incomplete delivery is not resumed
+        if self.partial_body.take().is_some() {
+            self.queue.pop_back();
+        }
+        let unsettled = &self.unsettled;
+        self.queue.retain(|tr| match tr.delivery_id {
+            Some(id) => !unsettled.contains_key(&id),
+            None => true,
+        });
+        self.txn_deliveries.clear();
+        self.epoch = self.epoch.wrapping_add(1);
+        self.session = session;
+    }
+
     pub(crate) fn detached(&mut self) {
         // drop pending transfers
         self.queue.clear();
+        self.txn_deliveries.clear();
+        self.unsettled.clear();
         self.closed = true;
     }
 
+    /// Settle delivery received in `epoch`, fails if disposition could
+    /// not be sent. Returns `false` if link is migrated since.
+    pub(crate) fn settle(
+        &mut self,
+        epoch: u32,
+        id: DeliveryNumber,
+        state: DeliveryState,
+    ) -> Result<bool, AmqpProtocolError> {
+        match self.state.get() {
+            LinkState::Attached | LinkState::Suspended if !self.closed => {
+                if epoch != self.epoch {
+                    return Ok(false);
+                }
+                self.send_disposition(Disposition {
+                    role: Role::Receiver,
+                    first: id,
+                    last: None,
+                    settled: true,
+                    state: Some(state),
+                    batchable: false,
+                });
+                Ok(true)
+            }
+            LinkState::Failed(err) => Err(err),
+            _ => Err(AmqpProtocolError::LinkDetached(self.error.clone())),
+        }
+    }
+
+    fn send_disposition(&mut self, mut disp: Disposition) {
+        // outcome of transactional delivery is reported within the same transaction
+        if disp.last.is_none() || disp.last == Some(disp.first) {
+            let txn_id = if disp.settled {
+                self.txn_deliveries.remove(&disp.first)
+            } else {
+                self.txn_deliveries.get(&disp.first).cloned()
+            };
+            if let Some(txn_id) = txn_id.filter(|_| self.txn_outcomes) {
+                disp.state = disp.state.map(|state| transactional(txn_id, state));
+            }
+        }
+        let mut settled = 0;
+        if disp.settled {
+            for id in disp.first..=disp.last.unwrap_or(disp.first) {
+                if self.unsettled.remove(&id).is_some() {
+                    settled += 1;
+                }
+            }
+        }
+        self.session.inner.get_mut().post_frame(disp.into());
+
+        if settled > 0 && self.credit_on_settle && !self.closed {
+            match self.state.get() {
+                LinkState::Attached => self.set_link_credit(settled, None),
+                LinkState::Suspended => self.suspended_credit += settled,
+                _ => (),
+            }
+        }
+    }
+
+    pub(crate) fn suspend_link(&mut self) -> impl Future<Output = Result<(), AmqpProtocolError>> {
+        let (tx, rx) = oneshot::channel();
+        if self.closed {
+            let _ = tx.send(Err(AmqpProtocolError::LinkDetached(self.error.clone())));
+        } else {
+            self.session
+                .inner
+                .get_mut()
+                .detach_receiver_link(self.handle, false, None, tx);
+        }
+
+        async move {
+            match rx.await {
+                Ok(res) => res,
+                Err(_) => Err(AmqpProtocolError::Disconnected),
+            }
+        }
+    }
+
+    /// `Attach` frame for resuming suspended link with new handle,
+    /// link credit is issued after link is attached
+    pub(crate) fn resume_frame(&mut self, handle: Handle) -> Attach {
+        self.handle = handle;
+        self.credit = 0;
+        self.closed = false;
+        self.error = None;
+
+        let mut frame = self.attach.clone();
+        frame.handle = handle;
+        frame.unsettled = if self.unsettled.is_empty() {
+            None
+        } else {
+            Some(
+                self.unsettled
+                    .values()
+                    .map(|tag| (Variant::Binary(tag.clone()), Variant::Null))
+                    .collect::<Map>(),
+            )
+        };
+        frame
+    }
+
     pub(crate) fn close(
         &mut self,
         error: Option<Error>,
@@ -208,119 +742,263 @@ impl ReceiverLinkInner {
         self.partial_body_max = size;
     }
 
-    pub(crate) fn set_link_credit(&mut self, credit: u32) {
+    fn reassembly_exceeded(&mut self) {
+        log::trace!(
+            "Multi-frame delivery exceeds {} bytes, detach link",
+            self.partial_body_max
+        );
+        let err = Error {
+            condition: LinkError::MessageSizeExceeded.into(),
+            description: Some(ByteString::from(format!(
+                "Multi-frame delivery exceeds {} bytes",
+                self.partial_body_max
+            ))),
+            info: None,
+        };
+        let _ = self.close(Some(err));
+    }
+
+    pub(crate) fn set_link_credit(&mut self, credit: u32, properties: Option<Fields>) {
+        if let LinkState::Suspended = self.state.get() {
+            self.set_state(LinkState::Attached);
+        }
         self.credit += credit;
-        self.session
-            .inner
-            .get_mut()
-            .rcv_link_flow(self.handle as u32, self.delivery_count, credit);
+
+        let properties = match (self.flow_properties.clone(), properties) {
+            (Some(mut defaults), Some(properties)) => {
+                defaults.extend(properties);
+                Some(defaults)
+            }
+            (defaults, properties) => properties.or(defaults),
+        };
+        self.session.inner.get_mut().rcv_link_flow(
+            self.handle as u32,
+            self.delivery_count,
+            self.credit,
+            false,
+            false,
+            properties,
+        );
+    }
+
+    fn suspend(&mut self) {
+        if let LinkState::Attached = self.state.get() {
+            self.suspended_credit = self.credit;
+            self.credit = 0;
+            self.session.inner.get_mut().rcv_link_flow(
+                self.handle as u32,
+                self.delivery_count,
+                0,
+                false,
+                false,
+                self.flow_properties.clone(),
+            );
+            self.set_state(LinkState::Suspended);
+        }
+    }
+
+    fn resume(&mut self) {
+        if let LinkState::Suspended = self.state.get() {
+            self.credit = mem::take(&mut self.suspended_credit);
+            self.session.inner.get_mut().rcv_link_flow(
+                self.handle as u32,
+                self.delivery_count,
+                self.credit,
+                false,
+                false,
+                self.flow_properties.clone(),
+            );
+            self.set_state(LinkState::Attached);
+        }
+    }
+
+    pub(crate) fn apply_flow(&mut self, flow: &Flow) {
+        trace!(
+            "Apply receiver link {:?} flow, delivery count: {:?}, available: {:?}",
+            self.attach.name,
+            flow.delivery_count(),
+            flow.available()
+        );
+
+        // #2.6.7 sender's delivery count is authoritative
+        if let Some(delivery_count) = flow.delivery_count() {
+            let advanced = delivery_count.wrapping_sub(self.delivery_count);
+            if advanced != 0 && advanced < u32::MAX / 2 {
+                self.credit = self.credit.saturating_sub(advanced);
+                self.delivery_count = delivery_count;
+            }
+        }
+        if let Some(available) = flow.available() {
+            self.available = available;
+        }
+        if flow.properties.is_some() {
+            self.remote_flow_properties = flow.properties.clone();
+        }
+        if flow.echo() {
+            let session = self.session.inner.get_mut();
+            session.rcv_link_flow(
+                self.handle as u32,
+                self.delivery_count,
+                self.credit,
+                false,
+                false,
+                self.flow_properties.clone(),
+            );
+            session.flush_flow();
+        }
     }
 
     pub(crate) fn handle_transfer(&mut self, mut transfer: Transfer) {
-        if self.credit == 0 {
-            // check link credit
-            let err = Error {
-                condition: LinkError::TransferLimitExceeded.into(),
-                description: None,
-                info: None,
-            };
-            let _ = self.close(Some(err));
-        } else {
+        self.stats.transfers += 1;
+        self.stats.bytes += transfer.body.as_ref().map(|b| b.len()).unwrap_or(0) as u64;
+
+        // link credit is consumed by the first transfer of a delivery
+        if self.partial_body.is_none() {
+            if self.credit == 0 {
+                // check link credit
+                let err = Error {
+                    condition: LinkError::TransferLimitExceeded.into(),
+                    description: None,
+                    info: None,
+                };
+                let _ = self.close(Some(err));
+                return;
+            }
             self.credit -= 1;
+        }
 
-            if let Some(ref mut body) = self.partial_body {
-                if transfer.delivery_id.is_some() {
-                    // if delivery_id is set, then it should be equal to first transfer
-                    if self
-                        .queue
-                        .back()
-                        .map(|back| back.delivery_id != transfer.delivery_id)
-                        .unwrap_or(true)
-                    {
-                        let err = Error {
-                            condition: LinkError::DetachForced.into(),
-                            description: Some(ByteString::from_static("delivery_id is wrong")),
-                            info: None,
-                        };
-                        let _ = self.close(Some(err));
-                        return;
-                    }
+        // #2.6.14 delivery is aborted by sender, drop received data
+        if transfer.aborted {
+            if self.partial_body.take().is_some() {
+                if let Some(id) = self.queue.pop_back().and_then(|tr| tr.delivery_id) {
+                    self.txn_deliveries.remove(&id);
+                    self.unsettled.remove(&id);
                 }
+            }
+            self.delivery_count = self.delivery_count.wrapping_add(1);
+            return;
+        }
 
-                // merge transfer data and check size
-                if let Some(transfer_body) = transfer.body.take() {
-                    if body.len() + transfer_body.len() > self.partial_body_max {
-                        let err = Error {
-                            condition: LinkError::MessageSizeExceeded.into(),
-                            description: None,
-                            info: None,
-                        };
-                        let _ = self.close(Some(err));
-                        return;
-                    }
-
-                    transfer_body.encode(body);
+        // delivery state is carried by the first transfer of a delivery,
+        // state of continuation transfers is ignored
+        if self.partial_body.is_none() {
+            if let (Some(id), Some(DeliveryState::TransactionalState(ref state))) =
+                (transfer.delivery_id, &transfer.state)
+            {
+                self.txn_deliveries.insert(id, state.txn_id.clone());
+            }
+            if let (Some(id), Some(ref tag)) = (transfer.delivery_id, &transfer.delivery_tag) {
+                if !transfer.settled.unwrap_or(false) {
+                    self.unsettled.insert(id, tag.clone());
                 }
+            }
+        }
 
-                // received last partial transfer
-                if !transfer.more {
-                    self.delivery_count += 1;
-                    let partial_body = self.partial_body.take();
-                    if partial_body.is_some() && !self.queue.is_empty() {
-                        self.queue.back_mut().unwrap().body =
-                            Some(TransferBody::Data(partial_body.unwrap().freeze()));
-                        if self.queue.len() == 1 {
-                            self.reader_task.wake()
-                        }
-                    } else {
-                        log::error!("Inconsistent state, bug");
-                        let err = Error {
-                            condition: LinkError::DetachForced.into(),
-                            description: Some(ByteString::from_static("Internal error")),
-                            info: None,
-                        };
-                        let _ = self.close(Some(err));
-                        return;
-                    }
-                }
-            } else if transfer.more {
-                if transfer.delivery_id.is_none() {
+        if let Some(ref mut body) = self.partial_body {
+            if transfer.delivery_id.is_some() {
+                // if delivery_id is set, then it should be equal to first transfer
+                if self
+                    .queue
+                    .back()
+                    .map(|back| back.delivery_id != transfer.delivery_id)
+                    .unwrap_or(true)
+                {
                     let err = Error {
                         condition: LinkError::DetachForced.into(),
-                        description: Some(ByteString::from_static("delivery_id is required")),
+                        description: Some(ByteString::from_static("delivery_id is wrong")),
                         info: None,
                     };
                     let _ = self.close(Some(err));
+                    return;
+                }
+            }
+
+            // merge transfer data and check size
+            if let Some(transfer_body) = transfer.body.take() {
+                if body.len() + transfer_body.len() > self.partial_body_max {
+                    self.reassembly_exceeded();
+                    return;
+                }
+
+                transfer_body.encode(body);
+            }
+
+            // received last partial transfer
+            if !transfer.more {
+                self.delivery_count = self.delivery_count.wrapping_add(1);
+                let partial_body = self.partial_body.take();
+                if partial_body.is_some() && !self.queue.is_empty() {
+                    self.queue.back_mut().unwrap().body =
+                        Some(TransferBody::Data(partial_body.unwrap().freeze()));
+                    if self.queue.len() == 1 {
+                        self.reader_task.wake()
+                    }
                 } else {
-                    let body = if let Some(body) = transfer.body.take() {
-                        match body {
-                            TransferBody::Data(data) => BytesMut::from(data.as_ref()),
-                            TransferBody::Message(msg) => {
-                                let mut buf = BytesMut::with_capacity(msg.encoded_size());
-                                msg.encode(&mut buf);
-                                buf
-                            }
-                        }
-                    } else {
-                        BytesMut::new()
+                    log::error!("Inconsistent state, bug");
+                    let err = Error {
+                        condition: LinkError::DetachForced.into(),
+                        description: Some(ByteString::from_static("Internal error")),
+                        info: None,
                     };
-                    self.partial_body = Some(body);
-                    self.queue.push_back(transfer);
+                    let _ = self.close(Some(err));
                 }
+            }
+        } else if transfer.more {
+            if transfer.delivery_id.is_none() {
+                let err = Error {
+                    condition: LinkError::DetachForced.into(),
+                    description: Some(ByteString::from_static("delivery_id is required")),
+                    info: None,
+                };
+                let _ = self.close(Some(err));
             } else {
-                self.delivery_count += 1;
-                self.queue.push_back(transfer);
-                if self.queue.len() == 1 {
-                    self.reader_task.wake()
+                let body = if let Some(body) = transfer.body.take() {
+                    match body {
+                        TransferBody::Data(data) => BytesMut::from(data.as_ref()),
+                        TransferBody::Message(msg) => {
+                            let mut buf = BytesMut::with_capacity(msg.encoded_size());
+                            msg.encode(&mut buf);
+                            buf
+                        }
+                    }
+                } else {
+                    BytesMut::new()
+                };
+                if body.len() > self.partial_body_max {
+                    self.reassembly_exceeded();
+                    return;
                 }
+                self.partial_body = Some(body);
+                self.queue.push_back(transfer);
+            }
+        } else {
+            self.delivery_count = self.delivery_count.wrapping_add(1);
+            self.queue.push_back(transfer);
+            if self.queue.len() == 1 {
+                self.reader_task.wake()
             }
         }
     }
 }
 
+fn transactional(txn_id: Bytes, state: DeliveryState) -> DeliveryState {
+    let outcome = match state {
+        DeliveryState::Accepted(st) => Outcome::Accepted(st),
+        DeliveryState::Rejected(st) => Outcome::Rejected(st),
+        DeliveryState::Released(st) => Outcome::Released(st),
+        DeliveryState::Modified(st) => Outcome::Modified(st),
+        state => return state,
+    };
+    DeliveryState::TransactionalState(TransactionalState {
+        txn_id,
+        outcome: Some(outcome),
+    })
+}
+
 pub struct ReceiverLinkBuilder {
     frame: Attach,
     session: Cell<SessionInner>,
+    flow_properties: Option<Fields>,
 }
 
 impl ReceiverLinkBuilder {
@@ -355,7 +1033,11 @@ impl ReceiverLinkBuilder {
             properties: None,
         };
 
-        ReceiverLinkBuilder { frame, session }
+        ReceiverLinkBuilder {
+            frame,
+            session,
+            flow_properties: None,
+        }
     }
 
     pub fn max_message_size(mut self, size: u64) -> Self {
@@ -363,6 +1045,67 @@ impl ReceiverLinkBuilder {
         self
     }
 
+    /// Set expiry policy and timeout (in seconds) of the source terminus
+    pub fn source_expiry(mut self, policy: TerminusExpiryPolicy, timeout: Seconds) -> Self {
+        if let Some(ref mut source) = self.frame.source {
+            source.expiry_policy = policy;
+            source.timeout = timeout;
+        }
+        self
+    }
+
+    /// Set expiry policy and timeout (in seconds) of the target terminus
+    pub fn target_expiry(mut self, policy: TerminusExpiryPolicy, timeout: Seconds) -> Self {
+        let target = self.frame.target.get_or_insert_with(|| Target {
+            address: None,
+            durable: TerminusDurability::None,
+            expiry_policy: TerminusExpiryPolicy::SessionEnd,
+            timeout: 0,
+            dynamic: false,
+            dynamic_node_properties: None,
+            capabilities: None,
+        });
+        target.expiry_policy = policy;
+        target.timeout = timeout;
+        self
+    }
+
+    /// Ask peer to create source node, address of the node
+    /// is reported in peer's `Attach`
+    pub fn dynamic_source(mut self) -> Self {
+        if let Some(ref mut source) = self.frame.source {
+            source.address = None;
+            source.dynamic = true;
+        }
+        self
+    }
+
+    /// Set lifetime policy of dynamically created source node
+    pub fn lifetime_policy(mut self, policy: LifetimePolicy) -> Self {
+        if let Some(ref mut source) = self.frame.source {
+            node::set_lifetime_policy(&mut source.dynamic_node_properties, policy);
+        }
+        self
+    }
+
+    /// Set distribution modes dynamically created source node must support
+    pub fn supported_dist_modes(mut self, modes: &[DistributionMode]) -> Self {
+        if let Some(ref mut source) = self.frame.source {
+            node::set_supported_dist_modes(&mut source.dynamic_node_properties, modes);
+        }
+        self
+    }
+
+    /// Set distribution mode of the source terminus.
+    ///
+    /// `DistributionMode::Copy` browses messages without acquiring them.
+    pub fn distribution_mode(mut self, mode: DistributionMode) -> Self {
+        if let Some(ref mut source) = self.frame.source {
+            source.distribution_mode = Some(mode);
+        }
+        self
+    }
+
     /// Set or reset a receive link property
     pub fn property(mut self, key: Symbol, value: Option<Variant>) -> Self {
         let props = self.frame.properties.get_or_insert_with(HashMap::default);
@@ -374,6 +1117,26 @@ impl ReceiverLinkBuilder {
         self
     }
 
+    /// Set default properties for `Flow` frames sent by the link
+    pub fn flow_properties(mut self, properties: Fields) -> Self {
+        self.flow_properties = Some(properties);
+        self
+    }
+
+    /// Attach to global shared durable subscription
+    pub(crate) fn shared_subscription(mut self) -> Self {
+        if let Some(ref mut source) = self.frame.source {
+            source.durable = TerminusDurability::UnsettledState;
+            source.expiry_policy = TerminusExpiryPolicy::Never;
+            source.capabilities = Some(Multiple(vec![
+                Symbol::from_static("topic"),
+                Symbol::from_static("shared"),
+                Symbol::from_static("global"),
+            ]));
+        }
+        self
+    }
+
     pub async fn open(self) -> Result<ReceiverLink, AmqpProtocolError> {
         let cell = self.session.clone();
         let res = self
@@ -383,9 +1146,130 @@ impl ReceiverLinkBuilder {
             .await;
 
         match res {
-            Ok(Ok(res)) => Ok(res),
+            Ok(Ok(res)) => {
+                res.inner.get_mut().flow_properties = self.flow_properties;
+                Ok(res)
+            }
             Ok(Err(err)) => Err(err),
             Err(_) => Err(AmqpProtocolError::Disconnected),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use ntex::framed::State;
+    use ntex_amqp_codec::protocol::{Begin, ProtocolVersion};
+
+    use super::*;
+    use crate::{connection::Connection, Configuration};
+
+    struct Next<'a>(&'a mut ReceiverLink);
+
+    impl<'a> Future for Next<'a> {
+        type Output = Option<Result<Transfer, AmqpProtocolError>>;
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+            match Pin::new(&mut *self.0).poll_next(cx) {
+                Poll::Pending => Poll::Ready(None),
+                res => res,
+            }
+        }
+    }
+
+    fn link(credit: u32) -> ReceiverLink {
+        let cfg = Configuration::default();
+        let con = Connection::new(
+            State::with_params(8 * 1024, 8 * 1024, 1024, 3),
+            &cfg,
+            &cfg,
+            ProtocolVersion::V1_0_0,
+        );
+        let begin = Begin {
+            remote_channel: Some(0),
+            next_outgoing_id: 1,
+            incoming_window: std::u32::MAX,
+            outgoing_window: std::u32::MAX,
+            handle_max: std::u32::MAX,
+            offered_capabilities: None,
+            desired_capabilities: None,
+            properties: None,
+        };
+        let session = Cell::new(SessionInner::new(0, true, con, 0, &begin, &begin));
+        let frame = ReceiverLinkBuilder::new("test".into(), "test".into(), session.clone()).frame;
+        let link = ReceiverLink::new(Cell::new(ReceiverLinkInner::new(session, 0, frame)));
+        link.inner.get_mut().credit = credit;
+        link
+    }
+
+    fn transfer(id: Option<DeliveryNumber>, data: &'static [u8], more: bool) -> Transfer {
+        Transfer {
+            handle: 0,
+            delivery_id: id,
+            delivery_tag: id.map(|_| Bytes::from_static(b"tag")),
+            message_format: None,
+            settled: Some(false),
+            more,
+            rcv_settle_mode: None,
+            state: None,
+            resume: false,
+            aborted: false,
+            batchable: false,
+            body: Some(TransferBody::Data(Bytes::from_static(data))),
+        }
+    }
+
+    #[ntex::test]
+    async fn test_reassembly() {
+        let mut link = link(10);
+        let inner = link.inner.get_mut();
+        inner.handle_transfer(transfer(Some(1), b"first ", true));
+        inner.handle_transfer(transfer(None, b"second ", true));
+        assert!(Next(&mut link).await.is_none());
+
+        let inner = link.inner.get_mut();
+        inner.handle_transfer(transfer(Some(1), b"third", false));
+        assert_eq!(inner.credit, 9);
+        assert_eq!(inner.delivery_count, 1);
+
+        let tr = Next(&mut link).await.unwrap().unwrap();
+        assert_eq!(tr.delivery_id, Some(1));
+        assert_eq!(
+            tr.body,
+            Some(TransferBody::Data(Bytes::from_static(
+                b"first second third"
+            )))
+        );
+        assert!(Next(&mut link).await.is_none());
+    }
+
+    #[ntex::test]
+    async fn test_aborted_delivery() {
+        let mut link = link(10);
+        let inner = link.inner.get_mut();
+        inner.handle_transfer(transfer(Some(1), b"first ", true));
+        inner.handle_transfer(transfer(None, b"second ", true));
+        let mut aborted = transfer(None, b"", false);
+        aborted.aborted = true;
+        inner.handle_transfer(aborted);
+
+        // partial body and unsettled state are dropped, credit is spent
+        assert!(inner.partial_body.is_none());
+        assert!(inner.unsettled.is_empty());
+        assert_eq!(inner.credit, 9);
+        assert_eq!(inner.delivery_count, 1);
+        assert!(Next(&mut link).await.is_none());
+
+        // next delivery is not affected
+        link.inner
+            .get_mut()
+            .handle_transfer(transfer(Some(2), b"next", false));
+        let tr = Next(&mut link).await.unwrap().unwrap();
+        assert_eq!(tr.delivery_id, Some(2));
+        assert_eq!(
+            tr.body,
+            Some(TransferBody::Data(Bytes::from_static(b"next")))
+        );
+        assert_eq!(link.inner.get_ref().delivery_count, 2);
+    }
+}
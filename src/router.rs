@@ -1,19 +1,30 @@
+use std::panic::{self, AssertUnwindSafe};
 use std::task::{Context, Poll};
-use std::{convert::TryFrom, future::Future, marker::PhantomData, pin::Pin};
+use std::time::Duration;
+use std::{
+    any::Any, cell, convert::TryFrom, future::Future, marker::PhantomData, pin::Pin, rc::Rc,
+};
 
 use ntex::router::{IntoPattern, Router as PatternRouter};
+use ntex::rt::time::Instant;
 use ntex::service::{boxed, fn_factory_with_config, IntoServiceFactory, Service, ServiceFactory};
-use ntex::util::{Either, Ready};
+use ntex::util::{ByteString, Either, Ready};
 use ntex::Stream;
 
-use crate::codec::protocol::{DeliveryNumber, DeliveryState, Disposition, Error, Rejected, Role};
-use crate::error::LinkError;
+use crate::error::{AmqpError, LinkError};
+use crate::protocol::{DeliveryNumber, DeliveryState, Disposition, Error, Rejected, Role};
 use crate::types::{Link, Outcome, Transfer};
-use crate::{cell::Cell, rcvlink::ReceiverLink, State};
+use crate::{cell::Cell, rcvlink::ReceiverLink, LinkState, State};
 
 type Handle<S> = boxed::BoxServiceFactory<Link<S>, Transfer<S>, Outcome, Error, Error>;
 
-pub struct Router<S = ()>(Vec<(Vec<String>, Handle<S>)>);
+/// Max length of panic message reported to peer
+const MAX_PANIC_MESSAGE: usize = 128;
+
+pub struct Router<S = ()> {
+    routes: Vec<(Vec<String>, Handle<S>)>,
+    quarantine: Option<(u32, Duration)>,
+}
 
 impl<S: 'static> Default for Router<S> {
     fn default() -> Router<S> {
@@ -23,7 +34,10 @@ impl<S: 'static> Default for Router<S> {
 
 impl<S: 'static> Router<S> {
     pub fn new() -> Router<S> {
-        Router(Vec::new())
+        Router {
+            routes: Vec::new(),
+            quarantine: None,
+        }
     }
 
     pub fn service<T, F, U: 'static>(mut self, address: T, service: F) -> Self
@@ -34,7 +48,7 @@ impl<S: 'static> Router<S> {
         Error: From<U::Error> + From<U::InitError>,
         Outcome: TryFrom<U::Error, Error = Error>,
     {
-        self.0.push((
+        self.routes.push((
             address.patterns(),
             ResourceServiceFactory::create(service.into_factory()),
         ));
@@ -42,6 +56,17 @@ impl<S: 'static> Router<S> {
         self
     }
 
+    /// Quarantine route after repeated handler panics.
+    ///
+    /// Panic of link handler always detaches the link with
+    /// `amqp:internal-error`. With quarantine, attaches to the route
+    /// are rejected for `cooldown` once its handlers panicked `panics`
+    /// times. Disabled by default.
+    pub fn quarantine(mut self, panics: u32, cooldown: Duration) -> Self {
+        self.quarantine = Some((panics, cooldown));
+        self
+    }
+
     pub fn finish(
         self,
     ) -> impl ServiceFactory<
@@ -52,8 +77,13 @@ impl<S: 'static> Router<S> {
         InitError = std::convert::Infallible,
     > {
         let mut router = PatternRouter::build();
-        for (addr, hnd) in self.0 {
-            router.path(addr, hnd);
+        for (addr, hnd) in self.routes {
+            let guard = RouteGuard {
+                quarantine: self.quarantine,
+                panics: cell::Cell::new(0),
+                until: cell::Cell::new(None),
+            };
+            router.path(addr, (hnd, Rc::new(guard)));
         }
         let router = Cell::new(router.finish());
 
@@ -66,7 +96,33 @@ impl<S: 'static> Router<S> {
 }
 
 struct RouterService<S> {
-    router: Cell<PatternRouter<Handle<S>>>,
+    router: Cell<PatternRouter<(Handle<S>, Rc<RouteGuard>)>>,
+}
+
+/// Panic accounting of a route
+struct RouteGuard {
+    quarantine: Option<(u32, Duration)>,
+    panics: cell::Cell<u32>,
+    until: cell::Cell<Option<Instant>>,
+}
+
+impl RouteGuard {
+    fn is_quarantined(&self) -> bool {
+        matches!(self.until.get(), Some(until) if Instant::now() < until)
+    }
+
+    fn panicked(&self) {
+        if let Some((max, cooldown)) = self.quarantine {
+            let panics = self.panics.get() + 1;
+            if panics >= max {
+                log::warn!("Route is quarantined for {:?}", cooldown);
+                self.panics.set(0);
+                self.until.set(Some(Instant::now() + cooldown));
+            } else {
+                self.panics.set(panics);
+            }
+        }
+    }
 }
 
 impl<S: 'static> Service for RouterService<S> {
@@ -89,12 +145,24 @@ impl<S: 'static> Service for RouterService<S> {
 
         if let Some(path) = path {
             link.path_mut().set(path);
-            if let Some((hnd, _info)) = self.router.recognize(link.path_mut()) {
+            if let Some(((hnd, guard), _info)) = self.router.recognize(link.path_mut()) {
+                if guard.is_quarantined() {
+                    trace!("Target address is quarantined: {}", link.path().get_ref());
+                    return Either::Left(Ready::Err(
+                        LinkError::force_detach()
+                            .description(format!(
+                                "Target address is quarantined: {}",
+                                link.path().get_ref()
+                            ))
+                            .into(),
+                    ));
+                }
                 trace!("Create handler service for {}", link.path().get_ref());
                 let fut = hnd.new_service(link.clone());
                 Either::Right(RouterServiceResponse {
                     link: link.link.clone(),
                     app_state: link.state.clone(),
+                    guard: guard.clone(),
                     state: RouterServiceResponseState::NewService(fut),
                 })
             } else {
@@ -124,6 +192,7 @@ impl<S: 'static> Service for RouterService<S> {
 struct RouterServiceResponse<S> {
     link: ReceiverLink,
     app_state: State<S>,
+    guard: Rc<RouteGuard>,
     state: RouterServiceResponseState<S>,
 }
 
@@ -186,28 +255,39 @@ impl<S> Future for RouterServiceResponse<S> {
                                 }
                                 Some(delivery_id) => {
                                     if link.credit() == 0 {
-                                        // self.has_credit = self.link.credit() != 0;
-                                        link.set_link_credit(50);
+                                        link.set_link_credit(link_credit(&link));
                                     }
 
                                     let msg =
                                         Transfer::new(app_state.clone(), transfer, link.clone());
 
-                                    let mut fut = srv.call(msg);
-                                    match Pin::new(&mut fut).poll(cx) {
-                                        Poll::Ready(Ok(outcome)) => settle(
+                                    // handler panic is contained within its link
+                                    let res = panic::catch_unwind(AssertUnwindSafe(|| {
+                                        let mut fut = srv.call(msg);
+                                        match Pin::new(&mut fut).poll(cx) {
+                                            Poll::Ready(res) => Either::Left(res),
+                                            Poll::Pending => Either::Right(fut),
+                                        }
+                                    }));
+                                    match res {
+                                        Ok(Either::Left(Ok(outcome))) => settle(
                                             &mut this.link,
                                             delivery_id,
                                             outcome.into_delivery_state(),
                                         ),
-                                        Poll::Pending => {
+                                        Ok(Either::Right(fut)) => {
                                             ntex::rt::spawn(HandleMessage {
                                                 fut,
                                                 delivery_id,
                                                 link: this.link.clone(),
+                                                guard: this.guard.clone(),
                                             });
                                         }
-                                        Poll::Ready(Err(e)) => {
+                                        Err(payload) => {
+                                            handler_panicked(&this.link, &this.guard, payload);
+                                            return Poll::Ready(Ok(()));
+                                        }
+                                        Ok(Either::Left(Err(e))) => {
                                             log::trace!("Service response error: {:?}", e);
                                             settle(
                                                 &mut this.link,
@@ -237,6 +317,10 @@ impl<S> Future for RouterServiceResponse<S> {
                 RouterServiceResponseState::NewService(ref mut fut) => match Pin::new(fut).poll(cx)
                 {
                     Poll::Ready(Ok(srv)) => {
+                        if !matches!(this.link.state(), LinkState::Attaching) {
+                            log::trace!("Link is rejected by service factory");
+                            return Poll::Ready(Ok(()));
+                        }
                         log::trace!(
                             "Handler service is created for {}",
                             this.link
@@ -247,7 +331,7 @@ impl<S> Future for RouterServiceResponse<S> {
                                 .unwrap_or("")
                         );
                         this.link.open();
-                        this.link.set_link_credit(50);
+                        this.link.set_link_credit(link_credit(&this.link));
                         this.state = RouterServiceResponseState::Service(srv);
                         continue;
                     }
@@ -273,6 +357,7 @@ impl<S> Future for RouterServiceResponse<S> {
 
 struct HandleMessage {
     link: ReceiverLink,
+    guard: Rc<RouteGuard>,
     delivery_id: DeliveryNumber,
     fut: Pin<Box<dyn Future<Output = Result<Outcome, Error>>>>,
 }
@@ -283,7 +368,16 @@ impl Future for HandleMessage {
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         let mut this = self.as_mut();
 
-        match Pin::new(&mut this.fut).poll(cx) {
+        let res = panic::catch_unwind(AssertUnwindSafe(|| Pin::new(&mut this.fut).poll(cx)));
+        let res = match res {
+            Ok(res) => res,
+            Err(payload) => {
+                handler_panicked(&this.link, &this.guard, payload);
+                return Poll::Ready(());
+            }
+        };
+
+        match res {
             Poll::Pending => Poll::Pending,
             Poll::Ready(Ok(outcome)) => {
                 log::trace!(
@@ -336,6 +430,45 @@ fn settle(link: &mut ReceiverLink, id: DeliveryNumber, state: DeliveryState) {
     link.send_disposition(disposition);
 }
 
+/// Detach link of panicked handler
+fn handler_panicked(link: &ReceiverLink, guard: &RouteGuard, payload: Box<dyn Any + Send>) {
+    let message = panic_message(&*payload);
+    log::error!(
+        "Handler of link {:?} panicked: {}",
+        link.frame().name,
+        message
+    );
+    guard.panicked();
+
+    let error =
+        AmqpError::internal_error().description(format!("Link handler panicked: {}", message));
+    link.handler_panicked(ByteString::from(message), error.into());
+}
+
+/// First line of panic message without control characters
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    let message = if let Some(s) = payload.downcast_ref::<&str>() {
+        s
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.as_str()
+    } else {
+        "unknown panic"
+    };
+    message
+        .lines()
+        .next()
+        .unwrap_or("")
+        .chars()
+        .filter(|c| !c.is_control())
+        .take(MAX_PANIC_MESSAGE)
+        .collect()
+}
+
+/// Configured credit of routed links, see `Configuration::link_credit()`
+fn link_credit(link: &ReceiverLink) -> u32 {
+    link.session().inner.get_ref().link_credit()
+}
+
 struct ResourceServiceFactory<S, T> {
     factory: T,
     _t: PhantomData<S>,
@@ -0,0 +1,524 @@
+//! `SCRAM-SHA-256` sasl mechanism, rfc 5802 and rfc 7677
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use hmac::{Hmac, Mac, NewMac};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+pub(crate) const MECHANISM: &str = "SCRAM-SHA-256";
+
+/// Gs2 header without channel binding
+const GS2_HEADER: &str = "n,,";
+
+/// Base64 encoded gs2 header
+const CHANNEL_BINDING: &str = "biws";
+
+/// Default limit of pbkdf2 iterations accepted by client
+pub(crate) const DEFAULT_MAX_ITERATIONS: u32 = 1_000_000;
+
+/// Iterations announced for unknown users
+const UNKNOWN_USER_ITERATIONS: u32 = 4096;
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Debug, Display, PartialEq)]
+pub(crate) enum ScramError {
+    #[display(fmt = "Malformed scram message")]
+    InvalidMessage,
+    #[display(fmt = "Channel binding is not supported")]
+    ChannelBinding,
+    #[display(fmt = "Nonce does not match")]
+    InvalidNonce,
+    #[display(fmt = "Client proof is not valid")]
+    InvalidProof,
+    #[display(fmt = "Server signature is not valid")]
+    InvalidServerSignature,
+    #[display(fmt = "Iteration count {} exceeds limit", _0)]
+    IterationCount(u32),
+    #[display(fmt = "Server error: {}", _0)]
+    Server(String),
+}
+
+impl std::error::Error for ScramError {}
+
+/// Credentials of `SCRAM-SHA-256` user as stored by server
+#[derive(Clone, Debug)]
+pub struct ScramCredentials {
+    salt: Vec<u8>,
+    iterations: u32,
+    salted_password: [u8; 32],
+}
+
+impl ScramCredentials {
+    /// Credentials with precomputed salted password
+    pub fn new(salt: &[u8], iterations: u32, salted_password: [u8; 32]) -> Self {
+        ScramCredentials {
+            salt: salt.to_vec(),
+            iterations,
+            salted_password,
+        }
+    }
+
+    /// Derive salted password from plain text password
+    pub fn from_password(password: &str, salt: &[u8], iterations: u32) -> Self {
+        Self::new(salt, iterations, salt_password(password, salt, iterations))
+    }
+
+    /// Credentials that never match, used for unknown users.
+    ///
+    /// Salt is stable per user name for the lifetime of the process,
+    /// so repeated attempts do not reveal that user does not exist.
+    pub(crate) fn unknown_user(username: &str) -> Self {
+        let salt = hmac(&unknown_user_secret(), username.as_bytes());
+        let mut salted_password = [0; 32];
+        salted_password[..16].copy_from_slice(Uuid::new_v4().as_bytes());
+        salted_password[16..].copy_from_slice(Uuid::new_v4().as_bytes());
+        Self::new(&salt[..16], UNKNOWN_USER_ITERATIONS, salted_password)
+    }
+
+    /// Salt used for password derivation
+    pub fn salt(&self) -> &[u8] {
+        &self.salt
+    }
+
+    /// Number of pbkdf2 iterations
+    pub fn iterations(&self) -> u32 {
+        self.iterations
+    }
+
+    /// Salted password, `Hi(password, salt, iterations)`
+    pub fn salted_password(&self) -> &[u8; 32] {
+        &self.salted_password
+    }
+}
+
+/// Client side of the exchange
+pub(crate) struct ScramClient {
+    password: String,
+    nonce: String,
+    first_bare: String,
+    max_iterations: u32,
+}
+
+impl ScramClient {
+    pub(crate) fn new(username: &str, password: &str) -> Self {
+        Self::with_nonce(username, password, &new_nonce())
+    }
+
+    fn with_nonce(username: &str, password: &str, nonce: &str) -> Self {
+        ScramClient {
+            password: password.to_string(),
+            nonce: nonce.to_string(),
+            first_bare: format!("n={},r={}", escape(username), nonce),
+            max_iterations: DEFAULT_MAX_ITERATIONS,
+        }
+    }
+
+    /// Limit pbkdf2 iterations requested by server
+    pub(crate) fn max_iterations(mut self, max_iterations: u32) -> Self {
+        self.max_iterations = max_iterations;
+        self
+    }
+
+    /// `client-first-message`
+    pub(crate) fn client_first(&self) -> String {
+        format!("{}{}", GS2_HEADER, self.first_bare)
+    }
+
+    /// `client-final-message` and expected server signature
+    pub(crate) fn client_final(
+        &self,
+        server_first: &str,
+    ) -> Result<(String, [u8; 32]), ScramError> {
+        let mut nonce = None;
+        let mut salt = None;
+        let mut iterations = None;
+        for (attr, value) in attributes(server_first)? {
+            match attr {
+                'r' => nonce = Some(value),
+                's' => salt = Some(base64::decode(value).map_err(|_| ScramError::InvalidMessage)?),
+                'i' => iterations = Some(value.parse().map_err(|_| ScramError::InvalidMessage)?),
+                'e' => return Err(ScramError::Server(value.to_string())),
+                _ => (),
+            }
+        }
+        let (nonce, salt, iterations): (_, _, u32) = match (nonce, salt, iterations) {
+            (Some(nonce), Some(salt), Some(iterations)) if iterations > 0 => {
+                (nonce, salt, iterations)
+            }
+            _ => return Err(ScramError::InvalidMessage),
+        };
+        if iterations > self.max_iterations {
+            return Err(ScramError::IterationCount(iterations));
+        }
+        if !nonce.starts_with(&self.nonce) || nonce.len() == self.nonce.len() {
+            return Err(ScramError::InvalidNonce);
+        }
+
+        let without_proof = format!("c={},r={}", CHANNEL_BINDING, nonce);
+        let auth_message = format!("{},{},{}", self.first_bare, server_first, without_proof);
+        let keys = Keys::new(&salt_password(&self.password, &salt, iterations));
+
+        let signature = hmac(&keys.stored_key, auth_message.as_bytes());
+        let mut proof = keys.client_key;
+        proof.iter_mut().zip(&signature).for_each(|(p, s)| *p ^= s);
+
+        Ok((
+            format!("{},p={}", without_proof, base64::encode(proof)),
+            hmac(&keys.server_key, auth_message.as_bytes()),
+        ))
+    }
+}
+
+/// Verify `server-final-message`
+pub(crate) fn verify_server_final(
+    server_final: &str,
+    expected: &[u8; 32],
+) -> Result<(), ScramError> {
+    for (attr, value) in attributes(server_final)? {
+        match attr {
+            'v' => {
+                let signature = base64::decode(value).map_err(|_| ScramError::InvalidMessage)?;
+                return if ct_eq(&signature, expected) {
+                    Ok(())
+                } else {
+                    Err(ScramError::InvalidServerSignature)
+                };
+            }
+            'e' => return Err(ScramError::Server(value.to_string())),
+            _ => (),
+        }
+    }
+    Err(ScramError::InvalidMessage)
+}
+
+/// Server side of the exchange
+pub(crate) struct ScramServer {
+    first_bare: String,
+    server_first: String,
+    nonce: String,
+    credentials: ScramCredentials,
+}
+
+/// Parse `client-first-message`, returns username
+pub(crate) fn client_username(client_first: &str) -> Result<String, ScramError> {
+    let (_, bare) = split_gs2_header(client_first)?;
+    for (attr, value) in attributes(bare)? {
+        if attr == 'n' {
+            return unescape(value);
+        }
+    }
+    Err(ScramError::InvalidMessage)
+}
+
+fn split_gs2_header(client_first: &str) -> Result<(&str, &str), ScramError> {
+    if let Some(bare) = client_first.strip_prefix(GS2_HEADER) {
+        Ok((GS2_HEADER, bare))
+    } else if client_first.starts_with("y,") || client_first.starts_with("p=") {
+        Err(ScramError::ChannelBinding)
+    } else {
+        Err(ScramError::InvalidMessage)
+    }
+}
+
+impl ScramServer {
+    pub(crate) fn new(
+        client_first: &str,
+        credentials: ScramCredentials,
+    ) -> Result<Self, ScramError> {
+        Self::with_nonce(client_first, credentials, &new_nonce())
+    }
+
+    fn with_nonce(
+        client_first: &str,
+        credentials: ScramCredentials,
+        server_nonce: &str,
+    ) -> Result<Self, ScramError> {
+        let (_, bare) = split_gs2_header(client_first)?;
+        let client_nonce = attributes(bare)?
+            .into_iter()
+            .find(|(attr, _)| *attr == 'r')
+            .map(|(_, value)| value)
+            .ok_or(ScramError::InvalidMessage)?;
+
+        let nonce = format!("{}{}", client_nonce, server_nonce);
+        let server_first = format!(
+            "r={},s={},i={}",
+            nonce,
+            base64::encode(&credentials.salt),
+            credentials.iterations
+        );
+        Ok(ScramServer {
+            first_bare: bare.to_string(),
+            server_first,
+            nonce,
+            credentials,
+        })
+    }
+
+    /// `server-first-message`
+    pub(crate) fn server_first(&self) -> &str {
+        &self.server_first
+    }
+
+    /// Verify `client-final-message`, returns `server-final-message`
+    pub(crate) fn server_final(&self, client_final: &str) -> Result<String, ScramError> {
+        let without_proof = client_final
+            .rfind(",p=")
+            .map(|pos| &client_final[..pos])
+            .ok_or(ScramError::InvalidMessage)?;
+
+        let mut proof = None;
+        for (attr, value) in attributes(client_final)? {
+            match attr {
+                'c' if value != CHANNEL_BINDING => return Err(ScramError::ChannelBinding),
+                'r' if value != self.nonce => return Err(ScramError::InvalidNonce),
+                'p' => proof = Some(base64::decode(value).map_err(|_| ScramError::InvalidMessage)?),
+                _ => (),
+            }
+        }
+        let proof = proof.ok_or(ScramError::InvalidMessage)?;
+        if proof.len() != 32 {
+            return Err(ScramError::InvalidProof);
+        }
+
+        let auth_message = format!(
+            "{},{},{}",
+            self.first_bare, self.server_first, without_proof
+        );
+        let keys = Keys::new(&self.credentials.salted_password);
+
+        // client key is recovered from proof and checked against stored key
+        let mut client_key = hmac(&keys.stored_key, auth_message.as_bytes());
+        client_key.iter_mut().zip(&proof).for_each(|(k, p)| *k ^= p);
+        if !ct_eq(&Sha256::digest(&client_key), &keys.stored_key) {
+            return Err(ScramError::InvalidProof);
+        }
+
+        let signature = hmac(&keys.server_key, auth_message.as_bytes());
+        Ok(format!("v={}", base64::encode(signature)))
+    }
+}
+
+struct Keys {
+    client_key: [u8; 32],
+    stored_key: [u8; 32],
+    server_key: [u8; 32],
+}
+
+impl Keys {
+    fn new(salted_password: &[u8; 32]) -> Self {
+        let client_key = hmac(salted_password, b"Client Key");
+        let mut stored_key = [0; 32];
+        stored_key.copy_from_slice(&Sha256::digest(&client_key));
+        Keys {
+            client_key,
+            stored_key,
+            server_key: hmac(salted_password, b"Server Key"),
+        }
+    }
+}
+
+fn salt_password(password: &str, salt: &[u8], iterations: u32) -> [u8; 32] {
+    let mut salted = [0; 32];
+    pbkdf2::pbkdf2::<HmacSha256>(password.as_bytes(), salt, iterations, &mut salted);
+    salted
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> [u8; 32] {
+    let mut mac = HmacSha256::new_from_slice(key).expect("Hmac accepts any key size");
+    mac.update(data);
+    let mut result = [0; 32];
+    result.copy_from_slice(&mac.finalize().into_bytes());
+    result
+}
+
+fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// Per-process secret for unknown user salts
+fn unknown_user_secret() -> [u8; 16] {
+    static SECRET: [AtomicU64; 2] = [AtomicU64::new(0), AtomicU64::new(0)];
+
+    let mut secret = [0; 16];
+    for (part, chunk) in SECRET.iter().zip(secret.chunks_mut(8)) {
+        let mut value = part.load(Ordering::Acquire);
+        if value == 0 {
+            let mut random = [0; 8];
+            random.copy_from_slice(&Uuid::new_v4().as_bytes()[..8]);
+            let random = u64::from_le_bytes(random) | 1;
+            value = match part.compare_exchange(0, random, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => random,
+                Err(current) => current,
+            };
+        }
+        chunk.copy_from_slice(&value.to_le_bytes());
+    }
+    secret
+}
+
+fn new_nonce() -> String {
+    Uuid::new_v4().to_simple().to_string()
+}
+
+/// Split message to `attr=value` pairs
+fn attributes(msg: &str) -> Result<Vec<(char, &str)>, ScramError> {
+    msg.split(',')
+        .map(|item| {
+            let mut chars = item.chars();
+            match (chars.next(), chars.next()) {
+                (Some(attr), Some('=')) if attr.is_ascii_alphabetic() => Ok((attr, &item[2..])),
+                _ => Err(ScramError::InvalidMessage),
+            }
+        })
+        .collect()
+}
+
+fn escape(name: &str) -> String {
+    name.replace('=', "=3D").replace(',', "=2C")
+}
+
+fn unescape(name: &str) -> Result<String, ScramError> {
+    let mut result = String::with_capacity(name.len());
+    let mut rest = name;
+    while let Some(pos) = rest.find('=') {
+        result.push_str(&rest[..pos]);
+        match rest.get(pos..pos + 3) {
+            Some("=3D") => result.push('='),
+            Some("=2C") => result.push(','),
+            _ => return Err(ScramError::InvalidMessage),
+        }
+        rest = &rest[pos + 3..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // rfc 7677, section 3
+    const CLIENT_FIRST: &str = "n,,n=user,r=rOprNGfwEbeRWgbNEkqO";
+    const SERVER_FIRST: &str =
+        "r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096";
+    const CLIENT_FINAL: &str = "c=biws,r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,\
+                                p=dHzbZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ=";
+    const SERVER_FINAL: &str = "v=6rriTRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4=";
+
+    #[test]
+    fn test_rfc_exchange() {
+        let client = ScramClient::with_nonce("user", "pencil", "rOprNGfwEbeRWgbNEkqO");
+        assert_eq!(client.client_first(), CLIENT_FIRST);
+        let (client_final, signature) = client.client_final(SERVER_FIRST).unwrap();
+        assert_eq!(client_final, CLIENT_FINAL);
+        assert!(verify_server_final(SERVER_FINAL, &signature).is_ok());
+
+        assert_eq!(client_username(CLIENT_FIRST).unwrap(), "user");
+        let salt = base64::decode("W22ZaJ0SNY7soEsUEjb6gQ==").unwrap();
+        let credentials = ScramCredentials::from_password("pencil", &salt, 4096);
+        let server =
+            ScramServer::with_nonce(CLIENT_FIRST, credentials, "%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0")
+                .unwrap();
+        assert_eq!(server.server_first(), SERVER_FIRST);
+        assert_eq!(server.server_final(CLIENT_FINAL).unwrap(), SERVER_FINAL);
+    }
+
+    #[test]
+    fn test_failures() {
+        let salt = b"salt";
+        let client = ScramClient::new("user", "pencil");
+        let server = ScramServer::new(
+            &client.client_first(),
+            ScramCredentials::from_password("pencil", salt, 4096),
+        )
+        .unwrap();
+        let (client_final, signature) = client.client_final(server.server_first()).unwrap();
+        let server_final = server.server_final(&client_final).unwrap();
+        assert!(verify_server_final(&server_final, &signature).is_ok());
+
+        // wrong password
+        let server = ScramServer::new(
+            &client.client_first(),
+            ScramCredentials::from_password("pen", salt, 4096),
+        )
+        .unwrap();
+        let (client_final, signature) = client.client_final(server.server_first()).unwrap();
+        assert_eq!(
+            server.server_final(&client_final),
+            Err(ScramError::InvalidProof)
+        );
+
+        // server does not know the password
+        assert_eq!(
+            verify_server_final(SERVER_FINAL, &signature),
+            Err(ScramError::InvalidServerSignature)
+        );
+        assert_eq!(
+            verify_server_final("e=invalid-proof", &signature),
+            Err(ScramError::Server("invalid-proof".to_string()))
+        );
+
+        // nonce is not extended by server
+        let server_first = format!("r={},s=c2FsdA==,i=4096", client.nonce);
+        assert_eq!(
+            client.client_final(&server_first).err(),
+            Some(ScramError::InvalidNonce)
+        );
+
+        // channel binding
+        assert_eq!(
+            client_username("p=tls-unique,,n=user,r=nonce"),
+            Err(ScramError::ChannelBinding)
+        );
+    }
+
+    #[test]
+    fn test_max_iterations() {
+        let client = ScramClient::with_nonce("user", "pencil", "rOprNGfwEbeRWgbNEkqO");
+        assert!(client.client_final(SERVER_FIRST).is_ok());
+
+        let client = client.max_iterations(4095);
+        assert_eq!(
+            client.client_final(SERVER_FIRST).err(),
+            Some(ScramError::IterationCount(4096))
+        );
+
+        let client = ScramClient::new("user", "pencil");
+        let server_first = format!("r={}srv,s=c2FsdA==,i=4294967295", client.nonce);
+        assert_eq!(
+            client.client_final(&server_first).err(),
+            Some(ScramError::IterationCount(u32::MAX))
+        );
+    }
+
+    #[test]
+    fn test_unknown_user() {
+        let first = ScramCredentials::unknown_user("nobody");
+        let second = ScramCredentials::unknown_user("nobody");
+        assert_eq!(first.salt(), second.salt());
+        assert_eq!(first.iterations(), UNKNOWN_USER_ITERATIONS);
+        assert_ne!(first.salt(), ScramCredentials::unknown_user("other").salt());
+
+        // exchange continues, proof is rejected by client-final
+        let client = ScramClient::new("nobody", "pencil");
+        let server = ScramServer::new(&client.client_first(), first).unwrap();
+        let (client_final, _) = client.client_final(server.server_first()).unwrap();
+        assert_eq!(
+            server.server_final(&client_final),
+            Err(ScramError::InvalidProof)
+        );
+    }
+
+    #[test]
+    fn test_username_escape() {
+        let client = ScramClient::new("a=b,c", "pencil");
+        assert!(client.client_first().starts_with("n,,n=a=3Db=2Cc,r="));
+        assert_eq!(client_username(&client.client_first()).unwrap(), "a=b,c");
+        assert_eq!(
+            client_username("n,,n=a=3Xb,r=nonce"),
+            Err(ScramError::InvalidMessage)
+        );
+    }
+}
@@ -1,8 +1,9 @@
 use derive_more::Display;
 use ntex::util::{ByteString, Either};
 
-use crate::codec::{protocol, AmqpCodecError, AmqpFrame, ProtocolIdError, SaslFrame};
+use crate::codec::{AmqpCodecError, AmqpFrame, ProtocolIdError, SaslFrame};
 use crate::error::AmqpProtocolError;
+use crate::protocol;
 
 /// Errors which can occur when attempting to handle amqp connection.
 #[derive(Debug, Display)]
@@ -76,6 +77,10 @@ pub enum HandshakeError {
     Sasl(protocol::SaslCode),
     #[display(fmt = "Peer disconnected")]
     Disconnected,
+    /// Tls handshake error
+    #[from(ignore)]
+    #[display(fmt = "Tls handshake error: {}", _0)]
+    Tls(std::io::Error),
     /// Unexpected io error
     Io(std::io::Error),
 }
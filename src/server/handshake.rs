@@ -2,10 +2,11 @@ use std::rc::Rc;
 
 use ntex::codec::{AsyncRead, AsyncWrite};
 use ntex::framed::State;
+use ntex::util::Either;
 
-use crate::codec::protocol::{Frame, Open};
-use crate::codec::{AmqpCodec, AmqpFrame};
-use crate::{connection::Connection, Configuration};
+use crate::codec::{AmqpCodec, AmqpFrame, ProtocolHeaderCodec, ProtocolIdError, PRE_OPEN_MAX_SIZE};
+use crate::protocol::{Close, Frame, Open, ProtocolHeader, ProtocolVersion};
+use crate::{connection::Connection, error::framing_error, Configuration};
 
 use super::{error::HandshakeError, sasl::Sasl};
 
@@ -16,11 +17,17 @@ pub enum Handshake<Io> {
 }
 
 impl<Io> Handshake<Io> {
-    pub(crate) fn new_plain(io: Io, state: State, local_config: Rc<Configuration>) -> Self {
+    pub(crate) fn new_plain(
+        io: Io,
+        state: State,
+        local_config: Rc<Configuration>,
+        version: ProtocolVersion,
+    ) -> Self {
         Handshake::Amqp(HandshakeAmqp {
             io,
             state,
             local_config,
+            version,
         })
     }
 
@@ -34,9 +41,15 @@ pub struct HandshakeAmqp<Io> {
     io: Io,
     state: State,
     local_config: Rc<Configuration>,
+    version: ProtocolVersion,
 }
 
 impl<Io> HandshakeAmqp<Io> {
+    /// Protocol version negotiated with protocol header exchange
+    pub fn protocol_version(&self) -> ProtocolVersion {
+        self.version
+    }
+
     /// Returns reference to io object
     pub fn get_ref(&self) -> &Io {
         &self.io
@@ -54,12 +67,10 @@ impl<Io: AsyncRead + AsyncWrite + Unpin> HandshakeAmqp<Io> {
         let mut io = self.io;
         let state = self.state;
         let local_config = self.local_config;
-        let codec = AmqpCodec::<AmqpFrame>::new();
+        let version = self.version;
 
-        let frame = state
-            .next(&mut io, &codec)
-            .await
-            .map_err(HandshakeError::from)?
+        let frame = next_open(&mut io, &state, &local_config, version)
+            .await?
             .ok_or_else(|| {
                 log::trace!("Server amqp is disconnected during open frame");
                 HandshakeError::Disconnected
@@ -70,7 +81,7 @@ impl<Io: AsyncRead + AsyncWrite + Unpin> HandshakeAmqp<Io> {
             Frame::Open(frame) => {
                 trace!("Got open frame: {:?}", frame);
                 let remote_config = (&frame).into();
-                let sink = Connection::new(state.clone(), &local_config, &remote_config);
+                let sink = Connection::new(state.clone(), &local_config, &remote_config, version);
                 Ok(HandshakeAmqpOpened {
                     frame,
                     io,
@@ -85,6 +96,74 @@ impl<Io: AsyncRead + AsyncWrite + Unpin> HandshakeAmqp<Io> {
     }
 }
 
+/// Read remote protocol header.
+///
+/// Version that is not acceptable is answered with preferred local
+/// version and handshake fails.
+pub(super) async fn next_header<Io>(
+    io: &mut Io,
+    state: &State,
+    local_config: &Configuration,
+) -> Result<ProtocolHeader, HandshakeError>
+where
+    Io: AsyncRead + AsyncWrite + Unpin,
+{
+    let header = state
+        .next(io, &ProtocolHeaderCodec)
+        .await
+        .map_err(HandshakeError::from)?
+        .ok_or_else(|| {
+            log::trace!("Server amqp is disconnected during handshake");
+            HandshakeError::Disconnected
+        })?;
+
+    if !local_config.accepts_version(header.version) {
+        log::trace!("Protocol version is not supported: {}", header.version);
+        let local = ProtocolHeader {
+            id: header.id,
+            version: local_config.protocol_version(),
+        };
+        let _ = state.send(io, &ProtocolHeaderCodec, local).await;
+        state.close();
+        return Err(ProtocolIdError::Incompatible.into());
+    }
+    Ok(header)
+}
+
+/// Read remote `Open` frame.
+///
+/// Frame size is limited until `Open` is exchanged, size violation
+/// is reported to the peer with `Open` followed by `Close`.
+pub(super) async fn next_open<Io>(
+    io: &mut Io,
+    state: &State,
+    local_config: &Configuration,
+    version: ProtocolVersion,
+) -> Result<Option<AmqpFrame>, HandshakeError>
+where
+    Io: AsyncRead + AsyncWrite + Unpin,
+{
+    let codec = AmqpCodec::<AmqpFrame>::new()
+        .max_size(PRE_OPEN_MAX_SIZE)
+        .frame_table(local_config.frame_table(version));
+    let err = match state.next(io, &codec).await {
+        Ok(frame) => return Ok(frame),
+        Err(err) => err,
+    };
+
+    if let Either::Left(ref err) = err {
+        if let Some(error) = framing_error(err) {
+            log::trace!("Frame size violation during open: {}", err);
+            let open = AmqpFrame::new(0, local_config.to_open().into());
+            let close = AmqpFrame::new(0, Close { error: Some(error) }.into());
+            if state.send(io, &codec, open).await.is_ok() {
+                let _ = state.send(io, &codec, close).await;
+            }
+        }
+    }
+    Err(HandshakeError::from(err))
+}
+
 /// Connection is opened
 pub struct HandshakeAmqpOpened<Io> {
     frame: Open,
@@ -151,7 +230,6 @@ impl<Io> HandshakeAmqpOpened<Io> {
             io: self.io,
             sink: self.sink,
             state: self.state,
-            idle_timeout: self.remote_config.timeout_remote_secs(),
         }
     }
 }
@@ -162,11 +240,10 @@ pub struct HandshakeAck<Io, St> {
     io: Io,
     sink: Connection,
     state: State,
-    idle_timeout: usize,
 }
 
 impl<Io, St> HandshakeAck<Io, St> {
-    pub(crate) fn into_inner(self) -> (St, Io, Connection, State, usize) {
-        (self.st, self.io, self.sink, self.state, self.idle_timeout)
+    pub(crate) fn into_inner(self) -> (St, Io, Connection, State) {
+        (self.st, self.io, self.sink, self.state)
     }
 }
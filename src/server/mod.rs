@@ -2,13 +2,16 @@ mod error;
 mod handshake;
 pub mod sasl;
 mod service;
+mod tls;
 
 pub use self::error::{HandshakeError, ServerError};
 pub use self::handshake::{Handshake, HandshakeAck, HandshakeAmqp, HandshakeAmqpOpened};
 pub use self::sasl::Sasl;
 pub use self::service::Server;
+pub use self::tls::{MaybeTls, TlsAcceptor};
 pub use crate::control::{ControlFrame, ControlFrameKind};
 pub use crate::error::{Error, LinkError};
 pub use crate::router::Router;
+pub use crate::scram::ScramCredentials;
 pub use crate::state::State;
 pub use crate::types::{Link, Outcome, Transfer};
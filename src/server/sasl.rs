@@ -1,21 +1,31 @@
-use std::{fmt, rc::Rc};
+use std::{fmt, future::Future, pin::Pin, rc::Rc};
 
 use ntex::codec::{AsyncRead, AsyncWrite};
 use ntex::framed::State;
 use ntex::util::{ByteString, Bytes};
 
-use crate::codec::protocol::{
+use crate::codec::types::{Multiple, Symbol};
+use crate::codec::{AmqpCodec, ProtocolHeaderCodec, ProtocolIdError, SaslFrame, PRE_OPEN_MAX_SIZE};
+use crate::protocol::{
     self, ProtocolId, SaslChallenge, SaslCode, SaslFrameBody, SaslMechanisms, SaslOutcome, Symbols,
 };
-use crate::codec::{AmqpCodec, AmqpFrame, ProtocolIdCodec, ProtocolIdError, SaslFrame};
 
-use super::{handshake::HandshakeAmqpOpened, HandshakeError};
+use super::handshake::{next_header, next_open, HandshakeAmqpOpened};
+use super::HandshakeError;
+use crate::scram::{self, ScramCredentials, ScramServer};
 use crate::{connection::Connection, Configuration};
 
+type SaslHandler<Io> = Box<
+    dyn FnOnce(
+        SaslInit<Io>,
+    ) -> Pin<Box<dyn Future<Output = Result<SaslSuccess<Io>, HandshakeError>>>>,
+>;
+
 pub struct Sasl<Io> {
     io: Io,
     state: State,
     mechanisms: Symbols,
+    handlers: Vec<(Symbol, SaslHandler<Io>)>,
     local_config: Rc<Configuration>,
 }
 
@@ -32,8 +42,9 @@ impl<Io> Sasl<Io> {
         Sasl {
             io,
             state,
+            mechanisms: Multiple(local_config.sasl_mechanisms.clone()),
+            handlers: Vec::new(),
             local_config,
-            mechanisms: Symbols::default(),
         }
     }
 }
@@ -54,11 +65,59 @@ where
 
     /// Add supported sasl mechanism
     pub fn mechanism<U: Into<String>>(mut self, symbol: U) -> Self {
-        self.mechanisms.push(ByteString::from(symbol.into()).into());
+        let symbol = ByteString::from(symbol.into());
+        if !self.mechanisms.iter().any(|m| m.as_str() == &*symbol) {
+            self.mechanisms.push(symbol.into());
+        }
+        self
+    }
+
+    /// Sasl mechanisms offered to client
+    pub fn mechanisms(&self) -> &[Symbol] {
+        &self.mechanisms
+    }
+
+    /// Add supported sasl mechanism with handler.
+    ///
+    /// Mechanisms are offered in order of registration, handler is called
+    /// by `Sasl::authenticate()` if client picks this mechanism.
+    pub fn handler<U, F, R>(mut self, symbol: U, handler: F) -> Self
+    where
+        U: Into<String>,
+        F: FnOnce(SaslInit<Io>) -> R + 'static,
+        R: Future<Output = Result<SaslSuccess<Io>, HandshakeError>> + 'static,
+    {
+        let symbol = symbol.into();
+        self = self.mechanism(symbol.clone());
+        let symbol = ByteString::from(symbol);
+        self.handlers.retain(|(m, _)| m.as_str() != &*symbol);
+        self.handlers
+            .push((symbol.into(), Box::new(move |init| Box::pin(handler(init)))));
         self
     }
 
-    /// Initialize sasl auth procedure
+    /// Run sasl negotiation with handler of mechanism picked by client.
+    ///
+    /// If client picks mechanism without registered handler, `auth` outcome
+    /// is sent and connection is closed.
+    pub async fn authenticate(mut self) -> Result<SaslSuccess<Io>, HandshakeError> {
+        let mut handlers = std::mem::take(&mut self.handlers);
+        let init = self.init().await?;
+        if let Some(idx) = handlers
+            .iter()
+            .position(|(m, _)| m.as_str() == init.mechanism())
+        {
+            let (_, handler) = handlers.swap_remove(idx);
+            handler(init).await
+        } else {
+            Err(init.reject().await)
+        }
+    }
+
+    /// Initialize sasl auth procedure.
+    ///
+    /// If client picks mechanism that is not offered, `auth` outcome
+    /// is sent and connection is closed.
     pub async fn init(self) -> Result<SaslInit<Io>, HandshakeError> {
         let Sasl {
             mut io,
@@ -69,11 +128,11 @@ where
         } = self;
 
         let frame = SaslMechanisms {
-            sasl_server_mechanisms: mechanisms,
+            sasl_server_mechanisms: mechanisms.clone(),
         }
         .into();
 
-        let codec = AmqpCodec::<SaslFrame>::new();
+        let codec = AmqpCodec::<SaslFrame>::new().max_size(PRE_OPEN_MAX_SIZE);
         state
             .send(&mut io, &codec, frame)
             .await
@@ -85,21 +144,134 @@ where
             .ok_or(HandshakeError::Disconnected)?;
 
         match frame.body {
-            SaslFrameBody::SaslInit(frame) => Ok(SaslInit {
-                frame,
-                io,
-                state,
-                codec,
-                local_config,
-            }),
+            SaslFrameBody::SaslInit(frame) => {
+                if !mechanisms.iter().any(|m| *m == frame.mechanism) {
+                    trace!("Sasl mechanism is not supported: {:?}", frame.mechanism);
+                    let outcome = SaslOutcome {
+                        code: SaslCode::Auth,
+                        additional_data: None,
+                    }
+                    .into();
+                    let _ = state.send(&mut io, &codec, outcome).await;
+                    state.close();
+                    return Err(HandshakeError::UnsupportedSaslMechanism(
+                        frame.mechanism.as_str().to_string(),
+                    ));
+                }
+
+                Ok(SaslInit {
+                    frame,
+                    mechanisms,
+                    io,
+                    state,
+                    codec,
+                    local_config,
+                })
+            }
             body => Err(HandshakeError::UnexpectedSaslBodyFrame(body)),
         }
     }
+
+    /// Accept sasl `ANONYMOUS` mechanism.
+    ///
+    /// `ANONYMOUS` is added to offered mechanisms, initial response of
+    /// the client is ignored. If client picks other offered mechanism
+    /// `auth` outcome is sent and connection is closed.
+    pub async fn anonymous(self) -> Result<SaslSuccess<Io>, HandshakeError> {
+        let init = self.mechanism("ANONYMOUS").init().await?;
+        if init.mechanism() == "ANONYMOUS" {
+            init.outcome(SaslCode::Ok).await
+        } else {
+            Err(init.reject().await)
+        }
+    }
+
+    /// Accept sasl `EXTERNAL` mechanism.
+    ///
+    /// Client identity is established by transport, i.e. by tls client
+    /// certificate, and must be verified by the application. Authorization
+    /// identity asserted by client is available via `SaslSuccess::authz_id()`.
+    pub async fn external(self) -> Result<SaslSuccess<Io>, HandshakeError> {
+        let init = self.mechanism("EXTERNAL").init().await?;
+        if init.mechanism() != "EXTERNAL" {
+            return Err(init.reject().await);
+        }
+
+        let authz_id = match init.initial_response().map(std::str::from_utf8) {
+            None => None,
+            Some(Ok("")) => None,
+            Some(Ok(authz_id)) => Some(ByteString::from(authz_id)),
+            Some(Err(_)) => {
+                trace!("Sasl EXTERNAL authorization identity is not utf-8");
+                let succ = init.outcome(SaslCode::Auth).await?;
+                succ.state.close();
+                return Err(HandshakeError::Sasl(SaslCode::Auth));
+            }
+        };
+        let mut succ = init.outcome(SaslCode::Ok).await?;
+        succ.authz_id = authz_id;
+        Ok(succ)
+    }
+
+    /// Accept sasl `SCRAM-SHA-256` mechanism.
+    ///
+    /// `credentials` resolves user name to stored credentials, invalid client
+    /// proofs get `auth` outcome and connection is closed. Exchange with unknown
+    /// users continues with generated salt and fails on client proof, the same
+    /// way as for wrong password.
+    /// Authenticated user name is available via `SaslSuccess::authn_id()`.
+    pub async fn scram_sha256<F>(self, credentials: F) -> Result<SaslSuccess<Io>, HandshakeError>
+    where
+        F: Fn(&str) -> Option<ScramCredentials>,
+    {
+        let init = self.mechanism(scram::MECHANISM).init().await?;
+        if init.mechanism() != scram::MECHANISM {
+            return Err(init.reject().await);
+        }
+
+        let server = init.initial_response().and_then(|resp| {
+            let client_first = std::str::from_utf8(resp).ok()?;
+            let username = scram::client_username(client_first).ok()?;
+            let credentials =
+                credentials(&username).unwrap_or_else(|| ScramCredentials::unknown_user(&username));
+            let server = ScramServer::new(client_first, credentials).ok()?;
+            Some((username, server))
+        });
+        let (username, server) = match server {
+            Some(server) => server,
+            None => {
+                trace!("Sasl SCRAM-SHA-256 client-first message is rejected");
+                let succ = init.outcome(SaslCode::Auth).await?;
+                succ.state.close();
+                return Err(HandshakeError::Sasl(SaslCode::Auth));
+            }
+        };
+
+        let server_first = Bytes::copy_from_slice(server.server_first().as_bytes());
+        let resp = init.challenge_with(server_first).await?;
+        let server_final = std::str::from_utf8(resp.response())
+            .ok()
+            .and_then(|client_final| server.server_final(client_final).ok());
+
+        if let Some(server_final) = server_final {
+            let mut succ = resp
+                .outcome_with(SaslCode::Ok, Bytes::from(server_final))
+                .await?;
+            succ.authn_id = Some(ByteString::from(username));
+            Ok(succ)
+        } else {
+            trace!("Sasl SCRAM-SHA-256 client proof is not valid");
+            let succ = resp.outcome(SaslCode::Auth).await?;
+            succ.state.close();
+            Err(HandshakeError::Sasl(SaslCode::Auth))
+        }
+    }
 }
 
 /// Initialization stage of sasl negotiation
 pub struct SaslInit<Io> {
     frame: protocol::SaslInit,
+    mechanisms: Symbols,
     io: Io,
     state: State,
     codec: AmqpCodec<SaslFrame>,
@@ -110,6 +282,7 @@ impl<Io> fmt::Debug for SaslInit<Io> {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
         fmt.debug_struct("SaslInit")
             .field("frame", &self.frame)
+            .field("mechanisms", &self.mechanisms)
             .finish()
     }
 }
@@ -118,17 +291,22 @@ impl<Io> SaslInit<Io>
 where
     Io: AsyncRead + AsyncWrite + Unpin,
 {
-    /// Sasl mechanism
+    /// Sasl mechanism selected by client
     pub fn mechanism(&self) -> &str {
         self.frame.mechanism.as_str()
     }
 
-    /// Sasl initial response
+    /// Sasl mechanisms offered to client
+    pub fn offered_mechanisms(&self) -> &[Symbol] {
+        &self.mechanisms
+    }
+
+    /// Sasl initial response, raw bytes as sent by client
     pub fn initial_response(&self) -> Option<&[u8]> {
         self.frame.initial_response.as_ref().map(|b| b.as_ref())
     }
 
-    /// Sasl initial response
+    /// Hostname provided by client
     pub fn hostname(&self) -> Option<&str> {
         self.frame.hostname.as_ref().map(|b| b.as_ref())
     }
@@ -143,6 +321,25 @@ where
         &mut self.io
     }
 
+    /// Send `auth` outcome for mechanism that is not handled and close connection
+    async fn reject(self) -> HandshakeError {
+        let SaslInit {
+            frame,
+            mut io,
+            state,
+            codec,
+            ..
+        } = self;
+        let outcome = SaslOutcome {
+            code: SaslCode::Auth,
+            additional_data: None,
+        }
+        .into();
+        let _ = state.send(&mut io, &codec, outcome).await;
+        state.close();
+        HandshakeError::UnsupportedSaslMechanism(frame.mechanism.as_str().to_string())
+    }
+
     /// Initiate sasl challenge
     pub async fn challenge(self) -> Result<SaslResponse<Io>, HandshakeError> {
         self.challenge_with(Bytes::new()).await
@@ -157,6 +354,8 @@ where
         let state = self.state;
         let codec = self.codec;
         let local_config = self.local_config;
+        let mechanism = self.frame.mechanism;
+        let mechanisms = self.mechanisms;
         let frame = SaslChallenge { challenge }.into();
 
         state
@@ -172,6 +371,8 @@ where
         match frame.body {
             SaslFrameBody::SaslResponse(frame) => Ok(SaslResponse {
                 frame,
+                mechanism,
+                mechanisms,
                 io,
                 state,
                 codec,
@@ -183,6 +384,23 @@ where
 
     /// Sasl challenge outcome
     pub async fn outcome(self, code: SaslCode) -> Result<SaslSuccess<Io>, HandshakeError> {
+        self.send_outcome(code, None).await
+    }
+
+    /// Sasl challenge outcome with additional data, for example session token
+    pub async fn outcome_with(
+        self,
+        code: SaslCode,
+        additional_data: Bytes,
+    ) -> Result<SaslSuccess<Io>, HandshakeError> {
+        self.send_outcome(code, Some(additional_data)).await
+    }
+
+    async fn send_outcome(
+        self,
+        code: SaslCode,
+        additional_data: Option<Bytes>,
+    ) -> Result<SaslSuccess<Io>, HandshakeError> {
         let mut io = self.io;
         let state = self.state;
         let codec = self.codec;
@@ -190,7 +408,7 @@ where
 
         let frame = SaslOutcome {
             code,
-            additional_data: None,
+            additional_data,
         }
         .into();
         state
@@ -202,12 +420,16 @@ where
             io,
             state,
             local_config,
+            authz_id: None,
+            authn_id: None,
         })
     }
 }
 
 pub struct SaslResponse<Io> {
     frame: protocol::SaslResponse,
+    mechanism: Symbol,
+    mechanisms: Symbols,
     io: Io,
     state: State,
     codec: AmqpCodec<SaslFrame>,
@@ -218,6 +440,7 @@ impl<Io> fmt::Debug for SaslResponse<Io> {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
         fmt.debug_struct("SaslResponse")
             .field("frame", &self.frame)
+            .field("mechanism", &self.mechanism)
             .finish()
     }
 }
@@ -231,8 +454,35 @@ where
         &self.frame.response[..]
     }
 
+    /// Sasl mechanism selected by client
+    pub fn mechanism(&self) -> &str {
+        self.mechanism.as_str()
+    }
+
+    /// Sasl mechanisms offered to client
+    pub fn offered_mechanisms(&self) -> &[Symbol] {
+        &self.mechanisms
+    }
+
     /// Sasl challenge outcome
     pub async fn outcome(self, code: SaslCode) -> Result<SaslSuccess<Io>, HandshakeError> {
+        self.send_outcome(code, None).await
+    }
+
+    /// Sasl challenge outcome with additional data, for example session token
+    pub async fn outcome_with(
+        self,
+        code: SaslCode,
+        additional_data: Bytes,
+    ) -> Result<SaslSuccess<Io>, HandshakeError> {
+        self.send_outcome(code, Some(additional_data)).await
+    }
+
+    async fn send_outcome(
+        self,
+        code: SaslCode,
+        additional_data: Option<Bytes>,
+    ) -> Result<SaslSuccess<Io>, HandshakeError> {
         let mut io = self.io;
         let state = self.state;
         let codec = self.codec;
@@ -240,23 +490,20 @@ where
 
         let frame = SaslOutcome {
             code,
-            additional_data: None,
+            additional_data,
         }
         .into();
         state
             .send(&mut io, &codec, frame)
             .await
             .map_err(HandshakeError::from)?;
-        state
-            .next(&mut io, &codec)
-            .await
-            .map_err(HandshakeError::from)?
-            .ok_or(HandshakeError::Disconnected)?;
 
         Ok(SaslSuccess {
             io,
             state,
             local_config,
+            authz_id: None,
+            authn_id: None,
         })
     }
 }
@@ -265,12 +512,24 @@ pub struct SaslSuccess<Io> {
     io: Io,
     state: State,
     local_config: Rc<Configuration>,
+    authz_id: Option<ByteString>,
+    authn_id: Option<ByteString>,
 }
 
 impl<Io> SaslSuccess<Io>
 where
     Io: AsyncRead + AsyncWrite + Unpin,
 {
+    /// Authorization identity asserted by client with `EXTERNAL` mechanism
+    pub fn authz_id(&self) -> Option<&str> {
+        self.authz_id.as_deref()
+    }
+
+    /// Authentication identity verified by `SCRAM-SHA-256` mechanism
+    pub fn authn_id(&self) -> Option<&str> {
+        self.authn_id.as_deref()
+    }
+
     /// Returns reference to io object
     pub fn get_ref(&self) -> &Io {
         &self.io
@@ -286,26 +545,20 @@ where
         let mut io = self.io;
         let state = self.state;
 
-        let protocol = state
-            .next(&mut io, &ProtocolIdCodec)
-            .await
-            .map_err(HandshakeError::from)?
-            .ok_or(HandshakeError::Disconnected)?;
+        let header = next_header(&mut io, &state, &self.local_config).await?;
+        let version = header.version;
 
-        match protocol {
+        match header.id {
             ProtocolId::Amqp => {
                 // confirm protocol
                 state
-                    .send(&mut io, &ProtocolIdCodec, ProtocolId::Amqp)
+                    .send(&mut io, &ProtocolHeaderCodec, header)
                     .await
                     .map_err(HandshakeError::from)?;
 
                 // Wait for connection open frame
-                let codec = AmqpCodec::<AmqpFrame>::new();
-                let frame = state
-                    .next(&mut io, &codec)
-                    .await
-                    .map_err(HandshakeError::from)?
+                let frame = next_open(&mut io, &state, &self.local_config, version)
+                    .await?
                     .ok_or(HandshakeError::Disconnected)?;
 
                 let frame = frame.into_parts().1;
@@ -315,7 +568,8 @@ where
 
                         let local_config = self.local_config;
                         let remote_config = (&frame).into();
-                        let sink = Connection::new(state.clone(), &local_config, &remote_config);
+                        let sink =
+                            Connection::new(state.clone(), &local_config, &remote_config, version);
 
                         Ok(HandshakeAmqpOpened::new(
                             frame,
@@ -4,13 +4,14 @@ use ntex::codec::{AsyncRead, AsyncWrite};
 use ntex::framed::{Dispatcher as FramedDispatcher, State as IoState, Timer};
 use ntex::service::{IntoServiceFactory, Service, ServiceFactory};
 
-use crate::codec::{protocol::ProtocolId, AmqpCodec, AmqpFrame, ProtocolIdCodec, ProtocolIdError};
+use crate::codec::{AmqpCodec, AmqpFrame, ProtocolHeaderCodec, ProtocolIdError};
 use crate::dispatcher::Dispatcher;
+use crate::protocol::ProtocolId;
 use crate::types::Link;
 use crate::{default::DefaultControlService, Configuration, Connection, ControlFrame, State};
 
-use super::handshake::{Handshake, HandshakeAck};
-use super::{Error, HandshakeError, ServerError};
+use super::handshake::{next_header, Handshake, HandshakeAck};
+use super::{Error, HandshakeError, ServerError, TlsAcceptor};
 
 /// Server dispatcher factory
 pub struct Server<Io, St, H, Ctl> {
@@ -23,6 +24,7 @@ pub struct Server<Io, St, H, Ctl> {
     write_hw: u16,
     handshake_timeout: u64,
     disconnect_timeout: u16,
+    tls: Option<Rc<dyn TlsAcceptor<Io>>>,
     _t: marker::PhantomData<(Io, St)>,
 }
 
@@ -63,6 +65,7 @@ where
             control: DefaultControlService::default(),
             max_size: 0,
             config: Rc::new(Configuration::default()),
+            tls: None,
             _t: marker::PhantomData,
         }
     }
@@ -75,10 +78,23 @@ impl<Io, St, H, Ctl> Server<Io, St, H, Ctl> {
         self
     }
 
+    /// Accept `amqp tls` connections.
+    ///
+    /// Peer that sends tls protocol header gets tls header in reply,
+    /// then `acceptor` performs tls handshake and amqp negotiation continues
+    /// over encrypted stream. Without acceptor such peers are rejected.
+    pub fn tls<A>(mut self, acceptor: A) -> Self
+    where
+        A: TlsAcceptor<Io>,
+    {
+        self.tls = Some(Rc::new(acceptor));
+        self
+    }
+
     /// Set max inbound frame size.
     ///
-    /// If max size is set to `0`, size is unlimited.
-    /// By default max size is set to `0`
+    /// If max size is set to `0`, max frame size of connection
+    /// configuration is used. By default max size is set to `0`
     pub fn max_size(mut self, size: usize) -> Self {
         self.max_size = size;
         self
@@ -177,6 +193,7 @@ where
             lw: self.lw,
             read_hw: self.read_hw,
             write_hw: self.write_hw,
+            tls: self.tls,
             _t: marker::PhantomData,
         }
     }
@@ -201,6 +218,7 @@ where
     {
         ServerImpl {
             handshake: self.handshake,
+            tls: self.tls,
             inner: Rc::new(ServerInner {
                 handshake_timeout: self.handshake_timeout,
                 config: self.config,
@@ -221,6 +239,7 @@ where
 
 struct ServerImpl<Io, St, H, Ctl, Pb> {
     handshake: H,
+    tls: Option<Rc<dyn TlsAcceptor<Io>>>,
     inner: Rc<ServerInner<St, Ctl, Pb>>,
     _t: marker::PhantomData<(Io,)>,
 }
@@ -250,11 +269,13 @@ where
 
     fn new_service(&self, _: ()) -> Self::Future {
         let inner = self.inner.clone();
+        let tls = self.tls.clone();
         let fut = self.handshake.new_service(());
 
         Box::pin(async move {
             fut.await.map(move |handshake| ServerImplService {
                 inner,
+                tls,
                 handshake: Rc::new(handshake),
                 _t: marker::PhantomData,
             })
@@ -264,6 +285,7 @@ where
 
 struct ServerImplService<Io, St, H, Ctl, Pb> {
     handshake: Rc<H>,
+    tls: Option<Rc<dyn TlsAcceptor<Io>>>,
     inner: Rc<ServerInner<St, Ctl, Pb>>,
     _t: marker::PhantomData<(Io,)>,
 }
@@ -302,18 +324,18 @@ where
 
     fn call(&self, req: Self::Request) -> Self::Future {
         let timeout = self.inner.handshake_timeout;
-        let keepalive = self.inner.config.idle_time_out / 1000;
         let disconnect_timeout = self.inner.disconnect_timeout;
         let inner = self.inner.clone();
         let fut = handshake(
             req,
             self.inner.max_size,
             self.handshake.clone(),
+            self.tls.clone(),
             self.inner.clone(),
         );
 
         Box::pin(async move {
-            let (io, state, codec, sink, st, idle_timeout) = if timeout == 0 {
+            let (io, state, codec, sink, st) = if timeout == 0 {
                 fut.await?
             } else {
                 ntex::rt::time::timeout(time::Duration::from_millis(timeout), fut)
@@ -333,11 +355,11 @@ where
                 ServerError::ControlServiceError
             })?;
 
-            let dispatcher = Dispatcher::new(st, sink, pb_srv, ctl_srv, idle_timeout)
-                .map(|_| Option::<AmqpFrame>::None);
+            let dispatcher =
+                Dispatcher::new(st, sink, pb_srv, ctl_srv).map(|_| Option::<AmqpFrame>::None);
 
             FramedDispatcher::new(io, codec, state, dispatcher, inner.time.clone())
-                .keepalive_timeout(keepalive as u16)
+                .keepalive_timeout(0)
                 .disconnect_timeout(disconnect_timeout)
                 .await
                 .map_err(|_| ServerError::Disconnected)
@@ -349,18 +371,9 @@ async fn handshake<Io, St, H, Ctl, Pb>(
     mut io: Io,
     max_size: usize,
     handshake: Rc<H>,
+    tls: Option<Rc<dyn TlsAcceptor<Io>>>,
     inner: Rc<ServerInner<St, Ctl, Pb>>,
-) -> Result<
-    (
-        Io,
-        IoState,
-        AmqpCodec<AmqpFrame>,
-        Connection,
-        State<St>,
-        usize,
-    ),
-    ServerError<H::Error>,
->
+) -> Result<(Io, IoState, AmqpCodec<AmqpFrame>, Connection, State<St>), ServerError<H::Error>>
 where
     St: 'static,
     Io: AsyncRead + AsyncWrite + Unpin + 'static,
@@ -368,42 +381,66 @@ where
     Ctl: ServiceFactory<Config = State<St>, Request = ControlFrame, Response = ()> + 'static,
     Pb: ServiceFactory<Config = State<St>, Request = Link<St>, Response = ()> + 'static,
 {
-    let state = IoState::with_params(
+    let mut state = IoState::with_params(
         inner.read_hw,
         inner.write_hw,
         inner.lw,
         inner.disconnect_timeout,
     );
 
-    let protocol = state
-        .next(&mut io, &ProtocolIdCodec)
-        .await
-        .map_err(HandshakeError::from)?
-        .ok_or_else(|| {
-            log::trace!("Server amqp is disconnected during handshake");
-            HandshakeError::Disconnected
+    let mut header = next_header(&mut io, &state, &inner.config).await?;
+
+    // #5.2.1 tls handshake, then protocol negotiation starts over
+    if header.id == ProtocolId::AmqpTls {
+        let acceptor = tls.ok_or_else(|| {
+            HandshakeError::from(ProtocolIdError::Unexpected {
+                exp: ProtocolId::Amqp,
+                got: ProtocolId::AmqpTls,
+            })
         })?;
+        state
+            .send(&mut io, &ProtocolHeaderCodec, header)
+            .await
+            .map_err(HandshakeError::from)?;
+
+        io = acceptor.accept(io).await.map_err(HandshakeError::Tls)?;
+        state = IoState::with_params(
+            inner.read_hw,
+            inner.write_hw,
+            inner.lw,
+            inner.disconnect_timeout,
+        );
+        header = next_header(&mut io, &state, &inner.config).await?;
+    }
 
-    let (io, sink, state, codec, st, idle_timeout) = match protocol {
+    let (io, sink, state, codec, st) = match header.id {
         // start amqp processing
         ProtocolId::Amqp | ProtocolId::AmqpSasl => {
             state
-                .send(&mut io, &ProtocolIdCodec, protocol)
+                .send(&mut io, &ProtocolHeaderCodec, header)
                 .await
                 .map_err(HandshakeError::from)?;
 
             let ack = handshake
-                .call(if protocol == ProtocolId::Amqp {
-                    Handshake::new_plain(io, state, inner.config.clone())
+                .call(if header.id == ProtocolId::Amqp {
+                    Handshake::new_plain(io, state, inner.config.clone(), header.version)
                 } else {
                     Handshake::new_sasl(io, state, inner.config.clone())
                 })
                 .await
                 .map_err(ServerError::Service)?;
 
-            let (st, mut io, sink, state, idle_timeout) = ack.into_inner();
+            let (st, mut io, sink, state) = ack.into_inner();
 
-            let codec = AmqpCodec::new().max_size(max_size);
+            // peer has seen local `Open`, max frame size is negotiated
+            let max_size = if max_size != 0 {
+                max_size
+            } else {
+                inner.config.max_frame_size as usize
+            };
+            let codec = AmqpCodec::new()
+                .max_size(max_size)
+                .frame_table(inner.config.frame_table(sink.protocol_version()));
 
             // confirm Open
             let local = inner.config.to_open();
@@ -414,8 +451,9 @@ where
 
             let st = State::new(st);
 
-            (io, sink, state, codec, st, idle_timeout)
+            (io, sink, state, codec, st)
         }
+        // tls over tls is not supported
         ProtocolId::AmqpTls => {
             return Err(HandshakeError::from(ProtocolIdError::Unexpected {
                 exp: ProtocolId::Amqp,
@@ -425,5 +463,5 @@ where
         }
     };
 
-    Ok((io, state, codec, sink, st, idle_timeout))
+    Ok((io, state, codec, sink, st))
 }
@@ -0,0 +1,103 @@
+use std::{future::Future, io, pin::Pin, task::Context, task::Poll};
+
+use ntex::codec::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// Tls handshake of `amqp tls` connections, #5.2.1
+///
+/// Acceptor is called after server replied with tls protocol header,
+/// amqp protocol negotiation continues over returned stream.
+pub trait TlsAcceptor<Io>: 'static {
+    fn accept(&self, io: Io) -> Pin<Box<dyn Future<Output = io::Result<Io>>>>;
+}
+
+/// Connection stream that could be upgraded to tls
+#[derive(Debug)]
+pub enum MaybeTls<Io, T> {
+    Plain(Io),
+    Tls(T),
+}
+
+impl<Io, T> MaybeTls<Io, T> {
+    /// Check if stream is encrypted
+    pub fn is_tls(&self) -> bool {
+        matches!(self, MaybeTls::Tls(_))
+    }
+}
+
+impl<Io, T> AsyncRead for MaybeTls<Io, T>
+where
+    Io: AsyncRead + Unpin,
+    T: AsyncRead + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            MaybeTls::Plain(io) => Pin::new(io).poll_read(cx, buf),
+            MaybeTls::Tls(io) => Pin::new(io).poll_read(cx, buf),
+        }
+    }
+}
+
+impl<Io, T> AsyncWrite for MaybeTls<Io, T>
+where
+    Io: AsyncWrite + Unpin,
+    T: AsyncWrite + Unpin,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            MaybeTls::Plain(io) => Pin::new(io).poll_write(cx, buf),
+            MaybeTls::Tls(io) => Pin::new(io).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            MaybeTls::Plain(io) => Pin::new(io).poll_flush(cx),
+            MaybeTls::Tls(io) => Pin::new(io).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            MaybeTls::Plain(io) => Pin::new(io).poll_shutdown(cx),
+            MaybeTls::Tls(io) => Pin::new(io).poll_shutdown(cx),
+        }
+    }
+}
+
+#[cfg(feature = "rustls")]
+mod rustls {
+    use tokio_rustls::{server::TlsStream, TlsAcceptor as RustlsAcceptor};
+
+    use super::*;
+
+    impl<Io> TlsAcceptor<MaybeTls<Io, TlsStream<Io>>> for RustlsAcceptor
+    where
+        Io: AsyncRead + AsyncWrite + Unpin + 'static,
+    {
+        fn accept(
+            &self,
+            io: MaybeTls<Io, TlsStream<Io>>,
+        ) -> Pin<Box<dyn Future<Output = io::Result<MaybeTls<Io, TlsStream<Io>>>>>> {
+            match io {
+                MaybeTls::Plain(io) => {
+                    let fut = RustlsAcceptor::accept(self, io);
+                    Box::pin(async move { fut.await.map(MaybeTls::Tls) })
+                }
+                MaybeTls::Tls(_) => Box::pin(async {
+                    Err(io::Error::new(
+                        io::ErrorKind::Other,
+                        "Connection is already encrypted",
+                    ))
+                }),
+            }
+        }
+    }
+}
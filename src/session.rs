@@ -2,25 +2,70 @@ use std::collections::VecDeque;
 use std::future::Future;
 
 use ntex::channel::oneshot;
-use ntex::util::{BufMut, ByteString, Bytes, BytesMut, Either, HashMap, Ready};
+use ntex::util::{BufMut, ByteString, Bytes, BytesMut, Either, HashMap};
 use slab::Slab;
+use uuid::Uuid;
 
 use ntex_amqp_codec::protocol::{
-    Accepted, Attach, DeliveryNumber, DeliveryState, Detach, Disposition, Error, Flow, Frame,
-    Handle, MessageFormat, ReceiverSettleMode, Role, SenderSettleMode, Transfer, TransferBody,
-    TransferNumber,
+    Accepted, AmqpError, Attach, Begin, DeliveryNumber, DeliveryState, Detach, Disposition, Error,
+    Fields, Flow, Frame, Handle, Map, MessageFormat, ReceiverSettleMode, Role, SenderSettleMode,
+    Transfer, TransferBody, TransferNumber,
 };
-use ntex_amqp_codec::AmqpFrame;
+use ntex_amqp_codec::types::Variant;
+use ntex_amqp_codec::{AmqpFrame, Decode, Encode};
 
 use crate::cell::Cell;
 use crate::connection::Connection;
 use crate::error::AmqpProtocolError;
+use crate::lifecycle::{
+    EventSubscribers, Events, LinkState, SessionState, StateCell, StateChanges,
+};
 use crate::rcvlink::{ReceiverLink, ReceiverLinkBuilder, ReceiverLinkInner};
-use crate::sndlink::{SenderLink, SenderLinkBuilder, SenderLinkInner};
+use crate::sndlink::{ReattachSummary, SenderLink, SenderLinkBuilder, SenderLinkInner};
+use crate::stats::SessionStats;
 use crate::DeliveryPromise;
 
 const INITIAL_OUTGOING_ID: TransferNumber = 0;
 
+/// Delivery tags of deliveries sent without explicit tag
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TagGenerator {
+    /// Delivery id as 4 byte big-endian number, default
+    Sequential,
+    /// Random uuid, 16 bytes
+    Uuid,
+}
+
+/// Link event of a session
+#[derive(Clone, Debug, PartialEq)]
+pub enum LinkEvent {
+    /// Link is attached, `handle` is local link handle and `role` is local role
+    Attached {
+        handle: Handle,
+        name: ByteString,
+        role: Role,
+    },
+    /// Attached link is detached, `error` is error of `Detach` frame
+    Detached {
+        handle: Handle,
+        error: Option<Error>,
+    },
+    /// Link is migrated from another session of the connection,
+    /// reported after `Attached` event of the link
+    Migrated {
+        handle: Handle,
+        name: ByteString,
+        role: Role,
+    },
+    /// Handler of routed receiver link panicked, `message` is sanitized
+    /// panic message. Link is detached with `amqp:internal-error`
+    Panicked {
+        handle: Handle,
+        name: ByteString,
+        message: ByteString,
+    },
+}
+
 #[derive(Clone)]
 pub struct Session {
     pub(crate) inner: Cell<SessionInner>,
@@ -38,7 +83,98 @@ impl Session {
     }
 
     pub fn close(&self) -> impl Future<Output = Result<(), AmqpProtocolError>> {
-        Ready::Ok(())
+        self.end()
+    }
+
+    /// End session.
+    ///
+    /// Sends End frame and waits for remote End. Links of the session
+    /// are detached, pending operations fail with `AmqpProtocolError::SessionEnded`.
+    pub fn end(&self) -> impl Future<Output = Result<(), AmqpProtocolError>> {
+        let inner = self.inner.get_ref();
+        let rx = inner.sink.0.get_mut().end_session(inner.id, None);
+
+        async move {
+            match rx.await {
+                Ok(res) => res,
+                Err(_) => Err(AmqpProtocolError::Disconnected),
+            }
+        }
+    }
+
+    /// Session id for logging, `connid.ch3` where `3` is local channel
+    pub fn log_id(&self) -> String {
+        self.inner.get_ref().log_id()
+    }
+
+    /// Current session state
+    pub fn state(&self) -> SessionState {
+        self.inner.get_ref().state.get()
+    }
+
+    /// Subscribe to session state changes
+    pub fn state_changes(&self) -> StateChanges<SessionState> {
+        self.inner.get_ref().state.subscribe()
+    }
+
+    /// Subscribe to attach and detach events of session links.
+    ///
+    /// Only links attached after subscription are reported,
+    /// stream terminates when session ends.
+    pub fn link_events(&self) -> Events<LinkEvent> {
+        self.inner.get_mut().link_events.subscribe()
+    }
+
+    /// Begin frame received from remote peer
+    pub fn remote_begin(&self) -> &Begin {
+        &self.inner.get_ref().remote_begin
+    }
+
+    /// Max link handle, lowest of local and remote values
+    pub fn handle_max(&self) -> u32 {
+        self.inner.get_ref().handle_max
+    }
+
+    /// Current remote outgoing window
+    pub fn remote_outgoing_window(&self) -> u32 {
+        self.inner.get_ref().remote_outgoing_window
+    }
+
+    /// Current remote incoming window
+    pub fn remote_incoming_window(&self) -> u32 {
+        self.inner.get_ref().remote_incoming_window
+    }
+
+    /// Snapshot of session counters
+    pub fn stats(&self) -> SessionStats {
+        let inner = self.inner.get_ref();
+        SessionStats {
+            pending_transfers: inner.pending_transfers.len(),
+            ..inner.stats
+        }
+    }
+
+    /// Properties of the last session `Flow` received from peer
+    pub fn remote_flow_properties(&self) -> Option<&Fields> {
+        self.inner.get_ref().remote_flow_properties.as_ref()
+    }
+
+    /// Set properties for session `Flow` frames sent to peer
+    pub fn set_flow_properties(&self, properties: Option<Fields>) {
+        self.inner.get_mut().flow_properties = properties;
+    }
+
+    /// Set generator of delivery tags, default is `TagGenerator::Sequential`
+    pub fn set_tag_generator(&self, generator: TagGenerator) {
+        self.inner.get_mut().tag_generator = generator;
+    }
+
+    /// Send pending `Flow` frames immediately.
+    ///
+    /// Flow updates of session and links are merged within one task poll,
+    /// latest values of each link are sent in a single frame.
+    pub fn flush_flow(&self) {
+        self.inner.get_mut().flush_flow();
     }
 
     pub fn get_sender_link(&self, name: &str) -> Option<&SenderLink> {
@@ -72,6 +208,18 @@ impl Session {
         SenderLinkBuilder::new(name, address, self.inner.clone())
     }
 
+    /// Open sender link with default settings.
+    ///
+    /// Link shares flow control and channel of the session, it could be opened
+    /// by server as well, i.e. from `Link::session()`, to push messages to client.
+    pub fn open_sender_link<T: Into<ByteString>, U: Into<ByteString>>(
+        &mut self,
+        name: U,
+        address: T,
+    ) -> impl Future<Output = Result<SenderLink, AmqpProtocolError>> {
+        self.build_sender_link(name, address).open()
+    }
+
     /// Open receiver link
     pub fn build_receiver_link<T: Into<ByteString>, U: Into<ByteString>>(
         &mut self,
@@ -83,6 +231,47 @@ impl Session {
         ReceiverLinkBuilder::new(name, address, self.inner.clone())
     }
 
+    /// Open receiver link to the shared subscription of the topic.
+    ///
+    /// Subscription is durable and global, link name is `<subscription>|global`
+    /// and source carries `shared` and `global` capabilities. Receivers
+    /// attached with the same subscription name share topic messages.
+    pub fn open_shared_receiver<T: Into<ByteString>, U: AsRef<str>>(
+        &mut self,
+        topic: T,
+        subscription: U,
+    ) -> impl Future<Output = Result<ReceiverLink, AmqpProtocolError>> {
+        let name = format!("{}|global", subscription.as_ref());
+        self.build_receiver_link(name, topic)
+            .shared_subscription()
+            .open()
+    }
+
+    /// Check if node exists on peer.
+    ///
+    /// Receiver link is attached to `address` and closed right away,
+    /// peer refuses the link with `amqp:not-found` if node does not exist.
+    pub async fn node_exists<T: Into<ByteString>>(
+        &self,
+        address: T,
+    ) -> Result<bool, AmqpProtocolError> {
+        let name = format!("node-check-{}", Uuid::new_v4().to_simple());
+        let res = self.clone().build_receiver_link(name, address).open().await;
+
+        match res {
+            Ok(link) => {
+                let _ = link.close().await;
+                Ok(true)
+            }
+            Err(AmqpProtocolError::LinkDetached(Some(ref err)))
+                if err.condition == AmqpError::NotFound.into() =>
+            {
+                Ok(false)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
     /// Detach receiver link
     pub fn detach_receiver_link(
         &mut self,
@@ -122,7 +311,14 @@ impl Session {
 enum SenderLinkState {
     Established(SenderLink),
     Opening(Option<oneshot::Sender<Result<SenderLink, AmqpProtocolError>>>),
-    Closing(Option<oneshot::Sender<Result<(), AmqpProtocolError>>>),
+    Resuming(
+        SenderLink,
+        Option<oneshot::Sender<Result<ReattachSummary, AmqpProtocolError>>>,
+    ),
+    Closing(
+        Option<oneshot::Sender<Result<(), AmqpProtocolError>>>,
+        Option<SenderLink>,
+    ),
 }
 
 #[derive(Debug)]
@@ -135,12 +331,18 @@ enum ReceiverLinkState {
         )>,
     ),
     Established(ReceiverLink),
-    Closing(Option<oneshot::Sender<Result<(), AmqpProtocolError>>>),
+    Closing(
+        Option<oneshot::Sender<Result<(), AmqpProtocolError>>>,
+        ReceiverLink,
+    ),
 }
 
 impl SenderLinkState {
     fn is_opening(&self) -> bool {
-        matches!(self, SenderLinkState::Opening(_))
+        matches!(
+            self,
+            SenderLinkState::Opening(_) | SenderLinkState::Resuming(..)
+        )
     }
 }
 
@@ -155,6 +357,10 @@ pub(crate) struct SessionInner {
     sink: Connection,
     next_outgoing_id: TransferNumber,
     local: bool,
+    incoming_window: u32,
+    // window advertised in local Begin
+    outgoing_window: u32,
+    remote_begin: Begin,
 
     remote_channel_id: u16,
     next_incoming_id: TransferNumber,
@@ -162,13 +368,31 @@ pub(crate) struct SessionInner {
     remote_incoming_window: u32,
 
     unsettled_deliveries: HashMap<DeliveryNumber, DeliveryPromise>,
+    // tags of unsettled deliveries with local handle of sender link,
+    // encoded payload of single frame deliveries is kept for link reattach
+    unsettled_tags: HashMap<DeliveryNumber, (Handle, Bytes, Option<Bytes>)>,
+    // deliveries of sender links migrated from another session, by link name
+    // and tag, with delivery id of previous session and encoded payload
+    adopted_deliveries:
+        HashMap<(ByteString, Bytes), (DeliveryNumber, Option<Bytes>, DeliveryPromise)>,
 
     links: Slab<Either<SenderLinkState, ReceiverLinkState>>,
     links_by_name: HashMap<ByteString, usize>,
     remote_handles: HashMap<Handle, usize>,
+    handle_max: u32,
     pending_transfers: VecDeque<PendingTransfer>,
     disposition_subscribers: HashMap<DeliveryNumber, oneshot::Sender<Disposition>>,
+    // properties of outgoing session flows and of last remote session flow
+    flow_properties: Option<Fields>,
+    remote_flow_properties: Option<Fields>,
+    // flows waiting for flush, one per link and one for session
+    pending_flows: Vec<Flow>,
+    flush_scheduled: bool,
+    tag_generator: TagGenerator,
+    link_events: EventSubscribers<LinkEvent>,
     error: Option<AmqpProtocolError>,
+    state: StateCell<SessionState>,
+    stats: SessionStats,
 }
 
 struct PendingTransfer {
@@ -176,6 +400,7 @@ struct PendingTransfer {
     idx: u32,
     body: Option<TransferBody>,
     state: TransferState,
+    delivery_state: Option<DeliveryState>,
     tag: Option<Bytes>,
     settled: Option<bool>,
     message_format: Option<MessageFormat>,
@@ -187,15 +412,46 @@ pub(crate) enum TransferState {
     Continue,
     Last,
     Only(DeliveryPromise),
+    /// Delivery of reattached link is sent again with `resume` flag,
+    /// delivery without promise is settled
+    Resume(Option<DeliveryPromise>),
+}
+
+/// Check if delivery state is an outcome
+fn is_terminal(state: &Option<DeliveryState>) -> bool {
+    matches!(
+        state,
+        Some(DeliveryState::Accepted(_))
+            | Some(DeliveryState::Rejected(_))
+            | Some(DeliveryState::Released(_))
+            | Some(DeliveryState::Modified(_))
+    )
 }
 
 impl TransferState {
+    /// First transfer of a delivery
+    pub(crate) fn is_first(&self) -> bool {
+        matches!(
+            self,
+            TransferState::First(_) | TransferState::Only(_) | TransferState::Resume(_)
+        )
+    }
+
     fn more(&self) -> bool {
         match self {
-            TransferState::Only(_) | TransferState::Last => false,
+            TransferState::Only(_) | TransferState::Last | TransferState::Resume(_) => false,
             _ => true,
         }
     }
+
+    /// Promise of delivery, set for the first transfer only
+    pub(crate) fn into_promise(self) -> Option<DeliveryPromise> {
+        match self {
+            TransferState::First(tx) | TransferState::Only(tx) => Some(tx),
+            TransferState::Resume(tx) => tx,
+            TransferState::Continue | TransferState::Last => None,
+        }
+    }
 }
 
 impl SessionInner {
@@ -204,26 +460,39 @@ impl SessionInner {
         local: bool,
         sink: Connection,
         remote_channel_id: u16,
-        next_incoming_id: DeliveryNumber,
-        remote_incoming_window: u32,
-        remote_outgoing_window: u32,
+        local_begin: &Begin,
+        begin: &Begin,
     ) -> SessionInner {
         SessionInner {
+            handle_max: std::cmp::min(local_begin.handle_max(), begin.handle_max()),
             id,
             local,
             sink,
-            next_incoming_id,
             remote_channel_id,
-            remote_incoming_window,
-            remote_outgoing_window,
+            incoming_window: local_begin.incoming_window(),
+            outgoing_window: local_begin.outgoing_window(),
+            next_incoming_id: begin.next_outgoing_id(),
+            remote_incoming_window: begin.incoming_window(),
+            remote_outgoing_window: begin.outgoing_window(),
+            remote_begin: begin.clone(),
             next_outgoing_id: INITIAL_OUTGOING_ID,
             unsettled_deliveries: HashMap::default(),
+            unsettled_tags: HashMap::default(),
+            adopted_deliveries: HashMap::default(),
             links: Slab::new(),
             links_by_name: HashMap::default(),
             remote_handles: HashMap::default(),
             pending_transfers: VecDeque::new(),
             disposition_subscribers: HashMap::default(),
+            flow_properties: None,
+            remote_flow_properties: None,
+            pending_flows: Vec::new(),
+            flush_scheduled: false,
+            tag_generator: TagGenerator::Sequential,
+            link_events: EventSubscribers::new(),
             error: None,
+            state: StateCell::new(SessionState::Opened, SessionState::is_terminal),
+            stats: SessionStats::default(),
         }
     }
 
@@ -232,54 +501,251 @@ impl SessionInner {
         self.id as u16
     }
 
+    pub(crate) fn connection(&self) -> &Connection {
+        &self.sink
+    }
+
+    pub(crate) fn log_id(&self) -> String {
+        format!("{}.ch{}", self.sink.id(), self.id)
+    }
+
+    /// Change session state, all transitions go through this method
+    pub(crate) fn set_state(&mut self, st: SessionState) {
+        log::trace!("{}: Session state: {:?}", self.log_id(), st);
+        self.state.set(st);
+    }
+
+    fn link_attached(&mut self, handle: usize, name: &ByteString, role: Role) {
+        self.link_events.emit(LinkEvent::Attached {
+            handle: handle as Handle,
+            name: name.clone(),
+            role,
+        });
+    }
+
+    pub(crate) fn link_migrated(&mut self, handle: Handle, name: &ByteString, role: Role) {
+        self.link_events.emit(LinkEvent::Migrated {
+            handle,
+            name: name.clone(),
+            role,
+        });
+    }
+
+    pub(crate) fn link_panicked(&mut self, handle: Handle, name: &ByteString, message: ByteString) {
+        self.link_events.emit(LinkEvent::Panicked {
+            handle,
+            name: name.clone(),
+            message,
+        });
+    }
+
+    /// Check if link of this session could be migrated to `target` session.
+    ///
+    /// Returns `false` if `target` is this session.
+    pub(crate) fn check_migration(&self, target: &SessionInner) -> Result<bool, AmqpProtocolError> {
+        if self.error.is_some() {
+            // link of failed session is recovered
+            Ok(true)
+        } else if self.sink.id() != target.sink.id() {
+            Err(AmqpProtocolError::MigrationForeignConnection)
+        } else {
+            Ok(self.id != target.id)
+        }
+    }
+
+    /// Move unsettled deliveries of migrated sender link from `source` session.
+    ///
+    /// Delivery ids are scoped to session, deliveries are kept by link name
+    /// and tag until they are resumed with new ids on link reattach.
+    /// Deliveries settled while link is suspended are dropped.
+    pub(crate) fn adopt_deliveries(
+        &mut self,
+        source: &mut SessionInner,
+        name: &ByteString,
+        unsettled: &mut Vec<(DeliveryNumber, Bytes, Option<Bytes>)>,
+    ) {
+        for (id, tag, body) in std::mem::take(unsettled) {
+            if let Some(promise) = source.unsettled_deliveries.remove(&id) {
+                self.adopted_deliveries
+                    .insert((name.clone(), tag.clone()), (id, body, promise));
+                unsettled.push((id, tag, None));
+            }
+        }
+    }
+
+    /// Fail adopted deliveries of sender link, link could not be reattached
+    fn fail_adopted_deliveries(&mut self, name: &ByteString, err: &AmqpProtocolError) {
+        let keys: Vec<_> = self
+            .adopted_deliveries
+            .keys()
+            .filter(|(link, _)| link == name)
+            .cloned()
+            .collect();
+        for key in keys {
+            if let Some((_, _, promise)) = self.adopted_deliveries.remove(&key) {
+                promise.settle(Err(err.clone()));
+            }
+        }
+    }
+
+    /// Local `End` is sent, session stays in `Ending` state until remote `End`
+    pub(crate) fn ending(&mut self) {
+        self.flush_flow();
+        self.set_state(SessionState::Ending);
+        self.set_error(AmqpProtocolError::SessionEnded(None));
+    }
+
     /// Set error. New operations will return error.
     pub(crate) fn set_error(&mut self, err: AmqpProtocolError) {
         log::trace!("Connection is failed, dropping state: {:?}", err);
 
-        // drop pending transfers
+        if let SessionState::Opened = self.state.get() {
+            self.set_state(SessionState::closed(err.clone()));
+        }
+
+        // drop pending transfers and flows
         for tr in self.pending_transfers.drain(..) {
-            if let TransferState::First(tx) | TransferState::Only(tx) = tr.state {
-                let _ = tx.send(Err(err.clone()));
+            if let Some(tx) = tr.state.into_promise() {
+                tx.settle(Err(err.clone()));
             }
         }
+        self.pending_flows.clear();
 
-        // drop links
+        // unsettled deliveries of recoverable links are kept until
+        // links are reattached to another session
+        let mut kept = HashMap::default();
+        for (_, st) in self.links.iter_mut() {
+            if let Either::Left(SenderLinkState::Established(ref link)) = st {
+                let inner = link.inner.get_mut();
+                if inner.is_recoverable() {
+                    inner.unsettled = take_unsettled(&mut self.unsettled_tags, inner.id, false);
+                    for (id, _, _) in &inner.unsettled {
+                        if let Some(promise) = self.unsettled_deliveries.remove(id) {
+                            kept.insert(*id, promise);
+                        }
+                    }
+                }
+            }
+        }
+
+        // fail in-flight deliveries
+        for (_, promise) in self.unsettled_deliveries.drain() {
+            promise.settle(Err(err.clone()));
+        }
+        for (_, (_, _, promise)) in self.adopted_deliveries.drain() {
+            promise.settle(Err(err.clone()));
+        }
+        self.unsettled_deliveries = kept;
+        self.unsettled_tags.clear();
+        self.disposition_subscribers.clear();
+
+        // drop links, inners stay alive while application holds link handles
         self.links_by_name.clear();
         for (_, st) in self.links.iter_mut() {
             match st {
-                Either::Left(SenderLinkState::Opening(_)) => (),
+                Either::Left(SenderLinkState::Opening(ref mut tx)) => {
+                    if let Some(tx) = tx.take() {
+                        let _ = tx.send(Err(err.clone()));
+                    }
+                }
+                Either::Left(SenderLinkState::Resuming(ref link, ref mut tx)) => {
+                    if let Some(tx) = tx.take() {
+                        let _ = tx.send(Err(err.clone()));
+                    }
+                    link.inner.get_mut().detached(err.clone(), true);
+                }
                 Either::Left(SenderLinkState::Established(ref mut link)) => {
-                    link.inner.get_mut().detached(err.clone())
+                    let inner = link.inner.get_mut();
+                    let closed = !inner.is_recoverable();
+                    inner.detached(err.clone(), closed)
                 }
-                Either::Left(SenderLinkState::Closing(ref mut link)) => {
-                    if let Some(tx) = link.take() {
+                Either::Left(SenderLinkState::Closing(ref mut tx, ref mut link)) => {
+                    if let Some(tx) = tx.take() {
                         let _ = tx.send(Err(err.clone()));
                     }
+                    if let Some(link) = link {
+                        link.inner.get_mut().detached(err.clone(), true);
+                    }
                 }
                 Either::Right(ReceiverLinkState::Established(ref mut link)) => {
-                    link.remote_closed(None)
+                    link.inner
+                        .get_mut()
+                        .set_state(LinkState::closed(err.clone()));
+                    link.remote_closed(None, true)
+                }
+                Either::Right(ReceiverLinkState::Closing(ref mut tx, ref mut link)) => {
+                    if let Some(tx) = tx.take() {
+                        let _ = tx.send(Err(err.clone()));
+                    }
+                    link.inner
+                        .get_mut()
+                        .set_state(LinkState::closed(err.clone()));
+                }
+                Either::Right(ReceiverLinkState::Opening(ref mut inner)) => {
+                    if let Some(inner) = inner.take() {
+                        let inner = inner.get_mut();
+                        inner.set_state(LinkState::closed(err.clone()));
+                        inner.detached();
+                    }
+                }
+                Either::Right(ReceiverLinkState::OpeningLocal(ref mut item)) => {
+                    if let Some((inner, tx)) = item.take() {
+                        let _ = tx.send(Err(err.clone()));
+                        let inner = inner.get_mut();
+                        inner.set_state(LinkState::closed(err.clone()));
+                        inner.detached();
+                    }
                 }
                 _ => (),
             }
         }
         self.links.clear();
+        self.link_events.close();
 
         self.error = Some(err);
     }
 
+    /// Stop tracking deliveries nobody waits for
+    pub(crate) fn remove_canceled_deliveries(&mut self) {
+        self.unsettled_deliveries
+            .retain(|_, promise| !promise.is_canceled());
+        self.adopted_deliveries
+            .retain(|_, (_, _, promise)| !promise.is_canceled());
+        let deliveries = &self.unsettled_deliveries;
+        self.unsettled_tags
+            .retain(|id, _| deliveries.contains_key(id));
+    }
+
     fn wait_disposition(
         &mut self,
         id: DeliveryNumber,
     ) -> impl Future<Output = Result<Disposition, AmqpProtocolError>> {
         let (tx, rx) = oneshot::channel();
-        self.disposition_subscribers.insert(id, tx);
-        async move { rx.await.map_err(|_| AmqpProtocolError::Disconnected) }
+        let err = self.error.clone();
+        if err.is_none() {
+            self.disposition_subscribers.insert(id, tx);
+        }
+        async move {
+            if let Some(err) = err {
+                Err(err)
+            } else {
+                rx.await.map_err(|_| AmqpProtocolError::Disconnected)
+            }
+        }
     }
 
     pub(crate) fn max_frame_size(&self) -> usize {
         self.sink.0.max_frame_size
     }
 
+    pub(crate) fn max_reassembly_bytes(&self) -> usize {
+        self.sink.0.max_reassembly_bytes
+    }
+
+    pub(crate) fn link_credit(&self) -> u32 {
+        self.sink.0.link_credit
+    }
+
     /// Detach unconfirmed sender link
     pub(crate) fn detach_unconfirmed_sender_link(&mut self, attach: &Attach, error: Option<Error>) {
         let detach = Detach {
@@ -318,7 +784,9 @@ impl SessionInner {
         }
 
         link.get_mut().id = token;
+        link.get_mut().set_state(LinkState::Attached);
         self.remote_handles.insert(attach.handle(), token);
+        self.link_attached(token, &attach.name, Role::Sender);
         entry.insert(Either::Left(SenderLinkState::Established(SenderLink::new(
             link.clone(),
         ))));
@@ -368,9 +836,17 @@ impl SessionInner {
         mut frame: Attach,
     ) -> oneshot::Receiver<Result<ReceiverLink, AmqpProtocolError>> {
         let (tx, rx) = oneshot::channel();
+        if let Some(ref err) = self.error {
+            let _ = tx.send(Err(err.clone()));
+            return rx;
+        }
 
         let entry = self.links.vacant_entry();
         let token = entry.key();
+        if token as u64 > self.handle_max as u64 {
+            let _ = tx.send(Err(AmqpProtocolError::HandleMaxExceeded(self.handle_max)));
+            return rx;
+        }
 
         let inner = Cell::new(ReceiverLinkInner::new(cell, token as u32, frame.clone()));
         entry.insert(Either::Right(ReceiverLinkState::OpeningLocal(Some((
@@ -384,11 +860,20 @@ impl SessionInner {
         rx
     }
 
+    /// Check if receiver link is attached by peer and is not confirmed yet
+    pub(crate) fn is_unconfirmed_receiver_link(&self, token: Handle) -> bool {
+        matches!(
+            self.links.get(token as usize),
+            Some(Either::Right(ReceiverLinkState::Opening(Some(_))))
+        )
+    }
+
     pub(crate) fn confirm_receiver_link(&mut self, token: Handle, attach: &Attach) {
         if let Some(Either::Right(link)) = self.links.get_mut(token as usize) {
             match link {
                 ReceiverLinkState::Opening(l) => {
                     if let Some(l) = l.take() {
+                        let properties = l.get_mut().response_properties.take();
                         let attach = Attach {
                             name: attach.name.clone(),
                             handle: token as Handle,
@@ -403,9 +888,11 @@ impl SessionInner {
                             max_message_size: Some(65536),
                             offered_capabilities: None,
                             desired_capabilities: None,
-                            properties: None,
+                            properties,
                         };
+                        l.get_mut().set_state(LinkState::Attached);
                         *link = ReceiverLinkState::Established(ReceiverLink::new(l));
+                        self.link_attached(token as usize, &attach.name, Role::Receiver);
                         self.post_frame(attach.into());
                         return;
                     }
@@ -425,6 +912,47 @@ impl SessionInner {
         error: Option<Error>,
         tx: oneshot::Sender<Result<(), AmqpProtocolError>>,
     ) {
+        // link is refused, peer expects attach response before detach, #2.6.3
+        if let Some(Either::Right(ReceiverLinkState::Opening(Some(inner)))) =
+            self.links.get(id as usize)
+        {
+            let inner = inner.clone();
+            let _ = self.links.remove(id as usize);
+            self.remote_handles.retain(|_, idx| *idx != id as usize);
+            inner
+                .get_mut()
+                .set_state(LinkState::closed(AmqpProtocolError::LinkDetached(
+                    error.clone(),
+                )));
+
+            let attach = &inner.get_ref().attach;
+            let attach = Attach {
+                name: attach.name.clone(),
+                handle: id,
+                role: Role::Receiver,
+                snd_settle_mode: attach.snd_settle_mode(),
+                rcv_settle_mode: ReceiverSettleMode::First,
+                source: attach.source.clone(),
+                target: None,
+                unsettled: None,
+                incomplete_unsettled: false,
+                initial_delivery_count: None,
+                max_message_size: None,
+                offered_capabilities: None,
+                desired_capabilities: None,
+                properties: None,
+            };
+            self.post_frame(attach.into());
+            let detach = Detach {
+                handle: id,
+                closed,
+                error,
+            };
+            self.post_frame(detach.into());
+            let _ = tx.send(Ok(()));
+            return;
+        }
+
         if let Some(Either::Right(link)) = self.links.get_mut(id as usize) {
             match link {
                 ReceiverLinkState::Opening(_inner) => {
@@ -437,22 +965,45 @@ impl SessionInner {
                     let _ = tx.send(Ok(()));
                     let _ = self.links.remove(id as usize);
                 }
-                ReceiverLinkState::Established(_) => {
+                ReceiverLinkState::Established(ref rcv) => {
                     let detach = Detach {
                         handle: id,
                         closed,
                         error,
                     };
-                    *link = ReceiverLinkState::Closing(Some(tx));
+                    let rcv = rcv.clone();
+                    rcv.inner.get_mut().set_state(LinkState::Detaching);
+                    *link = ReceiverLinkState::Closing(Some(tx), rcv);
                     self.post_frame(detach.into());
                 }
-                ReceiverLinkState::Closing(_) => {
+                ReceiverLinkState::Closing(..) => {
                     let _ = tx.send(Ok(()));
                     let _ = self.links.remove(id as usize);
                     error!("Unexpected receiver link state: closing - {}", id);
                 }
-                ReceiverLinkState::OpeningLocal(_inner) => unimplemented!(),
+                ReceiverLinkState::OpeningLocal(ref mut item) => {
+                    // link is closed while attach is pending
+                    let err = AmqpProtocolError::LinkDetached(error.clone());
+                    if let Some((inner, attach_tx)) = item.take() {
+                        let _ = attach_tx.send(Err(err.clone()));
+                        let inner = inner.get_mut();
+                        inner.set_state(LinkState::closed(err));
+                        inner.detached();
+                        self.links_by_name.remove(&inner.attach.name);
+                    }
+                    let detach = Detach {
+                        handle: id,
+                        closed,
+                        error,
+                    };
+                    self.post_frame(detach.into());
+                    let _ = tx.send(Ok(()));
+                    let _ = self.links.remove(id as usize);
+                    self.remote_handles.retain(|_, idx| *idx != id as usize);
+                }
             }
+        } else if let Some(ref err) = self.error {
+            let _ = tx.send(Err(err.clone()));
         } else {
             let _ = tx.send(Ok(()));
             error!("Receiver link does not exist while detaching: {}", id);
@@ -474,23 +1025,42 @@ impl SessionInner {
                         closed,
                         error,
                     };
-                    *link = SenderLinkState::Closing(Some(tx));
+                    *link = SenderLinkState::Closing(Some(tx), None);
                     self.post_frame(detach.into());
                 }
-                SenderLinkState::Established(_) => {
+                SenderLinkState::Established(ref snd) => {
                     let detach = Detach {
                         handle: id as u32,
                         closed,
                         error,
                     };
-                    *link = SenderLinkState::Closing(Some(tx));
+                    let snd = snd.clone();
+                    snd.inner.get_mut().set_state(LinkState::Detaching);
+                    *link = SenderLinkState::Closing(Some(tx), Some(snd));
                     self.post_frame(detach.into());
                 }
-                SenderLinkState::Closing(_) => {
+                SenderLinkState::Resuming(ref snd, _) => {
+                    let detach = Detach {
+                        handle: id as u32,
+                        closed,
+                        error: error.clone(),
+                    };
+                    let snd = snd.clone();
+                    snd.inner.get_mut().set_state(LinkState::Detaching);
+                    *link = SenderLinkState::Closing(Some(tx), Some(snd.clone()));
+                    self.post_frame(detach.into());
+                    self.fail_adopted_deliveries(
+                        snd.inner.name(),
+                        &AmqpProtocolError::LinkDetached(error),
+                    );
+                }
+                SenderLinkState::Closing(..) => {
                     let _ = tx.send(Ok(()));
                     error!("Unexpected receiver link state: closing - {}", id);
                 }
             }
+        } else if let Some(ref err) = self.error {
+            let _ = tx.send(Err(err.clone()));
         } else {
             let _ = tx.send(Ok(()));
             error!("Receiver link does not exist while detaching: {}", id);
@@ -530,6 +1100,10 @@ impl SessionInner {
                     }
                 }
                 Frame::Transfer(transfer) => {
+                    self.stats.transfers_in += 1;
+                    self.stats.bytes_in +=
+                        transfer.body.as_ref().map(|b| b.len()).unwrap_or(0) as u64;
+
                     let idx = if let Some(idx) = self.remote_handles.get(&transfer.handle()) {
                         *idx
                     } else {
@@ -560,7 +1134,7 @@ impl SessionInner {
                                     let _ = self.next_incoming_id.wrapping_add(1);
                                     link.inner.get_mut().handle_transfer(transfer);
                                 }
-                                ReceiverLinkState::Closing(_) => (),
+                                ReceiverLinkState::Closing(..) => (),
                             },
                         }
                     } else {
@@ -587,6 +1161,14 @@ impl SessionInner {
             match self.links.get_mut(*index) {
                 Some(Either::Left(item)) => {
                     if item.is_opening() {
+                        self.remote_handles.insert(attach.handle(), *index);
+
+                        // #2.6.3 refused link, detach follows
+                        if attach.target.is_none() {
+                            trace!("Sender link is refused: {:?}, wait for detach", name);
+                            return true;
+                        }
+
                         trace!(
                             "Sender link opened: {:?} {} -> {}",
                             name,
@@ -594,23 +1176,41 @@ impl SessionInner {
                             attach.handle()
                         );
 
-                        self.remote_handles.insert(attach.handle(), *index);
-                        let delivery_count = attach.initial_delivery_count.unwrap_or(0);
-                        let link = Cell::new(SenderLinkInner::new(
-                            *index,
-                            name.clone(),
-                            attach.handle(),
-                            delivery_count,
-                            cell,
-                        ));
+                        let link = if let SenderLinkState::Resuming(ref link, _) = item {
+                            link.inner.get_mut().resumed(attach);
+                            link.inner.clone()
+                        } else {
+                            let delivery_count = attach.initial_delivery_count.unwrap_or(0);
+                            let link = Cell::new(SenderLinkInner::new(
+                                *index,
+                                name.clone(),
+                                attach.handle(),
+                                delivery_count,
+                                cell,
+                            ));
+                            link.get_mut().remote_attach = Some(attach.clone());
+                            link
+                        };
                         let local_sender = std::mem::replace(
                             item,
                             SenderLinkState::Established(SenderLink::new(link.clone())),
                         );
 
-                        if let SenderLinkState::Opening(Some(tx)) = local_sender {
-                            let _ = tx.send(Ok(SenderLink::new(link)));
+                        let index = *index;
+                        match local_sender {
+                            SenderLinkState::Opening(Some(tx)) => {
+                                let _ = tx.send(Ok(SenderLink::new(link)));
+                            }
+                            SenderLinkState::Resuming(_, tx) => {
+                                let summary =
+                                    self.reattach_deliveries(index, attach, link.get_mut());
+                                if let Some(tx) = tx {
+                                    let _ = tx.send(Ok(summary));
+                                }
+                            }
+                            _ => (),
                         }
+                        self.link_attached(index, name, Role::Sender);
                     }
                 }
                 Some(Either::Right(item)) => {
@@ -622,12 +1222,23 @@ impl SessionInner {
                             attach.handle()
                         );
                         if let ReceiverLinkState::OpeningLocal(opt_item) = item {
+                            // #2.6.3 refused link, detach follows
+                            if attach.source.is_none() {
+                                trace!("Receiver link is refused: {:?}, wait for detach", name);
+                                self.remote_handles.insert(attach.handle(), *index);
+                                return true;
+                            }
+
                             if let Some((link, tx)) = opt_item.take() {
                                 self.remote_handles.insert(attach.handle(), *index);
 
+                                link.get_mut().remote_attach = Some(attach.clone());
+                                link.get_mut().set_state(LinkState::Attached);
                                 *item =
                                     ReceiverLinkState::Established(ReceiverLink::new(link.clone()));
                                 let _ = tx.send(Ok(ReceiverLink::new(link)));
+                                let index = *index;
+                                self.link_attached(index, name, Role::Receiver);
                             } else {
                                 // TODO: close session
                                 error!("Inconsistent session state, bug");
@@ -646,8 +1257,95 @@ impl SessionInner {
         }
     }
 
+    /// Reconcile unsettled deliveries of reattached sender link with
+    /// unsettled map of remote `Attach`, #2.6.13
+    ///
+    /// Deliveries with terminal remote state are settled, deliveries known
+    /// by peer are queued to be sent again with `resume` flag, the rest is
+    /// aborted. Resumed transfers are sent as link credit permits.
+    fn reattach_deliveries(
+        &mut self,
+        token: usize,
+        attach: &Attach,
+        link: &mut SenderLinkInner,
+    ) -> ReattachSummary {
+        let mut summary = ReattachSummary::default();
+
+        let mut ids: Vec<_> = self
+            .unsettled_tags
+            .iter()
+            .filter(|(_, (hnd, _, _))| *hnd == token as Handle)
+            .map(|(id, _)| *id)
+            .collect();
+        ids.sort_unstable();
+
+        let mut deliveries = Vec::new();
+        for id in ids {
+            let (_, tag, payload) = self.unsettled_tags.remove(&id).unwrap();
+            if let Some(promise) = self.unsettled_deliveries.remove(&id) {
+                deliveries.push((id, tag, payload, promise));
+            }
+        }
+
+        // deliveries of migrated link, in order of previous session
+        let keys: Vec<_> = self
+            .adopted_deliveries
+            .keys()
+            .filter(|(name, _)| name == link.name())
+            .cloned()
+            .collect();
+        let mut adopted: Vec<_> = keys
+            .into_iter()
+            .filter_map(|key| {
+                let (id, payload, promise) = self.adopted_deliveries.remove(&key)?;
+                Some((id, key.1, payload, promise))
+            })
+            .collect();
+        adopted.sort_by_key(|(id, _, _, _)| *id);
+        deliveries.extend(adopted);
+
+        let mut resumed = Vec::new();
+        for (id, tag, payload, promise) in deliveries {
+            let remote = attach
+                .unsettled
+                .as_ref()
+                .and_then(|map| map.get(&Variant::Binary(tag.clone())));
+            let state = remote.and_then(remote_delivery_state);
+            let known = remote.is_some() || attach.incomplete_unsettled;
+
+            if is_terminal(&state) {
+                // peer has outcome, delivery is settled
+                resumed.push((tag.clone(), None, state.clone(), None));
+                promise.settle(Ok(Disposition {
+                    role: Role::Receiver,
+                    first: id,
+                    last: None,
+                    settled: true,
+                    state,
+                    batchable: false,
+                }));
+                summary.settled.push(tag);
+            } else if let (true, Some(payload)) = (known, payload) {
+                resumed.push((tag.clone(), Some(payload), None, Some(promise)));
+                summary.resumed.push(tag);
+            } else {
+                // delivery could not be resumed
+                promise.settle(Err(AmqpProtocolError::DeliveryAborted));
+                summary.aborted.push(tag);
+            }
+        }
+        link.resume_deliveries(resumed);
+        summary
+    }
+
     /// Handle `Detach` frame.
     pub(crate) fn handle_detach(&mut self, detach: &mut Detach) {
+        // flows of the link must precede detach response
+        self.flush_flow();
+
+        // remote endpoint attached refused link
+        let attached = self.remote_handles.contains_key(&detach.handle());
+
         // get local link instance
         let idx = if let Some(idx) = self.remote_handles.get(&detach.handle()) {
             *idx
@@ -659,6 +1357,16 @@ impl SessionInner {
             return;
         };
 
+        // only links reported as attached are reported as detached
+        let established = match self.links.get(idx) {
+            Some(Either::Left(SenderLinkState::Established(_)))
+            | Some(Either::Left(SenderLinkState::Closing(_, Some(_))))
+            | Some(Either::Right(ReceiverLinkState::Established(_)))
+            | Some(Either::Right(ReceiverLinkState::Closing(..))) => true,
+            _ => false,
+        };
+        let error = detach.error.clone();
+
         let remove = if let Some(link) = self.links.get_mut(idx) {
             match link {
                 Either::Left(link) => match link {
@@ -667,13 +1375,45 @@ impl SessionInner {
                             let err = AmqpProtocolError::LinkDetached(detach.error.clone());
                             let _ = tx.send(Err(err));
                         }
+                        if attached {
+                            let detach = Detach {
+                                handle: idx as Handle,
+                                closed: true,
+                                error: None,
+                            };
+                            self.sink
+                                .post_frame(AmqpFrame::new(self.remote_channel_id, detach.into()));
+                        }
+                        true
+                    }
+                    SenderLinkState::Resuming(ref link, ref mut tx) => {
+                        let err = AmqpProtocolError::LinkDetached(detach.error.clone());
+                        if let Some(tx) = tx.take() {
+                            let _ = tx.send(Err(err.clone()));
+                        }
+                        let name = link.inner.name().clone();
+                        link.inner.get_mut().detached(err.clone(), true);
+                        self.unsettled_tags
+                            .retain(|_, (hnd, _, _)| *hnd != idx as Handle);
+                        self.fail_adopted_deliveries(&name, &err);
+                        if attached {
+                            let detach = Detach {
+                                handle: idx as Handle,
+                                closed: true,
+                                error: None,
+                            };
+                            self.sink
+                                .post_frame(AmqpFrame::new(self.remote_channel_id, detach.into()));
+                        }
                         true
                     }
                     SenderLinkState::Established(link) => {
-                        // detach from remote endpoint
+                        // detach from remote endpoint, link is resumable
+                        // if peer keeps terminus
+                        let closed = detach.closed;
                         let detach = Detach {
                             handle: link.inner.get_ref().id(),
-                            closed: true,
+                            closed,
                             error: detach.error.clone(),
                         };
                         let err = AmqpProtocolError::LinkDetached(detach.error.clone());
@@ -687,9 +1427,8 @@ impl SessionInner {
                         while idx < self.pending_transfers.len() {
                             if self.pending_transfers[idx].link_handle == handle {
                                 let tr = self.pending_transfers.remove(idx).unwrap();
-                                if let TransferState::First(tx) | TransferState::Only(tx) = tr.state
-                                {
-                                    let _ = tx.send(Err(err.clone()));
+                                if let Some(tx) = tr.state.into_promise() {
+                                    tx.settle(Err(err.clone()));
                                 }
                             } else {
                                 idx += 1;
@@ -697,12 +1436,33 @@ impl SessionInner {
                         }
 
                         // detach snd link
-                        link.inner.get_mut().detached(err);
+                        let id = link.inner.get_ref().id;
+                        let unsettled = take_unsettled(&mut self.unsettled_tags, id, closed);
+                        link.inner.get_mut().unsettled = unsettled;
+                        link.inner.get_mut().detached(err, closed);
                         self.sink
                             .post_frame(AmqpFrame::new(self.remote_channel_id, detach.into()));
                         true
                     }
-                    SenderLinkState::Closing(_) => true,
+                    SenderLinkState::Closing(ref mut tx, ref link) => {
+                        // detach confirmation, link is suspended if
+                        // detach is not closing
+                        let err = AmqpProtocolError::LinkDetached(detach.error.clone());
+                        let unsettled =
+                            take_unsettled(&mut self.unsettled_tags, idx, detach.closed);
+                        if let Some(link) = link {
+                            link.inner.get_mut().unsettled = unsettled;
+                            link.inner.get_mut().detached(err.clone(), detach.closed);
+                        }
+                        if let Some(tx) = tx.take() {
+                            if detach.error.is_some() {
+                                let _ = tx.send(Err(err));
+                            } else {
+                                let _ = tx.send(Ok(()));
+                            }
+                        }
+                        true
+                    }
                 },
                 Either::Right(link) => match link {
                     ReceiverLinkState::Opening(_) => false,
@@ -717,16 +1477,25 @@ impl SessionInner {
                         } else {
                             error!("Inconsistent session state, bug");
                         }
+                        if attached {
+                            let detach = Detach {
+                                handle: idx as Handle,
+                                closed: true,
+                                error: None,
+                            };
+                            self.sink
+                                .post_frame(AmqpFrame::new(self.remote_channel_id, detach.into()));
+                        }
 
                         true
                     }
                     ReceiverLinkState::Established(link) => {
-                        link.remote_closed(detach.error.take());
+                        link.remote_closed(detach.error.take(), detach.closed);
 
                         // detach from remote endpoint
                         let detach = Detach {
                             handle: link.handle(),
-                            closed: true,
+                            closed: detach.closed,
                             error: None,
                         };
 
@@ -735,8 +1504,12 @@ impl SessionInner {
                             .post_frame(AmqpFrame::new(self.remote_channel_id, detach.into()));
                         true
                     }
-                    ReceiverLinkState::Closing(tx) => {
+                    ReceiverLinkState::Closing(tx, link) => {
                         // detach confirmation
+                        link.inner.get_mut().set_state(LinkState::detached(
+                            AmqpProtocolError::LinkDetached(detach.error.clone()),
+                            detach.closed,
+                        ));
                         if let Some(tx) = tx.take() {
                             if let Some(err) = detach.error.clone() {
                                 let _ = tx.send(Err(AmqpProtocolError::LinkDetached(Some(err))));
@@ -755,6 +1528,12 @@ impl SessionInner {
         if remove {
             self.links.remove(idx);
             self.remote_handles.remove(&detach.handle());
+            if established {
+                self.link_events.emit(LinkEvent::Detached {
+                    handle: idx as Handle,
+                    error,
+                });
+            }
         }
     }
 
@@ -773,29 +1552,40 @@ impl SessionInner {
             );
         }
 
-        if from == to {
-            if let Some(val) = self.unsettled_deliveries.remove(&from) {
-                if !disposition.settled {
-                    let mut disp = disposition.clone();
-                    disp.role = Role::Sender;
-                    disp.settled = true;
-                    disp.state = Some(DeliveryState::Accepted(Accepted {}));
-                    self.post_frame(Frame::Disposition(disp));
-                }
-                let _ = val.send(Ok(disposition));
+        if !disposition.settled {
+            // #3.4 delivery state is not terminal, outcome is not known yet
+            if !is_terminal(&disposition.state) {
+                return;
             }
-        } else {
-            if !disposition.settled {
-                let mut disp = disposition.clone();
-                disp.role = Role::Sender;
-                disp.settled = true;
-                disp.state = Some(DeliveryState::Accepted(Accepted {}));
-                self.post_frame(Frame::Disposition(disp));
+
+            // #2.6.12 receiver waits for settlement, settle deliveries with
+            // receiver's outcome. retransmitted disposition gets settled again
+            let mut disp = disposition.clone();
+            disp.role = Role::Sender;
+            disp.settled = true;
+            self.post_frame(Frame::Disposition(disp.clone()));
+
+            // deliveries are settled by sender, receiver's settlement is not required
+            for k in from..=to {
+                self.unsettled_tags.remove(&k);
+                if let Some(mut promise) = self.unsettled_deliveries.remove(&k) {
+                    promise.outcome(disposition.clone());
+                    promise.settle(Ok(disp.clone()));
+                }
             }
+            return;
+        }
 
+        if from == to {
+            self.unsettled_tags.remove(&from);
+            if let Some(promise) = self.unsettled_deliveries.remove(&from) {
+                promise.settle(Ok(disposition));
+            }
+        } else {
             for k in from..=to {
-                if let Some(val) = self.unsettled_deliveries.remove(&k) {
-                    let _ = val.send(Ok(disposition.clone()));
+                self.unsettled_tags.remove(&k);
+                if let Some(promise) = self.unsettled_deliveries.remove(&k) {
+                    promise.settle(Ok(disposition.clone()));
                 }
             }
         }
@@ -825,6 +1615,7 @@ impl SessionInner {
                 t.idx,
                 t.body,
                 t.state,
+                t.delivery_state,
                 t.tag,
                 t.settled,
                 t.message_format,
@@ -835,66 +1626,159 @@ impl SessionInner {
         }
 
         // apply link flow
-        if let Some(Either::Left(link)) = flow
+        if let Some(link) = flow
             .handle()
             .and_then(|h| self.remote_handles.get(&h).copied())
             .and_then(|h| self.links.get_mut(h))
         {
             match link {
-                SenderLinkState::Established(ref mut link) => {
+                Either::Left(SenderLinkState::Established(ref mut link)) => {
+                    link.inner.get_mut().apply_flow(&flow);
+                }
+                Either::Right(ReceiverLinkState::Established(ref mut link)) => {
                     link.inner.get_mut().apply_flow(&flow);
                 }
                 _ => warn!("Received flow frame"),
             }
         }
-        if flow.echo() {
+        if flow.handle().is_none() && flow.properties.is_some() {
+            self.remote_flow_properties = flow.properties.clone();
+        }
+        // link echo is handled by the link itself
+        if flow.echo() && flow.handle().is_none() {
             self.send_flow();
         }
     }
 
     fn send_flow(&mut self) {
-        let flow = Flow {
-            next_incoming_id: if self.local {
-                Some(self.next_incoming_id)
-            } else {
-                None
-            },
-            incoming_window: std::u32::MAX,
-            next_outgoing_id: self.next_outgoing_id,
-            outgoing_window: self.remote_incoming_window,
+        self.queue_flow(Flow {
+            next_incoming_id: None,
+            incoming_window: 0,
+            next_outgoing_id: 0,
+            outgoing_window: 0,
             handle: None,
             delivery_count: None,
             link_credit: None,
             available: None,
             drain: false,
             echo: false,
-            properties: None,
-        };
-        self.post_frame(flow.into());
+            properties: self.flow_properties.clone(),
+        });
+        // answer to echo is not delayed
+        self.flush_flow();
     }
 
-    pub(crate) fn rcv_link_flow(&mut self, handle: u32, delivery_count: u32, credit: u32) {
-        let flow = Flow {
-            next_incoming_id: if self.local {
-                Some(self.next_incoming_id)
-            } else {
-                None
-            },
-            incoming_window: std::u32::MAX,
-            next_outgoing_id: self.next_outgoing_id,
-            outgoing_window: self.remote_incoming_window,
+    pub(crate) fn rcv_link_flow(
+        &mut self,
+        handle: u32,
+        delivery_count: u32,
+        credit: u32,
+        drain: bool,
+        echo: bool,
+        properties: Option<Fields>,
+    ) {
+        self.queue_flow(Flow {
+            next_incoming_id: None,
+            incoming_window: 0,
+            next_outgoing_id: 0,
+            outgoing_window: 0,
             handle: Some(handle),
             delivery_count: Some(delivery_count),
             link_credit: Some(credit),
             available: None,
+            drain,
+            echo,
+            properties,
+        });
+    }
+
+    pub(crate) fn snd_link_flow(
+        &mut self,
+        handle: u32,
+        delivery_count: u32,
+        credit: u32,
+        available: u32,
+        properties: Option<Fields>,
+    ) {
+        // sender's flow is an answer to drain or echo
+        self.queue_flow(Flow {
+            next_incoming_id: None,
+            incoming_window: 0,
+            next_outgoing_id: 0,
+            outgoing_window: 0,
+            handle: Some(handle),
+            delivery_count: Some(delivery_count),
+            link_credit: Some(credit),
+            available: Some(available),
             drain: false,
             echo: false,
-            properties: None,
-        };
-        self.post_frame(flow.into());
+            properties,
+        });
+        self.flush_flow();
+    }
+
+    /// Merge flow with pending flow of the same link.
+    ///
+    /// Pending flows are sent at the end of current task poll, before
+    /// any other frame of the session or immediately if flow has
+    /// `drain` or `echo` flag. Session fields are filled in on flush.
+    fn queue_flow(&mut self, mut flow: Flow) {
+        let urgent = flow.drain || flow.echo;
+
+        if let Some(pending) = self
+            .pending_flows
+            .iter_mut()
+            .find(|f| f.handle == flow.handle)
+        {
+            if flow.properties.is_none() {
+                flow.properties = pending.properties.take();
+            }
+            *pending = flow;
+        } else {
+            self.pending_flows.push(flow);
+        }
+
+        if urgent {
+            self.flush_flow();
+        } else if !self.flush_scheduled {
+            self.flush_scheduled = true;
+            let sink = self.sink.clone();
+            let id = self.id;
+            ntex::rt::spawn(async move {
+                if let Some(session) = sink.get_session(id) {
+                    session.get_mut().flush_flow();
+                }
+            });
+        }
+    }
+
+    pub(crate) fn flush_flow(&mut self) {
+        self.flush_scheduled = false;
+        if self.error.is_some() {
+            self.pending_flows.clear();
+            return;
+        }
+        for mut flow in std::mem::take(&mut self.pending_flows) {
+            flow.next_incoming_id = if self.local {
+                Some(self.next_incoming_id)
+            } else {
+                None
+            };
+            flow.incoming_window = self.incoming_window;
+            flow.next_outgoing_id = self.next_outgoing_id;
+            flow.outgoing_window = std::cmp::min(self.outgoing_window, self.remote_incoming_window);
+            self.sink
+                .post_frame(AmqpFrame::new(self.remote_channel_id, flow.into()));
+        }
     }
 
     pub(crate) fn post_frame(&mut self, frame: Frame) {
+        // session is ended or connection is gone
+        if self.error.is_some() {
+            log::trace!("Session is terminated, drop frame: {:?}", frame);
+            return;
+        }
+        self.flush_flow();
         self.sink
             .post_frame(AmqpFrame::new(self.remote_channel_id, frame));
     }
@@ -904,9 +1788,17 @@ impl SessionInner {
         mut frame: Attach,
     ) -> oneshot::Receiver<Result<SenderLink, AmqpProtocolError>> {
         let (tx, rx) = oneshot::channel();
+        if let Some(ref err) = self.error {
+            let _ = tx.send(Err(err.clone()));
+            return rx;
+        }
 
         let entry = self.links.vacant_entry();
         let token = entry.key();
+        if token as u64 > self.handle_max as u64 {
+            let _ = tx.send(Err(AmqpProtocolError::HandleMaxExceeded(self.handle_max)));
+            return rx;
+        }
         entry.insert(Either::Left(SenderLinkState::Opening(Some(tx))));
 
         frame.handle = token as Handle;
@@ -916,6 +1808,92 @@ impl SessionInner {
         rx
     }
 
+    /// Re-attach suspended sender link, unsettled deliveries
+    /// are reported in `Attach` frame
+    pub(crate) fn resume_sender_link(
+        &mut self,
+        link: Cell<SenderLinkInner>,
+        mut frame: Attach,
+    ) -> oneshot::Receiver<Result<ReattachSummary, AmqpProtocolError>> {
+        let (tx, rx) = oneshot::channel();
+        if let Some(ref err) = self.error {
+            let _ = tx.send(Err(err.clone()));
+            return rx;
+        }
+
+        let entry = self.links.vacant_entry();
+        let token = entry.key();
+        if token as u64 > self.handle_max as u64 {
+            let _ = tx.send(Err(AmqpProtocolError::HandleMaxExceeded(self.handle_max)));
+            return rx;
+        }
+
+        // deliveries could be settled while link is suspended,
+        // adopted deliveries are not known to this session by id
+        let inner = link.get_mut();
+        let name = inner.name().clone();
+        let deliveries = &self.unsettled_deliveries;
+        let adopted = &self.adopted_deliveries;
+        let is_adopted = |tag: &Bytes| adopted.contains_key(&(name.clone(), tag.clone()));
+        inner
+            .unsettled
+            .retain(|(id, tag, _)| is_adopted(tag) || deliveries.contains_key(id));
+        inner.id = token;
+
+        let mut unsettled = Map::default();
+        for (id, tag, payload) in inner.unsettled.drain(..) {
+            unsettled.insert(Variant::Binary(tag.clone()), Variant::Null);
+            if !is_adopted(&tag) {
+                self.unsettled_tags
+                    .insert(id, (token as Handle, tag, payload));
+            }
+        }
+
+        frame.handle = token as Handle;
+        frame.initial_delivery_count = Some(inner.delivery_count());
+        frame.unsettled = if unsettled.is_empty() {
+            None
+        } else {
+            Some(unsettled)
+        };
+        entry.insert(Either::Left(SenderLinkState::Resuming(
+            SenderLink::new(link),
+            Some(tx),
+        )));
+
+        self.links_by_name.insert(frame.name.clone(), token);
+        self.post_frame(Frame::Attach(frame));
+        rx
+    }
+
+    /// Re-attach suspended receiver link
+    pub(crate) fn resume_receiver_link(
+        &mut self,
+        link: Cell<ReceiverLinkInner>,
+    ) -> oneshot::Receiver<Result<ReceiverLink, AmqpProtocolError>> {
+        let (tx, rx) = oneshot::channel();
+        if let Some(ref err) = self.error {
+            let _ = tx.send(Err(err.clone()));
+            return rx;
+        }
+
+        let entry = self.links.vacant_entry();
+        let token = entry.key();
+        if token as u64 > self.handle_max as u64 {
+            let _ = tx.send(Err(AmqpProtocolError::HandleMaxExceeded(self.handle_max)));
+            return rx;
+        }
+
+        let frame = link.get_mut().resume_frame(token as Handle);
+        entry.insert(Either::Right(ReceiverLinkState::OpeningLocal(Some((
+            link, tx,
+        )))));
+
+        self.links_by_name.insert(frame.name.clone(), token);
+        self.post_frame(Frame::Attach(frame));
+        rx
+    }
+
     #[allow(clippy::too_many_arguments)]
     pub(crate) fn send_transfer(
         &mut self,
@@ -923,6 +1901,7 @@ impl SessionInner {
         idx: u32,
         body: Option<TransferBody>,
         state: TransferState,
+        delivery_state: Option<DeliveryState>,
         tag: Option<Bytes>,
         settled: Option<bool>,
         message_format: Option<MessageFormat>,
@@ -937,13 +1916,21 @@ impl SessionInner {
                 idx,
                 body,
                 state,
+                delivery_state,
                 tag,
                 settled,
                 message_format,
             });
         } else {
-            let frame =
-                self.prepare_transfer(link_handle, body, state, tag, settled, message_format);
+            let frame = self.prepare_transfer(
+                link_handle,
+                body,
+                state,
+                delivery_state,
+                tag,
+                settled,
+                message_format,
+            );
             log::trace!(
                 "Sending transfer over {} window: {}",
                 link_handle,
@@ -959,14 +1946,41 @@ impl SessionInner {
         link_handle: Handle,
         body: Option<TransferBody>,
         tr_state: TransferState,
+        delivery_state: Option<DeliveryState>,
         delivery_tag: Option<Bytes>,
         settled: Option<bool>,
         message_format: Option<MessageFormat>,
     ) -> Frame {
         self.remote_incoming_window -= 1;
+        self.stats.transfers_out += 1;
+        self.stats.bytes_out += body.as_ref().map(|b| b.len()).unwrap_or(0) as u64;
 
         let settled2 = settled.clone().unwrap_or(false);
-        let state = if settled2 {
+        // encoded payload of unsettled single frame delivery, delivery
+        // could be resent. message is encoded once and sent as is
+        let (body, resend) = match body {
+            Some(body)
+                if !settled2
+                    && matches!(
+                        tr_state,
+                        TransferState::Only(_) | TransferState::Resume(Some(_))
+                    ) =>
+            {
+                let payload = match body {
+                    TransferBody::Data(data) => data,
+                    TransferBody::Message(msg) => {
+                        let mut buf = BytesMut::with_capacity(msg.encoded_size());
+                        msg.encode(&mut buf);
+                        buf.freeze()
+                    }
+                };
+                (Some(TransferBody::Data(payload.clone())), Some(payload))
+            }
+            body => (body, None),
+        };
+        let state = if delivery_state.is_some() {
+            delivery_state
+        } else if settled2 {
             Some(DeliveryState::Accepted(Accepted {}))
         } else {
             None
@@ -994,18 +2008,39 @@ impl SessionInner {
                 self.next_outgoing_id += 1;
 
                 transfer.delivery_id = Some(delivery_id);
-                transfer.delivery_tag = if let Some(tag) = delivery_tag {
-                    Some(tag)
-                } else {
-                    let mut buf = BytesMut::new();
-                    buf.put_u32(delivery_id);
-                    Some(buf.freeze())
+                let tag = match (delivery_tag, self.tag_generator) {
+                    (Some(tag), _) => tag,
+                    (None, TagGenerator::Sequential) => {
+                        let mut buf = BytesMut::new();
+                        buf.put_u32(delivery_id);
+                        buf.freeze()
+                    }
+                    (None, TagGenerator::Uuid) => Bytes::copy_from_slice(Uuid::new_v4().as_bytes()),
                 };
+                if !settled2 {
+                    self.unsettled_tags
+                        .insert(delivery_id, (link_handle, tag.clone(), resend));
+                }
+                transfer.delivery_tag = Some(tag);
 
                 transfer.more = more;
                 transfer.batchable = more;
                 self.unsettled_deliveries.insert(delivery_id, promise);
             }
+            TransferState::Resume(promise) => {
+                let delivery_id = self.next_outgoing_id;
+                self.next_outgoing_id += 1;
+
+                let tag = delivery_tag.unwrap_or_default();
+                if let Some(promise) = promise {
+                    self.unsettled_tags
+                        .insert(delivery_id, (link_handle, tag.clone(), resend));
+                    self.unsettled_deliveries.insert(delivery_id, promise);
+                }
+                transfer.delivery_id = Some(delivery_id);
+                transfer.delivery_tag = Some(tag);
+                transfer.resume = true;
+            }
             TransferState::Continue => {
                 transfer.more = true;
                 transfer.batchable = true;
@@ -1018,3 +2053,79 @@ impl SessionInner {
         Frame::Transfer(transfer)
     }
 }
+
+/// Remove unsettled deliveries of detached sender link, tags are returned
+/// if link could be resumed
+fn take_unsettled(
+    tags: &mut HashMap<DeliveryNumber, (Handle, Bytes, Option<Bytes>)>,
+    idx: usize,
+    closed: bool,
+) -> Vec<(DeliveryNumber, Bytes, Option<Bytes>)> {
+    let mut unsettled = Vec::new();
+    tags.retain(|id, (hnd, tag, body)| {
+        if *hnd != idx as Handle {
+            true
+        } else {
+            if !closed {
+                unsettled.push((*id, tag.clone(), body.take()));
+            }
+            false
+        }
+    });
+    unsettled
+}
+
+/// Delivery state of remote unsettled map entry, `None` for null or unknown state
+fn remote_delivery_state(value: &Variant) -> Option<DeliveryState> {
+    if let Variant::Null = value {
+        return None;
+    }
+    let mut buf = BytesMut::with_capacity(value.encoded_size());
+    value.encode(&mut buf);
+    DeliveryState::decode(&buf).ok().map(|(_, state)| state)
+}
+
+pub struct SessionBuilder {
+    frame: Begin,
+    connection: Connection,
+}
+
+impl SessionBuilder {
+    pub(crate) fn new(connection: Connection) -> Self {
+        let frame = Begin {
+            remote_channel: None,
+            next_outgoing_id: 1,
+            incoming_window: std::u32::MAX,
+            outgoing_window: std::u32::MAX,
+            handle_max: std::u32::MAX,
+            offered_capabilities: None,
+            desired_capabilities: None,
+            properties: None,
+        };
+
+        SessionBuilder { frame, connection }
+    }
+
+    /// Set session incoming window
+    pub fn incoming_window(mut self, window: u32) -> Self {
+        self.frame.incoming_window = window;
+        self
+    }
+
+    /// Set session outgoing window
+    pub fn outgoing_window(mut self, window: u32) -> Self {
+        self.frame.outgoing_window = window;
+        self
+    }
+
+    /// Set max link handle for the session
+    pub fn handle_max(mut self, handle_max: u32) -> Self {
+        self.frame.handle_max = handle_max;
+        self
+    }
+
+    /// Send Begin frame, session is ready after remote Begin is received
+    pub fn open(self) -> impl Future<Output = Result<Session, AmqpProtocolError>> {
+        self.connection.begin_session_with(self.frame)
+    }
+}
@@ -1,19 +1,58 @@
 use std::collections::VecDeque;
 use std::future::Future;
+use std::rc::Rc;
+use std::time::Duration;
 
 use ntex::channel::{condition, oneshot};
-use ntex::util::{ByteString, Bytes, BytesMut, Either, Ready};
+use ntex::rt::time::{sleep, Instant};
+use ntex::util::{select, ByteString, Bytes, BytesMut, Either, Ready};
 use ntex_amqp_codec::protocol::{
-    Attach, DeliveryNumber, DeliveryState, Disposition, Error, Flow, MessageFormat,
-    ReceiverSettleMode, Role, SenderSettleMode, SequenceNo, Target, TerminusDurability,
-    TerminusExpiryPolicy, TransferBody,
+    Accepted, Attach, DeliveryNumber, DeliveryState, Disposition, DistributionMode, Error, Fields,
+    Flow, Frame, LifetimePolicy, MessageFormat, MessageId, ReceiverSettleMode, Role, Seconds,
+    SenderSettleMode, SequenceNo, Source, Target, TerminusDurability, TerminusExpiryPolicy,
+    Transfer, TransferBody,
 };
-use ntex_amqp_codec::Encode;
+use ntex_amqp_codec::types::{Symbol, Variant};
+use ntex_amqp_codec::{AmqpCodecError, AmqpFrame, Decode, Encode, Message};
 
-use crate::cell::Cell;
+use crate::cell::{Cell, WeakCell};
+use crate::collision::CollisionDetector;
+use crate::connection::Connection;
+use crate::credit::SenderCredit;
 use crate::error::AmqpProtocolError;
+use crate::lifecycle::{LinkState, StateCell, StateChanges};
+use crate::node::{self, NodePropertyMismatch};
 use crate::session::{Session, SessionInner, TransferState};
-use crate::{Delivery, Handle};
+use crate::stats::SenderLinkStats;
+use crate::{Delivery, DeliveryPromise, Handle};
+
+/// Default max number of deliveries in send queue
+const DEFAULT_MAX_BUFFERED: usize = 1000;
+
+/// Max delivery tag length, #2.8.7
+const MAX_DELIVERY_TAG_LEN: usize = 32;
+
+/// Unsettled deliveries of reattached link, by delivery tag
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ReattachSummary {
+    /// Deliveries sent again with `resume` flag, transfers are queued
+    /// until peer grants link credit
+    pub resumed: Vec<Bytes>,
+    /// Deliveries settled with outcome reported by peer
+    pub settled: Vec<Bytes>,
+    /// Deliveries that could not be resumed, delivery fails with
+    /// `AmqpProtocolError::DeliveryAborted`
+    pub aborted: Vec<Bytes>,
+}
+
+/// Delivery of reattached link: tag, encoded payload, delivery state
+/// and promise. Delivery without promise is settled
+pub(crate) type ResumedDelivery = (
+    Bytes,
+    Option<Bytes>,
+    Option<DeliveryState>,
+    Option<DeliveryPromise>,
+);
 
 #[derive(Clone)]
 pub struct SenderLink {
@@ -34,12 +73,34 @@ pub(crate) struct SenderLinkInner {
     name: ByteString,
     session: Session,
     remote_handle: Handle,
-    delivery_count: SequenceNo,
-    link_credit: u32,
+    credit: SenderCredit,
     pending_transfers: VecDeque<PendingTransfer>,
+    max_buffered: usize,
+    rate_limit: Option<RateLimit>,
+    // changed on every rate limit update, invalidates scheduled refills
+    rate_generation: u64,
     error: Option<AmqpProtocolError>,
     closed: bool,
     on_close: condition::Condition,
+    state: StateCell<LinkState>,
+    pub(crate) remote_attach: Option<Attach>,
+    node_properties: Option<Fields>,
+    // local attach frame, required for resuming
+    attach: Option<Attach>,
+    // unsettled deliveries of suspended link, encoded payload of
+    // single frame deliveries
+    pub(crate) unsettled: Vec<(DeliveryNumber, Bytes, Option<Bytes>)>,
+    // unsettled deliveries are kept if session fails
+    recoverable: bool,
+    flow_properties: Option<Fields>,
+    remote_flow_properties: Option<Fields>,
+    stats: SenderLinkStats,
+    require_message_id: bool,
+    message_id_generator: Option<Rc<dyn Fn() -> MessageId>>,
+    forgotten_outcome: Option<Rc<dyn Fn(&Result<Disposition, AmqpProtocolError>)>>,
+    collisions: Option<CollisionDetector>,
+    // transfers are queued while link is moved to another session
+    migrating: bool,
 }
 
 struct PendingTransfer {
@@ -47,10 +108,147 @@ struct PendingTransfer {
     tag: Option<Bytes>,
     body: Option<TransferBody>,
     state: TransferState,
+    delivery_state: Option<DeliveryState>,
     settle: Option<bool>,
     message_format: Option<MessageFormat>,
 }
 
+impl PendingTransfer {
+    fn len(&self) -> usize {
+        self.body.as_ref().map(|b| b.len()).unwrap_or(0)
+    }
+}
+
+/// Transfer options for pre-encoded messages
+#[derive(Clone, Debug)]
+pub struct SendOptions {
+    tag: Option<Bytes>,
+    state: Option<DeliveryState>,
+    message_format: Option<MessageFormat>,
+    validate: bool,
+}
+
+impl Default for SendOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SendOptions {
+    pub fn new() -> Self {
+        SendOptions {
+            tag: None,
+            state: None,
+            message_format: None,
+            validate: cfg!(debug_assertions),
+        }
+    }
+
+    /// Set delivery tag
+    pub fn tag(mut self, tag: Bytes) -> Self {
+        self.tag = Some(tag);
+        self
+    }
+
+    /// Set delivery state of the first transfer
+    pub fn state(mut self, state: DeliveryState) -> Self {
+        self.state = Some(state);
+        self
+    }
+
+    /// Set transfer message format
+    pub fn message_format(mut self, format: MessageFormat) -> Self {
+        self.message_format = Some(format);
+        self
+    }
+
+    /// Decode message before sending, malformed message fails delivery.
+    ///
+    /// By default validation is enabled in debug builds only
+    pub fn validate(mut self, validate: bool) -> Self {
+        self.validate = validate;
+        self
+    }
+}
+
+/// Token bucket for outgoing link bandwidth
+///
+/// Link keeps at most one refill scheduled on connection timer. Each refill
+/// carries generation of limits it was scheduled for, refills scheduled
+/// before limits got changed or removed are ignored.
+struct RateLimit {
+    rate: u64,
+    burst: u64,
+    tokens: i64,
+    updated: Instant,
+    generation: u64,
+    refill_scheduled: bool,
+    link: WeakCell<SenderLinkInner>,
+}
+
+impl RateLimit {
+    fn new(rate: u64, burst: u64, generation: u64, link: WeakCell<SenderLinkInner>) -> Self {
+        let burst = std::cmp::max(burst, 1);
+        RateLimit {
+            link,
+            burst,
+            generation,
+            rate: std::cmp::max(rate, 1),
+            tokens: burst as i64,
+            updated: Instant::now(),
+            refill_scheduled: false,
+        }
+    }
+
+    /// Change limits, accumulated tokens and debt are preserved
+    fn reconfigure(&mut self, rate: u64, burst: u64, generation: u64) {
+        self.refill();
+        self.updated = Instant::now();
+        self.rate = std::cmp::max(rate, 1);
+        self.burst = std::cmp::max(burst, 1);
+        self.tokens = std::cmp::min(self.tokens, self.burst as i64);
+        self.generation = generation;
+        self.refill_scheduled = false;
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.updated).as_micros() as u64;
+        let tokens = elapsed.saturating_mul(self.rate) / 1_000_000;
+        if tokens > 0 {
+            self.tokens =
+                std::cmp::min(self.burst as i64, self.tokens.saturating_add(tokens as i64));
+            self.updated = now;
+        }
+    }
+
+    /// Take tokens for transfer of `size` bytes.
+    ///
+    /// Transfer is allowed while bucket is not empty, it could take bucket
+    /// into debt so transfers larger than burst size still make progress.
+    fn acquire(&mut self, size: usize, sink: &Connection) -> bool {
+        self.refill();
+        if self.tokens > 0 {
+            self.tokens = self.tokens.saturating_sub(size as i64);
+            true
+        } else {
+            self.schedule_refill(sink);
+            false
+        }
+    }
+
+    fn schedule_refill(&mut self, sink: &Connection) {
+        if self.refill_scheduled {
+            return;
+        }
+        self.refill_scheduled = true;
+
+        let deficit = (1 - self.tokens) as u64;
+        let delay = Duration::from_micros(deficit.saturating_mul(1_000_000) / self.rate + 1);
+        sink.schedule_refill(Instant::now() + delay, self.generation, self.link.clone());
+    }
+}
+
 impl SenderLink {
     pub(crate) fn new(inner: Cell<SenderLinkInner>) -> SenderLink {
         SenderLink { inner }
@@ -64,6 +262,11 @@ impl SenderLink {
         &self.inner.name
     }
 
+    /// Link id for logging, `connid.ch3.h5` where `5` is local handle
+    pub fn log_id(&self) -> String {
+        format!("{}.h{}", self.inner.session.log_id(), self.inner.id)
+    }
+
     pub fn remote_handle(&self) -> Handle {
         self.inner.remote_handle
     }
@@ -76,13 +279,160 @@ impl SenderLink {
         &mut self.inner.get_mut().session
     }
 
+    /// Number of deliveries waiting for link credit.
+    ///
+    /// This value is reported to the peer as `available`.
+    pub fn available(&self) -> u32 {
+        self.inner.get_ref().available()
+    }
+
+    /// Current link credit
+    pub fn credit(&self) -> u32 {
+        self.inner.get_ref().credit.link_credit()
+    }
+
+    /// Current delivery count
+    pub fn delivery_count(&self) -> SequenceNo {
+        self.inner.get_ref().credit.delivery_count()
+    }
+
+    /// Distribution mode requested by peer's source terminus.
+    ///
+    /// Messages sent over `DistributionMode::Copy` link must stay
+    /// available for other links.
+    pub fn distribution_mode(&self) -> Option<&DistributionMode> {
+        self.inner
+            .get_ref()
+            .remote_attach
+            .as_ref()
+            .and_then(|attach| attach.source())
+            .and_then(|source| source.distribution_mode())
+    }
+
+    /// Number of transfers in send queue
+    pub fn pending_len(&self) -> usize {
+        self.inner.get_ref().pending_transfers.len()
+    }
+
+    /// Snapshot of link counters
+    pub fn stats(&self) -> SenderLinkStats {
+        let inner = self.inner.get_ref();
+        SenderLinkStats {
+            link_credit: inner.credit.link_credit(),
+            delivery_count: inner.credit.delivery_count(),
+            pending_transfers: inner.pending_transfers.len(),
+            ..inner.stats
+        }
+    }
+
+    /// Properties of the last link `Flow` received from peer
+    pub fn remote_flow_properties(&self) -> Option<&Fields> {
+        self.inner.get_ref().remote_flow_properties.as_ref()
+    }
+
+    /// Set properties for `Flow` frames sent by this link
+    pub fn set_flow_properties(&self, properties: Option<Fields>) {
+        self.inner.get_mut().flow_properties = properties;
+    }
+
     pub fn send<T>(&self, body: T) -> impl Future<Output = Result<Disposition, AmqpProtocolError>>
     where
         T: Into<TransferBody>,
     {
-        self.inner.get_mut().send(body, None)
+        self.inner.get_mut().send(body, None, None)
+    }
+
+    /// Send message without waiting for disposition.
+    ///
+    /// Delivery is not pre-settled, outcome is awaited in background task.
+    /// Outcomes other than `Accepted` and send errors are logged at warn level
+    /// and passed to hook set with `set_forgotten_outcome_hook()`.
+    pub fn send_and_forget<T>(&self, body: T)
+    where
+        T: Into<TransferBody>,
+    {
+        let log_id = self.log_id();
+        let inner = self.inner.get_mut();
+        let delivery = inner.send(body, None, None);
+        let hook = inner.forgotten_outcome.clone();
+
+        ntex::rt::spawn(async move {
+            let res = delivery.await;
+            match res {
+                Ok(ref disp) => match disp.state {
+                    None | Some(DeliveryState::Accepted(_)) => return,
+                    ref state => {
+                        log::warn!(
+                            "{}: Forgotten delivery is not accepted: {:?}",
+                            log_id,
+                            state
+                        )
+                    }
+                },
+                Err(ref err) => log::warn!("{}: Forgotten delivery failed: {}", log_id, err),
+            }
+            if let Some(hook) = hook {
+                (*hook)(&res);
+            }
+        });
+    }
+
+    /// Set hook called with failed outcomes of `send_and_forget()` deliveries
+    pub fn set_forgotten_outcome_hook<F>(&self, hook: F)
+    where
+        F: Fn(&Result<Disposition, AmqpProtocolError>) + 'static,
+    {
+        self.inner.get_mut().forgotten_outcome = Some(Rc::new(hook));
+    }
+
+    /// Send message and wait for disposition at most `timeout`.
+    ///
+    /// Resolves with `AmqpProtocolError::Timeout` if peer does not
+    /// settle delivery in time, delivery is not tracked after that.
+    pub fn send_with_timeout<T>(
+        &self,
+        body: T,
+        timeout: Duration,
+    ) -> impl Future<Output = Result<Disposition, AmqpProtocolError>>
+    where
+        T: Into<TransferBody>,
+    {
+        let delivery = self.inner.get_mut().send(body, None, None);
+        let inner = self.inner.clone();
+
+        async move {
+            match select(delivery, sleep(timeout)).await {
+                Either::Left(res) => res,
+                Either::Right(_) => {
+                    log::trace!("Delivery is not settled in {:?}", timeout);
+                    let inner = inner.get_mut();
+                    inner.remove_canceled();
+                    inner.session.inner.get_mut().remove_canceled_deliveries();
+                    Err(AmqpProtocolError::Timeout)
+                }
+            }
+        }
     }
 
+    /// Send pre-encoded message.
+    ///
+    /// Message is sent as is, sections are not decoded or re-encoded
+    /// unless link has to set `message-id`, see `set_auto_message_id()`.
+    /// Message size is checked against peer's max message size.
+    /// `bare_message` is usually produced once with `Message::encode_bare()`
+    /// and patched with `Message::splice()`.
+    pub fn send_preencoded(
+        &self,
+        bare_message: Bytes,
+        opts: SendOptions,
+    ) -> impl Future<Output = Result<Disposition, AmqpProtocolError>> {
+        self.inner.get_mut().send_preencoded(bare_message, opts)
+    }
+
+    /// Send message with delivery tag.
+    ///
+    /// Tag must be unique among unsettled deliveries of the link,
+    /// tags longer than 32 bytes are rejected.
     pub fn send_with_tag<T>(
         &self,
         body: T,
@@ -91,7 +441,34 @@ impl SenderLink {
     where
         T: Into<TransferBody>,
     {
-        self.inner.get_mut().send(body, Some(tag))
+        self.inner.get_mut().send(body, Some(tag), None)
+    }
+
+    /// Send messages as single batched delivery, see `Message::batch()`.
+    ///
+    /// Peer must support batch message format, i.e. Azure Service Bus.
+    pub fn send_batched(
+        &self,
+        messages: Vec<Message>,
+    ) -> impl Future<Output = Result<Disposition, AmqpProtocolError>> {
+        self.inner
+            .get_mut()
+            .send(Message::batch(messages), None, None)
+    }
+
+    /// Send message with explicit delivery state.
+    ///
+    /// State is carried by the first transfer of the delivery, it is used
+    /// for transactional work (`TransactionalState`) and for resumed deliveries.
+    pub fn send_with_state<T>(
+        &self,
+        body: T,
+        state: DeliveryState,
+    ) -> impl Future<Output = Result<Disposition, AmqpProtocolError>>
+    where
+        T: Into<TransferBody>,
+    {
+        self.inner.get_mut().send(body, None, Some(state))
     }
 
     pub fn settle_message(&self, id: DeliveryNumber, state: DeliveryState) {
@@ -115,6 +492,282 @@ impl SenderLink {
     pub fn on_close(&self) -> condition::Waiter {
         self.inner.get_ref().on_close.wait()
     }
+
+    /// Current link state
+    pub fn state(&self) -> LinkState {
+        self.inner.get_ref().state.get()
+    }
+
+    /// Subscribe to link state changes
+    pub fn state_changes(&self) -> StateChanges<LinkState> {
+        self.inner.get_ref().state.subscribe()
+    }
+
+    /// Suspend link, `Detach` frame with `closed=false` is sent to peer.
+    ///
+    /// Unsettled deliveries are kept and resolved after link is resumed with
+    /// `resume_link()`, sending fails while link is in `Resumable` state.
+    pub fn suspend_link(&self) -> impl Future<Output = Result<(), AmqpProtocolError>> {
+        self.inner.get_mut().suspend_link()
+    }
+
+    /// Re-attach suspended link.
+    ///
+    /// Link is attached with the same name and terminus, unsettled
+    /// deliveries are reported to peer. Only locally opened links
+    /// could be resumed, see `reattach()`.
+    pub fn resume_link(&self) -> impl Future<Output = Result<(), AmqpProtocolError>> {
+        let fut = self.reattach();
+        async move { fut.await.map(|_| ()) }
+    }
+
+    /// Re-attach suspended link and recover unsettled deliveries.
+    ///
+    /// Unsettled deliveries are matched with unsettled map of peer's `Attach`:
+    /// deliveries with peer's outcome are settled, deliveries known to peer
+    /// are sent again with `resume` flag, as link credit permits, and the rest
+    /// is aborted. Only single frame deliveries could be sent again.
+    pub fn reattach(&self) -> impl Future<Output = Result<ReattachSummary, AmqpProtocolError>> {
+        let inner = self.inner.get_mut();
+        let rx = match (inner.state.get(), inner.attach.clone()) {
+            (LinkState::Resumable, Some(frame)) => Some(
+                inner
+                    .session
+                    .inner
+                    .get_mut()
+                    .resume_sender_link(self.inner.clone(), frame),
+            ),
+            _ => None,
+        };
+
+        async move {
+            match rx {
+                Some(rx) => match rx.await {
+                    Ok(res) => res,
+                    Err(_) => Err(AmqpProtocolError::Disconnected),
+                },
+                None => Err(AmqpProtocolError::LinkDetached(None)),
+            }
+        }
+    }
+
+    /// Move link to another session of the same connection.
+    ///
+    /// Link is suspended, attached with the same name and terminus to
+    /// `session` and unsettled deliveries are recovered as with `reattach()`.
+    /// Transfers sent during migration are queued and sent after link is
+    /// attached. Fails with `AmqpProtocolError::MigrationRefused` if peer
+    /// does not keep link terminus, in that case link stays detached.
+    /// Only locally opened links could be migrated. Recoverable link of
+    /// failed session could be moved to session of another connection,
+    /// see `set_recoverable()`.
+    pub fn migrate_to(
+        &self,
+        session: &Session,
+    ) -> impl Future<Output = Result<ReattachSummary, AmqpProtocolError>> {
+        let link = self.clone();
+        let target = session.clone();
+
+        async move {
+            let source = link.inner.get_ref().session.clone();
+            if !source
+                .inner
+                .get_ref()
+                .check_migration(target.inner.get_ref())?
+            {
+                return Ok(ReattachSummary::default());
+            }
+            if link.inner.get_ref().attach.is_none() {
+                return Err(AmqpProtocolError::LinkDetached(None));
+            }
+
+            link.inner.get_mut().migrating = true;
+            let res = link.migrate(&source, &target).await;
+
+            let inner = link.inner.get_mut();
+            inner.migrating = false;
+            match res {
+                Ok(summary) => {
+                    trace!(
+                        "Sender link {:?} is migrated to {}",
+                        inner.name,
+                        target.log_id()
+                    );
+                    inner.stats.migrations += 1;
+                    target
+                        .inner
+                        .get_mut()
+                        .link_migrated(inner.id(), &inner.name, Role::Sender);
+                    inner.release_pending();
+                    Ok(summary)
+                }
+                Err(err) => {
+                    inner.fail_pending(&err);
+                    Err(err)
+                }
+            }
+        }
+    }
+
+    async fn migrate(
+        &self,
+        source: &Session,
+        target: &Session,
+    ) -> Result<ReattachSummary, AmqpProtocolError> {
+        if let LinkState::Attached | LinkState::Suspended = self.state() {
+            self.suspend_link().await?;
+        }
+
+        let inner = self.inner.get_mut();
+        target.inner.get_mut().adopt_deliveries(
+            source.inner.get_mut(),
+            &inner.name,
+            &mut inner.unsettled,
+        );
+        inner.session = target.clone();
+
+        self.reattach().await.map_err(|err| match err {
+            AmqpProtocolError::LinkDetached(_) => AmqpProtocolError::MigrationRefused,
+            err => err,
+        })
+    }
+
+    /// Set max number of deliveries buffered while link has no credit.
+    ///
+    /// Delivery split into several transfer frames counts once.
+    /// Sending to a link with full buffer fails with
+    /// `AmqpProtocolError::SendQueueFull`. Default is 1000 deliveries
+    pub fn set_max_buffered(&self, n: usize) {
+        self.inner.get_mut().max_buffered = n;
+    }
+
+    /// Limit outgoing bandwidth of the link
+    ///
+    /// `bytes_per_sec` is the sustained rate and `burst` is the number of
+    /// bytes that could be sent at once. Transfers over the limit stay queued
+    /// on the link and do not consume link credit until released.
+    /// Could be called at any time to adjust limits, tokens accumulated
+    /// by the link are kept.
+    pub fn set_rate_limit(&self, bytes_per_sec: u64, burst: u64) {
+        let link = self.inner.downgrade();
+        let inner = self.inner.get_mut();
+        inner.rate_generation = inner.rate_generation.wrapping_add(1);
+        if let Some(ref mut rate) = inner.rate_limit {
+            rate.reconfigure(bytes_per_sec, burst, inner.rate_generation);
+        } else {
+            inner.rate_limit = Some(RateLimit::new(
+                bytes_per_sec,
+                burst,
+                inner.rate_generation,
+                link,
+            ));
+        }
+        inner.release_pending();
+    }
+
+    /// Remove bandwidth limit, queued transfers are released immediately
+    pub fn remove_rate_limit(&self) {
+        let inner = self.inner.get_mut();
+        inner.rate_generation = inner.rate_generation.wrapping_add(1);
+        inner.rate_limit = None;
+        inner.release_pending();
+    }
+
+    /// Reject messages without `message-id` property.
+    ///
+    /// Sending such message fails with `AmqpProtocolError::MessageIdRequired`,
+    /// unless id generator is set with `set_auto_message_id()`.
+    pub fn set_require_message_id(&self, require: bool) {
+        self.inner.get_mut().require_message_id = require;
+    }
+
+    /// Set `message-id` property of messages that do not have one.
+    ///
+    /// Pre-encoded messages without id are decoded and encoded again.
+    pub fn set_auto_message_id<F>(&self, generator: F)
+    where
+        F: Fn() -> MessageId + 'static,
+    {
+        self.inner.get_mut().message_id_generator = Some(Rc::new(generator));
+    }
+
+    /// Remove `message-id` generator
+    pub fn remove_auto_message_id(&self) {
+        self.inner.get_mut().message_id_generator = None;
+    }
+
+    /// Keep unsettled deliveries if connection or session fails.
+    ///
+    /// Link goes to `Resumable` state instead of failing its unsettled
+    /// deliveries, link is recovered by `migrate_to()` session of new
+    /// connection. Deliveries wait for outcome until link is recovered or
+    /// dropped. Only locally opened links could be recovered.
+    pub fn set_recoverable(&self, recoverable: bool) {
+        self.inner.get_mut().recoverable = recoverable;
+    }
+
+    /// Detect reuse of `message-id` with different payload.
+    ///
+    /// Ids set by `set_auto_message_id()` generator are not checked.
+    pub fn set_collision_detector(&self, detector: CollisionDetector) {
+        self.inner.get_mut().collisions = Some(detector);
+    }
+
+    /// Remove collision detector
+    pub fn remove_collision_detector(&self) {
+        self.inner.get_mut().collisions = None;
+    }
+
+    /// Peer's `Attach` frame
+    pub fn remote_frame(&self) -> Option<&Attach> {
+        self.inner.get_ref().remote_attach.as_ref()
+    }
+
+    /// Target terminus of the link.
+    ///
+    /// Target is defined by receiving peer, terminus of peer's `Attach`
+    /// is returned. Requested target is returned until peer attaches.
+    pub fn target(&self) -> Option<&Target> {
+        let inner = self.inner.get_ref();
+        inner
+            .remote_attach
+            .as_ref()
+            .or_else(|| inner.attach.as_ref())
+            .and_then(|attach| attach.target())
+    }
+
+    /// Properties of dynamically created target node, as reported by peer
+    pub fn dynamic_node_properties(&self) -> Option<&Fields> {
+        self.remote_frame()
+            .and_then(|attach| attach.target())
+            .and_then(|target| target.dynamic_node_properties())
+    }
+
+    /// Check that peer created dynamic target node with requested properties
+    pub fn verify_dynamic_node(&self) -> Result<(), Vec<NodePropertyMismatch>> {
+        node::verify(
+            self.inner.get_ref().node_properties.as_ref(),
+            self.dynamic_node_properties(),
+        )
+    }
+
+    /// Close link and check that peer removed target node.
+    ///
+    /// Resolves to `false` if node is still reachable after close,
+    /// see `Session::node_exists()`.
+    pub async fn close_and_verify(&self) -> Result<bool, AmqpProtocolError> {
+        let address = self
+            .remote_frame()
+            .and_then(|attach| attach.target())
+            .and_then(|target| target.address.clone());
+        self.close().await?;
+
+        if let Some(address) = address {
+            Ok(!self.session().node_exists(address).await?)
+        } else {
+            Ok(true)
+        }
+    }
 }
 
 impl SenderLinkInner {
@@ -128,15 +781,31 @@ impl SenderLinkInner {
         SenderLinkInner {
             id,
             name,
-            delivery_count,
+            credit: SenderCredit::new(delivery_count),
             idx: 0,
             session: Session::new(session),
             remote_handle: handle,
-            link_credit: 0,
             pending_transfers: VecDeque::new(),
+            max_buffered: DEFAULT_MAX_BUFFERED,
+            rate_limit: None,
+            rate_generation: 0,
             error: None,
             closed: false,
             on_close: condition::Condition::new(),
+            state: StateCell::new(LinkState::Attached, LinkState::is_terminal),
+            remote_attach: None,
+            node_properties: None,
+            attach: None,
+            unsettled: Vec::new(),
+            recoverable: false,
+            flow_properties: None,
+            remote_flow_properties: None,
+            stats: SenderLinkStats::default(),
+            require_message_id: false,
+            message_id_generator: None,
+            forgotten_outcome: None,
+            collisions: None,
+            migrating: false,
         }
     }
 
@@ -150,17 +819,33 @@ impl SenderLinkInner {
         let delivery_count = frame.initial_delivery_count.unwrap_or(0);
 
         SenderLinkInner {
-            delivery_count,
+            credit: SenderCredit::new(delivery_count),
             id: 0,
             idx: 0,
             name: name.unwrap_or_else(ByteString::default),
             session: Session::new(session),
             remote_handle: frame.handle(),
-            link_credit: 0,
             pending_transfers: VecDeque::new(),
+            max_buffered: DEFAULT_MAX_BUFFERED,
+            rate_limit: None,
+            rate_generation: 0,
             error: None,
             closed: false,
             on_close: condition::Condition::new(),
+            state: StateCell::new(LinkState::Attaching, LinkState::is_terminal),
+            remote_attach: Some(frame.clone()),
+            node_properties: None,
+            attach: None,
+            unsettled: Vec::new(),
+            recoverable: false,
+            flow_properties: None,
+            remote_flow_properties: None,
+            stats: SenderLinkStats::default(),
+            require_message_id: false,
+            message_id_generator: None,
+            forgotten_outcome: None,
+            collisions: None,
+            migrating: false,
         }
     }
 
@@ -176,20 +861,90 @@ impl SenderLinkInner {
         &self.name
     }
 
-    pub(crate) fn detached(&mut self, err: AmqpProtocolError) {
+    pub(crate) fn delivery_count(&self) -> SequenceNo {
+        self.credit.delivery_count()
+    }
+
+    /// Change link state, all transitions go through this method
+    pub(crate) fn set_state(&mut self, st: LinkState) {
+        trace!("Sender link {:?} state: {:?}", self.name, st);
+        self.state.set(st);
+    }
+
+    pub(crate) fn detached(&mut self, err: AmqpProtocolError, closed: bool) {
         trace!("Detaching sender link {:?} with error {:?}", self.name, err);
+        self.set_state(LinkState::detached(err.clone(), closed));
+        self.credit.reset();
 
-        // drop pending transfers
-        for tr in self.pending_transfers.drain(..) {
-            if let TransferState::First(tx) | TransferState::Only(tx) = tr.state {
-                let _ = tx.send(Err(err.clone()));
-            }
+        // pending transfers are kept for migrated link
+        if !self.migrating {
+            self.fail_pending(&err);
         }
 
         self.error = Some(err);
         self.on_close.notify();
     }
 
+    /// Drop pending transfers
+    fn fail_pending(&mut self, err: &AmqpProtocolError) {
+        for tr in self.pending_transfers.drain(..) {
+            if let Some(tx) = tr.state.into_promise() {
+                tx.settle(Err(err.clone()));
+            }
+        }
+    }
+
+    /// Unsettled deliveries are kept if session fails
+    pub(crate) fn is_recoverable(&self) -> bool {
+        self.recoverable && self.attach.is_some() && !self.closed
+    }
+
+    /// Queue transfers of resumed deliveries ahead of pending transfers
+    pub(crate) fn resume_deliveries(&mut self, deliveries: Vec<ResumedDelivery>) {
+        for (tag, payload, state, promise) in deliveries.into_iter().rev() {
+            self.pending_transfers.push_front(PendingTransfer {
+                idx: self.idx,
+                tag: Some(tag),
+                settle: Some(promise.is_none()),
+                body: payload.map(TransferBody::Data),
+                state: TransferState::Resume(promise),
+                delivery_state: state,
+                message_format: None,
+            });
+        }
+    }
+
+    /// Suspended link is attached again
+    pub(crate) fn resumed(&mut self, attach: &Attach) {
+        trace!("Sender link {:?} is resumed", self.name);
+        self.remote_handle = attach.handle();
+        self.remote_attach = Some(attach.clone());
+        self.credit.reset();
+        self.error = None;
+        self.set_state(LinkState::Attached);
+    }
+
+    pub(crate) fn suspend_link(&mut self) -> impl Future<Output = Result<(), AmqpProtocolError>> {
+        let (tx, rx) = oneshot::channel();
+        if let Some(ref err) = self.error {
+            let _ = tx.send(Err(err.clone()));
+        } else if self.closed {
+            let _ = tx.send(Err(AmqpProtocolError::LinkDetached(None)));
+        } else {
+            self.session
+                .inner
+                .get_mut()
+                .detach_sender_link(self.id, false, None, tx);
+        }
+
+        async move {
+            match rx.await {
+                Ok(res) => res,
+                Err(_) => Err(AmqpProtocolError::Disconnected),
+            }
+        }
+    }
+
     pub(crate) fn close(
         &mut self,
         error: Option<Error>,
@@ -224,63 +979,286 @@ impl SenderLinkInner {
                 "Apply sender link {:?} flow, credit: {:?} flow count: {:?}, delivery count: {:?}",
                 self.name,
                 credit,
-                flow.delivery_count.unwrap_or(0),
-                self.delivery_count
+                flow.delivery_count,
+                self.credit.delivery_count()
             );
 
-            let delta = flow
-                .delivery_count
-                .unwrap_or(0)
-                .saturating_add(credit)
-                .saturating_sub(self.delivery_count);
-            self.link_credit += delta;
+            self.stats.flows += 1;
 
-            let session = self.session.inner.get_mut();
+            // link credit is absolute, relative to receiver's delivery count
+            self.credit.apply(flow.delivery_count, credit);
+
+            // peer withdraws credit => link is suspended
+            match self.state.get() {
+                LinkState::Attached if credit == 0 => self.set_state(LinkState::Suspended),
+                LinkState::Suspended if self.credit.link_credit() > 0 => {
+                    self.set_state(LinkState::Attached)
+                }
+                _ => (),
+            }
 
             // credit became available => drain pending_transfers
-            while self.link_credit > 0 {
-                if let Some(transfer) = self.pending_transfers.pop_front() {
-                    self.link_credit -= 1;
-                    self.delivery_count = self.delivery_count.saturating_add(1);
-                    session.send_transfer(
-                        self.id as u32,
-                        transfer.idx,
-                        transfer.body,
-                        transfer.state,
-                        transfer.tag,
-                        transfer.settle,
-                        transfer.message_format,
-                    );
-                } else {
+            self.release_pending();
+        }
+
+        if flow.properties.is_some() {
+            self.remote_flow_properties = flow.properties.clone();
+        }
+
+        // #2.6.7 drain: no queued deliveries, credit is used up
+        let drained = flow.drain() && self.pending_transfers.is_empty();
+        if drained {
+            self.credit.drain();
+        }
+
+        if flow.echo() || drained {
+            let available = self.available();
+            self.session.inner.get_mut().snd_link_flow(
+                self.id as u32,
+                self.credit.delivery_count(),
+                self.credit.link_credit(),
+                available,
+                self.flow_properties.clone(),
+            );
+        }
+    }
+
+    /// Number of queued deliveries
+    pub(crate) fn available(&self) -> u32 {
+        self.pending_transfers
+            .iter()
+            .filter(|tr| tr.state.is_first())
+            .count() as u32
+    }
+
+    /// Scheduled token refill is due
+    pub(crate) fn refill(&mut self, generation: u64) {
+        if let Some(ref mut rate) = self.rate_limit {
+            // limits are changed or removed since refill is scheduled
+            if rate.generation == generation {
+                rate.refill_scheduled = false;
+                self.release_pending();
+            }
+        }
+    }
+
+    /// Send queued transfers while link credit and rate limit allow it
+    fn release_pending(&mut self) {
+        self.remove_canceled();
+
+        // link is detached from previous session of migration
+        if self.migrating && !matches!(self.state.get(), LinkState::Attached) {
+            return;
+        }
+
+        let session = self.session.inner.get_mut();
+
+        while let Some(transfer) = self.pending_transfers.front() {
+            // link credit is consumed by the first transfer of a delivery
+            let first = transfer.state.is_first();
+            if first && self.credit.link_credit() == 0 {
+                break;
+            }
+            if let Some(ref mut rate) = self.rate_limit {
+                if !rate.acquire(transfer.len(), session.connection()) {
                     break;
                 }
             }
+
+            if let Some(transfer) = self.pending_transfers.pop_front() {
+                if first {
+                    let consumed = self.credit.consume();
+                    debug_assert!(consumed, "delivery is sent without credit");
+                }
+                session.send_transfer(
+                    self.id as u32,
+                    transfer.idx,
+                    transfer.body,
+                    transfer.state,
+                    transfer.delivery_state,
+                    transfer.tag,
+                    transfer.settle,
+                    transfer.message_format,
+                );
+            }
+        }
+    }
+
+    /// Drop queued deliveries if delivery future is dropped
+    fn remove_canceled(&mut self) {
+        let mut canceled = false;
+        self.pending_transfers.retain(|tr| match tr.state {
+            TransferState::First(ref tx)
+            | TransferState::Only(ref tx)
+            | TransferState::Resume(Some(ref tx)) => {
+                canceled = tx.is_canceled();
+                !canceled
+            }
+            TransferState::Resume(None) => {
+                canceled = false;
+                true
+            }
+            TransferState::Continue | TransferState::Last => !canceled,
+        });
+    }
+
+    /// Check send queue limit, canceled deliveries do not count
+    fn is_queue_full(&mut self) -> bool {
+        if self.available() as usize >= self.max_buffered {
+            self.remove_canceled();
         }
+        self.available() as usize >= self.max_buffered
+    }
 
-        if flow.echo() {
-            // todo: send flow
+    /// Peer's max message size, `None` if there is no limit
+    fn max_message_size(&self) -> Option<u64> {
+        self.remote_attach
+            .as_ref()
+            .and_then(|attach| attach.max_message_size())
+            .filter(|size| *size > 0)
+    }
+
+    pub(crate) fn send<T: Into<TransferBody>>(
+        &mut self,
+        body: T,
+        tag: Option<Bytes>,
+        delivery_state: Option<DeliveryState>,
+    ) -> Delivery {
+        let body = body.into();
+        let message_format = body.message_format();
+        match self.check_message(body) {
+            Ok(body) => self.send_body(body, tag, delivery_state, message_format),
+            Err(err) => Delivery::Resolved(Err(err)),
         }
     }
 
-    pub(crate) fn send<T: Into<TransferBody>>(&mut self, body: T, tag: Option<Bytes>) -> Delivery {
-        if let Some(ref err) = self.error {
-            Delivery::Resolved(Err(err.clone()))
+    /// Apply message-id policy and collision detector
+    fn check_message(&mut self, body: TransferBody) -> Result<TransferBody, AmqpProtocolError> {
+        let (body, generated) = self.apply_message_id(body)?;
+
+        // ids set by generator are exempt
+        if let (Some(detector), false) = (self.collisions.as_mut(), generated) {
+            if let Some(id) = detector.check_body(&body) {
+                if detector.is_reject() {
+                    return Err(AmqpProtocolError::MessageIdCollision(id));
+                }
+            }
+        }
+        Ok(body)
+    }
+
+    /// Check `message-id` of message, missing id is set by generator.
+    ///
+    /// Returns `true` if id is set by generator.
+    fn apply_message_id(
+        &self,
+        body: TransferBody,
+    ) -> Result<(TransferBody, bool), AmqpProtocolError> {
+        if !self.require_message_id && self.message_id_generator.is_none() {
+            return Ok((body, false));
+        }
+
+        match body {
+            TransferBody::Message(mut msg) => {
+                if msg.message_id().is_none() {
+                    msg.properties_mut().message_id = Some(self.next_message_id()?);
+                    Ok((TransferBody::Message(msg), true))
+                } else {
+                    Ok((TransferBody::Message(msg), false))
+                }
+            }
+            TransferBody::Data(data) => match Message::decode(&data) {
+                Ok((rest, _)) if !rest.is_empty() => self.opaque_body(data),
+                Ok((_, msg)) if msg.message_id().is_some() => Ok((TransferBody::Data(data), false)),
+                Ok((_, mut msg)) => {
+                    msg.properties_mut().message_id = Some(self.next_message_id()?);
+                    Ok((TransferBody::Message(Box::new(msg)), true))
+                }
+                Err(_) => self.opaque_body(data),
+            },
+        }
+    }
+
+    /// Body is not an amqp message, its id could not be checked
+    fn opaque_body(&self, data: Bytes) -> Result<(TransferBody, bool), AmqpProtocolError> {
+        if self.require_message_id {
+            Err(AmqpProtocolError::MessageIdRequired)
         } else {
-            let body = body.into();
-            let message_format = body.message_format();
-            let (delivery_tx, delivery_rx) = oneshot::channel();
-
-            let max_frame_size = self.session.inner.get_ref().max_frame_size();
-            let max_frame_size = if max_frame_size > 2048 {
-                max_frame_size - 2048
-            } else if max_frame_size == 0 {
-                usize::MAX
-            } else {
-                max_frame_size
+            Ok((TransferBody::Data(data), false))
+        }
+    }
+
+    fn next_message_id(&self) -> Result<MessageId, AmqpProtocolError> {
+        self.message_id_generator
+            .as_ref()
+            .map(|generator| generator())
+            .ok_or(AmqpProtocolError::MessageIdRequired)
+    }
+
+    pub(crate) fn send_preencoded(&mut self, bare_message: Bytes, opts: SendOptions) -> Delivery {
+        if let Some(max) = self.max_message_size() {
+            if bare_message.len() as u64 > max {
+                log::trace!(
+                    "Message size {} exceeds max message size {} of link {:?}",
+                    bare_message.len(),
+                    max,
+                    self.name
+                );
+                return Delivery::Resolved(Err(AmqpProtocolError::MessageSizeExceeded(max)));
+            }
+        }
+        if opts.validate {
+            let err = match Message::decode(&bare_message) {
+                Ok((rest, _)) if rest.is_empty() => None,
+                Ok(_) => Some(AmqpCodecError::UnparsedBytesLeft),
+                Err(err) => Some(AmqpCodecError::ParseError(err)),
             };
+            if let Some(err) = err {
+                log::trace!("Pre-encoded message is malformed: {:?}", err);
+                return Delivery::Resolved(Err(AmqpProtocolError::Codec(err)));
+            }
+        }
+
+        match self.check_message(TransferBody::Data(bare_message)) {
+            Ok(body) => self.send_body(body, opts.tag, opts.state, opts.message_format),
+            Err(err) => Delivery::Resolved(Err(err)),
+        }
+    }
+
+    fn send_body(
+        &mut self,
+        body: TransferBody,
+        tag: Option<Bytes>,
+        delivery_state: Option<DeliveryState>,
+        message_format: Option<MessageFormat>,
+    ) -> Delivery {
+        if let (Some(err), false) = (self.error.as_ref(), self.migrating) {
+            Delivery::Resolved(Err(err.clone()))
+        } else if let Some(len) = tag
+            .as_ref()
+            .map(Bytes::len)
+            .filter(|len| *len > MAX_DELIVERY_TAG_LEN)
+        {
+            Delivery::Resolved(Err(AmqpProtocolError::DeliveryTagTooLong(len)))
+        } else if self.is_queue_full() {
+            log::trace!(
+                "Sender link {:?} send queue is full, queued deliveries: {}",
+                self.name,
+                self.available()
+            );
+            Delivery::Resolved(Err(AmqpProtocolError::SendQueueFull))
+        } else {
+            let (delivery_tx, delivery) = DeliveryPromise::new();
+
+            let max_payload = max_transfer_payload(
+                self.session.inner.get_ref().max_frame_size(),
+                tag.as_ref(),
+                delivery_state.as_ref(),
+                message_format,
+            );
 
             // body is larger than allowed frame size, send body as a set of transfers
-            if body.len() > max_frame_size {
+            if body.len() > max_payload {
                 let mut body = match body {
                     TransferBody::Data(data) => data,
                     TransferBody::Message(msg) => {
@@ -290,35 +1268,49 @@ impl SenderLinkInner {
                     }
                 };
 
-                let chunk = body.split_to(std::cmp::min(max_frame_size, body.len()));
+                let chunk = body.split_to(std::cmp::min(max_payload, body.len()));
                 self.send_inner(
                     chunk.into(),
                     tag,
                     TransferState::First(delivery_tx),
+                    delivery_state,
                     message_format,
                 );
 
                 loop {
-                    let chunk = body.split_to(std::cmp::min(max_frame_size, body.len()));
+                    let chunk = body.split_to(std::cmp::min(max_payload, body.len()));
 
                     // last chunk
                     if body.is_empty() {
-                        self.send_inner(chunk.into(), None, TransferState::Last, message_format);
+                        self.send_inner(
+                            chunk.into(),
+                            None,
+                            TransferState::Last,
+                            None,
+                            message_format,
+                        );
                         break;
                     } else {
                         self.send_inner(
                             chunk.into(),
                             None,
                             TransferState::Continue,
+                            None,
                             message_format,
                         );
                     }
                 }
             } else {
-                self.send_inner(body, tag, TransferState::Only(delivery_tx), message_format);
+                self.send_inner(
+                    body,
+                    tag,
+                    TransferState::Only(delivery_tx),
+                    delivery_state,
+                    message_format,
+                );
             }
 
-            Delivery::Pending(delivery_rx)
+            delivery
         }
     }
 
@@ -327,11 +1319,22 @@ impl SenderLinkInner {
         body: TransferBody,
         tag: Option<Bytes>,
         state: TransferState,
+        delivery_state: Option<DeliveryState>,
         message_format: Option<MessageFormat>,
     ) {
-        if self.link_credit == 0 {
+        let first = state.is_first();
+        if first {
+            self.stats.deliveries += 1;
+        }
+        self.stats.bytes += body.len() as u64;
+
+        if (first && self.credit.link_credit() == 0)
+            || !self.pending_transfers.is_empty()
+            || self.rate_limit.is_some()
+            || self.migrating
+        {
             log::trace!(
-                "Sender link credit is 0, push to pending queue hnd:{} {:?}, queue size: {}",
+                "Sender link credit is 0 or link is rate limited, push to pending queue hnd:{} {:?}, queue size: {}",
                 self.id as u32,
                 tag,
                 self.pending_transfers.len()
@@ -339,25 +1342,33 @@ impl SenderLinkInner {
             self.pending_transfers.push_back(PendingTransfer {
                 tag,
                 state,
+                delivery_state,
                 message_format,
                 settle: Some(false),
                 body: Some(body),
                 idx: self.idx,
             });
         } else {
-            self.link_credit -= 1;
-            self.delivery_count = self.delivery_count.saturating_add(1);
+            if first {
+                let consumed = self.credit.consume();
+                debug_assert!(consumed, "delivery is sent without credit");
+            }
             self.session.inner.get_mut().send_transfer(
                 self.id as u32,
                 self.idx,
                 Some(body),
                 state,
+                delivery_state,
                 tag,
                 None,
                 message_format,
             );
         }
         self.idx = self.idx.saturating_add(1);
+
+        if self.rate_limit.is_some() {
+            self.release_pending();
+        }
     }
 
     pub(crate) fn settle_message(&mut self, id: DeliveryNumber, state: DeliveryState) {
@@ -373,9 +1384,53 @@ impl SenderLinkInner {
     }
 }
 
+/// Max payload of transfer frame within peer's max frame size.
+///
+/// Size of transfer performative is estimated with the largest values
+/// of fields that are set by session.
+fn max_transfer_payload(
+    max_frame_size: usize,
+    tag: Option<&Bytes>,
+    delivery_state: Option<&DeliveryState>,
+    message_format: Option<MessageFormat>,
+) -> usize {
+    if max_frame_size == 0 {
+        return usize::MAX;
+    }
+
+    let transfer = Transfer {
+        handle: u32::MAX,
+        delivery_id: Some(u32::MAX),
+        delivery_tag: Some(
+            tag.cloned()
+                .unwrap_or_else(|| Bytes::from(vec![0; MAX_DELIVERY_TAG_LEN])),
+        ),
+        message_format,
+        settled: Some(false),
+        more: true,
+        rcv_settle_mode: None,
+        state: Some(
+            delivery_state
+                .cloned()
+                .unwrap_or(DeliveryState::Accepted(Accepted {})),
+        ),
+        resume: false,
+        aborted: false,
+        batchable: true,
+        body: None,
+    };
+    let overhead = AmqpFrame::new(u16::MAX, Frame::Transfer(transfer)).encoded_size();
+    std::cmp::max(max_frame_size.saturating_sub(overhead), 1)
+}
+
 pub struct SenderLinkBuilder {
     frame: Attach,
     session: Cell<SessionInner>,
+    rate_limit: Option<(u64, u64)>,
+    flow_properties: Option<Fields>,
+    require_message_id: bool,
+    message_id_generator: Option<Rc<dyn Fn() -> MessageId>>,
+    recoverable: bool,
 }
 
 impl SenderLinkBuilder {
@@ -406,7 +1461,15 @@ impl SenderLinkBuilder {
             properties: None,
         };
 
-        SenderLinkBuilder { frame, session }
+        SenderLinkBuilder {
+            frame,
+            session,
+            rate_limit: None,
+            flow_properties: None,
+            require_message_id: false,
+            message_id_generator: None,
+            recoverable: false,
+        }
     }
 
     pub fn max_message_size(mut self, size: u64) -> Self {
@@ -414,6 +1477,108 @@ impl SenderLinkBuilder {
         self
     }
 
+    /// Set expiry policy and timeout (in seconds) of the source terminus
+    pub fn source_expiry(mut self, policy: TerminusExpiryPolicy, timeout: Seconds) -> Self {
+        let source = self.frame.source.get_or_insert_with(|| Source {
+            address: None,
+            durable: TerminusDurability::None,
+            expiry_policy: TerminusExpiryPolicy::SessionEnd,
+            timeout: 0,
+            dynamic: false,
+            dynamic_node_properties: None,
+            distribution_mode: None,
+            filter: None,
+            default_outcome: None,
+            outcomes: None,
+            capabilities: None,
+        });
+        source.expiry_policy = policy;
+        source.timeout = timeout;
+        self
+    }
+
+    /// Set expiry policy and timeout (in seconds) of the target terminus
+    pub fn target_expiry(mut self, policy: TerminusExpiryPolicy, timeout: Seconds) -> Self {
+        if let Some(ref mut target) = self.frame.target {
+            target.expiry_policy = policy;
+            target.timeout = timeout;
+        }
+        self
+    }
+
+    /// Ask peer to create target node, address of the node
+    /// is reported in peer's `Attach`
+    pub fn dynamic_target(mut self) -> Self {
+        if let Some(ref mut target) = self.frame.target {
+            target.address = None;
+            target.dynamic = true;
+        }
+        self
+    }
+
+    /// Set lifetime policy of dynamically created target node
+    pub fn lifetime_policy(mut self, policy: LifetimePolicy) -> Self {
+        if let Some(ref mut target) = self.frame.target {
+            node::set_lifetime_policy(&mut target.dynamic_node_properties, policy);
+        }
+        self
+    }
+
+    /// Set distribution modes dynamically created target node must support
+    pub fn supported_dist_modes(mut self, modes: &[DistributionMode]) -> Self {
+        if let Some(ref mut target) = self.frame.target {
+            node::set_supported_dist_modes(&mut target.dynamic_node_properties, modes);
+        }
+        self
+    }
+
+    /// Limit outgoing bandwidth of the link, see `SenderLink::set_rate_limit()`
+    pub fn rate_limit(mut self, bytes_per_sec: u64, burst: u64) -> Self {
+        self.rate_limit = Some((bytes_per_sec, burst));
+        self
+    }
+
+    /// Reject messages without `message-id`, see `SenderLink::set_require_message_id()`
+    pub fn require_message_id(mut self, require: bool) -> Self {
+        self.require_message_id = require;
+        self
+    }
+
+    /// Generate missing `message-id`, see `SenderLink::set_auto_message_id()`
+    pub fn auto_message_id<F>(mut self, generator: F) -> Self
+    where
+        F: Fn() -> MessageId + 'static,
+    {
+        self.message_id_generator = Some(Rc::new(generator));
+        self
+    }
+
+    /// Keep unsettled deliveries if connection fails, see `SenderLink::set_recoverable()`
+    pub fn recoverable(mut self, recoverable: bool) -> Self {
+        self.recoverable = recoverable;
+        self
+    }
+
+    /// Set properties for `Flow` frames sent by the link
+    pub fn flow_properties(mut self, properties: Fields) -> Self {
+        self.flow_properties = Some(properties);
+        self
+    }
+
+    /// Set or reset a sender link property.
+    ///
+    /// Link properties are exchanged on attach only, they could not be
+    /// changed on opened link.
+    pub fn property(mut self, key: Symbol, value: Option<Variant>) -> Self {
+        let props = self.frame.properties.get_or_insert_with(Fields::default);
+
+        match value {
+            Some(value) => props.insert(key, value),
+            None => props.remove(&key),
+        };
+        self
+    }
+
     pub fn with_frame<F>(mut self, f: F) -> Self
     where
         F: FnOnce(&mut Attach),
@@ -423,12 +1588,214 @@ impl SenderLinkBuilder {
     }
 
     pub async fn open(self) -> Result<SenderLink, AmqpProtocolError> {
+        let node_properties = self
+            .frame
+            .target
+            .as_ref()
+            .filter(|target| target.dynamic)
+            .and_then(|target| target.dynamic_node_properties.clone());
+        let frame = self.frame.clone();
         let result = self.session.get_mut().open_sender_link(self.frame).await;
 
         match result {
-            Ok(Ok(link)) => Ok(link),
+            Ok(Ok(link)) => {
+                link.inner.get_mut().node_properties = node_properties;
+                link.inner.get_mut().attach = Some(frame);
+                link.inner.get_mut().flow_properties = self.flow_properties;
+                link.inner.get_mut().require_message_id = self.require_message_id;
+                link.inner.get_mut().message_id_generator = self.message_id_generator;
+                link.inner.get_mut().recoverable = self.recoverable;
+                if let Some((rate, burst)) = self.rate_limit {
+                    link.set_rate_limit(rate, burst);
+                }
+                Ok(link)
+            }
             Ok(Err(e)) => Err(e),
             Err(_) => Err(AmqpProtocolError::Disconnected),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use ntex::framed::State;
+    use ntex_amqp_codec::protocol::{Begin, ProtocolVersion};
+    use proptest::prelude::*;
+    use proptest::test_runner::TestRunner;
+
+    use super::*;
+    use crate::Configuration;
+
+    #[derive(Clone, Debug)]
+    enum Op {
+        /// Flow with receiver's delivery count behind ours by `lag`,
+        /// `None` uses absent delivery count
+        Flow {
+            lag: Option<i64>,
+            credit: u32,
+            drain: bool,
+            echo: bool,
+        },
+        Send,
+        Detach,
+    }
+
+    fn op() -> impl Strategy<Value = Op> {
+        let credit = prop_oneof![0..16u32, Just(u32::MAX), any::<u32>()];
+        prop_oneof![
+            2 => (prop::option::of(-4..32i64), credit, any::<bool>(), any::<bool>())
+                .prop_map(|(lag, credit, drain, echo)| Op::Flow { lag, credit, drain, echo }),
+            4 => Just(Op::Send),
+            1 => Just(Op::Detach),
+        ]
+    }
+
+    /// Reference model without wrap-around
+    #[derive(Debug, Default)]
+    struct Model {
+        sent: i64,
+        credit: i64,
+        pending: usize,
+        detached: bool,
+    }
+
+    impl Model {
+        fn release(&mut self) {
+            while self.pending > 0 && self.credit > 0 {
+                self.pending -= 1;
+                self.credit -= 1;
+                self.sent += 1;
+            }
+        }
+    }
+
+    fn link(initial: SequenceNo) -> Cell<SenderLinkInner> {
+        let cfg = Configuration::default();
+        let con = Connection::new(
+            State::with_params(8 * 1024, 8 * 1024, 1024, 3),
+            &cfg,
+            &cfg,
+            ProtocolVersion::V1_0_0,
+        );
+        let begin = Begin {
+            remote_channel: Some(0),
+            next_outgoing_id: 1,
+            incoming_window: std::u32::MAX,
+            outgoing_window: std::u32::MAX,
+            handle_max: std::u32::MAX,
+            offered_capabilities: None,
+            desired_capabilities: None,
+            properties: None,
+        };
+        let session = Cell::new(SessionInner::new(0, true, con, 0, &begin, &begin));
+        Cell::new(SenderLinkInner::new(0, "test".into(), 0, initial, session))
+    }
+
+    fn flow(delivery_count: Option<SequenceNo>, credit: u32, drain: bool, echo: bool) -> Flow {
+        Flow {
+            next_incoming_id: Some(1),
+            incoming_window: std::u32::MAX,
+            next_outgoing_id: 1,
+            outgoing_window: std::u32::MAX,
+            handle: Some(0),
+            delivery_count,
+            link_credit: Some(credit),
+            available: None,
+            drain,
+            echo,
+            properties: None,
+        }
+    }
+
+    /// Drive `SenderLinkInner` and compare with reference model
+    fn run_ops(initial: SequenceNo, ops: Vec<Op>) -> Result<(), TestCaseError> {
+        let link = link(initial);
+        let inner = link.get_mut();
+        let mut model = Model::default();
+        // dropped delivery is removed from the send queue
+        let mut deliveries = Vec::new();
+
+        for op in ops {
+            let before = inner.credit;
+            let sent_before = inner.session.stats().transfers_out;
+            let mut drained = 0u32;
+
+            match op {
+                Op::Flow {
+                    lag,
+                    credit,
+                    drain,
+                    echo,
+                } => {
+                    // absent delivery count is valid only before any delivery
+                    let lag = match lag {
+                        None if model.sent < (1 << 31) => None,
+                        None => Some(0),
+                        lag => lag,
+                    };
+                    let rcv_count = lag
+                        .map(|lag| std::cmp::max(model.sent - lag, 0))
+                        .unwrap_or(0);
+                    let delivery_count = lag.map(|_| initial.wrapping_add(rcv_count as u32));
+
+                    inner.apply_flow(&flow(delivery_count, credit, drain, echo));
+                    prop_assert!(inner.credit.link_credit() <= credit);
+
+                    let in_flight = std::cmp::max(model.sent - rcv_count, 0);
+                    model.credit = std::cmp::max(i64::from(credit) - in_flight, 0);
+                    model.release();
+                    if drain && model.pending == 0 {
+                        drained = model.credit as u32;
+                        model.sent += model.credit;
+                        model.credit = 0;
+                    }
+                }
+                Op::Send => {
+                    let delivery = inner.send(Bytes::from_static(b"test"), None, None);
+                    prop_assert_eq!(model.detached, matches!(delivery, Delivery::Resolved(_)));
+                    deliveries.push(delivery);
+                    if !model.detached {
+                        model.pending += 1;
+                        model.release();
+                    }
+                }
+                Op::Detach => {
+                    inner.detached(AmqpProtocolError::LinkDetached(None), true);
+                    model.credit = 0;
+                    model.pending = 0;
+                    model.detached = true;
+                }
+            }
+
+            // delivery count advances only by sent or drained deliveries
+            let sent = (inner.session.stats().transfers_out - sent_before) as u32;
+            prop_assert_eq!(
+                inner.credit.delivery_count(),
+                before
+                    .delivery_count()
+                    .wrapping_add(sent)
+                    .wrapping_add(drained)
+            );
+            prop_assert_eq!(
+                inner.credit.delivery_count(),
+                initial.wrapping_add(model.sent as u32)
+            );
+            prop_assert_eq!(i64::from(inner.credit.link_credit()), model.credit);
+            prop_assert_eq!(inner.pending_transfers.len(), model.pending);
+            // queued deliveries wait only for credit
+            prop_assert!(inner.pending_transfers.is_empty() || inner.credit.link_credit() == 0);
+        }
+        Ok(())
+    }
+
+    #[ntex::test]
+    async fn sender_credit_model() {
+        let strategy = (
+            prop_oneof![any::<u32>(), Just(u32::MAX - 8)],
+            prop::collection::vec(op(), 0..200),
+        );
+        TestRunner::default()
+            .run(&strategy, |(initial, ops)| run_ops(initial, ops))
+            .unwrap();
+    }
+}
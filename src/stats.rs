@@ -0,0 +1,55 @@
+/// Snapshot of sender link counters
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct SenderLinkStats {
+    /// Current link credit
+    pub link_credit: u32,
+    /// Current delivery count
+    pub delivery_count: u32,
+    /// Number of transfers waiting for credit
+    pub pending_transfers: usize,
+    /// Deliveries submitted to the link
+    pub deliveries: u64,
+    /// Payload bytes submitted to the link
+    pub bytes: u64,
+    /// Flow frames received from peer
+    pub flows: u64,
+    /// Completed migrations to another session
+    pub migrations: u64,
+}
+
+/// Snapshot of receiver link counters
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ReceiverLinkStats {
+    /// Current link credit
+    pub link_credit: u32,
+    /// Current delivery count
+    pub delivery_count: u32,
+    /// Number of received transfers not yet consumed
+    pub queued_transfers: usize,
+    /// Transfer frames received from peer
+    pub transfers: u64,
+    /// Payload bytes received from peer
+    pub bytes: u64,
+    /// Completed migrations to another session
+    pub migrations: u64,
+    /// Panics of link handler caught by router
+    pub panics: u64,
+}
+
+/// Snapshot of session counters
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct SessionStats {
+    /// Number of transfers waiting for remote incoming window
+    pub pending_transfers: usize,
+    /// Transfer frames received
+    pub transfers_in: u64,
+    /// Transfer frames sent
+    pub transfers_out: u64,
+    /// Payload bytes received
+    pub bytes_in: u64,
+    /// Payload bytes sent
+    pub bytes_out: u64,
+}
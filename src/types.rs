@@ -1,12 +1,11 @@
-use std::fmt;
+use std::{fmt, future::Future};
 
 use ntex::router::Path;
 use ntex::util::{ByteString, Bytes};
 
-use crate::codec::protocol::{
-    self, Accepted, Attach, DeliveryState, Error, Rejected, TransferBody,
-};
-use crate::codec::{AmqpParseError, Decode};
+use crate::codec::{types::Symbol, AmqpParseError, Decode};
+use crate::error::AmqpProtocolError;
+use crate::protocol::{self, Accepted, Attach, DeliveryState, Error, Rejected, Role, TransferBody};
 use crate::{rcvlink::ReceiverLink, session::Session, Handle, State};
 
 pub struct Link<S> {
@@ -36,6 +35,50 @@ impl<S> Link<S> {
         self.link.frame()
     }
 
+    /// Link name
+    pub fn name(&self) -> &ByteString {
+        &self.link.frame().name
+    }
+
+    /// Requested node address.
+    ///
+    /// Target address if peer is sender, source address otherwise
+    pub fn address(&self) -> Option<&str> {
+        let attach = self.link.frame();
+        let address = if attach.role == Role::Sender {
+            attach.target().and_then(|t| t.address.as_ref())
+        } else {
+            attach.source().and_then(|s| s.address.as_ref())
+        };
+        address.map(|addr| addr.as_ref())
+    }
+
+    /// Check if peer requested dynamically created node
+    pub fn dynamic(&self) -> bool {
+        let attach = self.link.frame();
+        if attach.role == Role::Sender {
+            attach.target().map(|t| t.dynamic).unwrap_or(false)
+        } else {
+            attach.source().map(|s| s.dynamic).unwrap_or(false)
+        }
+    }
+
+    /// Refuse link.
+    ///
+    /// Peer gets `Attach` without target, followed by `Detach` with
+    /// provided error, service result is ignored after rejection.
+    pub fn reject(
+        &self,
+        condition: Symbol,
+        description: &str,
+    ) -> impl Future<Output = Result<(), AmqpProtocolError>> {
+        self.link.close_with_error(Error {
+            condition: protocol::ErrorCondition::Custom(condition),
+            description: Some(ByteString::from(description)),
+            info: None,
+        })
+    }
+
     pub fn state(&self) -> &S {
         self.state.get_ref()
     }
@@ -127,6 +170,16 @@ impl<S> Transfer<S> {
         &self.frame
     }
 
+    /// Delivery tag, set by the first transfer of a delivery
+    pub fn delivery_tag(&self) -> Option<&Bytes> {
+        self.frame.delivery_tag.as_ref()
+    }
+
+    /// Delivery state of the transfer, set by the first transfer of a delivery
+    pub fn remote_state(&self) -> Option<DeliveryState> {
+        self.frame.state.clone()
+    }
+
     pub fn body(&self) -> Option<&Bytes> {
         match self.frame.body {
             Some(TransferBody::Data(ref b)) => Some(b),
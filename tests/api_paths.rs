@@ -0,0 +1,55 @@
+//! Compile-time check of public import paths
+#![allow(unused_imports)]
+
+use ntex_amqp::prelude::*;
+
+use ntex_amqp::{
+    Address, AddressKind, AddressOptions, Body, Configuration, Connection, ConnectionState,
+    ControlFrame, ControlFrameKind, Delivery, LinkState, Message, MessageBody, PrefixStyle, Preset,
+    ReceiverLink, ReceiverLinkBuilder, SenderLink, SenderLinkBuilder, Session, SessionBuilder,
+    SessionState, State, StateChanges, Strictness, Symbol, Variant,
+};
+
+use ntex_amqp::client::{
+    Client, ConnectError, Connector, SaslAnonymous, SaslAuth, SaslError, SaslExternal,
+    SaslMechanism,
+};
+use ntex_amqp::error::{
+    AddressError, AmqpCodecError, AmqpError, AmqpParseError, AmqpProtocolError, DispatcherError,
+    Error, LinkError, ProtocolIdError,
+};
+use ntex_amqp::error_code;
+use ntex_amqp::protocol::{
+    Accepted, Attach, Begin, Close, DeliveryState, Detach, Disposition, End, ErrorCondition,
+    Fields, Flow, Open, Rejected, Released, Role, SaslCode, Source, Target, TransferBody,
+};
+use ntex_amqp::server::sasl::{SaslInit, SaslResponse, SaslSuccess};
+use ntex_amqp::server::{
+    Handshake, HandshakeAck, HandshakeAmqp, HandshakeAmqpOpened, HandshakeError, Router, Sasl,
+    Server, ServerError,
+};
+use ntex_amqp::types::{Link, Outcome, Transfer};
+
+use ntex_amqp::codec::types::{Str, VariantMap};
+use ntex_amqp::codec::{AmqpCodec, AmqpFrame, Decode, Encode, ProtocolIdCodec, SaslFrame};
+
+// deprecated paths are kept for one release
+#[allow(deprecated)]
+mod deprecated {
+    use ntex_amqp::codec::protocol::{Attach, Frame};
+    use ntex_amqp::codec::{Message, MessageBody, MessageBuilder};
+}
+
+#[test]
+fn test_api_paths() {
+    let _ = std::any::type_name::<Configuration>();
+    let _ = std::any::type_name::<Connection>();
+    let _ = std::any::type_name::<Session>();
+    let _ = std::any::type_name::<SenderLink>();
+    let _ = std::any::type_name::<ReceiverLink>();
+    let _ = std::any::type_name::<Message>();
+    let _ = std::any::type_name::<Variant>();
+    let _ = std::any::type_name::<Outcome>();
+    let _ = std::any::type_name::<LinkState>();
+    let _ = std::any::type_name::<StateChanges<SessionState>>();
+}
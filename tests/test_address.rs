@@ -0,0 +1,173 @@
+use std::convert::TryFrom;
+
+use ntex::router::IntoPattern;
+use ntex::service::{fn_factory_with_config, Service};
+use ntex::util::Ready;
+use ntex_amqp::error::{AddressError, LinkError};
+use ntex_amqp::{server, types, Configuration};
+use ntex_amqp::{Address, AddressKind, AddressOptions, Message, PrefixStyle, Preset, Strictness};
+
+async fn server(
+    _: types::Link<()>,
+) -> Result<
+    Box<
+        dyn Service<
+                Request = types::Transfer<()>,
+                Response = types::Outcome,
+                Error = LinkError,
+                Future = Ready<types::Outcome, LinkError>,
+            > + 'static,
+    >,
+    LinkError,
+> {
+    Err(LinkError::force_detach().description("unimplemented"))
+}
+
+#[test]
+fn test_invalid_addresses() {
+    assert_eq!(Address::new(""), Err(AddressError::Empty));
+    assert_eq!(
+        Address::new("queue\u{0}"),
+        Err(AddressError::InvalidChar('\u{0}', 5))
+    );
+    assert_eq!(
+        Address::new("my queue"),
+        Err(AddressError::InvalidChar(' ', 2))
+    );
+    assert_eq!(Address::new("q%2"), Err(AddressError::InvalidEncoding(1)));
+    assert_eq!(Address::new("q%zz"), Err(AddressError::InvalidEncoding(1)));
+    assert!(Address::try_from("x".repeat(1025)).is_err());
+
+    let opts = AddressOptions::default().max_len(4);
+    assert_eq!(
+        Address::with_options("queue", &opts),
+        Err(AddressError::TooLong(5, 4))
+    );
+
+    let lenient = AddressOptions::default().strictness(Strictness::Lenient);
+    assert!(Address::with_options("my queue", &lenient).is_ok());
+    assert!(Address::with_options("q\n", &lenient).is_err());
+
+    let strict = AddressOptions::default().strictness(Strictness::Strict);
+    assert!(Address::with_options("queue://orders.eu-1", &strict).is_ok());
+    assert_eq!(
+        Address::with_options("orders#1", &strict),
+        Err(AddressError::InvalidChar('#', 6))
+    );
+}
+
+#[test]
+fn test_no_normalization() {
+    let addr = Address::new("Orders/EU").unwrap();
+    assert_eq!(addr.as_str(), "Orders/EU");
+    assert_eq!(addr.to_string(), "Orders/EU");
+    assert!(Address::new(" orders").is_err());
+
+    let opts = AddressOptions::default().trim(true).lowercase(true);
+    let addr = Address::with_options(" Orders/EU ", &opts).unwrap();
+    assert_eq!(addr.as_str(), "orders/eu");
+
+    let addr = Address::encode("orders #1").unwrap();
+    assert_eq!(addr.as_str(), "orders%20%231");
+    assert_eq!(addr.decode().unwrap(), "orders #1");
+    assert_eq!(Address::unchecked("q%zz").decode(), None);
+}
+
+#[test]
+fn test_prefixed() {
+    let addr = Address::queue("orders", PrefixStyle::Scheme).unwrap();
+    assert_eq!(addr.as_str(), "queue://orders");
+    assert_eq!(
+        addr.kind(),
+        Some((AddressKind::Queue, PrefixStyle::Scheme, "orders"))
+    );
+
+    let addr = Address::topic("prices", PrefixStyle::Path).unwrap();
+    assert_eq!(addr.as_str(), "/topic/prices");
+    assert_eq!(
+        addr.kind(),
+        Some((AddressKind::Topic, PrefixStyle::Path, "prices"))
+    );
+
+    let addr = Address::queue("orders", PrefixStyle::Plain).unwrap();
+    assert_eq!(addr.as_str(), "orders");
+    assert_eq!(addr.kind(), None);
+    assert_eq!(
+        Address::queue("", PrefixStyle::Path),
+        Err(AddressError::Empty)
+    );
+}
+
+#[test]
+fn test_message_addresses() {
+    let msg = Message::build().reply_to("/queue/replies").done();
+    let addr = Address::message_reply_to(&msg).unwrap();
+    assert_eq!(
+        addr.kind(),
+        Some((AddressKind::Queue, PrefixStyle::Path, "replies"))
+    );
+    assert!(Address::message_to(&msg).is_none());
+}
+
+#[test]
+fn test_router_pattern() {
+    let addr = Address::queue("orders", PrefixStyle::Scheme).unwrap();
+    assert_eq!(addr.patterns(), vec!["queue://orders".to_string()]);
+    let _ = server::Router::<()>::new().service(addr, fn_factory_with_config(server));
+}
+
+#[test]
+fn test_config_presets() {
+    let config = Configuration::preset(Preset::ActiveMq);
+    assert_eq!(config.address_style, PrefixStyle::Scheme);
+    assert_eq!(
+        config.queue_address("orders").unwrap().as_str(),
+        "queue://orders"
+    );
+    assert_eq!(
+        config.topic_address("prices").unwrap().as_str(),
+        "topic://prices"
+    );
+
+    let config = Configuration::preset(Preset::RabbitMq);
+    assert_eq!(
+        config.queue_address("orders").unwrap().as_str(),
+        "/queue/orders"
+    );
+    assert_eq!(
+        config.topic_address("prices").unwrap().as_str(),
+        "/topic/prices"
+    );
+
+    let config = Configuration::preset(Preset::Generic);
+    assert_eq!(config.queue_address("orders").unwrap().as_str(), "orders");
+    assert_eq!(
+        Configuration::default().topic_address("prices").unwrap(),
+        config.topic_address("prices").unwrap()
+    );
+    assert_eq!(config.queue_address(""), Err(AddressError::Empty));
+}
+
+#[test]
+fn test_config_address_options() {
+    let mut config = Configuration::preset(Preset::RabbitMq);
+    assert_eq!(
+        config.queue_address("my orders"),
+        Err(AddressError::InvalidChar(' ', 9))
+    );
+    assert_eq!(
+        config.queue_address(" Orders").map(|a| a.to_string()),
+        Err(AddressError::InvalidChar(' ', 7))
+    );
+
+    config
+        .address_style(PrefixStyle::Plain)
+        .address_options(AddressOptions::default().trim(true).lowercase(true));
+    assert_eq!(config.queue_address(" Orders ").unwrap().as_str(), "orders");
+
+    config.address_options(AddressOptions::default().strictness(Strictness::Lenient));
+    assert_eq!(
+        config.queue_address("my orders").unwrap().as_str(),
+        "my orders"
+    );
+}
@@ -0,0 +1,57 @@
+use std::time::Duration;
+
+use ntex_amqp::{protocol, Configuration};
+
+#[test]
+fn test_keepalive_from_idle_timeout() {
+    let mut config = Configuration::new();
+    config.idle_timeout(30);
+    assert_eq!(config.local_idle_timeout(), Some(Duration::from_secs(30)));
+    assert_eq!(config.keepalive_secs(), 30);
+    assert_eq!(config.timeout_remote_secs(), 22);
+
+    // advertised value does not include grace period
+    config.idle_timeout_grace(5);
+    assert_eq!(config.local_idle_timeout(), Some(Duration::from_secs(35)));
+    assert_eq!(config.keepalive_secs(), 35);
+    assert_eq!(config.to_open().idle_time_out, Some(30_000));
+}
+
+#[test]
+fn test_keepalive_disabled() {
+    let mut config = Configuration::new();
+    config.idle_timeout(0).idle_timeout_grace(5);
+    assert_eq!(config.local_idle_timeout(), None);
+    assert_eq!(config.keepalive_secs(), 0);
+    assert_eq!(config.timeout_remote_secs(), 0);
+    assert_eq!(config.to_open().idle_time_out, None);
+}
+
+#[test]
+fn test_remote_timeout() {
+    let mut open = Configuration::new().to_open();
+    open.idle_time_out = Some(1500);
+    let remote = Configuration::from(&open);
+    assert_eq!(
+        remote.local_idle_timeout(),
+        Some(Duration::from_millis(1500))
+    );
+    assert_eq!(remote.keepalive_secs(), 1);
+    assert_eq!(remote.timeout_remote_secs(), 1);
+
+    let open = protocol::Open {
+        idle_time_out: None,
+        ..open
+    };
+    assert_eq!(Configuration::from(&open).keepalive_secs(), 0);
+}
+
+#[test]
+fn test_empty_protocol_versions() {
+    let mut config = Configuration::new();
+    config.protocol_versions(&[]);
+    assert_eq!(
+        config.protocol_versions,
+        vec![protocol::ProtocolVersion::V1_0_0]
+    );
+}
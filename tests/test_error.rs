@@ -0,0 +1,89 @@
+use ntex_amqp::client::{ConnectError, SaslError};
+use ntex_amqp::error::{AmqpProtocolError, ErrorKind};
+use ntex_amqp::protocol::{self, SaslCode};
+use ntex_amqp::Symbol;
+
+fn error<T>(condition: T) -> Option<protocol::Error>
+where
+    protocol::ErrorCondition: From<T>,
+{
+    Some(protocol::Error {
+        condition: condition.into(),
+        description: None,
+        info: None,
+    })
+}
+
+#[test]
+fn test_protocol_error_kind() {
+    let err = AmqpProtocolError::Disconnected;
+    assert_eq!(err.kind(), ErrorKind::Transport);
+    assert!(err.is_retryable());
+
+    let err = AmqpProtocolError::KeepAliveTimeout;
+    assert_eq!(err.kind(), ErrorKind::Timeout);
+    assert!(err.is_retryable());
+
+    let err = AmqpProtocolError::IdleTimeout;
+    assert_eq!(err.kind(), ErrorKind::Timeout);
+    assert!(err.is_retryable());
+
+    let err = AmqpProtocolError::SendQueueFull;
+    assert_eq!(err.kind(), ErrorKind::ResourceLimit);
+    assert!(err.is_retryable());
+
+    let err = AmqpProtocolError::Closed(None);
+    assert_eq!(err.kind(), ErrorKind::Closed);
+    assert!(err.is_retryable());
+
+    let err = AmqpProtocolError::Closed(error(protocol::ConnectionError::ConnectionForced));
+    assert_eq!(err.kind(), ErrorKind::Closed);
+    assert!(err.is_retryable());
+
+    let err = AmqpProtocolError::LinkDetached(error(protocol::LinkError::DetachForced));
+    assert!(err.is_retryable());
+
+    let err = AmqpProtocolError::LinkDetached(error(protocol::AmqpError::UnauthorizedAccess));
+    assert_eq!(err.kind(), ErrorKind::Unauthorized);
+    assert!(!err.is_retryable());
+
+    let err = AmqpProtocolError::LinkDetached(error(protocol::AmqpError::NotFound));
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+    assert!(!err.is_retryable());
+
+    let err = AmqpProtocolError::SessionEnded(error(protocol::AmqpError::DecodeError));
+    assert_eq!(err.kind(), ErrorKind::Malformed);
+    assert!(!err.is_retryable());
+
+    let err = AmqpProtocolError::LinkDetached(error(protocol::LinkError::MessageSizeExceeded));
+    assert_eq!(err.kind(), ErrorKind::Rejected);
+    assert!(!err.is_retryable());
+
+    let err = AmqpProtocolError::MessageSizeExceeded(65536);
+    assert_eq!(err.kind(), ErrorKind::Rejected);
+    assert!(!err.is_retryable());
+
+    let err = AmqpProtocolError::Closed(error(protocol::ErrorCondition::Custom(
+        Symbol::from_static("custom:error"),
+    )));
+    assert_eq!(err.kind(), ErrorKind::Other);
+    assert!(!err.is_retryable());
+}
+
+#[test]
+fn test_connect_error_kind() {
+    assert!(ConnectError::HandshakeTimeout.is_retryable());
+    assert!(ConnectError::Disconnected.is_retryable());
+    assert!(ConnectError::Sasl(SaslCode::SysTemp).is_retryable());
+
+    assert_eq!(
+        ConnectError::Sasl(SaslCode::Auth).kind(),
+        ErrorKind::Unauthorized
+    );
+    assert!(!ConnectError::Sasl(SaslCode::Auth).is_retryable());
+    assert!(!ConnectError::Sasl(SaslCode::SysPerm).is_retryable());
+    assert_eq!(
+        ConnectError::SaslChallenge(SaslError::InvalidChallenge).kind(),
+        ErrorKind::Unauthorized
+    );
+}